@@ -0,0 +1,162 @@
+use crate::game_genie::{GameGenieCode, GameGenieError};
+use alloc::vec::Vec;
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheatError {
+    GameGenie(GameGenieError),
+    // ADDR:VALUEの形になっていない
+    BadFormat,
+}
+
+impl fmt::Display for CheatError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::GameGenie(err) => err.fmt(f),
+            Self::BadFormat => f.write_str("Cheat must be a Game Genie code or ADDR:VALUE in hex."),
+        }
+    }
+}
+
+impl core::error::Error for CheatError {}
+
+impl From<GameGenieError> for CheatError {
+    fn from(err: GameGenieError) -> Self {
+        Self::GameGenie(err)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cheat {
+    // ROMを読んだときの値を差し替える
+    GameGenie(GameGenieCode),
+    // Pro Action Replayのように、RAMのアドレスを常にその値にしておく
+    Freeze { addr: u16, value: u8 },
+}
+
+impl Cheat {
+    // "0075:09" のような16進のアドレスと値か、Game Genieのコード
+    pub fn parse(code: &str) -> Result<Self, CheatError> {
+        let (addr, value) = match code.split_once(':') {
+            Some(pair) => pair,
+            None => return Ok(Self::GameGenie(GameGenieCode::decode(code)?)),
+        };
+        let addr = u16::from_str_radix(addr, 16).map_err(|_| CheatError::BadFormat)?;
+        let value = u8::from_str_radix(value, 16).map_err(|_| CheatError::BadFormat)?;
+        Ok(Self::Freeze { addr, value })
+    }
+}
+
+impl fmt::Display for Cheat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::GameGenie(code) => code.fmt(f),
+            Self::Freeze { addr, value } => write!(f, "${:04X} = ${:02X} (frozen)", addr, value),
+        }
+    }
+}
+
+// 有効にしているチート。バスが読み書きのたびに参照する
+#[derive(Debug, Clone, Default)]
+pub struct Cheats {
+    cheats: Vec<Cheat>,
+}
+
+impl Cheats {
+    pub fn as_slice(&self) -> &[Cheat] {
+        &self.cheats
+    }
+
+    // 同じものを2回入れても1つだけになる。追加したらtrue
+    pub fn add(&mut self, cheat: Cheat) -> bool {
+        if self.cheats.contains(&cheat) {
+            return false;
+        }
+        self.cheats.push(cheat);
+        true
+    }
+
+    // 入っていなければfalse
+    pub fn remove(&mut self, cheat: Cheat) -> bool {
+        let len = self.cheats.len();
+        self.cheats.retain(|c| *c != cheat);
+        self.cheats.len() != len
+    }
+
+    // ROMから読んだ値にGame Genieのコードを当てる
+    pub fn read_rom(&self, addr: u16, value: u8) -> u8 {
+        self.cheats
+            .iter()
+            .find_map(|cheat| match cheat {
+                Cheat::GameGenie(code) => code.apply(addr, value),
+                Cheat::Freeze { .. } => None,
+            })
+            .unwrap_or(value)
+    }
+
+    // 固定しているアドレスならその値。後から追加したものが優先
+    pub fn frozen(&self, addr: u16) -> Option<u8> {
+        self.freezes()
+            .filter(|&(a, _)| a == addr)
+            .map(|(_, value)| value)
+            .last()
+    }
+
+    pub fn freezes(&self) -> impl Iterator<Item = (u16, u8)> + '_ {
+        self.cheats.iter().filter_map(|cheat| match *cheat {
+            Cheat::Freeze { addr, value } => Some((addr, value)),
+            Cheat::GameGenie(_) => None,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Cheat, CheatError, Cheats};
+    use crate::game_genie::{GameGenieCode, GameGenieError};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(
+            Cheat::parse("0075:09"),
+            Ok(Cheat::Freeze {
+                addr: 0x0075,
+                value: 0x09
+            })
+        );
+        assert_eq!(
+            Cheat::parse("GOSSIP"),
+            Ok(Cheat::GameGenie(GameGenieCode::decode("GOSSIP").unwrap()))
+        );
+        assert_eq!(Cheat::parse("0075:100"), Err(CheatError::BadFormat));
+        assert_eq!(Cheat::parse("hello:09"), Err(CheatError::BadFormat));
+        assert_eq!(
+            Cheat::parse("GOSSI"),
+            Err(CheatError::GameGenie(GameGenieError::InvalidLength(5)))
+        );
+        assert_eq!(
+            Cheat::parse("0075:09").unwrap().to_string(),
+            "$0075 = $09 (frozen)"
+        );
+    }
+
+    #[test]
+    fn test_cheats() {
+        let mut cheats = Cheats::default();
+        let freeze = Cheat::parse("0075:09").unwrap();
+        assert!(cheats.add(freeze));
+        assert!(!cheats.add(freeze));
+        assert!(cheats.add(Cheat::parse("0075:0a").unwrap()));
+        assert!(cheats.add(Cheat::parse("GOSSIP").unwrap()));
+        assert_eq!(cheats.as_slice().len(), 3);
+
+        assert_eq!(cheats.frozen(0x0075), Some(0x0a));
+        assert_eq!(cheats.frozen(0x0076), None);
+        assert_eq!(cheats.read_rom(0xd1dd, 0x00), 0x14);
+        assert_eq!(cheats.read_rom(0xd1de, 0x00), 0x00);
+
+        assert!(cheats.remove(freeze));
+        assert!(!cheats.remove(freeze));
+        assert_eq!(cheats.frozen(0x0075), Some(0x0a));
+    }
+}
@@ -0,0 +1,135 @@
+use crate::{
+    cpu::bus::NesBus,
+    ram::{PRG_RAM_SIZE, WRAM_SIZE},
+};
+use alloc::vec::Vec;
+
+// 候補を絞り込む条件。Equal以外は前回のスナップショットとの比較
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchFilter {
+    Equal(u8),
+    NotEqual(u8),
+    Greater,
+    Less,
+    Changed,
+    Unchanged,
+    // 前回からの増減。1バイトの中で桁あふれしたものも含む
+    ChangedBy(i16),
+}
+
+impl SearchFilter {
+    fn matches(self, previous: u8, current: u8) -> bool {
+        match self {
+            Self::Equal(value) => current == value,
+            Self::NotEqual(value) => current != value,
+            Self::Greater => current > previous,
+            Self::Less => current < previous,
+            Self::Changed => current != previous,
+            Self::Unchanged => current == previous,
+            Self::ChangedBy(delta) => current.wrapping_sub(previous) == delta as u8,
+        }
+    }
+}
+
+// WRAMとPRG RAMの値をスナップショットして、条件に合うアドレスを絞り込んでいく
+#[derive(Debug, Clone)]
+pub struct CheatSearch {
+    snapshot: Vec<u8>,
+    // snapshotの添字
+    candidates: Vec<usize>,
+}
+
+impl CheatSearch {
+    // 最初は全部のアドレスが候補
+    pub fn new(bus: &NesBus) -> Self {
+        let snapshot = take_snapshot(bus);
+        Self {
+            candidates: (0..snapshot.len()).collect(),
+            snapshot,
+        }
+    }
+
+    // 今の値で絞り込んで、それを次の比較に使う。残った候補の数を返す
+    pub fn filter(&mut self, bus: &NesBus, filter: SearchFilter) -> usize {
+        let current = take_snapshot(bus);
+        let previous = &self.snapshot;
+        self.candidates
+            .retain(|&i| filter.matches(previous[i], current[i]));
+        self.snapshot = current;
+        self.candidates.len()
+    }
+
+    // 残っているアドレスと最後に見た値
+    pub fn candidates(&self) -> impl Iterator<Item = (u16, u8)> + '_ {
+        self.candidates
+            .iter()
+            .map(move |&i| (address(i), self.snapshot[i]))
+    }
+
+    pub fn len(&self) -> usize {
+        self.candidates.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.candidates.is_empty()
+    }
+}
+
+fn take_snapshot(bus: &NesBus) -> Vec<u8> {
+    let mut snapshot = Vec::with_capacity(WRAM_SIZE + PRG_RAM_SIZE);
+    snapshot.extend_from_slice(bus.ram());
+    snapshot.extend_from_slice(bus.prg_ram());
+    snapshot
+}
+
+// WRAMの後ろにPRG RAM($6000〜)を並べている
+fn address(index: usize) -> u16 {
+    if index < WRAM_SIZE {
+        index as u16
+    } else {
+        (0x6000 + index - WRAM_SIZE) as u16
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CheatSearch, SearchFilter};
+    use crate::cpu::bus::NesBus;
+
+    #[test]
+    fn test_filter() {
+        let mut bus = NesBus::new();
+        bus.ram_mut()[0x0075] = 3;
+        bus.prg_ram_mut()[0x0010] = 3;
+        let mut search = CheatSearch::new(&bus);
+        assert_eq!(search.len(), 0x800 + 0x2000);
+
+        assert_eq!(search.filter(&bus, SearchFilter::Equal(3)), 2);
+        assert_eq!(
+            search.candidates().collect::<Vec<_>>(),
+            vec![(0x0075, 3), (0x6010, 3)]
+        );
+
+        // 残機が1減った
+        bus.ram_mut()[0x0075] = 2;
+        assert_eq!(search.filter(&bus, SearchFilter::ChangedBy(-1)), 1);
+        assert_eq!(search.candidates().collect::<Vec<_>>(), vec![(0x0075, 2)]);
+
+        bus.ram_mut()[0x0075] = 0xff;
+        assert_eq!(search.filter(&bus, SearchFilter::Greater), 1);
+        assert_eq!(search.filter(&bus, SearchFilter::Unchanged), 1);
+        assert_eq!(search.filter(&bus, SearchFilter::Changed), 0);
+        assert!(search.is_empty());
+    }
+
+    #[test]
+    fn test_changed_by_wraps() {
+        let mut bus = NesBus::new();
+        let mut search = CheatSearch::new(&bus);
+        bus.ram_mut()[0x0001] = 0xff;
+        assert_eq!(search.filter(&bus, SearchFilter::ChangedBy(-1)), 1);
+        bus.ram_mut()[0x0001] = 0x04;
+        assert_eq!(search.filter(&bus, SearchFilter::ChangedBy(5)), 1);
+        assert_eq!(search.filter(&bus, SearchFilter::Less), 0);
+    }
+}
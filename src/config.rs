@@ -0,0 +1,113 @@
+use crate::region::Region;
+use serde::Deserialize;
+use std::{error::Error, fs, path::Path, result::Result};
+
+// 初めて起動したときに書き出す。全部コメントアウトしてあるので中身はConfig::default()と同じ
+pub const DEFAULT_CONFIG: &str = r#"# Settings for nes. Command line options take precedence.
+
+# Directory for save states.
+# state_dir = "./states"
+
+# Directory for battery-backed .sav files. Defaults to the ROM's directory.
+# save_dir = "./saves"
+
+# ntsc, pal or dendy. Defaults to the ROM header.
+# region = "ntsc"
+
+# Power-on RAM: zero, ff, alternating, random or random:SEED.
+# ram_init = "zero"
+
+# Same syntax as RUST_LOG, e.g. "debug" or "nes::cpu=trace".
+# log_level = "info"
+
+# Game Genie codes or ADDR:VALUE freezes.
+# cheats = ["SXIOPO", "0075:09"]
+"#;
+
+// 指定していないものはNone。コマンドラインの指定があればそちらを使う
+#[derive(Debug, Clone, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub state_dir: Option<String>,
+    pub save_dir: Option<String>,
+    pub region: Option<String>,
+    pub ram_init: Option<String>,
+    pub log_level: Option<String>,
+    pub cheats: Vec<String>,
+}
+
+impl Config {
+    pub fn parse(source: &str) -> Result<Self, Box<dyn Error>> {
+        let config: Self = toml::from_str(source)?;
+        if let Some(region) = &config.region {
+            if Region::from_name(region).is_none() {
+                return Err(format!("Unknown region: {}.", region).into());
+            }
+        }
+        Ok(config)
+    }
+
+    // 無ければコメントだけの設定ファイルを作る
+    pub fn load_or_create<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        if !path.exists() {
+            fs::write(path, DEFAULT_CONFIG)?;
+            return Ok(Self::default());
+        }
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn region(&self) -> Option<Region> {
+        self.region.as_deref().and_then(Region::from_name)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Config, DEFAULT_CONFIG};
+    use crate::region::Region;
+    use std::{env, fs};
+
+    #[test]
+    fn test_default_config() {
+        assert_eq!(Config::parse(DEFAULT_CONFIG).unwrap(), Config::default());
+        // コメントを外しても読める
+        let uncommented = DEFAULT_CONFIG.replace("# state_dir", "state_dir");
+        let uncommented = uncommented.replace("# region", "region");
+        let config = Config::parse(&uncommented).unwrap();
+        assert_eq!(config.state_dir.as_deref(), Some("./states"));
+        assert_eq!(config.region(), Some(Region::Ntsc));
+    }
+
+    #[test]
+    fn test_parse() {
+        let config = Config::parse(
+            "region = \"pal\"\nram_init = \"random:42\"\ncheats = [\"GOSSIP\", \"0075:09\"]\n",
+        )
+        .unwrap();
+        assert_eq!(config.region(), Some(Region::Pal));
+        assert_eq!(config.ram_init.as_deref(), Some("random:42"));
+        assert_eq!(config.cheats, vec!["GOSSIP", "0075:09"]);
+
+        assert!(Config::parse("video_scale = 2\n").is_err());
+        assert_eq!(
+            Config::parse("region = \"secam\"\n")
+                .unwrap_err()
+                .to_string(),
+            "Unknown region: secam."
+        );
+    }
+
+    #[test]
+    fn test_load_or_create() {
+        let path = env::temp_dir().join(format!("nes-config-test-{}.toml", std::process::id()));
+        let _ = fs::remove_file(&path);
+        assert_eq!(Config::load_or_create(&path).unwrap(), Config::default());
+        assert_eq!(fs::read_to_string(&path).unwrap(), DEFAULT_CONFIG);
+
+        fs::write(&path, "state_dir = \"/tmp/states\"\n").unwrap();
+        let config = Config::load_or_create(&path).unwrap();
+        assert_eq!(config.state_dir.as_deref(), Some("/tmp/states"));
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,359 @@
+use crate::cheat::{Cheat, Cheats};
+use crate::ram::{PRG_RAM_SIZE, WRAM_SIZE};
+use crate::rom_data::RomData;
+use alloc::{boxed::Box, vec, vec::Vec};
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BusError {
+    // 何もつながっていないアドレス
+    UnmappedRead(u16),
+    UnmappedWrite { addr: u16, value: u8 },
+    NoRom,
+}
+
+impl fmt::Display for BusError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnmappedRead(addr) => write!(f, "Read from unmapped address ${:04X}.", addr),
+            Self::UnmappedWrite { addr, value } => write!(
+                f,
+                "Write of ${:02X} to unmapped address ${:04X}.",
+                value, addr
+            ),
+            Self::NoRom => write!(f, "No ROM."),
+        }
+    }
+}
+
+impl core::error::Error for BusError {}
+
+// CPUから見たメモリ空間
+pub trait Bus {
+    fn read(&mut self, addr: u16) -> Result<u8, BusError>;
+    fn write(&mut self, addr: u16, value: u8) -> Result<(), BusError>;
+    // 副作用なしで読む。I/OレジスタなどメモリでないところはNone
+    fn peek(&self, addr: u16) -> Option<u8>;
+    // デバッガから書き換える。書き換えられないところはfalse
+    fn poke(&mut self, _addr: u16, _value: u8) -> bool {
+        false
+    }
+}
+
+// 普段は何もつながっていない$4018〜$5FFFにつなぐ独自の周辺機器。Cpuごと別スレッドに渡せるようにSend
+pub trait Expansion: Send {
+    // 応答しないアドレスはNone
+    fn read(&mut self, addr: u16) -> Option<u8>;
+    // 受け取らなかったらfalse
+    fn write(&mut self, addr: u16, value: u8) -> bool;
+    fn peek(&self, _addr: u16) -> Option<u8> {
+        None
+    }
+}
+
+impl fmt::Debug for dyn Expansion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Expansion")
+    }
+}
+
+// NESのメモリマップ。今はマッパー0だけ
+#[derive(Debug)]
+pub struct NesBus {
+    pub(super) rom: Option<RomData>,
+    ram: [u8; WRAM_SIZE],
+    prg_ram: [u8; PRG_RAM_SIZE],
+    cheats: Cheats,
+    expansion: Option<Box<dyn Expansion>>,
+}
+
+impl NesBus {
+    pub fn new() -> Self {
+        Self {
+            rom: None,
+            ram: [0; WRAM_SIZE],
+            prg_ram: [0; PRG_RAM_SIZE],
+            cheats: Cheats::default(),
+            expansion: None,
+        }
+    }
+
+    pub fn ram(&self) -> &[u8] {
+        &self.ram
+    }
+
+    pub fn ram_mut(&mut self) -> &mut [u8] {
+        &mut self.ram
+    }
+
+    pub fn prg_ram(&self) -> &[u8] {
+        &self.prg_ram
+    }
+
+    pub fn prg_ram_mut(&mut self) -> &mut [u8] {
+        &mut self.prg_ram
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        self.cheats.as_slice()
+    }
+
+    pub fn set_expansion(&mut self, expansion: Option<Box<dyn Expansion>>) {
+        self.expansion = expansion;
+    }
+
+    // 固定するアドレスにはすぐにその値を書く
+    pub fn add_cheat(&mut self, cheat: Cheat) {
+        if self.cheats.add(cheat) {
+            self.apply_freezes();
+        }
+    }
+
+    // 入っていなければfalse
+    pub fn remove_cheat(&mut self, cheat: Cheat) -> bool {
+        let removed = self.cheats.remove(cheat);
+        // 同じアドレスを別の値で固定しているものがあればそちらに戻す
+        self.apply_freezes();
+        removed
+    }
+
+    // ステートを読み込んだときなど、RAMを丸ごと書き換えた後に呼ぶ
+    pub fn apply_freezes(&mut self) {
+        let freezes: Vec<_> = self.cheats.freezes().collect();
+        for (addr, value) in freezes {
+            self.poke(addr, value);
+        }
+    }
+
+    // 16KBのROMは$C000からも同じものが見える。Game Genieのコードはここで当てる
+    fn read_rom(&self, addr: u16) -> Option<u8> {
+        let rom = self.rom.as_ref().filter(|rom| !rom.is_empty())?;
+        let value = rom[(addr - 0x8000) as usize % rom.len()];
+        Some(self.cheats.read_rom(addr, value))
+    }
+}
+
+impl Default for NesBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Bus for NesBus {
+    fn read(&mut self, addr: u16) -> Result<u8, BusError> {
+        match addr {
+            0x0000..=0x07ff => Ok(self.ram[addr as usize]),
+            0x6000..=0x7fff => Ok(self.prg_ram[(addr - 0x6000) as usize]),
+            0x8000..=0xffff => self.read_rom(addr).ok_or(BusError::NoRom),
+            0x4018..=0x5fff => self
+                .expansion
+                .as_mut()
+                .and_then(|e| e.read(addr))
+                .ok_or(BusError::UnmappedRead(addr)),
+            _ => Err(BusError::UnmappedRead(addr)),
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) -> Result<(), BusError> {
+        // 固定しているアドレスへの書き込みはその値に置き換える
+        let value = self.cheats.frozen(addr).unwrap_or(value);
+        match addr {
+            0x0000..=0x07ff => {
+                self.ram[addr as usize] = value;
+            }
+            0x6000..=0x7fff => {
+                self.prg_ram[(addr - 0x6000) as usize] = value;
+            }
+            0x2000..=0x2007 => {
+                log::debug!(target: "nes::ppu", "write 0x{:x} to 0x{:x}", value, addr);
+            }
+            0x4018..=0x5fff
+                if self
+                    .expansion
+                    .as_mut()
+                    .is_some_and(|e| e.write(addr, value)) => {}
+            _ => return Err(BusError::UnmappedWrite { addr, value }),
+        }
+        Ok(())
+    }
+
+    // ROMが無いときはNone
+    fn peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x0000..=0x07ff => Some(self.ram[addr as usize]),
+            0x6000..=0x7fff => Some(self.prg_ram[(addr - 0x6000) as usize]),
+            0x8000..=0xffff => self.read_rom(addr),
+            0x4018..=0x5fff => self.expansion.as_ref()?.peek(addr),
+            _ => None,
+        }
+    }
+
+    // RAM以外には書き込めない
+    fn poke(&mut self, addr: u16, value: u8) -> bool {
+        match addr {
+            0x0000..=0x07ff => self.ram[addr as usize] = value,
+            0x6000..=0x7fff => self.prg_ram[(addr - 0x6000) as usize] = value,
+            _ => return false,
+        }
+        true
+    }
+}
+
+// 64KB全部がただのRAM。命令単体のテストに使う
+#[derive(Debug, Clone)]
+pub struct TestBus {
+    memory: Vec<u8>,
+}
+
+impl Default for TestBus {
+    fn default() -> Self {
+        Self {
+            memory: vec![0; 0x10000],
+        }
+    }
+}
+
+impl Bus for TestBus {
+    fn read(&mut self, addr: u16) -> Result<u8, BusError> {
+        Ok(self.memory[addr as usize])
+    }
+
+    fn write(&mut self, addr: u16, value: u8) -> Result<(), BusError> {
+        self.memory[addr as usize] = value;
+        Ok(())
+    }
+
+    fn peek(&self, addr: u16) -> Option<u8> {
+        Some(self.memory[addr as usize])
+    }
+
+    fn poke(&mut self, addr: u16, value: u8) -> bool {
+        self.memory[addr as usize] = value;
+        true
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Bus, BusError, Expansion, NesBus, TestBus};
+    use crate::cheat::Cheat;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_nes_bus() {
+        let mut bus = NesBus::new();
+        assert_eq!(bus.peek(0x8000), None);
+        assert_eq!(bus.read(0x8000), Err(BusError::NoRom));
+
+        let mut rom = vec![0; 0x4000];
+        rom[0x0010] = 0x12;
+        bus.rom = Some(rom.into());
+        assert_eq!(bus.read(0x8010), Ok(0x12));
+        assert_eq!(bus.read(0xc010), Ok(0x12));
+
+        bus.write(0x0123, 0x45).unwrap();
+        assert_eq!(bus.ram()[0x0123], 0x45);
+        assert!(bus.poke(0x7000, 0x67));
+        assert_eq!(bus.peek(0x7000), Some(0x67));
+        assert_eq!(bus.prg_ram()[0x1000], 0x67);
+        assert!(!bus.poke(0x8000, 0x00));
+        assert_eq!(bus.peek(0x2002), None);
+        assert_eq!(bus.read(0x2002), Err(BusError::UnmappedRead(0x2002)));
+        assert_eq!(
+            bus.write(0x4016, 0x01),
+            Err(BusError::UnmappedWrite {
+                addr: 0x4016,
+                value: 0x01
+            })
+        );
+    }
+
+    #[test]
+    fn test_game_genie() {
+        let mut bus = NesBus::new();
+        let mut rom = vec![0; 0x4000];
+        rom[0x14a7] = 0x03;
+        bus.rom = Some(rom.into());
+
+        // $94A7が$03のときだけ$02にする
+        let code = Cheat::parse("ZEXPYGLA").unwrap();
+        bus.add_cheat(code);
+        bus.add_cheat(code);
+        assert_eq!(bus.cheats(), &[code]);
+        assert_eq!(bus.read(0x94a7), Ok(0x02));
+        assert_eq!(bus.peek(0x94a7), Some(0x02));
+        // ミラーされた$D4A7には効かない
+        assert_eq!(bus.read(0xd4a7), Ok(0x03));
+
+        assert!(bus.remove_cheat(code));
+        assert!(!bus.remove_cheat(code));
+        assert_eq!(bus.read(0x94a7), Ok(0x03));
+    }
+
+    #[test]
+    fn test_freeze() {
+        let mut bus = NesBus::new();
+        bus.write(0x0075, 0x01).unwrap();
+        let freeze = Cheat::parse("0075:09").unwrap();
+        bus.add_cheat(freeze);
+        assert_eq!(bus.read(0x0075), Ok(0x09));
+        bus.write(0x0075, 0x02).unwrap();
+        assert_eq!(bus.read(0x0075), Ok(0x09));
+
+        // ステートの読み込みなどで書き換わっても戻せる
+        bus.ram_mut()[0x0075] = 0x03;
+        bus.apply_freezes();
+        assert_eq!(bus.read(0x0075), Ok(0x09));
+
+        assert!(bus.remove_cheat(freeze));
+        bus.write(0x0075, 0x02).unwrap();
+        assert_eq!(bus.read(0x0075), Ok(0x02));
+    }
+
+    // $4100に書いたバイトを貯めていく、テスト用の出力ポート
+    struct PrintPort(Arc<Mutex<Vec<u8>>>);
+
+    impl Expansion for PrintPort {
+        fn read(&mut self, addr: u16) -> Option<u8> {
+            (addr == 0x4101).then(|| self.0.lock().unwrap().len() as u8)
+        }
+
+        fn write(&mut self, addr: u16, value: u8) -> bool {
+            if addr != 0x4100 {
+                return false;
+            }
+            self.0.lock().unwrap().push(value);
+            true
+        }
+    }
+
+    #[test]
+    fn test_expansion() {
+        let mut bus = NesBus::new();
+        assert_eq!(bus.read(0x4101), Err(BusError::UnmappedRead(0x4101)));
+
+        let output = Arc::new(Mutex::new(Vec::new()));
+        bus.set_expansion(Some(Box::new(PrintPort(output.clone()))));
+        bus.write(0x4100, b'o').unwrap();
+        bus.write(0x4100, b'k').unwrap();
+        assert_eq!(&*output.lock().unwrap(), b"ok");
+        assert_eq!(bus.read(0x4101), Ok(2));
+        assert_eq!(bus.peek(0x4101), None);
+        assert_eq!(
+            bus.write(0x5000, 0x01),
+            Err(BusError::UnmappedWrite {
+                addr: 0x5000,
+                value: 0x01
+            })
+        );
+    }
+
+    #[test]
+    fn test_test_bus() {
+        let mut bus = TestBus::default();
+        bus.write(0xfffe, 0x12).unwrap();
+        assert_eq!(bus.read(0xfffe), Ok(0x12));
+        assert!(bus.poke(0x2002, 0x34));
+        assert_eq!(bus.peek(0x2002), Some(0x34));
+    }
+}
@@ -0,0 +1,156 @@
+use alloc::vec::Vec;
+use core::fmt;
+
+// JSRで積んでRTSで降ろす。デバッガでバックトレースを出すためのもの
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallFrame {
+    // JSR命令のアドレス
+    pub call_site: u16,
+    // 呼び出したサブルーチンの先頭
+    pub target: u16,
+    // RTSで戻ってくるはずのアドレス
+    pub return_addr: u16,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StackWarning {
+    // 対応するJSRが無いのにRTSした。スタックに積んだアドレスへ飛ぶテクニックでも起きる
+    UnmatchedReturn {
+        addr: u16,
+        return_addr: u16,
+    },
+    // 一番内側ではなく、もっと外側のJSRの戻り先に戻った
+    SkippedFrames {
+        addr: u16,
+        return_addr: u16,
+        skipped: usize,
+    },
+}
+
+impl fmt::Display for StackWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StackWarning::UnmatchedReturn { addr, return_addr } => write!(
+                f,
+                "RTS at ${:04X} returned to ${:04X} without a matching JSR",
+                addr, return_addr
+            ),
+            StackWarning::SkippedFrames {
+                addr,
+                return_addr,
+                skipped,
+            } => write!(
+                f,
+                "RTS at ${:04X} returned to ${:04X} skipping {} frame(s)",
+                addr, return_addr, skipped
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct CallStack {
+    frames: Vec<CallFrame>,
+    warnings: Vec<StackWarning>,
+}
+
+impl CallStack {
+    pub fn call(&mut self, frame: CallFrame) {
+        self.frames.push(frame);
+    }
+
+    // addrのRTSでreturn_addrに戻った
+    pub fn ret(&mut self, addr: u16, return_addr: u16) {
+        match self
+            .frames
+            .iter()
+            .rposition(|frame| frame.return_addr == return_addr)
+        {
+            Some(depth) => {
+                let skipped = self.frames.len() - depth - 1;
+                if skipped > 0 {
+                    self.warnings.push(StackWarning::SkippedFrames {
+                        addr,
+                        return_addr,
+                        skipped,
+                    });
+                }
+                self.frames.truncate(depth);
+            }
+            None => self
+                .warnings
+                .push(StackWarning::UnmatchedReturn { addr, return_addr }),
+        }
+    }
+
+    // 外側から順に並んでいる
+    pub fn frames(&self) -> &[CallFrame] {
+        &self.frames
+    }
+
+    pub fn take_warnings(&mut self) -> Vec<StackWarning> {
+        core::mem::take(&mut self.warnings)
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.warnings.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CallFrame, CallStack, StackWarning};
+
+    #[test]
+    fn test_call_and_return() {
+        let mut stack = CallStack::default();
+        stack.call(frame(0x8000, 0x8010));
+        stack.call(frame(0x8010, 0x8020));
+        assert_eq!(stack.frames().len(), 2);
+
+        stack.ret(0x8021, 0x8013);
+        assert_eq!(stack.frames(), &[frame(0x8000, 0x8010)]);
+        stack.ret(0x8014, 0x8003);
+        assert!(stack.frames().is_empty());
+        assert!(stack.take_warnings().is_empty());
+    }
+
+    #[test]
+    fn test_unmatched_return() {
+        let mut stack = CallStack::default();
+        stack.call(frame(0x8000, 0x8010));
+        stack.ret(0x8010, 0x9000);
+        assert_eq!(stack.frames().len(), 1);
+        assert_eq!(
+            stack.take_warnings(),
+            vec![StackWarning::UnmatchedReturn {
+                addr: 0x8010,
+                return_addr: 0x9000,
+            }]
+        );
+        assert!(stack.take_warnings().is_empty());
+    }
+
+    #[test]
+    fn test_skipped_frames() {
+        let mut stack = CallStack::default();
+        stack.call(frame(0x8000, 0x8010));
+        stack.call(frame(0x8010, 0x8020));
+        stack.call(frame(0x8020, 0x8030));
+        stack.ret(0x8030, 0x8003);
+        assert!(stack.frames().is_empty());
+        assert_eq!(
+            stack.take_warnings()[0].to_string(),
+            "RTS at $8030 returned to $8003 skipping 2 frame(s)"
+        );
+    }
+
+    fn frame(call_site: u16, target: u16) -> CallFrame {
+        CallFrame {
+            call_site,
+            target,
+            return_addr: call_site + 3,
+        }
+    }
+}
@@ -0,0 +1,113 @@
+use super::instruction::Instruction;
+use alloc::{format, string::String, vec, vec::Vec};
+
+// 実行したopcodeと、実行しようとしたが実装していなかったopcodeを数える
+#[derive(Debug, Clone)]
+pub struct OpcodeCoverage {
+    executed: [u64; 256],
+    unsupported: [u64; 256],
+}
+
+impl OpcodeCoverage {
+    pub fn new() -> Self {
+        Self {
+            executed: [0; 256],
+            unsupported: [0; 256],
+        }
+    }
+
+    pub fn record(&mut self, opcode: u8) {
+        self.executed[opcode as usize] += 1;
+    }
+
+    pub fn record_unsupported(&mut self, opcode: u8) {
+        self.unsupported[opcode as usize] += 1;
+    }
+
+    pub fn executed(&self, opcode: u8) -> u64 {
+        self.executed[opcode as usize]
+    }
+
+    // 実装済みなのに1回も実行しなかったもの
+    pub fn unexecuted(&self) -> Vec<u8> {
+        implemented()
+            .filter(|&opcode| self.executed(opcode) == 0)
+            .collect()
+    }
+
+    // 実装していなくて実行できなかったものと、その回数
+    pub fn unsupported(&self) -> Vec<(u8, u64)> {
+        (0..=255)
+            .map(|opcode: u8| (opcode, self.unsupported[opcode as usize]))
+            .filter(|(_, count)| *count > 0)
+            .collect()
+    }
+
+    pub fn report(&self) -> String {
+        let executed = implemented().filter(|&op| self.executed(op) > 0).count();
+        let mut lines = vec![format!(
+            "Executed {} of {} implemented opcodes.",
+            executed,
+            implemented().count()
+        )];
+        for opcode in self.unexecuted() {
+            lines.push(format!("not executed: {}", describe(opcode)));
+        }
+        for (opcode, count) in self.unsupported() {
+            lines.push(format!("unsupported: {} x{}", describe(opcode), count));
+        }
+        lines.join("\n") + "\n"
+    }
+}
+
+impl Default for OpcodeCoverage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+fn implemented() -> impl Iterator<Item = u8> {
+    (0..=255).filter(|&opcode| Instruction::from_opcode(opcode).is_some())
+}
+
+// 公式でないopcodeは番号だけ
+fn describe(opcode: u8) -> String {
+    match Instruction::decode(opcode) {
+        Some(instruction) => format!(
+            "${:02X} {:?} {:?}",
+            opcode, instruction.kind, instruction.addressing
+        ),
+        None => format!("${:02X}", opcode),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::OpcodeCoverage;
+
+    #[test]
+    fn test_report() {
+        let mut coverage = OpcodeCoverage::new();
+        let implemented = [
+            0x20, 0x4c, 0x60, 0x78, 0x88, 0x8d, 0x9a, 0xa0, 0xa2, 0xa9, 0xbd, 0xd0, 0xe8,
+        ];
+        for opcode in implemented.iter().copied().filter(|&op| op != 0x88) {
+            coverage.record(opcode);
+        }
+        coverage.record(0xa9);
+        coverage.record_unsupported(0x0a);
+        coverage.record_unsupported(0x0a);
+        coverage.record_unsupported(0xff);
+
+        assert_eq!(coverage.executed(0xa9), 2);
+        assert_eq!(coverage.unexecuted(), vec![0x88]);
+        assert_eq!(coverage.unsupported(), vec![(0x0a, 2), (0xff, 1)]);
+        assert_eq!(
+            coverage.report(),
+            "Executed 12 of 13 implemented opcodes.\n\
+             not executed: $88 DEY Implied\n\
+             unsupported: $0A ASL Accumulator x2\n\
+             unsupported: $FF x1\n"
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use super::instruction::Instruction;
+use alloc::{vec, vec::Vec};
+
+// PRG ROMが見える$8000〜$FFFFだけをキャッシュする
+const BASE: u16 = 0x8000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Decoded {
+    pub opcode: u8,
+    pub instruction: Instruction,
+    pub clock: u8,
+    // opcodeを含めたバイト数
+    pub len: u16,
+}
+
+impl Decoded {
+    fn new(opcode: u8) -> Option<Self> {
+        let instruction = Instruction::from_opcode(opcode)?;
+        Some(Self {
+            opcode,
+            instruction,
+            clock: instruction.clock(),
+            len: 1 + instruction.addressing.operand_len(),
+        })
+    }
+}
+
+// アドレスごとにデコードした結果を覚えておく。
+// 毎回読んだopcodeと比べるので、チートやROMの差し替えで中身が変わっても古い結果は使わない
+#[derive(Debug)]
+pub struct DecodeCache {
+    entries: Vec<Option<Decoded>>,
+    enabled: bool,
+}
+
+impl DecodeCache {
+    // 実装していない命令ならNone
+    pub fn get(&mut self, addr: u16, opcode: u8) -> Option<Decoded> {
+        if !self.enabled || addr < BASE {
+            return Decoded::new(opcode);
+        }
+        if self.entries.is_empty() {
+            self.entries = vec![None; 0x10000 - BASE as usize];
+        }
+        let entry = &mut self.entries[(addr - BASE) as usize];
+        match entry.filter(|decoded| decoded.opcode == opcode) {
+            Some(decoded) => Some(decoded),
+            None => {
+                *entry = Decoded::new(opcode);
+                *entry
+            }
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.entries = Vec::new();
+    }
+}
+
+impl Default for DecodeCache {
+    fn default() -> Self {
+        Self {
+            entries: Vec::new(),
+            enabled: true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{DecodeCache, Decoded};
+
+    #[test]
+    fn test_get() {
+        let mut cache = DecodeCache::default();
+        let decoded = cache.get(0x8000, 0x8d).unwrap();
+        assert_eq!(decoded.clock, 4);
+        assert_eq!(decoded.len, 3);
+        assert_eq!(cache.get(0x8000, 0x8d), Some(decoded));
+        // 同じアドレスでもopcodeが変わればデコードし直す
+        assert_eq!(cache.get(0x8000, 0xe8), Decoded::new(0xe8));
+        assert_eq!(cache.get(0x8000, 0x02), None);
+        assert_eq!(cache.get(0x8000, 0x8d), Some(decoded));
+        // RAMはキャッシュしない
+        assert_eq!(cache.get(0x0300, 0xe8), Decoded::new(0xe8));
+    }
+
+    #[test]
+    fn test_disabled() {
+        let mut cache = DecodeCache::default();
+        cache.set_enabled(false);
+        assert_eq!(cache.get(0x8000, 0x8d), Decoded::new(0x8d));
+        assert!(cache.entries.is_empty());
+    }
+}
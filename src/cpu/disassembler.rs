@@ -0,0 +1,200 @@
+use super::instruction::{Addressing, Instruction};
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec,
+    vec::Vec,
+};
+use core::fmt;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Disassembled {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub mnemonic: String,
+    pub operand: String,
+    // 分岐先やジャンプ先など、命令のバイト列だけで決まるアドレス
+    pub target: Option<u16>,
+}
+
+impl Disassembled {
+    pub fn next_address(&self) -> u16 {
+        self.address.wrapping_add(self.bytes.len() as u16)
+    }
+
+    // オペランドのアドレスにラベルがあれば置き換えて表示する。即値はそのまま
+    pub fn to_string_with_labels<'a, F: Fn(u16) -> Option<&'a str>>(&self, label: F) -> String {
+        let start = match self.operand.find('$') {
+            Some(start) if !self.operand.starts_with('#') && self.mnemonic != ".db" => start,
+            _ => return self.to_string(),
+        };
+        let len = self.operand[start + 1..]
+            .chars()
+            .take_while(char::is_ascii_hexdigit)
+            .count();
+        let end = start + 1 + len;
+        match u16::from_str_radix(&self.operand[start + 1..end], 16)
+            .ok()
+            .and_then(label)
+        {
+            Some(name) => format!(
+                "{} {}{}{}",
+                self.mnemonic,
+                &self.operand[..start],
+                name,
+                &self.operand[end..]
+            ),
+            None => self.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Disassembled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.operand.is_empty() {
+            write!(f, "{}", self.mnemonic)
+        } else {
+            write!(f, "{} {}", self.mnemonic, self.operand)
+        }
+    }
+}
+
+// addressから1命令分を逆アセンブルする。メモリはreadで読む
+pub fn disassemble<F: FnMut(u16) -> u8>(address: u16, mut read: F) -> Disassembled {
+    let opcode = read(address);
+    let instruction = match Instruction::decode(opcode) {
+        Some(instruction) => instruction,
+        None => {
+            return Disassembled {
+                address,
+                bytes: vec![opcode],
+                mnemonic: ".db".to_string(),
+                operand: format!("${:02X}", opcode),
+                target: None,
+            }
+        }
+    };
+
+    let len = instruction.addressing.operand_len();
+    let bytes: Vec<u8> = (0..=len).map(|i| read(address.wrapping_add(i))).collect();
+    let byte = bytes.get(1).copied().unwrap_or(0);
+    let word = (byte as u16) | ((bytes.get(2).copied().unwrap_or(0) as u16) << 8);
+    let next = address.wrapping_add(len + 1);
+
+    let (operand, target) = match instruction.addressing {
+        Addressing::Implied => (String::new(), None),
+        Addressing::Accumulator => ("A".to_string(), None),
+        Addressing::Immediate => (format!("#${:02X}", byte), None),
+        Addressing::ZeroPage => (format!("${:02X}", byte), Some(byte as u16)),
+        Addressing::ZeroPageX => (format!("${:02X},X", byte), None),
+        Addressing::ZeroPageY => (format!("${:02X},Y", byte), None),
+        Addressing::Relative => {
+            let target = next.wrapping_add(byte as i8 as u16);
+            (format!("${:04X}", target), Some(target))
+        }
+        Addressing::Absolute => (format!("${:04X}", word), Some(word)),
+        Addressing::AbsoluteX => (format!("${:04X},X", word), None),
+        Addressing::AbsoluteY => (format!("${:04X},Y", word), None),
+        Addressing::Indirect => (format!("(${:04X})", word), None),
+        Addressing::IndirectX => (format!("(${:02X},X)", byte), None),
+        Addressing::IndirectY => (format!("(${:02X}),Y", byte), None),
+    };
+
+    Disassembled {
+        address,
+        bytes,
+        mnemonic: format!("{:?}", instruction.kind),
+        operand,
+        target,
+    }
+}
+
+// startからendまでを順番に逆アセンブルする。endの途中で命令が終わらなくてもその命令は含める
+pub fn disassemble_range<F: FnMut(u16) -> u8>(
+    start: u16,
+    end: u16,
+    mut read: F,
+) -> Vec<Disassembled> {
+    let mut result = Vec::new();
+    let mut address = start;
+    loop {
+        let disassembled = disassemble(address, &mut read);
+        let next = disassembled.next_address();
+        result.push(disassembled);
+        // 範囲の終わりに達したか、0xffffを越えて一周した
+        if next > end || next <= address {
+            return result;
+        }
+        address = next;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{disassemble, disassemble_range};
+
+    #[test]
+    fn test_disassemble() {
+        let memory = [0xbd, 0x51, 0x80];
+        let disassembled = disassemble(0x0000, |addr| memory[addr as usize]);
+        assert_eq!(disassembled.bytes, vec![0xbd, 0x51, 0x80]);
+        assert_eq!(disassembled.mnemonic, "LDA");
+        assert_eq!(disassembled.operand, "$8051,X");
+        assert_eq!(disassembled.target, None);
+        assert_eq!(disassembled.next_address(), 0x0003);
+        assert_eq!(disassembled.to_string(), "LDA $8051,X");
+    }
+
+    #[test]
+    fn test_disassemble_relative() {
+        let disassembled = disassemble(0x8031, |addr| match addr {
+            0x8031 => 0xd0,
+            0x8032 => 0xf6,
+            _ => 0x00,
+        });
+        assert_eq!(disassembled.to_string(), "BNE $8029");
+        assert_eq!(disassembled.target, Some(0x8029));
+    }
+
+    #[test]
+    fn test_to_string_with_labels() {
+        let label = |addr: u16| match addr {
+            0x8010 => Some("player_x"),
+            0x0010 => Some("temp"),
+            _ => None,
+        };
+        let to_string = |memory: [u8; 3]| {
+            disassemble(0x0000, |addr| memory[addr as usize]).to_string_with_labels(label)
+        };
+        assert_eq!(to_string([0xbd, 0x10, 0x80]), "LDA player_x,X");
+        assert_eq!(to_string([0xb1, 0x10, 0x00]), "LDA (temp),Y");
+        assert_eq!(to_string([0xa9, 0x10, 0x00]), "LDA #$10");
+        assert_eq!(to_string([0x8d, 0x00, 0x20]), "STA $2000");
+    }
+
+    #[test]
+    fn test_disassemble_unknown() {
+        let disassembled = disassemble(0x0000, |_| 0x02);
+        assert_eq!(disassembled.to_string(), ".db $02");
+        assert_eq!(disassembled.next_address(), 0x0001);
+    }
+
+    #[test]
+    fn test_disassemble_range() {
+        let memory = [0x78, 0xa2, 0xff, 0x9a, 0xa9, 0x00, 0x8d, 0x00, 0x20];
+        let lines: Vec<String> = disassemble_range(0x0000, 0x0006, |addr| memory[addr as usize])
+            .iter()
+            .map(|d| format!("{:04X} {}", d.address, d))
+            .collect();
+        assert_eq!(
+            lines,
+            vec![
+                "0000 SEI",
+                "0001 LDX #$FF",
+                "0003 TXS",
+                "0004 LDA #$00",
+                "0006 STA $2000",
+            ]
+        );
+    }
+}
@@ -1,14 +1,17 @@
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct Instruction {
     pub kind: Kind,
     pub addressing: Addressing,
 }
 
 impl Instruction {
-    pub fn from_opcode(opcode: u8) -> Self {
+    // 実装済みの命令だけ。それ以外はNone
+    pub fn from_opcode(opcode: u8) -> Option<Self> {
         // とりあえずhello worldを動かすのに必要なopcode
         let (kind, addressing) = match opcode {
+            0x20 => (Kind::JSR, Addressing::Absolute),
             0x4c => (Kind::JMP, Addressing::Absolute),
+            0x60 => (Kind::RTS, Addressing::Implied),
             0x78 => (Kind::SEI, Addressing::Implied),
             0x88 => (Kind::DEY, Addressing::Implied),
             0x8d => (Kind::STA, Addressing::Absolute),
@@ -19,15 +22,176 @@ impl Instruction {
             0xbd => (Kind::LDA, Addressing::AbsoluteX),
             0xd0 => (Kind::BNE, Addressing::Relative),
             0xe8 => (Kind::INX, Addressing::Implied),
-            _ => panic!("Instruction is not implemented! 0x{:x}", opcode),
+            _ => return None,
         };
-        Self { kind, addressing }
+        Some(Self { kind, addressing })
+    }
+
+    // 実装済みかどうかに関係なく公式のopcodeをすべてデコードする。逆アセンブラ用
+    pub fn decode(opcode: u8) -> Option<Self> {
+        let (kind, addressing) = match opcode {
+            0x00 => (Kind::BRK, Addressing::Implied),
+            0x01 => (Kind::ORA, Addressing::IndirectX),
+            0x05 => (Kind::ORA, Addressing::ZeroPage),
+            0x06 => (Kind::ASL, Addressing::ZeroPage),
+            0x08 => (Kind::PHP, Addressing::Implied),
+            0x09 => (Kind::ORA, Addressing::Immediate),
+            0x0a => (Kind::ASL, Addressing::Accumulator),
+            0x0d => (Kind::ORA, Addressing::Absolute),
+            0x0e => (Kind::ASL, Addressing::Absolute),
+            0x10 => (Kind::BPL, Addressing::Relative),
+            0x11 => (Kind::ORA, Addressing::IndirectY),
+            0x15 => (Kind::ORA, Addressing::ZeroPageX),
+            0x16 => (Kind::ASL, Addressing::ZeroPageX),
+            0x18 => (Kind::CLC, Addressing::Implied),
+            0x19 => (Kind::ORA, Addressing::AbsoluteY),
+            0x1d => (Kind::ORA, Addressing::AbsoluteX),
+            0x1e => (Kind::ASL, Addressing::AbsoluteX),
+            0x20 => (Kind::JSR, Addressing::Absolute),
+            0x21 => (Kind::AND, Addressing::IndirectX),
+            0x24 => (Kind::BIT, Addressing::ZeroPage),
+            0x25 => (Kind::AND, Addressing::ZeroPage),
+            0x26 => (Kind::ROL, Addressing::ZeroPage),
+            0x28 => (Kind::PLP, Addressing::Implied),
+            0x29 => (Kind::AND, Addressing::Immediate),
+            0x2a => (Kind::ROL, Addressing::Accumulator),
+            0x2c => (Kind::BIT, Addressing::Absolute),
+            0x2d => (Kind::AND, Addressing::Absolute),
+            0x2e => (Kind::ROL, Addressing::Absolute),
+            0x30 => (Kind::BMI, Addressing::Relative),
+            0x31 => (Kind::AND, Addressing::IndirectY),
+            0x35 => (Kind::AND, Addressing::ZeroPageX),
+            0x36 => (Kind::ROL, Addressing::ZeroPageX),
+            0x38 => (Kind::SEC, Addressing::Implied),
+            0x39 => (Kind::AND, Addressing::AbsoluteY),
+            0x3d => (Kind::AND, Addressing::AbsoluteX),
+            0x3e => (Kind::ROL, Addressing::AbsoluteX),
+            0x40 => (Kind::RTI, Addressing::Implied),
+            0x41 => (Kind::EOR, Addressing::IndirectX),
+            0x45 => (Kind::EOR, Addressing::ZeroPage),
+            0x46 => (Kind::LSR, Addressing::ZeroPage),
+            0x48 => (Kind::PHA, Addressing::Implied),
+            0x49 => (Kind::EOR, Addressing::Immediate),
+            0x4a => (Kind::LSR, Addressing::Accumulator),
+            0x4c => (Kind::JMP, Addressing::Absolute),
+            0x4d => (Kind::EOR, Addressing::Absolute),
+            0x4e => (Kind::LSR, Addressing::Absolute),
+            0x50 => (Kind::BVC, Addressing::Relative),
+            0x51 => (Kind::EOR, Addressing::IndirectY),
+            0x55 => (Kind::EOR, Addressing::ZeroPageX),
+            0x56 => (Kind::LSR, Addressing::ZeroPageX),
+            0x58 => (Kind::CLI, Addressing::Implied),
+            0x59 => (Kind::EOR, Addressing::AbsoluteY),
+            0x5d => (Kind::EOR, Addressing::AbsoluteX),
+            0x5e => (Kind::LSR, Addressing::AbsoluteX),
+            0x60 => (Kind::RTS, Addressing::Implied),
+            0x61 => (Kind::ADC, Addressing::IndirectX),
+            0x65 => (Kind::ADC, Addressing::ZeroPage),
+            0x66 => (Kind::ROR, Addressing::ZeroPage),
+            0x68 => (Kind::PLA, Addressing::Implied),
+            0x69 => (Kind::ADC, Addressing::Immediate),
+            0x6a => (Kind::ROR, Addressing::Accumulator),
+            0x6c => (Kind::JMP, Addressing::Indirect),
+            0x6d => (Kind::ADC, Addressing::Absolute),
+            0x6e => (Kind::ROR, Addressing::Absolute),
+            0x70 => (Kind::BVS, Addressing::Relative),
+            0x71 => (Kind::ADC, Addressing::IndirectY),
+            0x75 => (Kind::ADC, Addressing::ZeroPageX),
+            0x76 => (Kind::ROR, Addressing::ZeroPageX),
+            0x78 => (Kind::SEI, Addressing::Implied),
+            0x79 => (Kind::ADC, Addressing::AbsoluteY),
+            0x7d => (Kind::ADC, Addressing::AbsoluteX),
+            0x7e => (Kind::ROR, Addressing::AbsoluteX),
+            0x81 => (Kind::STA, Addressing::IndirectX),
+            0x84 => (Kind::STY, Addressing::ZeroPage),
+            0x85 => (Kind::STA, Addressing::ZeroPage),
+            0x86 => (Kind::STX, Addressing::ZeroPage),
+            0x88 => (Kind::DEY, Addressing::Implied),
+            0x8a => (Kind::TXA, Addressing::Implied),
+            0x8c => (Kind::STY, Addressing::Absolute),
+            0x8d => (Kind::STA, Addressing::Absolute),
+            0x8e => (Kind::STX, Addressing::Absolute),
+            0x90 => (Kind::BCC, Addressing::Relative),
+            0x91 => (Kind::STA, Addressing::IndirectY),
+            0x94 => (Kind::STY, Addressing::ZeroPageX),
+            0x95 => (Kind::STA, Addressing::ZeroPageX),
+            0x96 => (Kind::STX, Addressing::ZeroPageY),
+            0x98 => (Kind::TYA, Addressing::Implied),
+            0x99 => (Kind::STA, Addressing::AbsoluteY),
+            0x9a => (Kind::TXS, Addressing::Implied),
+            0x9d => (Kind::STA, Addressing::AbsoluteX),
+            0xa0 => (Kind::LDY, Addressing::Immediate),
+            0xa1 => (Kind::LDA, Addressing::IndirectX),
+            0xa2 => (Kind::LDX, Addressing::Immediate),
+            0xa4 => (Kind::LDY, Addressing::ZeroPage),
+            0xa5 => (Kind::LDA, Addressing::ZeroPage),
+            0xa6 => (Kind::LDX, Addressing::ZeroPage),
+            0xa8 => (Kind::TAY, Addressing::Implied),
+            0xa9 => (Kind::LDA, Addressing::Immediate),
+            0xaa => (Kind::TAX, Addressing::Implied),
+            0xac => (Kind::LDY, Addressing::Absolute),
+            0xad => (Kind::LDA, Addressing::Absolute),
+            0xae => (Kind::LDX, Addressing::Absolute),
+            0xb0 => (Kind::BCS, Addressing::Relative),
+            0xb1 => (Kind::LDA, Addressing::IndirectY),
+            0xb4 => (Kind::LDY, Addressing::ZeroPageX),
+            0xb5 => (Kind::LDA, Addressing::ZeroPageX),
+            0xb6 => (Kind::LDX, Addressing::ZeroPageY),
+            0xb8 => (Kind::CLV, Addressing::Implied),
+            0xb9 => (Kind::LDA, Addressing::AbsoluteY),
+            0xba => (Kind::TSX, Addressing::Implied),
+            0xbc => (Kind::LDY, Addressing::AbsoluteX),
+            0xbd => (Kind::LDA, Addressing::AbsoluteX),
+            0xbe => (Kind::LDX, Addressing::AbsoluteY),
+            0xc0 => (Kind::CPY, Addressing::Immediate),
+            0xc1 => (Kind::CMP, Addressing::IndirectX),
+            0xc4 => (Kind::CPY, Addressing::ZeroPage),
+            0xc5 => (Kind::CMP, Addressing::ZeroPage),
+            0xc6 => (Kind::DEC, Addressing::ZeroPage),
+            0xc8 => (Kind::INY, Addressing::Implied),
+            0xc9 => (Kind::CMP, Addressing::Immediate),
+            0xca => (Kind::DEX, Addressing::Implied),
+            0xcc => (Kind::CPY, Addressing::Absolute),
+            0xcd => (Kind::CMP, Addressing::Absolute),
+            0xce => (Kind::DEC, Addressing::Absolute),
+            0xd0 => (Kind::BNE, Addressing::Relative),
+            0xd1 => (Kind::CMP, Addressing::IndirectY),
+            0xd5 => (Kind::CMP, Addressing::ZeroPageX),
+            0xd6 => (Kind::DEC, Addressing::ZeroPageX),
+            0xd8 => (Kind::CLD, Addressing::Implied),
+            0xd9 => (Kind::CMP, Addressing::AbsoluteY),
+            0xdd => (Kind::CMP, Addressing::AbsoluteX),
+            0xde => (Kind::DEC, Addressing::AbsoluteX),
+            0xe0 => (Kind::CPX, Addressing::Immediate),
+            0xe1 => (Kind::SBC, Addressing::IndirectX),
+            0xe4 => (Kind::CPX, Addressing::ZeroPage),
+            0xe5 => (Kind::SBC, Addressing::ZeroPage),
+            0xe6 => (Kind::INC, Addressing::ZeroPage),
+            0xe8 => (Kind::INX, Addressing::Implied),
+            0xe9 => (Kind::SBC, Addressing::Immediate),
+            0xea => (Kind::NOP, Addressing::Implied),
+            0xec => (Kind::CPX, Addressing::Absolute),
+            0xed => (Kind::SBC, Addressing::Absolute),
+            0xee => (Kind::INC, Addressing::Absolute),
+            0xf0 => (Kind::BEQ, Addressing::Relative),
+            0xf1 => (Kind::SBC, Addressing::IndirectY),
+            0xf5 => (Kind::SBC, Addressing::ZeroPageX),
+            0xf6 => (Kind::INC, Addressing::ZeroPageX),
+            0xf8 => (Kind::SED, Addressing::Implied),
+            0xf9 => (Kind::SBC, Addressing::AbsoluteY),
+            0xfd => (Kind::SBC, Addressing::AbsoluteX),
+            0xfe => (Kind::INC, Addressing::AbsoluteX),
+            _ => return None,
+        };
+        Some(Self { kind, addressing })
     }
 
     pub fn clock(&self) -> u8 {
         // とりあえずhello worldを動かすのに必要なやつ
         let base = match self.kind {
             Kind::JMP => 1,
+            Kind::JSR => 4,
+            Kind::RTS => 6,
             Kind::SEI => 2,
             Kind::DEY => 2,
             Kind::STA => 2,
@@ -37,6 +201,7 @@ impl Instruction {
             Kind::LDA => 2,
             Kind::BNE => 2,
             Kind::INX => 2,
+            _ => panic!("Instruction is not implemented! {:?}", self.kind),
         };
 
         base + match self.addressing {
@@ -45,106 +210,128 @@ impl Instruction {
             Addressing::Relative => 0,
             Addressing::Absolute => 2,
             Addressing::AbsoluteX => 2,
+            _ => panic!("Addressing is not implemented! {:?}", self.addressing),
         }
     }
 
     pub fn affects_status_negative(&self) -> bool {
-        match self.kind {
-            Kind::DEY | Kind::LDY | Kind::LDX | Kind::LDA | Kind::TXS | Kind::INX => true,
-            _ => false,
-        }
+        matches!(
+            self.kind,
+            Kind::DEY | Kind::LDY | Kind::LDX | Kind::LDA | Kind::TXS | Kind::INX
+        )
     }
 
     pub fn affects_status_zero(&self) -> bool {
-        match self.kind {
-            Kind::DEY | Kind::LDY | Kind::LDX | Kind::LDA | Kind::TXS | Kind::INX => true,
-            _ => false,
-        }
+        matches!(
+            self.kind,
+            Kind::DEY | Kind::LDY | Kind::LDX | Kind::LDA | Kind::TXS | Kind::INX
+        )
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Kind {
     // 転送
     LDA,
     LDX,
     LDY,
     STA,
-    // STX,
-    // STY,
-    // TAX,
-    // TAY,
-    // TSX,
-    // TXA,
+    STX,
+    STY,
+    TAX,
+    TAY,
+    TSX,
+    TXA,
     TXS,
-    // TYA,
+    TYA,
     // 算術
-    // ADC,
-    // AND,
-    // ASL,
-    // BIT,
-    // CMP,
-    // CPX,
-    // CPY,
-    // DEC,
-    // DEX,
+    ADC,
+    AND,
+    ASL,
+    BIT,
+    CMP,
+    CPX,
+    CPY,
+    DEC,
+    DEX,
     DEY,
-    // EOR,
-    // INC,
+    EOR,
+    INC,
     INX,
-    // INY,
-    // LSR,
-    // ORA,
-    // ROL,
-    // ROR,
-    // SBC,
+    INY,
+    LSR,
+    ORA,
+    ROL,
+    ROR,
+    SBC,
     // stack
-    // PHA,
-    // PHP,
-    // PLA,
-    // PLP,
+    PHA,
+    PHP,
+    PLA,
+    PLP,
     // jump
     JMP,
-    // JSR,
-    // RTS,
-    // RTI,
+    JSR,
+    RTS,
+    RTI,
     // 分岐
-    // BCC,
-    // BCS,
-    // BEQ,
-    // BMI,
+    BCC,
+    BCS,
+    BEQ,
+    BMI,
     BNE,
-    // BPL,
-    // BVC,
-    // BVS,
+    BPL,
+    BVC,
+    BVS,
     // フラグ変更
-    // CLC,
-    // CLD,
-    // IRQ,
-    // CLV,
-    // SEC,
-    // SED,
+    CLC,
+    CLD,
+    CLI,
+    CLV,
+    SEC,
+    SED,
     SEI,
     // その他
-    // BRK,
-    // NOP,
+    BRK,
+    NOP,
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Addressing {
     Implied,
-    // Accumulator,
+    Accumulator,
     Immediate,
-    // ZeroPage,
-    // ZeroPageX,
-    // ZeroPageY,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
     Relative,
     Absolute,
     AbsoluteX,
-    // AbsoluteY,
-    // Indirect,
-    // IndirectX,
-    // IndirectY,
+    AbsoluteY,
+    Indirect,
+    IndirectX,
+    IndirectY,
+}
+
+impl Addressing {
+    // opcodeの後ろに続くオペランドのバイト数
+    pub fn operand_len(&self) -> u16 {
+        match self {
+            Addressing::Implied | Addressing::Accumulator => 0,
+            Addressing::Immediate
+            | Addressing::ZeroPage
+            | Addressing::ZeroPageX
+            | Addressing::ZeroPageY
+            | Addressing::Relative
+            | Addressing::IndirectX
+            | Addressing::IndirectY => 1,
+            Addressing::Absolute
+            | Addressing::AbsoluteX
+            | Addressing::AbsoluteY
+            | Addressing::Indirect => 2,
+        }
+    }
 }
 
 #[cfg(test)]
@@ -153,11 +340,39 @@ mod test {
 
     #[test]
     fn test_from_opcode() {
-        let instruction = Instruction::from_opcode(0xa9);
+        let instruction = Instruction::from_opcode(0xa9).unwrap();
         let expectation = Instruction {
             kind: Kind::LDA,
             addressing: Addressing::Immediate,
         };
         assert_eq!(instruction, expectation);
+        assert_eq!(Instruction::from_opcode(0x02), None);
+    }
+
+    #[test]
+    fn test_decode() {
+        assert_eq!(
+            Instruction::decode(0x6c),
+            Some(Instruction {
+                kind: Kind::JMP,
+                addressing: Addressing::Indirect,
+            })
+        );
+        assert_eq!(Instruction::decode(0x02), None);
+        assert_eq!((0..=0xff).filter_map(Instruction::decode).count(), 151);
+    }
+
+    #[test]
+    fn test_decode_contains_implemented() {
+        for opcode in [
+            0x20, 0x4c, 0x60, 0x78, 0x88, 0x8d, 0x9a, 0xa0, 0xa2, 0xa9, 0xbd, 0xd0, 0xe8,
+        ]
+        .iter()
+        {
+            assert_eq!(
+                Instruction::decode(*opcode),
+                Instruction::from_opcode(*opcode)
+            );
+        }
     }
 }
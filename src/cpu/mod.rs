@@ -1,51 +1,291 @@
-use crate::ram::Ram;
-use instruction::{Addressing, Instruction, Kind};
+use crate::{region::Region, rom_data::RomData};
+use alloc::{string::String, vec::Vec};
+use bus::{Bus, BusError, NesBus};
+use call_stack::{CallFrame, CallStack, StackWarning};
+use core::fmt;
+use decode_cache::DecodeCache;
+use instruction::{Addressing, Kind};
 use register::Registers;
-use std::rc::Rc;
+use serde::{Deserialize, Serialize};
 
+pub mod bus;
+pub mod call_stack;
+pub mod coverage;
+mod decode_cache;
+pub mod disassembler;
 mod instruction;
-mod register;
+pub mod register;
+#[cfg(feature = "std")]
+pub mod tracer;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessKind {
+    // opcodeとオペランドの読み込み
+    Execute,
+    Read,
+    Write,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryAccess {
+    pub addr: u16,
+    pub value: u8,
+    pub kind: AccessKind,
+}
+
+// 1命令実行した結果。ツールが逆アセンブルし直さなくても何を実行したか分かる
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StepResult {
+    pub cycles: u8,
+    pub opcode: u8,
+    pub pc_before: u16,
+    // 割り込みはまだ無いので常にfalse
+    pub interrupt: bool,
+}
 
-#[derive(Debug)]
-pub struct Cpu {
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CpuError {
+    // 実装していないか、公式でないopcode
+    UnimplementedOpcode { addr: u16, opcode: u8 },
+    // JAM(KIL)で止まっている。リセットするまで何も実行しない
+    Halted { addr: u16, opcode: u8 },
+    Bus(BusError),
+    // set_differentialで参照実装と比べて結果が違った。detailは違っていたところ
+    DifferentialMismatch { addr: u16, detail: String },
+}
+
+impl fmt::Display for CpuError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::UnimplementedOpcode { addr, opcode } => write!(
+                f,
+                "Opcode ${:02X} at ${:04X} is not implemented.",
+                opcode, addr
+            ),
+            Self::Halted { addr, opcode } => {
+                write!(f, "CPU halted by opcode ${:02X} at ${:04X}.", opcode, addr)
+            }
+            Self::Bus(err) => err.fmt(f),
+            Self::DifferentialMismatch { addr, detail } => {
+                write!(f, "Differential mismatch at ${:04X}: {}", addr, detail)
+            }
+        }
+    }
+}
+
+impl core::error::Error for CpuError {}
+
+impl From<BusError> for CpuError {
+    fn from(err: BusError) -> Self {
+        Self::Bus(err)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Cpu<B = NesBus> {
     registers: Registers,
-    rom: Option<Rc<Vec<u8>>>,
-    ram: Ram,
+    cycles: u64,
+    #[serde(skip)]
+    bus: B,
+    // 直前に実行した命令でのメモリアクセス
+    #[serde(skip)]
+    accesses: Vec<MemoryAccess>,
+    // デバッガ用なのでステートには含めない
+    #[serde(skip)]
+    call_stack: CallStack,
+    #[serde(skip)]
+    decode_cache: DecodeCache,
+    // 設定なのでステートには含めない
+    #[serde(skip)]
+    region: Region,
+    // 止めたJAMのopcode。PCはJAMを指したままなので、ステートから戻しても次の実行でまた止まる
+    #[serde(skip)]
+    halted: Option<u8>,
 }
 
 impl Cpu {
-    pub fn new(ram: Ram) -> Self {
+    pub fn new() -> Self {
+        Self::with_bus(NesBus::new())
+    }
+
+    pub fn set_rom(&mut self, rom: Option<RomData>) {
+        self.bus.rom = rom;
+    }
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<B: Bus> Cpu<B> {
+    pub fn with_bus(bus: B) -> Self {
         Cpu {
             registers: Registers::default(),
-            rom: None,
-            ram,
+            cycles: 0,
+            bus,
+            accesses: Vec::new(),
+            call_stack: CallStack::default(),
+            decode_cache: DecodeCache::default(),
+            region: Region::default(),
+            halted: None,
         }
     }
 
-    pub fn set_rom(&mut self, rom: Option<Rc<Vec<u8>>>) {
-        self.rom = rom;
+    pub fn bus(&self) -> &B {
+        &self.bus
     }
 
-    pub fn reset(&mut self) {
-        self.registers = Registers::default();
-        self.registers.program_counter = self.read_word(0xfffc);
+    pub fn bus_mut(&mut self) -> &mut B {
+        &mut self.bus
     }
 
-    pub fn run(&mut self) -> u8 {
-        let opcode = self.fetch();
-        let instruction = Instruction::from_opcode(opcode);
+    // ステートから読み込んだCPUの状態を反映する。ROMとRAMは今つながっているものをそのまま使う
+    pub fn restore<S>(&mut self, state: Cpu<S>) {
+        self.registers = state.registers;
+        self.cycles = state.cycles;
+        // 今のコールスタックとは関係が無くなるので捨てる
+        self.call_stack.clear();
+        // JAMで止まった後でも、読み込んだところから動き出す
+        self.halted = None;
+    }
+
+    // 電源を入れたときの状態にする
+    pub fn power_on(&mut self) -> Result<(), CpuError> {
+        let program_counter = self.read_word(0xfffc)?;
+        self.registers = Registers {
+            program_counter,
+            ..Registers::default()
+        };
+        // 0にしてからリセットと同じくSPを3減らしてIRQを禁止するので、SPは$FDになる
+        self.registers.stack_pointer = self.registers.stack_pointer.wrapping_sub(3);
+        self.registers.status.irq_prohibited = true;
+        // リセットの処理自体に7クロックかかる
+        self.cycles = 7;
+        self.call_stack.clear();
+        self.halted = None;
+        Ok(())
+    }
+
+    // リセットボタン。A X Yはそのままで、スタックに積まずにSPだけ3減らしてIRQを禁止する
+    pub fn reset(&mut self) -> Result<(), CpuError> {
+        self.registers.program_counter = self.read_word(0xfffc)?;
+        self.registers.stack_pointer = self.registers.stack_pointer.wrapping_sub(3);
+        self.registers.status.irq_prohibited = true;
+        self.cycles += 7;
+        self.call_stack.clear();
+        self.halted = None;
+        Ok(())
+    }
+
+    // 電源を入れてからの合計クロック数
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn region(&self) -> Region {
+        self.region
+    }
+
+    pub fn set_region(&mut self, region: Region) {
+        self.region = region;
+    }
 
-        let mut clock_count = instruction.clock();
+    // PPUの今のスキャンラインとドット。PPUがまだ無いので、描画が無効のときと同じく
+    // 奇数フレームのドット飛ばしは無いものとして、1フレームを341ドット×地域ごとのライン数で計算する
+    pub fn ppu_position(&self) -> (u16, u16) {
+        let (numerator, denominator) = self.region.dots_per_cycle();
+        let dot = self.cycles * numerator / denominator;
+        let scanlines = self.region.scanlines() as u64;
+        (((dot / 341) % scanlines) as u16, (dot % 341) as u16)
+    }
+
+    pub fn registers(&self) -> &Registers {
+        &self.registers
+    }
+
+    pub fn set_registers(&mut self, registers: Registers) {
+        self.registers = registers;
+    }
+
+    // ステートを外から組み立てるときに使う
+    pub fn set_cycles(&mut self, cycles: u64) {
+        self.cycles = cycles;
+    }
+
+    pub fn accesses(&self) -> &[MemoryAccess] {
+        &self.accesses
+    }
+
+    pub fn call_depth(&self) -> usize {
+        self.call_stack.frames().len()
+    }
+
+    pub fn call_stack(&self) -> &[CallFrame] {
+        self.call_stack.frames()
+    }
+
+    // 前回取り出してから検出したスタックの食い違い
+    pub fn take_stack_warnings(&mut self) -> Vec<StackWarning> {
+        self.call_stack.take_warnings()
+    }
+
+    // JAMを実行して止まっていればtrue
+    pub fn halted(&self) -> bool {
+        self.halted.is_some()
+    }
+
+    // 1命令実行する。エラーのときは途中までしか実行していない
+    pub fn run(&mut self) -> Result<StepResult, CpuError> {
+        self.accesses.clear();
+        let pc = self.registers.program_counter;
+        if let Some(opcode) = self.halted {
+            return Err(CpuError::Halted { addr: pc, opcode });
+        }
+        let opcode = self.fetch()?;
+        if is_jam(opcode) {
+            self.registers.program_counter = pc;
+            self.halted = Some(opcode);
+            return Err(CpuError::Halted { addr: pc, opcode });
+        }
+        let decoded = self
+            .decode_cache
+            .get(pc, opcode)
+            .ok_or(CpuError::UnimplementedOpcode { addr: pc, opcode })?;
+        let instruction = decoded.instruction;
+
+        let mut clock_count = decoded.clock;
         let calc_result = match instruction.kind {
             Kind::JMP => {
-                match self.fetch_operand(&instruction.addressing) {
-                    Operand::Address(addr, _) => {
-                        self.registers.program_counter = addr;
-                    }
-                    _ => {}
+                if let Operand::Address(addr, _) = self.fetch_operand(&instruction.addressing)? {
+                    self.registers.program_counter = addr;
                 }
                 None
             }
+            Kind::JSR => {
+                if let Operand::Address(addr, _) = self.fetch_operand(&instruction.addressing)? {
+                    // 戻り先の1つ前のアドレスを積む
+                    let return_addr = self.registers.program_counter.wrapping_sub(1);
+                    self.push((return_addr >> 8) as u8)?;
+                    self.push(return_addr as u8)?;
+                    self.registers.program_counter = addr;
+                    self.call_stack.call(CallFrame {
+                        call_site: return_addr.wrapping_sub(2),
+                        target: addr,
+                        return_addr: return_addr.wrapping_add(1),
+                    });
+                }
+                None
+            }
+            Kind::RTS => {
+                let addr = self.registers.program_counter.wrapping_sub(1);
+                let lower = self.pull()? as u16;
+                let upper = self.pull()? as u16;
+                self.registers.program_counter = (lower | (upper << 8)).wrapping_add(1);
+                self.call_stack.ret(addr, self.registers.program_counter);
+                None
+            }
             Kind::SEI => {
                 self.registers.status.irq_prohibited = true;
                 None
@@ -55,15 +295,14 @@ impl Cpu {
                 Some(self.registers.index_y)
             }
             Kind::STA => {
-                match self.fetch_operand(&instruction.addressing) {
-                    Operand::Address(addr, page_crossed) => {
-                        self.write(addr, self.registers.accumulator);
-                        if page_crossed {
-                            clock_count += 1;
-                        }
+                if let Operand::Address(addr, page_crossed) =
+                    self.fetch_operand(&instruction.addressing)?
+                {
+                    self.write(addr, self.registers.accumulator)?;
+                    if page_crossed {
+                        clock_count += 1;
                     }
-                    _ => {}
-                };
+                }
                 None
             }
             Kind::TXS => {
@@ -71,10 +310,10 @@ impl Cpu {
                 Some(self.registers.stack_pointer)
             }
             Kind::LDY => {
-                match self.fetch_operand(&instruction.addressing) {
+                match self.fetch_operand(&instruction.addressing)? {
                     Operand::Value(v) => self.registers.index_y = v,
                     Operand::Address(addr, page_crossed) => {
-                        self.registers.index_y = self.read(addr);
+                        self.registers.index_y = self.read(addr)?;
                         if page_crossed {
                             clock_count += 1;
                         }
@@ -84,10 +323,10 @@ impl Cpu {
                 Some(self.registers.index_y)
             }
             Kind::LDX => {
-                match self.fetch_operand(&instruction.addressing) {
+                match self.fetch_operand(&instruction.addressing)? {
                     Operand::Value(v) => self.registers.index_x = v,
                     Operand::Address(addr, page_crossed) => {
-                        self.registers.index_x = self.read(addr);
+                        self.registers.index_x = self.read(addr)?;
                         if page_crossed {
                             clock_count += 1;
                         }
@@ -97,10 +336,10 @@ impl Cpu {
                 Some(self.registers.index_x)
             }
             Kind::LDA => {
-                match self.fetch_operand(&instruction.addressing) {
+                match self.fetch_operand(&instruction.addressing)? {
                     Operand::Value(v) => self.registers.accumulator = v,
                     Operand::Address(addr, page_crossed) => {
-                        self.registers.accumulator = self.read(addr);
+                        self.registers.accumulator = self.read(addr)?;
                         if page_crossed {
                             clock_count += 1;
                         }
@@ -110,21 +349,21 @@ impl Cpu {
                 Some(self.registers.accumulator)
             }
             Kind::BNE => {
-                match self.fetch_operand(&instruction.addressing) {
-                    Operand::Address(addr, page_crossed) => {
-                        if !self.registers.status.zero {
-                            self.registers.program_counter = addr;
-                            clock_count += if page_crossed { 2 } else { 1 };
-                        }
+                if let Operand::Address(addr, page_crossed) =
+                    self.fetch_operand(&instruction.addressing)?
+                {
+                    if !self.registers.status.zero {
+                        self.registers.program_counter = addr;
+                        clock_count += if page_crossed { 2 } else { 1 };
                     }
-                    _ => {}
-                };
+                }
                 None
             }
             Kind::INX => {
                 self.registers.index_x = self.registers.index_x.wrapping_add(1);
                 Some(self.registers.index_x)
             }
+            _ => unreachable!(),
         };
 
         if let Some(result) = calc_result {
@@ -137,85 +376,108 @@ impl Cpu {
             }
         }
 
-        clock_count
+        self.cycles += clock_count as u64;
+        Ok(StepResult {
+            cycles: clock_count,
+            opcode,
+            pc_before: pc,
+            interrupt: false,
+        })
     }
 
-    fn fetch(&mut self) -> u8 {
-        let value = self.read(self.registers.program_counter);
-        self.registers.program_counter += 1;
-        value
+    fn fetch(&mut self) -> Result<u8, CpuError> {
+        let addr = self.registers.program_counter;
+        let value = self.bus.read(addr)?;
+        self.record(addr, value, AccessKind::Execute);
+        self.registers.program_counter = addr.wrapping_add(1);
+        Ok(value)
     }
 
-    fn fetch_word(&mut self) -> u16 {
-        let lower = self.fetch() as u16;
-        let upper = self.fetch() as u16;
-        lower + (upper << 8)
+    fn fetch_word(&mut self) -> Result<u16, CpuError> {
+        let lower = self.fetch()? as u16;
+        let upper = self.fetch()? as u16;
+        Ok(lower + (upper << 8))
     }
 
-    fn fetch_operand(&mut self, addressing: &Addressing) -> Operand {
-        match addressing {
-            Addressing::Immediate => Operand::Value(self.fetch()),
+    fn fetch_operand(&mut self, addressing: &Addressing) -> Result<Operand, CpuError> {
+        let operand = match addressing {
+            Addressing::Immediate => Operand::Value(self.fetch()?),
             Addressing::Relative => {
-                let offset = self.fetch() as i8;
+                let offset = self.fetch()? as i8;
                 let pc = self.registers.program_counter;
                 let addr = if offset >= 0 {
                     pc.wrapping_add(offset as u16)
                 } else {
-                    pc.wrapping_sub(offset.abs() as u16)
+                    pc.wrapping_sub(offset.unsigned_abs() as u16)
                 };
                 let page_crossed = (pc >> 8) != (addr >> 8);
                 Operand::Address(addr, page_crossed)
             }
-            Addressing::Absolute => Operand::Address(self.fetch_word(), false),
+            Addressing::Absolute => Operand::Address(self.fetch_word()?, false),
             Addressing::AbsoluteX => {
-                let orig = self.fetch_word();
+                let orig = self.fetch_word()?;
                 let x = self.registers.index_x as u16;
                 let addr = orig.wrapping_add(x);
                 let page_crossed = (orig >> 8) != (addr >> 8);
                 Operand::Address(addr, page_crossed)
             }
             _ => Operand::None,
-        }
+        };
+        Ok(operand)
     }
 
-    fn read(&self, addr: u16) -> u8 {
-        match addr {
-            0x0000..=0x07ff => self.ram.borrow()[addr as usize],
-            0x8000..=0xffff => {
-                let i = addr - 0x8000;
-                if let Some(rom) = &self.rom {
-                    rom[i as usize]
-                } else {
-                    panic!("No ROM.")
-                }
-            }
-            _ => panic!("Read not implemented! addr: 0x{:x}", addr),
-        }
+    fn push(&mut self, value: u8) -> Result<(), CpuError> {
+        let addr = 0x0100 | self.registers.stack_pointer as u16;
+        self.write(addr, value)?;
+        self.registers.stack_pointer = self.registers.stack_pointer.wrapping_sub(1);
+        Ok(())
     }
 
-    fn read_word(&self, addr: u16) -> u16 {
-        let lower_byte = self.read(addr) as u16;
-        let upper_byte = self.read(addr + 1) as u16;
-        lower_byte | (upper_byte << 8)
+    fn pull(&mut self) -> Result<u8, CpuError> {
+        self.registers.stack_pointer = self.registers.stack_pointer.wrapping_add(1);
+        let addr = 0x0100 | self.registers.stack_pointer as u16;
+        self.read(addr)
     }
 
-    fn write(&self, addr: u16, value: u8) {
-        match addr {
-            0x0000..=0x07ff => {
-                self.ram.borrow_mut()[addr as usize] = value;
-            }
-            0x2000..=0x2007 => {
-                println!("@@@ write 0x{:x} to 0x{:x}", value, addr);
-            }
-            _ => panic!(
-                "Write not implemented! addr: 0x{:x}, value: 0x{:x}",
-                addr, value
-            ),
-        }
+    fn read(&mut self, addr: u16) -> Result<u8, CpuError> {
+        let value = self.bus.read(addr)?;
+        self.record(addr, value, AccessKind::Read);
+        Ok(value)
+    }
+
+    // 副作用なしでメモリを読む。I/Oレジスタなどメモリでないところと、ROMが無いときはNone
+    pub fn peek(&self, addr: u16) -> Option<u8> {
+        self.bus.peek(addr)
+    }
+
+    // デバッガからメモリを書き換える。アクセスの記録は残さず、RAM以外には書き込めない
+    pub fn poke(&mut self, addr: u16, value: u8) -> bool {
+        self.bus.poke(addr, value)
+    }
+
+    // 命令のデコード結果をアドレスごとに使い回すかどうか。lockstepで有無を比べるためのもの
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.decode_cache.set_enabled(enabled);
+    }
+
+    fn read_word(&mut self, addr: u16) -> Result<u16, CpuError> {
+        let lower_byte = self.read(addr)? as u16;
+        let upper_byte = self.read(addr.wrapping_add(1))? as u16;
+        Ok(lower_byte | (upper_byte << 8))
+    }
+
+    fn write(&mut self, addr: u16, value: u8) -> Result<(), CpuError> {
+        self.record(addr, value, AccessKind::Write);
+        self.bus.write(addr, value)?;
+        Ok(())
+    }
+
+    fn record(&mut self, addr: u16, value: u8, kind: AccessKind) {
+        self.accesses.push(MemoryAccess { addr, value, kind });
     }
 
     pub fn dump_registers(&self) {
-        println!("{:?}", self.registers);
+        log::debug!(target: "nes::cpu", "{:?}", self.registers);
     }
 
     #[cfg(test)]
@@ -231,10 +493,15 @@ enum Operand {
     None,
 }
 
+// $x2のうち$82 $A2 $C2 $E2以外
+fn is_jam(opcode: u8) -> bool {
+    opcode & 0x0f == 0x02 && opcode & 0x9f != 0x82
+}
+
 #[cfg(test)]
 mod test {
-    use super::{Cpu, Ram};
-    use std::{cell::RefCell, rc::Rc};
+    use super::{bus::TestBus, AccessKind, CallFrame, Cpu, CpuError, MemoryAccess, StackWarning};
+    use crate::region::Region;
 
     #[test]
     fn test_reset() {
@@ -242,145 +509,355 @@ mod test {
         rom[0x7ffc] = 0x00;
         rom[0x7ffd] = 0x80;
 
-        let rom = Rc::new(rom);
-        let ram = Rc::new(RefCell::new(vec![0; 0x800]));
-        let mut cpu = Cpu::new(ram);
-        cpu.set_rom(Some(rom));
+        let mut cpu = Cpu::new();
+        cpu.set_rom(Some(rom.into()));
         assert_eq!(cpu.get_registers().program_counter, 0);
 
-        cpu.reset();
+        cpu.reset().unwrap();
+        assert_eq!(cpu.get_registers().program_counter, 0x8000);
+        assert_eq!(cpu.cycles(), 7);
+    }
+
+    #[test]
+    fn test_soft_reset() {
+        // LDA #$12
+        let mut cpu = prepare(&[0xa9, 0x12]);
+        cpu.power_on().unwrap();
+        assert_eq!(cpu.get_registers().stack_pointer, 0xfd);
+        assert_eq!(u8::from(&cpu.get_registers().status), 0x24);
+        cpu.run().unwrap();
+        cpu.get_registers().stack_pointer = 0xfd;
+        cpu.get_registers().status.irq_prohibited = false;
+
+        cpu.reset().unwrap();
+        let registers = cpu.get_registers().clone();
+        assert_eq!(registers.program_counter, 0x8000);
+        assert_eq!(registers.accumulator, 0x12);
+        assert_eq!(registers.stack_pointer, 0xfa);
+        assert!(registers.status.irq_prohibited);
+        assert_eq!(cpu.cycles(), 7 + 2 + 7);
+
+        cpu.power_on().unwrap();
+        assert_eq!(cpu.get_registers().accumulator, 0x00);
+        assert_eq!(cpu.get_registers().stack_pointer, 0xfd);
+        assert_eq!(cpu.cycles(), 7);
+    }
+
+    #[test]
+    fn test_jam() {
+        // SEI, JAM
+        let mut cpu = prepare(&[0x78, 0x02]);
+        cpu.run().unwrap();
+        let halted = Err(CpuError::Halted {
+            addr: 0x8001,
+            opcode: 0x02,
+        });
+        assert_eq!(cpu.run(), halted);
+        assert!(cpu.halted());
+        // 何度実行しても止まったまま
+        assert_eq!(cpu.run(), halted);
+        assert_eq!(cpu.get_registers().program_counter, 0x8001);
+
+        cpu.reset().unwrap();
+        assert!(!cpu.halted());
         assert_eq!(cpu.get_registers().program_counter, 0x8000);
     }
 
+    #[test]
+    fn test_wrap_program_counter() {
+        let mut cpu = Cpu::with_bus(TestBus::default());
+        // $FFFE: JMP $1234。オペランドの上位は$0000から読む
+        cpu.poke(0xfffe, 0x4c);
+        cpu.poke(0xffff, 0x34);
+        cpu.poke(0x0000, 0x12);
+        cpu.get_registers().program_counter = 0xfffe;
+        cpu.run().unwrap();
+        assert_eq!(cpu.get_registers().program_counter, 0x1234);
+
+        // $FFFF: INX の次は$0000
+        cpu.poke(0xffff, 0xe8);
+        cpu.get_registers().program_counter = 0xffff;
+        cpu.run().unwrap();
+        assert_eq!(cpu.get_registers().program_counter, 0x0000);
+    }
+
+    #[test]
+    fn test_self_modifying_code() {
+        let mut cpu = Cpu::with_bus(TestBus::default());
+        // INX; STA $8000; JMP $8000
+        for (i, b) in [0xe8, 0x8d, 0x00, 0x80, 0x4c, 0x00, 0x80]
+            .iter()
+            .enumerate()
+        {
+            cpu.poke(0x8000 + i as u16, *b);
+        }
+        cpu.get_registers().program_counter = 0x8000;
+        // 1周目でINXをDEYに書き換える
+        cpu.get_registers().accumulator = 0x88;
+
+        for _ in 0..3 {
+            cpu.run().unwrap();
+        }
+        assert_eq!(cpu.get_registers().index_x, 0x01);
+        cpu.run().unwrap();
+        assert_eq!(cpu.get_registers().index_x, 0x01);
+        assert_eq!(cpu.get_registers().index_y, 0xff);
+    }
+
+    #[test]
+    fn test_ppu_position_pal() {
+        let mut cpu = prepare(&[]);
+        cpu.set_region(Region::Pal);
+        // リセットの7クロックで22.4ドット
+        assert_eq!(cpu.ppu_position(), (0, 22));
+        cpu.cycles = 33247;
+        assert_eq!(cpu.ppu_position(), (311, 339));
+        cpu.cycles += 1;
+        assert_eq!(cpu.ppu_position(), (0, 1));
+    }
+
+    #[test]
+    fn test_send() {
+        // 別スレッドに渡せるように、RAMもROMも共有の可変参照を持たない
+        fn assert_send<T: Send>() {}
+        assert_send::<Cpu>();
+    }
+
     #[test]
     fn test_instruction_jmp_0x4c() {
-        let (mut cpu, _ram) = prepare(&[0x4c, 0xff, 0x80]);
+        let mut cpu = prepare(&[0x4c, 0xff, 0x80]);
 
-        let clock = cpu.run();
+        let clock = cpu.run().unwrap().cycles;
         assert_eq!(clock, 3);
         assert_eq!(cpu.get_registers().program_counter, 0x80ff);
     }
 
+    #[test]
+    fn test_instruction_jsr_0x20() {
+        let mut cpu = prepare(&[0x20, 0x34, 0x92]);
+        cpu.get_registers().stack_pointer = 0xfd;
+
+        let clock = cpu.run().unwrap().cycles;
+        assert_eq!(clock, 6);
+        assert_eq!(cpu.get_registers().program_counter, 0x9234);
+        assert_eq!(cpu.get_registers().stack_pointer, 0xfb);
+        assert_eq!(cpu.bus().ram()[0x01fd], 0x80);
+        assert_eq!(cpu.bus().ram()[0x01fc], 0x02);
+        assert_eq!(
+            cpu.call_stack(),
+            &[CallFrame {
+                call_site: 0x8000,
+                target: 0x9234,
+                return_addr: 0x8003,
+            }]
+        );
+    }
+
+    #[test]
+    fn test_instruction_rts_0x60() {
+        let mut cpu = prepare(&[0x20, 0x04, 0x80, 0x00, 0x60]);
+        cpu.get_registers().stack_pointer = 0xfd;
+
+        cpu.run().unwrap();
+        let clock = cpu.run().unwrap().cycles;
+        assert_eq!(clock, 6);
+        assert_eq!(cpu.get_registers().program_counter, 0x8003);
+        assert_eq!(cpu.get_registers().stack_pointer, 0xfd);
+        assert_eq!(cpu.call_depth(), 0);
+
+        assert!(cpu.take_stack_warnings().is_empty());
+
+        // JSRしていないのにRTSすると警告が出る
+        {
+            let ram = cpu.bus_mut().ram_mut();
+            ram[0x01fe] = 0xff;
+            ram[0x01ff] = 0x8f;
+        }
+        cpu.get_registers().program_counter = 0x8004;
+        cpu.run().unwrap();
+        assert_eq!(cpu.get_registers().program_counter, 0x9000);
+        assert_eq!(cpu.call_depth(), 0);
+        assert_eq!(
+            cpu.take_stack_warnings(),
+            vec![StackWarning::UnmatchedReturn {
+                addr: 0x8004,
+                return_addr: 0x9000,
+            }]
+        );
+    }
+
     #[test]
     fn test_instruction_sei_0x78() {
-        let (mut cpu, _ram) = prepare(&[0x78]);
-        let clock = cpu.run();
+        let mut cpu = prepare(&[0x78]);
+        let clock = cpu.run().unwrap().cycles;
         assert_eq!(clock, 2);
         assert!(cpu.get_registers().status.irq_prohibited);
     }
 
     #[test]
     fn test_instruction_dey_0x88() {
-        let (mut cpu, _ram) = prepare(&[0x88, 0x88]);
+        let mut cpu = prepare(&[0x88, 0x88]);
         cpu.get_registers().index_y = 0x01;
 
-        let clock = cpu.run();
+        let clock = cpu.run().unwrap().cycles;
         assert_eq!(clock, 2);
         assert_eq!(cpu.get_registers().index_y, 0x00);
-        assert_eq!(cpu.get_registers().status.negative, false);
-        assert_eq!(cpu.get_registers().status.zero, true);
+        assert!(!cpu.get_registers().status.negative);
+        assert!(cpu.get_registers().status.zero);
 
-        let clock = cpu.run();
+        let clock = cpu.run().unwrap().cycles;
         assert_eq!(clock, 2);
         assert_eq!(cpu.get_registers().index_y, 0xff);
-        assert_eq!(cpu.get_registers().status.negative, true);
-        assert_eq!(cpu.get_registers().status.zero, false);
+        assert!(cpu.get_registers().status.negative);
+        assert!(!cpu.get_registers().status.zero);
     }
 
     #[test]
     fn test_instruction_sta_0x8d() {
-        let (mut cpu, ram) = prepare(&[0x8d, 0x23, 0x01]);
+        let mut cpu = prepare(&[0x8d, 0x23, 0x01]);
         cpu.get_registers().accumulator = 0x56;
-        let clock = cpu.run();
+        let clock = cpu.run().unwrap().cycles;
         assert_eq!(clock, 4);
-        assert_eq!(ram.borrow()[0x0123], 0x56);
+        assert_eq!(cpu.bus().ram()[0x0123], 0x56);
+    }
+
+    #[test]
+    fn test_instruction_sta_0x8d_prg_ram() {
+        let mut cpu = prepare(&[0x8d, 0x00, 0x60]);
+        cpu.get_registers().accumulator = 0x56;
+        cpu.run().unwrap();
+        assert_eq!(cpu.read(0x6000), Ok(0x56));
+    }
+
+    #[test]
+    fn test_accesses() {
+        let mut cpu = prepare(&[0xbd, 0x00, 0x01, 0x8d, 0x00, 0x02]);
+        cpu.bus_mut().ram_mut()[0x0105] = 0x56;
+        cpu.get_registers().index_x = 0x05;
+
+        cpu.run().unwrap();
+        assert_eq!(
+            cpu.accesses(),
+            &[
+                MemoryAccess {
+                    addr: 0x8000,
+                    value: 0xbd,
+                    kind: AccessKind::Execute
+                },
+                MemoryAccess {
+                    addr: 0x8001,
+                    value: 0x00,
+                    kind: AccessKind::Execute
+                },
+                MemoryAccess {
+                    addr: 0x8002,
+                    value: 0x01,
+                    kind: AccessKind::Execute
+                },
+                MemoryAccess {
+                    addr: 0x0105,
+                    value: 0x56,
+                    kind: AccessKind::Read
+                },
+            ]
+        );
+
+        cpu.run().unwrap();
+        assert_eq!(
+            cpu.accesses()[3],
+            MemoryAccess {
+                addr: 0x0200,
+                value: 0x56,
+                kind: AccessKind::Write
+            }
+        );
     }
 
     #[test]
     fn test_instruction_txs_0x9a() {
-        let (mut cpu, _ram) = prepare(&[0x9a, 0x9a]);
+        let mut cpu = prepare(&[0x9a, 0x9a]);
 
         cpu.get_registers().index_x = 0xff;
-        let clock = cpu.run();
+        let clock = cpu.run().unwrap().cycles;
         assert_eq!(clock, 2);
         assert_eq!(cpu.get_registers().stack_pointer, 0xff);
-        assert_eq!(cpu.get_registers().status.negative, true);
-        assert_eq!(cpu.get_registers().status.zero, false);
+        assert!(cpu.get_registers().status.negative);
+        assert!(!cpu.get_registers().status.zero);
 
         cpu.get_registers().index_x = 0x00;
-        let clock = cpu.run();
+        let clock = cpu.run().unwrap().cycles;
         assert_eq!(clock, 2);
         assert_eq!(cpu.get_registers().stack_pointer, 0x00);
-        assert_eq!(cpu.get_registers().status.negative, false);
-        assert_eq!(cpu.get_registers().status.zero, true);
+        assert!(!cpu.get_registers().status.negative);
+        assert!(cpu.get_registers().status.zero);
     }
 
     #[test]
     fn test_instruction_ldy_0xa0() {
-        let (mut cpu, _ram) = prepare(&[0xa0, 0xff, 0xa0, 0x00]);
+        let mut cpu = prepare(&[0xa0, 0xff, 0xa0, 0x00]);
 
-        let clock = cpu.run();
+        let clock = cpu.run().unwrap().cycles;
         assert_eq!(clock, 2);
         assert_eq!(cpu.get_registers().index_y, 0xff);
-        assert_eq!(cpu.get_registers().status.negative, true);
-        assert_eq!(cpu.get_registers().status.zero, false);
+        assert!(cpu.get_registers().status.negative);
+        assert!(!cpu.get_registers().status.zero);
 
-        let clock = cpu.run();
+        let clock = cpu.run().unwrap().cycles;
         assert_eq!(clock, 2);
         assert_eq!(cpu.get_registers().index_y, 0x00);
-        assert_eq!(cpu.get_registers().status.negative, false);
-        assert_eq!(cpu.get_registers().status.zero, true);
+        assert!(!cpu.get_registers().status.negative);
+        assert!(cpu.get_registers().status.zero);
     }
 
     #[test]
     fn test_instruction_ldx_0xa2() {
-        let (mut cpu, _ram) = prepare(&[0xa2, 0xff, 0xa2, 0x00]);
+        let mut cpu = prepare(&[0xa2, 0xff, 0xa2, 0x00]);
 
-        let clock = cpu.run();
+        let clock = cpu.run().unwrap().cycles;
         assert_eq!(clock, 2);
         assert_eq!(cpu.get_registers().index_x, 0xff);
-        assert_eq!(cpu.get_registers().status.negative, true);
-        assert_eq!(cpu.get_registers().status.zero, false);
+        assert!(cpu.get_registers().status.negative);
+        assert!(!cpu.get_registers().status.zero);
 
-        let clock = cpu.run();
+        let clock = cpu.run().unwrap().cycles;
         assert_eq!(clock, 2);
         assert_eq!(cpu.get_registers().index_x, 0x00);
-        assert_eq!(cpu.get_registers().status.negative, false);
-        assert_eq!(cpu.get_registers().status.zero, true);
+        assert!(!cpu.get_registers().status.negative);
+        assert!(cpu.get_registers().status.zero);
     }
 
     #[test]
     fn test_instruction_lda_0xa9() {
-        let (mut cpu, _ram) = prepare(&[0xa9, 0xff, 0xa9, 0x00]);
+        let mut cpu = prepare(&[0xa9, 0xff, 0xa9, 0x00]);
 
-        let clock = cpu.run();
+        let clock = cpu.run().unwrap().cycles;
         assert_eq!(clock, 2);
         assert_eq!(cpu.get_registers().accumulator, 0xff);
-        assert_eq!(cpu.get_registers().status.negative, true);
-        assert_eq!(cpu.get_registers().status.zero, false);
+        assert!(cpu.get_registers().status.negative);
+        assert!(!cpu.get_registers().status.zero);
 
-        let clock = cpu.run();
+        let clock = cpu.run().unwrap().cycles;
         assert_eq!(clock, 2);
         assert_eq!(cpu.get_registers().accumulator, 0x00);
-        assert_eq!(cpu.get_registers().status.negative, false);
-        assert_eq!(cpu.get_registers().status.zero, true);
+        assert!(!cpu.get_registers().status.negative);
+        assert!(cpu.get_registers().status.zero);
     }
 
     #[test]
     fn test_instruction_lda_0xbd() {
-        let (mut cpu, ram) = prepare(&[0xbd, 0x00, 0x00, 0xbd, 0xff, 0x01]);
+        let mut cpu = prepare(&[0xbd, 0x00, 0x00, 0xbd, 0xff, 0x01]);
         {
-            let mut ram = ram.borrow_mut();
+            let ram = cpu.bus_mut().ram_mut();
             ram[0x0056] = 0xff;
             ram[0x0255] = 0x45;
         }
         cpu.get_registers().index_x = 0x56;
 
-        let clock = cpu.run();
+        let clock = cpu.run().unwrap().cycles;
         assert_eq!(clock, 4);
         assert_eq!(cpu.get_registers().accumulator, 0xff);
 
-        let clock = cpu.run();
+        let clock = cpu.run().unwrap().cycles;
         assert_eq!(clock, 5); // page crossed
         assert_eq!(cpu.get_registers().accumulator, 0x45);
     }
@@ -388,59 +865,57 @@ mod test {
     #[test]
     fn test_instruction_bne_0xd0() {
         // INXの1回目は0になるから分岐せず、2回目のINXを実行したあとに分岐する
-        let (mut cpu, _ram) = prepare(&[0xe8, 0xd0, 0xfa, 0xe8, 0xd0, 0xfa]);
+        let mut cpu = prepare(&[0xe8, 0xd0, 0xfa, 0xe8, 0xd0, 0xfa]);
         cpu.get_registers().index_x = 0xff;
         assert_eq!(cpu.get_registers().program_counter, 0x8000);
 
-        cpu.run();
-        let clock = cpu.run();
+        cpu.run().unwrap();
+        let clock = cpu.run().unwrap().cycles;
         assert_eq!(clock, 2);
         assert_eq!(cpu.get_registers().program_counter, 0x8003);
 
-        cpu.run();
-        let clock = cpu.run();
+        cpu.run().unwrap();
+        let clock = cpu.run().unwrap().cycles;
         assert_eq!(clock, 3); // branched
         assert_eq!(cpu.get_registers().program_counter, 0x8000);
 
         cpu.get_registers().index_x = 0x00;
-        cpu.run();
-        let clock = cpu.run();
+        cpu.run().unwrap();
+        let clock = cpu.run().unwrap().cycles;
         assert_eq!(clock, 4); // branched, page crossed
         assert_eq!(cpu.get_registers().program_counter, 0x7ffd);
     }
 
     #[test]
     fn test_instruction_inx_0xe8() {
-        let (mut cpu, _ram) = prepare(&[0xe8, 0xe8]);
+        let mut cpu = prepare(&[0xe8, 0xe8]);
         cpu.get_registers().index_x = 0xfe;
 
-        let clock = cpu.run();
+        let clock = cpu.run().unwrap().cycles;
         assert_eq!(clock, 2);
         assert_eq!(cpu.get_registers().index_x, 0xff);
-        assert_eq!(cpu.get_registers().status.negative, true);
-        assert_eq!(cpu.get_registers().status.zero, false);
+        assert!(cpu.get_registers().status.negative);
+        assert!(!cpu.get_registers().status.zero);
 
-        let clock = cpu.run();
+        let clock = cpu.run().unwrap().cycles;
         assert_eq!(clock, 2);
         assert_eq!(cpu.get_registers().index_x, 0x00);
-        assert_eq!(cpu.get_registers().status.negative, false);
-        assert_eq!(cpu.get_registers().status.zero, true);
+        assert!(!cpu.get_registers().status.negative);
+        assert!(cpu.get_registers().status.zero);
     }
 
-    fn prepare(initial_bytes: &[u8]) -> (Cpu, Ram) {
+    fn prepare(initial_bytes: &[u8]) -> Cpu {
         let mut rom = vec![0; 0x8000];
         rom[0x7ffc] = 0x00;
         rom[0x7ffd] = 0x80;
 
         for (i, b) in initial_bytes.iter().enumerate() {
-            rom[i] = b.clone();
+            rom[i] = *b;
         }
 
-        let rom = Rc::new(rom);
-        let ram = Rc::new(RefCell::new(vec![0; 0x800]));
-        let mut cpu = Cpu::new(ram.clone());
-        cpu.set_rom(Some(rom));
-        cpu.reset();
-        (cpu, ram)
+        let mut cpu = Cpu::new();
+        cpu.set_rom(Some(rom.into()));
+        cpu.reset().unwrap();
+        cpu
     }
 }
@@ -1,4 +1,6 @@
-#[derive(Debug, Default)]
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Registers {
     pub accumulator: u8,      // A
     pub index_x: u8,          // X
@@ -8,7 +10,7 @@ pub struct Registers {
     pub program_counter: u16, // PC
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Status {
     pub negative: bool,       // N
     pub overflow: bool,       // V
@@ -34,3 +36,64 @@ impl Default for Status {
         }
     }
 }
+
+impl From<&Status> for u8 {
+    fn from(status: &Status) -> Self {
+        [
+            status.carry,
+            status.zero,
+            status.irq_prohibited,
+            status.decimal_mode,
+            status.break_mode,
+            status.reserved,
+            status.overflow,
+            status.negative,
+        ]
+        .iter()
+        .enumerate()
+        .fold(0, |acc, (i, flag)| acc | ((*flag as u8) << i))
+    }
+}
+
+impl From<u8> for Status {
+    fn from(value: u8) -> Self {
+        let flag = |i: u8| (value >> i) & 0x01 == 0x01;
+        Self {
+            negative: flag(7),
+            overflow: flag(6),
+            reserved: flag(5),
+            break_mode: flag(4),
+            decimal_mode: flag(3),
+            irq_prohibited: flag(2),
+            zero: flag(1),
+            carry: flag(0),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Status;
+
+    #[test]
+    fn test_status_to_u8() {
+        let mut status = Status::default();
+        assert_eq!(u8::from(&status), 0x20);
+
+        status.irq_prohibited = true;
+        status.negative = true;
+        status.carry = true;
+        assert_eq!(u8::from(&status), 0xa5);
+    }
+
+    #[test]
+    fn test_status_from_u8() {
+        let status = Status::from(0xc3);
+        assert!(status.negative);
+        assert!(status.overflow);
+        assert!(!status.reserved);
+        assert!(status.zero);
+        assert!(status.carry);
+        assert_eq!(u8::from(&status), 0xc3);
+    }
+}
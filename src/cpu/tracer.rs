@@ -0,0 +1,205 @@
+use super::{
+    disassembler::disassemble,
+    instruction::{Addressing, Instruction, Kind},
+    Cpu,
+};
+#[cfg(feature = "debugger")]
+use crate::debugger::symbols::SymbolTable;
+use std::{fmt, io::Write};
+
+// 1命令ごとにnestest.logと同じ形式の行を書き出す
+pub struct Tracer {
+    out: Box<dyn Write + Send>,
+    #[cfg(feature = "debugger")]
+    symbols: Option<SymbolTable>,
+}
+
+impl Tracer {
+    pub fn new<W: Write + Send + 'static>(out: W) -> Self {
+        Self {
+            out: Box::new(out),
+            #[cfg(feature = "debugger")]
+            symbols: None,
+        }
+    }
+
+    // オペランドのアドレスをラベルで表示する。nestest.logとは比較できなくなる
+    #[cfg(feature = "debugger")]
+    pub fn set_symbols(&mut self, symbols: Option<SymbolTable>) {
+        self.symbols = symbols;
+    }
+
+    pub fn trace(&mut self, cpu: &Cpu) {
+        #[cfg(feature = "debugger")]
+        let line = format_line(cpu, |addr| self.symbols.as_ref()?.label(addr));
+        #[cfg(not(feature = "debugger"))]
+        let line = trace_line(cpu);
+        writeln!(self.out, "{}", line).expect("Failed to write trace.");
+    }
+}
+
+impl fmt::Debug for Tracer {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Tracer")
+    }
+}
+
+// PCにある命令を実行する前の状態を1行にする
+pub fn trace_line(cpu: &Cpu) -> String {
+    format_line(cpu, |_| None)
+}
+
+// labelはオペランドのアドレスに付ける名前。無ければNone
+fn format_line<'a, F: Fn(u16) -> Option<&'a str>>(cpu: &Cpu, label: F) -> String {
+    let registers = &cpu.registers;
+    let pc = registers.program_counter;
+    let disassembled = disassemble(pc, |addr| cpu.peek(addr).unwrap_or(0));
+
+    let bytes: Vec<String> = disassembled
+        .bytes
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect();
+    let mut assembly = disassembled.to_string_with_labels(label);
+    if let Some(instruction) = Instruction::decode(disassembled.bytes[0]) {
+        assembly.push_str(&annotation(cpu, &instruction, &disassembled.bytes));
+    }
+
+    let (scanline, dot) = cpu.ppu_position();
+    format!(
+        "{:04X}  {:<8}  {:<32}A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} PPU:{:>3},{:>3} CYC:{}",
+        pc,
+        bytes.join(" "),
+        assembly,
+        registers.accumulator,
+        registers.index_x,
+        registers.index_y,
+        u8::from(&registers.status),
+        registers.stack_pointer,
+        scanline,
+        dot,
+        cpu.cycles,
+    )
+}
+
+// 実際にアクセスするアドレスとそこの値。nestest.logの書式に合わせている
+fn annotation(cpu: &Cpu, instruction: &Instruction, bytes: &[u8]) -> String {
+    let registers = &cpu.registers;
+    let byte = bytes.get(1).copied().unwrap_or(0);
+    let word = (byte as u16) | ((bytes.get(2).copied().unwrap_or(0) as u16) << 8);
+    let peek_word = |lower: u16, upper: u16| {
+        let lower = cpu.peek(lower).unwrap_or(0) as u16;
+        let upper = cpu.peek(upper).unwrap_or(0) as u16;
+        lower | (upper << 8)
+    };
+    let value = |addr: u16| match cpu.peek(addr) {
+        Some(value) => format!(" = {:02X}", value),
+        None => String::new(),
+    };
+
+    match instruction.addressing {
+        Addressing::ZeroPage => value(byte as u16),
+        Addressing::ZeroPageX => {
+            let addr = byte.wrapping_add(registers.index_x);
+            format!(" @ {:02X}{}", addr, value(addr as u16))
+        }
+        Addressing::ZeroPageY => {
+            let addr = byte.wrapping_add(registers.index_y);
+            format!(" @ {:02X}{}", addr, value(addr as u16))
+        }
+        Addressing::Absolute => match instruction.kind {
+            Kind::JMP | Kind::JSR => String::new(),
+            _ => value(word),
+        },
+        Addressing::AbsoluteX => {
+            let addr = word.wrapping_add(registers.index_x as u16);
+            format!(" @ {:04X}{}", addr, value(addr))
+        }
+        Addressing::AbsoluteY => {
+            let addr = word.wrapping_add(registers.index_y as u16);
+            format!(" @ {:04X}{}", addr, value(addr))
+        }
+        Addressing::Indirect => {
+            // 6502のバグでページをまたがずに上位バイトを読む
+            let upper = (word & 0xff00) | (word.wrapping_add(1) & 0x00ff);
+            format!(" = {:04X}", peek_word(word, upper))
+        }
+        Addressing::IndirectX => {
+            let pointer = byte.wrapping_add(registers.index_x);
+            let addr = peek_word(pointer as u16, pointer.wrapping_add(1) as u16);
+            format!(" @ {:02X} = {:04X}{}", pointer, addr, value(addr))
+        }
+        Addressing::IndirectY => {
+            let base = peek_word(byte as u16, byte.wrapping_add(1) as u16);
+            let addr = base.wrapping_add(registers.index_y as u16);
+            format!(" = {:04X} @ {:04X}{}", base, addr, value(addr))
+        }
+        _ => String::new(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{format_line, trace_line};
+    use crate::cpu::Cpu;
+
+    #[test]
+    fn test_trace_line() {
+        let mut cpu = prepare(&[0x78, 0xa2, 0xff, 0xbd, 0x10, 0x00]);
+        assert_eq!(
+            trace_line(&cpu),
+            "8000  78        SEI                             A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7"
+        );
+
+        cpu.run().unwrap();
+        assert_eq!(
+            trace_line(&cpu),
+            "8001  A2 FF     LDX #$FF                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 27 CYC:9"
+        );
+
+        cpu.run().unwrap();
+        cpu.poke(0x010f, 0x5a);
+        assert_eq!(
+            trace_line(&cpu),
+            "8003  BD 10 00  LDA $0010,X @ 010F = 5A         A:00 X:FF Y:00 P:A4 SP:FD PPU:  0, 33 CYC:11"
+        );
+    }
+
+    #[test]
+    fn test_trace_line_indirect() {
+        let mut cpu = prepare(&[0x6c, 0xff, 0x02, 0xb1, 0x80]);
+        for (addr, value) in [
+            (0x02ff, 0x34),
+            (0x0200, 0x12),
+            (0x0080, 0x00),
+            (0x0081, 0x03),
+            (0x0305, 0x89),
+        ] {
+            cpu.poke(addr, value);
+        }
+        assert!(trace_line(&cpu).starts_with("8000  6C FF 02  JMP ($02FF) = 1234    "));
+
+        cpu.registers.program_counter = 0x8003;
+        cpu.registers.index_y = 0x05;
+        assert!(trace_line(&cpu).starts_with("8003  B1 80     LDA ($80),Y = 0300 @ 0305 = 89  "));
+    }
+
+    #[test]
+    fn test_trace_line_with_symbols() {
+        let cpu = prepare(&[0x20, 0x10, 0x80]);
+        let label = |addr| if addr == 0x8010 { Some("init") } else { None };
+        assert!(format_line(&cpu, label).starts_with("8000  20 10 80  JSR init    "));
+    }
+
+    fn prepare(initial_bytes: &[u8]) -> Cpu {
+        let mut rom = vec![0; 0x8000];
+        rom[0x7ffc] = 0x00;
+        rom[0x7ffd] = 0x80;
+        rom[..initial_bytes.len()].copy_from_slice(initial_bytes);
+
+        let mut cpu = Cpu::new();
+        cpu.set_rom(Some(rom.into()));
+        cpu.power_on().unwrap();
+        cpu
+    }
+}
@@ -0,0 +1,110 @@
+use crate::cpu::{AccessKind, MemoryAccess};
+use std::{error::Error, io::Write, result::Result};
+
+// FCEUXの.cdlと同じビット
+pub const CODE: u8 = 0x01;
+pub const DATA: u8 = 0x02;
+// どの8KBのCPUバンク($8000/$A000/$C000/$E000)から見えていたか
+const BANK_SHIFT: u8 = 2;
+
+// PRG ROMのどこが命令として実行され、どこがデータとして読まれたかを記録する。
+// 書き出すファイルはPRG ROMと同じ長さのフラグの後ろにCHR ROMと同じ長さのフラグが続く。
+// PPUがまだ無いのでCHRのフラグは常に0
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CodeDataLogger {
+    prg: Vec<u8>,
+    chr: Vec<u8>,
+}
+
+impl CodeDataLogger {
+    pub fn new(prg_len: usize, chr_len: usize) -> Self {
+        Self {
+            prg: vec![0; prg_len],
+            chr: vec![0; chr_len],
+        }
+    }
+
+    // 既存の.cdlに追記していくときに使う
+    pub fn from_bytes(data: &[u8], prg_len: usize, chr_len: usize) -> Result<Self, Box<dyn Error>> {
+        if data.len() != prg_len + chr_len {
+            return Err("Invalid CDL size.".into());
+        }
+        Ok(Self {
+            prg: data[..prg_len].to_vec(),
+            chr: data[prg_len..].to_vec(),
+        })
+    }
+
+    // 1命令分のメモリアクセスを記録する
+    pub fn log(&mut self, accesses: &[MemoryAccess]) {
+        if self.prg.is_empty() {
+            return;
+        }
+        for access in accesses {
+            if access.addr < 0x8000 {
+                continue;
+            }
+            let flag = match access.kind {
+                AccessKind::Execute => CODE,
+                AccessKind::Read => DATA,
+                AccessKind::Write => continue,
+            };
+            let bank = ((access.addr >> 13) & 0x03) as u8;
+            let offset = (access.addr - 0x8000) as usize % self.prg.len();
+            self.prg[offset] |= flag | (bank << BANK_SHIFT);
+        }
+    }
+
+    pub fn prg(&self) -> &[u8] {
+        &self.prg
+    }
+
+    // PRG ROMのうちコードとデータそれぞれで使われたバイト数
+    pub fn coverage(&self) -> (usize, usize) {
+        let count = |flag: u8| self.prg.iter().filter(|f| *f & flag != 0).count();
+        (count(CODE), count(DATA))
+    }
+
+    pub fn write<W: Write>(&self, out: &mut W) -> Result<(), Box<dyn Error>> {
+        out.write_all(&self.prg)?;
+        out.write_all(&self.chr)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{CodeDataLogger, CODE, DATA};
+    use crate::cpu::{AccessKind, MemoryAccess};
+
+    #[test]
+    fn test_log() {
+        let mut cdl = CodeDataLogger::new(0x8000, 0x10);
+        cdl.log(&[
+            access(0x8000, AccessKind::Execute),
+            access(0x8001, AccessKind::Execute),
+            access(0xe051, AccessKind::Read),
+            access(0x0200, AccessKind::Read),
+            access(0x8002, AccessKind::Write),
+        ]);
+        assert_eq!(cdl.prg()[0x0000], CODE);
+        assert_eq!(cdl.prg()[0x0001], CODE);
+        assert_eq!(cdl.prg()[0x0002], 0);
+        assert_eq!(cdl.prg()[0x6051], DATA | 0x0c);
+        assert_eq!(cdl.coverage(), (2, 1));
+
+        let mut out = Vec::new();
+        cdl.write(&mut out).unwrap();
+        assert_eq!(out.len(), 0x8010);
+        assert_eq!(CodeDataLogger::from_bytes(&out, 0x8000, 0x10).unwrap(), cdl);
+        assert!(CodeDataLogger::from_bytes(&out, 0x4000, 0x10).is_err());
+    }
+
+    fn access(addr: u16, kind: AccessKind) -> MemoryAccess {
+        MemoryAccess {
+            addr,
+            value: 0,
+            kind,
+        }
+    }
+}
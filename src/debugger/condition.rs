@@ -0,0 +1,399 @@
+use crate::cpu::Cpu;
+use std::{error::Error, fmt, iter::Peekable, result::Result, str::Chars};
+
+// "A == 0x3F && scanline > 200" のようなブレーク条件。
+// 使えるもの:
+//   数値: 10進数, 0x3F, $3F
+//   変数: A X Y SP P PC, フラグ N V D I Z C, cycles scanline dot (大文字小文字は区別しない)
+//   メモリ: [$0300] のように書くとそのアドレスの値
+//   演算子: ! - (単項), + -, &, ^, |, == != < <= > >=, &&, || (右ほど優先度が低い)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Condition {
+    source: String,
+    expr: Expr,
+}
+
+impl Condition {
+    pub fn parse(source: &str) -> Result<Self, Box<dyn Error>> {
+        let tokens = tokenize(source)?;
+        let mut parser = Parser { tokens, pos: 0 };
+        let expr = parser.parse_or()?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("Unexpected token: {}", token).into());
+        }
+        Ok(Self {
+            source: source.to_string(),
+            expr,
+        })
+    }
+
+    pub fn evaluate(&self, cpu: &Cpu) -> bool {
+        self.expr.evaluate(cpu) != 0
+    }
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Variable {
+    A,
+    X,
+    Y,
+    SP,
+    P,
+    PC,
+    N,
+    V,
+    D,
+    I,
+    Z,
+    C,
+    Cycles,
+    Scanline,
+    Dot,
+}
+
+impl Variable {
+    fn from_name(name: &str) -> Option<Self> {
+        let variable = match name.to_ascii_lowercase().as_str() {
+            "a" => Variable::A,
+            "x" => Variable::X,
+            "y" => Variable::Y,
+            "sp" | "s" => Variable::SP,
+            "p" => Variable::P,
+            "pc" => Variable::PC,
+            "n" => Variable::N,
+            "v" => Variable::V,
+            "d" => Variable::D,
+            "i" => Variable::I,
+            "z" => Variable::Z,
+            "c" => Variable::C,
+            "cycles" => Variable::Cycles,
+            "scanline" => Variable::Scanline,
+            "dot" => Variable::Dot,
+            _ => return None,
+        };
+        Some(variable)
+    }
+
+    fn value(&self, cpu: &Cpu) -> i64 {
+        let registers = cpu.registers();
+        let status = &registers.status;
+        match self {
+            Variable::A => registers.accumulator as i64,
+            Variable::X => registers.index_x as i64,
+            Variable::Y => registers.index_y as i64,
+            Variable::SP => registers.stack_pointer as i64,
+            Variable::P => u8::from(status) as i64,
+            Variable::PC => registers.program_counter as i64,
+            Variable::N => status.negative as i64,
+            Variable::V => status.overflow as i64,
+            Variable::D => status.decimal_mode as i64,
+            Variable::I => status.irq_prohibited as i64,
+            Variable::Z => status.zero as i64,
+            Variable::C => status.carry as i64,
+            Variable::Cycles => cpu.cycles() as i64,
+            Variable::Scanline => cpu.ppu_position().0 as i64,
+            Variable::Dot => cpu.ppu_position().1 as i64,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum UnaryOp {
+    Not,
+    Neg,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum BinaryOp {
+    Or,
+    And,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Add,
+    Sub,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Expr {
+    Number(i64),
+    Variable(Variable),
+    Memory(Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+impl Expr {
+    fn evaluate(&self, cpu: &Cpu) -> i64 {
+        match self {
+            Expr::Number(n) => *n,
+            Expr::Variable(v) => v.value(cpu),
+            Expr::Memory(addr) => cpu.peek(addr.evaluate(cpu) as u16).unwrap_or(0) as i64,
+            Expr::Unary(UnaryOp::Not, e) => (e.evaluate(cpu) == 0) as i64,
+            Expr::Unary(UnaryOp::Neg, e) => e.evaluate(cpu).wrapping_neg(),
+            Expr::Binary(BinaryOp::Or, l, r) => {
+                (l.evaluate(cpu) != 0 || r.evaluate(cpu) != 0) as i64
+            }
+            Expr::Binary(BinaryOp::And, l, r) => {
+                (l.evaluate(cpu) != 0 && r.evaluate(cpu) != 0) as i64
+            }
+            Expr::Binary(op, l, r) => {
+                let (l, r) = (l.evaluate(cpu), r.evaluate(cpu));
+                match op {
+                    BinaryOp::Eq => (l == r) as i64,
+                    BinaryOp::Ne => (l != r) as i64,
+                    BinaryOp::Lt => (l < r) as i64,
+                    BinaryOp::Le => (l <= r) as i64,
+                    BinaryOp::Gt => (l > r) as i64,
+                    BinaryOp::Ge => (l >= r) as i64,
+                    BinaryOp::BitOr => l | r,
+                    BinaryOp::BitXor => l ^ r,
+                    BinaryOp::BitAnd => l & r,
+                    BinaryOp::Add => l.wrapping_add(r),
+                    BinaryOp::Sub => l.wrapping_sub(r),
+                    BinaryOp::Or | BinaryOp::And => unreachable!(),
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Token {
+    Number(i64),
+    Ident(String),
+    Op(&'static str),
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Number(n) => write!(f, "{}", n),
+            Token::Ident(s) => f.write_str(s),
+            Token::Op(s) => f.write_str(s),
+        }
+    }
+}
+
+// 2文字の演算子を先に試す
+const OPERATORS: [&str; 18] = [
+    "||", "&&", "==", "!=", "<=", ">=", "<", ">", "|", "^", "&", "+", "-", "!", "(", ")", "[", "]",
+];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, Box<dyn Error>> {
+    let mut tokens = Vec::new();
+    let mut chars = source.chars().peekable();
+    while let Some(&c) = chars.peek() {
+        if c.is_whitespace() {
+            chars.next();
+        } else if c.is_ascii_digit() {
+            tokens.push(Token::Number(read_number(&mut chars)?));
+        } else if c == '$' {
+            chars.next();
+            tokens.push(Token::Number(read_digits(&mut chars, 16)?));
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let mut ident = String::new();
+            while let Some(&c) = chars.peek() {
+                if !(c.is_ascii_alphanumeric() || c == '_') {
+                    break;
+                }
+                ident.push(c);
+                chars.next();
+            }
+            tokens.push(Token::Ident(ident));
+        } else {
+            let rest: String = chars.clone().take(2).collect();
+            let op = OPERATORS
+                .iter()
+                .find(|op| rest.starts_with(*op))
+                .ok_or_else(|| format!("Unexpected character: {}", c))?;
+            for _ in 0..op.len() {
+                chars.next();
+            }
+            tokens.push(Token::Op(op));
+        }
+    }
+    Ok(tokens)
+}
+
+fn read_number(chars: &mut Peekable<Chars>) -> Result<i64, Box<dyn Error>> {
+    if chars.peek() == Some(&'0') {
+        let mut lookahead = chars.clone();
+        lookahead.next();
+        if matches!(lookahead.peek(), Some('x') | Some('X')) {
+            chars.next();
+            chars.next();
+            return read_digits(chars, 16);
+        }
+    }
+    read_digits(chars, 10)
+}
+
+fn read_digits(chars: &mut Peekable<Chars>, radix: u32) -> Result<i64, Box<dyn Error>> {
+    let mut digits = String::new();
+    while let Some(&c) = chars.peek() {
+        if !c.is_digit(radix) {
+            break;
+        }
+        digits.push(c);
+        chars.next();
+    }
+    i64::from_str_radix(&digits, radix).map_err(|_| format!("Invalid number: {}", digits).into())
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Result<Token, Box<dyn Error>> {
+        let token = self
+            .tokens
+            .get(self.pos)
+            .cloned()
+            .ok_or("Unexpected end of expression.")?;
+        self.pos += 1;
+        Ok(token)
+    }
+
+    fn eat(&mut self, ops: &[(&str, BinaryOp)]) -> Option<BinaryOp> {
+        if let Some(Token::Op(op)) = self.peek() {
+            if let Some((_, binary_op)) = ops.iter().find(|(s, _)| s == op) {
+                self.pos += 1;
+                return Some(*binary_op);
+            }
+        }
+        None
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), Box<dyn Error>> {
+        match self.next()? {
+            Token::Op(op) if op == expected => Ok(()),
+            token => Err(format!("Unexpected token: {}", token).into()),
+        }
+    }
+
+    // 優先度の低い順に並べた二項演算子
+    fn parse_binary(&mut self, levels: &[&[(&str, BinaryOp)]]) -> Result<Expr, Box<dyn Error>> {
+        let (ops, higher) = match levels.split_first() {
+            Some(split) => split,
+            None => return self.parse_unary(),
+        };
+        let mut expr = self.parse_binary(higher)?;
+        while let Some(op) = self.eat(ops) {
+            let rhs = self.parse_binary(higher)?;
+            expr = Expr::Binary(op, Box::new(expr), Box::new(rhs));
+        }
+        Ok(expr)
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, Box<dyn Error>> {
+        self.parse_binary(&[
+            &[("||", BinaryOp::Or)],
+            &[("&&", BinaryOp::And)],
+            &[
+                ("==", BinaryOp::Eq),
+                ("!=", BinaryOp::Ne),
+                ("<=", BinaryOp::Le),
+                (">=", BinaryOp::Ge),
+                ("<", BinaryOp::Lt),
+                (">", BinaryOp::Gt),
+            ],
+            &[("|", BinaryOp::BitOr)],
+            &[("^", BinaryOp::BitXor)],
+            &[("&", BinaryOp::BitAnd)],
+            &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+        ])
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, Box<dyn Error>> {
+        match self.next()? {
+            Token::Op("!") => Ok(Expr::Unary(UnaryOp::Not, Box::new(self.parse_unary()?))),
+            Token::Op("-") => Ok(Expr::Unary(UnaryOp::Neg, Box::new(self.parse_unary()?))),
+            Token::Op("(") => {
+                let expr = self.parse_or()?;
+                self.expect(")")?;
+                Ok(expr)
+            }
+            Token::Op("[") => {
+                let expr = self.parse_or()?;
+                self.expect("]")?;
+                Ok(Expr::Memory(Box::new(expr)))
+            }
+            Token::Number(n) => Ok(Expr::Number(n)),
+            Token::Ident(name) => Variable::from_name(&name)
+                .map(Expr::Variable)
+                .ok_or_else(|| format!("Unknown variable: {}", name).into()),
+            token => Err(format!("Unexpected token: {}", token).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Condition;
+    use crate::cpu::Cpu;
+
+    #[test]
+    fn test_evaluate() {
+        let cpu = prepare();
+        let evaluate = |source: &str| Condition::parse(source).unwrap().evaluate(&cpu);
+
+        assert!(evaluate("A == 0x3F"));
+        assert!(evaluate("a == $3f && x != 0"));
+        assert!(!evaluate("A == 63 && X == 0"));
+        assert!(evaluate("A == 0 || X == 2"));
+        assert!(evaluate("[0x10] == 0x80 && [$0010 + X - 2] == 0x80"));
+        assert!(evaluate("P & 0x80 == 0x80"));
+        assert!(evaluate("N && !Z"));
+        assert!(evaluate(
+            "PC == 0x8000 && cycles == 7 && scanline == 0 && dot == 21"
+        ));
+        assert!(evaluate("-(X - 4) > 1"));
+        assert!(!evaluate("!(A < 0x40)"));
+    }
+
+    #[test]
+    fn test_parse_error() {
+        let error = |source: &str| Condition::parse(source).unwrap_err().to_string();
+        assert_eq!(error("A == "), "Unexpected end of expression.");
+        assert_eq!(error("foo == 1"), "Unknown variable: foo");
+        assert_eq!(error("A = 1"), "Unexpected character: =");
+        assert_eq!(error("(A == 1"), "Unexpected end of expression.");
+        assert_eq!(error("A 1"), "Unexpected token: 1");
+        assert_eq!(error("A @ 1"), "Unexpected character: @");
+    }
+
+    fn prepare() -> Cpu {
+        let mut rom = vec![0; 0x8000];
+        rom[0x7ffc] = 0x00;
+        rom[0x7ffd] = 0x80;
+        let mut cpu = Cpu::new();
+        cpu.bus_mut().ram_mut()[0x0010] = 0x80;
+        cpu.set_rom(Some(rom.into()));
+        cpu.reset().unwrap();
+
+        let registers = cpu.get_registers();
+        registers.accumulator = 0x3f;
+        registers.index_x = 0x02;
+        registers.status.negative = true;
+        cpu
+    }
+}
@@ -0,0 +1,222 @@
+use crate::cpu::{register::Registers, AccessKind, MemoryAccess, StepResult};
+use serde_json::{json, Value};
+use std::{fmt, io::Write};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Category {
+    // 実行した命令と、実行した後のレジスタ
+    Instruction,
+    Frame,
+    Scanline,
+    // 割り込みはまだ無いので今は出ない
+    Interrupt,
+    // PPU($2000〜$2007)とAPUとI/O($4000〜$4017)への書き込み
+    Io,
+}
+
+impl Category {
+    pub const ALL: [Category; 5] = [
+        Self::Instruction,
+        Self::Frame,
+        Self::Scanline,
+        Self::Interrupt,
+        Self::Io,
+    ];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.iter().copied().find(|c| c.name() == name)
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Instruction => "instruction",
+            Self::Frame => "frame",
+            Self::Scanline => "scanline",
+            Self::Interrupt => "interrupt",
+            Self::Io => "io",
+        }
+    }
+
+    // "instruction,frame" のようにカンマで区切る。"all"なら全部
+    pub fn parse_list(list: &str) -> Result<Vec<Self>, String> {
+        if list == "all" {
+            return Ok(Self::ALL.to_vec());
+        }
+        list.split(',')
+            .map(|name| {
+                Self::from_name(name.trim())
+                    .ok_or_else(|| format!("Unknown event category: {}.", name.trim()))
+            })
+            .collect()
+    }
+}
+
+// ツールで読むための、1行に1つのJSONのイベント。どれも"event"にカテゴリの名前が入る
+pub struct EventStream {
+    out: Box<dyn Write + Send>,
+    categories: Vec<Category>,
+}
+
+impl EventStream {
+    pub fn new<W: Write + Send + 'static>(out: W, categories: &[Category]) -> Self {
+        Self {
+            out: Box::new(out),
+            categories: categories.to_vec(),
+        }
+    }
+
+    pub fn enabled(&self, category: Category) -> bool {
+        self.categories.contains(&category)
+    }
+
+    // 1命令実行した後に呼ぶ
+    pub fn instruction(&mut self, result: &StepResult, registers: &Registers, cycles: u64) {
+        if result.interrupt && self.enabled(Category::Interrupt) {
+            self.write(json!({
+                "event": "interrupt",
+                "pc": result.pc_before,
+                "cycles": cycles,
+            }));
+        }
+        if self.enabled(Category::Instruction) {
+            self.write(json!({
+                "event": "instruction",
+                "pc": result.pc_before,
+                "opcode": result.opcode,
+                "clock": result.cycles,
+                "a": registers.accumulator,
+                "x": registers.index_x,
+                "y": registers.index_y,
+                "p": u8::from(&registers.status),
+                "sp": registers.stack_pointer,
+                "cycles": cycles,
+            }));
+        }
+    }
+
+    // positionは命令を実行した後のスキャンラインとドット
+    pub fn io(&mut self, accesses: &[MemoryAccess], position: (u16, u16)) {
+        if !self.enabled(Category::Io) {
+            return;
+        }
+        for access in accesses.iter().filter(|a| a.kind == AccessKind::Write) {
+            if let 0x2000..=0x2007 | 0x4000..=0x4017 = access.addr {
+                self.write(json!({
+                    "event": "io",
+                    "addr": access.addr,
+                    "value": access.value,
+                    "scanline": position.0,
+                    "dot": position.1,
+                }));
+            }
+        }
+    }
+
+    pub fn frame(&mut self, number: u64, cycles: u64) {
+        if self.enabled(Category::Frame) {
+            self.write(json!({ "event": "frame", "frame": number, "cycles": cycles }));
+        }
+    }
+
+    pub fn scanline(&mut self, frame: u64, line: u16, cycles: u64) {
+        if self.enabled(Category::Scanline) {
+            self.write(json!({
+                "event": "scanline",
+                "frame": frame,
+                "line": line,
+                "cycles": cycles,
+            }));
+        }
+    }
+
+    fn write(&mut self, event: Value) {
+        writeln!(self.out, "{}", event).expect("Failed to write events.");
+    }
+}
+
+impl fmt::Debug for EventStream {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("EventStream")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Category, EventStream};
+    use crate::cpu::{register::Registers, AccessKind, MemoryAccess, StepResult};
+    use serde_json::Value;
+    use std::{
+        io,
+        sync::{Arc, Mutex},
+    };
+
+    #[test]
+    fn test_parse_list() {
+        assert_eq!(Category::parse_list("all").unwrap(), Category::ALL.to_vec());
+        assert_eq!(
+            Category::parse_list("frame, io").unwrap(),
+            vec![Category::Frame, Category::Io]
+        );
+        assert_eq!(
+            Category::parse_list("frame,bank").unwrap_err(),
+            "Unknown event category: bank."
+        );
+    }
+
+    #[test]
+    fn test_events() {
+        let out = Output::default();
+        let mut stream = EventStream::new(out.clone(), &[Category::Instruction, Category::Io]);
+        let result = StepResult {
+            cycles: 4,
+            opcode: 0x8d,
+            pc_before: 0x8000,
+            interrupt: false,
+        };
+        stream.instruction(&result, &Registers::default(), 11);
+        stream.io(
+            &[
+                MemoryAccess {
+                    addr: 0x8000,
+                    value: 0x8d,
+                    kind: AccessKind::Execute,
+                },
+                MemoryAccess {
+                    addr: 0x2006,
+                    value: 0x3f,
+                    kind: AccessKind::Write,
+                },
+            ],
+            (241, 33),
+        );
+        // 選んでいないカテゴリは出ない
+        stream.frame(1, 29781);
+
+        let text = String::from_utf8(out.0.lock().unwrap().clone()).unwrap();
+        let events: Vec<Value> = text
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0]["event"], "instruction");
+        assert_eq!(events[0]["pc"], 0x8000);
+        assert_eq!(events[0]["clock"], 4);
+        assert_eq!(events[0]["p"], 0x20);
+        assert_eq!(events[1]["event"], "io");
+        assert_eq!(events[1]["addr"], 0x2006);
+        assert_eq!(events[1]["scanline"], 241);
+    }
+
+    #[derive(Default, Clone)]
+    struct Output(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Output {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+}
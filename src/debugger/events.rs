@@ -0,0 +1,144 @@
+use crate::{
+    cpu::{AccessKind, MemoryAccess},
+    region::Region,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    // PPU($2000〜$2007)への書き込み
+    PpuWrite,
+    // APUとI/O($4000〜$4017)への書き込み
+    ApuWrite,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TimelineEvent {
+    pub kind: EventKind,
+    pub scanline: u16,
+    pub dot: u16,
+    pub addr: u16,
+    pub value: u8,
+}
+
+// フレームの中でいつレジスタに書き込んだかを記録する。FCEUXのイベントビューアのように
+// スキャンラインとドットの位置に並べて表示すると、ラスタースクロールの不具合を探しやすい
+#[derive(Debug, Default)]
+pub struct EventLog {
+    current: Vec<TimelineEvent>,
+    last_frame: Vec<TimelineEvent>,
+    frame: u64,
+}
+
+impl EventLog {
+    // 1命令実行した後に呼ぶ。positionは実行した後のスキャンラインとドット
+    pub fn record(
+        &mut self,
+        accesses: &[MemoryAccess],
+        region: Region,
+        before: (u16, u16),
+        position: (u16, u16),
+    ) {
+        // VBlankに入ったところでフレームを区切る
+        if before.0 != position.0 && position.0 == region.vblank_scanline() {
+            self.last_frame = std::mem::take(&mut self.current);
+            self.frame += 1;
+        }
+        for access in accesses.iter().filter(|a| a.kind == AccessKind::Write) {
+            let kind = match access.addr {
+                0x2000..=0x2007 => EventKind::PpuWrite,
+                0x4000..=0x4017 => EventKind::ApuWrite,
+                _ => continue,
+            };
+            self.current.push(TimelineEvent {
+                kind,
+                scanline: position.0,
+                dot: position.1,
+                addr: access.addr,
+                value: access.value,
+            });
+        }
+    }
+
+    // 最後に終わったフレームのイベント。起きた順に並んでいる
+    pub fn frame_events(&self) -> &[TimelineEvent] {
+        &self.last_frame
+    }
+
+    // 今のフレームでここまでに起きたイベント
+    pub fn current_events(&self) -> &[TimelineEvent] {
+        &self.current
+    }
+
+    // 終わったフレームの数
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{EventKind, EventLog, TimelineEvent};
+    use crate::{
+        cpu::{AccessKind, MemoryAccess},
+        region::Region,
+    };
+
+    #[test]
+    fn test_record() {
+        let mut log = EventLog::default();
+        log.record(
+            &[
+                access(0x2000, AccessKind::Write),
+                access(0x2002, AccessKind::Read),
+                access(0x0300, AccessKind::Write),
+            ],
+            Region::Ntsc,
+            (10, 0),
+            (10, 21),
+        );
+        log.record(
+            &[access(0x4014, AccessKind::Write)],
+            Region::Ntsc,
+            (10, 21),
+            (11, 1),
+        );
+        assert_eq!(
+            log.current_events(),
+            &[
+                TimelineEvent {
+                    kind: EventKind::PpuWrite,
+                    scanline: 10,
+                    dot: 21,
+                    addr: 0x2000,
+                    value: 0x80,
+                },
+                TimelineEvent {
+                    kind: EventKind::ApuWrite,
+                    scanline: 11,
+                    dot: 1,
+                    addr: 0x4014,
+                    value: 0x80,
+                },
+            ]
+        );
+        assert!(log.frame_events().is_empty());
+
+        // DendyではまだVBlankではない
+        log.record(&[], Region::Dendy, (240, 339), (241, 8));
+        assert_eq!(log.frame(), 0);
+        let write = [access(0x2001, AccessKind::Write)];
+        log.record(&write, Region::Ntsc, (240, 339), (241, 8));
+        assert_eq!(log.frame(), 1);
+        assert_eq!(log.frame_events().len(), 2);
+        assert_eq!(log.current_events()[0].addr, 0x2001);
+        assert_eq!(log.current_events().len(), 1);
+    }
+
+    fn access(addr: u16, kind: AccessKind) -> MemoryAccess {
+        MemoryAccess {
+            addr,
+            value: 0x80,
+            kind,
+        }
+    }
+}
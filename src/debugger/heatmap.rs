@@ -0,0 +1,139 @@
+use crate::{
+    cpu::{AccessKind, MemoryAccess},
+    png,
+};
+use std::io::{self, Write};
+
+const ADDRESS_COUNT: usize = 0x10000;
+// PNGは1ドットが1アドレスで、1行が256バイト
+const IMAGE_SIZE: u32 = 256;
+
+// CPUのアドレスごとに読み込み、書き込み、実行(opcodeとオペランドの読み込み)の回数を数える
+#[derive(Debug, Clone)]
+pub struct Heatmap {
+    reads: Vec<u32>,
+    writes: Vec<u32>,
+    executes: Vec<u32>,
+}
+
+impl Heatmap {
+    pub fn new() -> Self {
+        Self {
+            reads: vec![0; ADDRESS_COUNT],
+            writes: vec![0; ADDRESS_COUNT],
+            executes: vec![0; ADDRESS_COUNT],
+        }
+    }
+
+    pub fn record(&mut self, accesses: &[MemoryAccess]) {
+        for access in accesses {
+            let counts = match access.kind {
+                AccessKind::Read => &mut self.reads,
+                AccessKind::Write => &mut self.writes,
+                AccessKind::Execute => &mut self.executes,
+            };
+            let count = &mut counts[access.addr as usize];
+            *count = count.saturating_add(1);
+        }
+    }
+
+    // (読み込み, 書き込み, 実行)
+    pub fn counts(&self, addr: u16) -> (u32, u32, u32) {
+        let i = addr as usize;
+        (self.reads[i], self.writes[i], self.executes[i])
+    }
+
+    // 1回でも触ったアドレスだけを書き出す
+    pub fn write_csv<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        writeln!(out, "address,reads,writes,executes")?;
+        for addr in 0..=0xffff {
+            let (reads, writes, executes) = self.counts(addr);
+            if reads > 0 || writes > 0 || executes > 0 {
+                writeln!(out, "{:04X},{},{},{}", addr, reads, writes, executes)?;
+            }
+        }
+        Ok(())
+    }
+
+    // 赤が書き込み、緑が読み込み、青が実行。回数が多いほど明るい(対数)
+    pub fn to_png(&self) -> Vec<u8> {
+        let reads = brightness(&self.reads);
+        let writes = brightness(&self.writes);
+        let executes = brightness(&self.executes);
+        let mut rgba = Vec::with_capacity(ADDRESS_COUNT * 4);
+        for i in 0..ADDRESS_COUNT {
+            rgba.extend_from_slice(&[writes[i], reads[i], executes[i], 0xff]);
+        }
+        png::encode_rgba(IMAGE_SIZE, IMAGE_SIZE, &rgba)
+    }
+}
+
+impl Default for Heatmap {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// いちばん多いものを255にする
+fn brightness(counts: &[u32]) -> Vec<u8> {
+    let max = counts.iter().copied().max().unwrap_or(0);
+    if max == 0 {
+        return vec![0; counts.len()];
+    }
+    let scale = 255.0 / (max as f64).ln_1p();
+    counts
+        .iter()
+        .map(|&n| ((n as f64).ln_1p() * scale).round() as u8)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::Heatmap;
+    use crate::cpu::{AccessKind, MemoryAccess};
+
+    #[test]
+    fn test_record() {
+        let mut heatmap = Heatmap::new();
+        let access = |addr, kind| MemoryAccess {
+            addr,
+            value: 0,
+            kind,
+        };
+        // LDA $0010 を2回、STA $0200 を1回
+        for _ in 0..2 {
+            heatmap.record(&[
+                access(0x8000, AccessKind::Execute),
+                access(0x8001, AccessKind::Execute),
+                access(0x0010, AccessKind::Read),
+            ]);
+        }
+        heatmap.record(&[access(0x0200, AccessKind::Write)]);
+        assert_eq!(heatmap.counts(0x8000), (0, 0, 2));
+        assert_eq!(heatmap.counts(0x0010), (2, 0, 0));
+        assert_eq!(heatmap.counts(0x0200), (0, 1, 0));
+
+        let mut csv = Vec::new();
+        heatmap.write_csv(&mut csv).unwrap();
+        assert_eq!(
+            String::from_utf8(csv).unwrap(),
+            "address,reads,writes,executes\n\
+             0010,2,0,0\n\
+             0200,0,1,0\n\
+             8000,0,0,2\n\
+             8001,0,0,2\n"
+        );
+    }
+
+    #[test]
+    fn test_to_png() {
+        let mut heatmap = Heatmap::new();
+        heatmap.record(&[MemoryAccess {
+            addr: 0x0001,
+            value: 0,
+            kind: AccessKind::Write,
+        }]);
+        let png = heatmap.to_png();
+        assert_eq!(&png[16..24], &[0, 0, 1, 0, 0, 0, 1, 0]);
+    }
+}
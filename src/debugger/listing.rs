@@ -0,0 +1,173 @@
+use super::{cdl, cdl::CodeDataLogger, symbols::SymbolTable};
+use crate::{cpu::disassembler::disassemble, rom::Rom};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    ops::RangeInclusive,
+};
+
+const VECTORS: [(&str, u16); 3] = [("NMI", 0xfffa), ("RESET", 0xfffc), ("IRQ", 0xfffe)];
+const BRANCHES: [&str; 8] = ["BCC", "BCS", "BEQ", "BMI", "BNE", "BPL", "BVC", "BVS"];
+// 1行の.dbに並べるバイト数
+const DATA_PER_LINE: usize = 8;
+
+// PRG ROMの逆アセンブル結果。ベクタからたどれる命令とCDLでコードとされたところを命令にして、
+// それ以外は.dbにする
+pub fn listing(
+    rom: &Rom,
+    range: RangeInclusive<u16>,
+    cdl: Option<&CodeDataLogger>,
+    symbols: Option<&SymbolTable>,
+) -> String {
+    let read = |addr: u16| match addr {
+        0x8000..=0xffff if !rom.program.is_empty() => {
+            rom.program[(addr - 0x8000) as usize % rom.program.len()]
+        }
+        _ => 0,
+    };
+    let mut lines = Vec::new();
+    let mut entries = BTreeMap::new();
+    for (name, vector) in VECTORS.iter() {
+        let addr = read(*vector) as u16 | (read(vector + 1) as u16) << 8;
+        lines.push(format!("; {} vector: ${:04X}", name, addr));
+        entries.entry(addr).or_insert_with(Vec::new).push(*name);
+    }
+    let code = find_code(entries.keys().copied(), cdl, read);
+
+    let mut addr = *range.start() as u32;
+    while addr <= *range.end() as u32 {
+        let a = addr as u16;
+        for name in entries.get(&a).into_iter().flatten() {
+            lines.push(format!("{}:", name));
+        }
+        if let Some(label) = symbols.and_then(|s| s.label(a)) {
+            lines.push(format!("{}:", label));
+        }
+        if code.contains(&a) {
+            let d = disassemble(a, read);
+            let bytes: Vec<String> = d.bytes.iter().map(|b| format!("{:02X}", b)).collect();
+            let assembly = match symbols {
+                Some(symbols) => d.to_string_with_labels(|addr| symbols.label(addr)),
+                None => d.to_string(),
+            };
+            lines.push(format!("{:04X}  {:<8}  {}", a, bytes.join(" "), assembly));
+            addr += d.bytes.len() as u32;
+            continue;
+        }
+        // 次の命令かラベルの手前までを.dbにまとめる
+        let mut bytes = Vec::new();
+        while addr <= *range.end() as u32 && bytes.len() < DATA_PER_LINE {
+            let b = addr as u16;
+            let labeled = entries.contains_key(&b) || symbols.and_then(|s| s.label(b)).is_some();
+            if !bytes.is_empty() && (code.contains(&b) || labeled) {
+                break;
+            }
+            bytes.push(format!("${:02X}", read(b)));
+            addr += 1;
+        }
+        lines.push(format!("{:04X}  .db {}", a, bytes.join(",")));
+    }
+    lines.join("\n") + "\n"
+}
+
+// 命令の先頭のアドレス。エントリポイントから分岐とジャンプをたどり、CDLで実行されたところも足す
+fn find_code<I, F>(entries: I, cdl: Option<&CodeDataLogger>, mut read: F) -> BTreeSet<u16>
+where
+    I: Iterator<Item = u16>,
+    F: FnMut(u16) -> u8,
+{
+    let mut starts = BTreeSet::new();
+    let mut pending: Vec<u16> = entries.collect();
+    if let Some(cdl) = cdl {
+        pending.extend(cdl_code_starts(cdl));
+    }
+    while let Some(addr) = pending.pop() {
+        if addr < 0x8000 || starts.contains(&addr) {
+            continue;
+        }
+        let d = disassemble(addr, &mut read);
+        if d.mnemonic == ".db" {
+            continue;
+        }
+        starts.insert(addr);
+        let next = d.next_address();
+        match d.mnemonic.as_str() {
+            "RTS" | "RTI" | "BRK" => {}
+            // 間接ジャンプの先は分からない
+            "JMP" => pending.extend(d.target),
+            "JSR" => pending.extend(d.target.into_iter().chain(Some(next))),
+            m if BRANCHES.contains(&m) => pending.extend(d.target.into_iter().chain(Some(next))),
+            _ => pending.push(next),
+        }
+    }
+    starts
+}
+
+// CDLで実行されたとされる命令の先頭。フラグはバイトごとなので、
+// 直前のバイトがコードでないところを先頭とみなす
+fn cdl_code_starts(cdl: &CodeDataLogger) -> Vec<u16> {
+    let prg = cdl.prg();
+    (0..prg.len())
+        .filter(|&i| prg[i] & cdl::CODE != 0 && (i == 0 || prg[i - 1] & cdl::CODE == 0))
+        .filter(|&i| i < 0x8000)
+        .map(|i| (0x8000 + i) as u16)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::listing;
+    use crate::{
+        cpu::{AccessKind, MemoryAccess},
+        debugger::{cdl::CodeDataLogger, symbols::SymbolTable},
+        rom::Rom,
+    };
+
+    #[test]
+    fn test_listing() {
+        let rom = prepare(&[0x78, 0x4c, 0x00, 0x80, 0x12, 0x34, 0x60]);
+        assert_eq!(
+            listing(&rom, 0x8000..=0x8007, None, None),
+            "; NMI vector: $8006\n\
+             ; RESET vector: $8000\n\
+             ; IRQ vector: $8006\n\
+             RESET:\n\
+             8000  78        SEI\n\
+             8001  4C 00 80  JMP $8000\n\
+             8004  .db $12,$34\n\
+             NMI:\n\
+             IRQ:\n\
+             8006  60        RTS\n\
+             8007  .db $00\n"
+        );
+    }
+
+    #[test]
+    fn test_listing_with_cdl_and_symbols() {
+        let rom = prepare(&[0x60, 0xa9, 0x01, 0x60]);
+        let mut cdl = CodeDataLogger::new(0x8000, 0);
+        let access = |addr, kind| MemoryAccess {
+            addr,
+            value: 0,
+            kind,
+        };
+        cdl.log(&[
+            access(0x8001, AccessKind::Execute),
+            access(0x8002, AccessKind::Execute),
+        ]);
+        let mut symbols = SymbolTable::default();
+        symbols.insert(0x8001, "load_one");
+        let text = listing(&rom, 0x8000..=0x8003, Some(&cdl), Some(&symbols));
+        assert!(text.contains("load_one:\n8001  A9 01     LDA #$01\n8003  60        RTS\n"));
+    }
+
+    // ベクタはすべて$8006を指す。RESETだけは$8000
+    fn prepare(program: &[u8]) -> Rom {
+        let mut data = vec![0x4e, 0x45, 0x53, 0x1a, 0x02, 0x00];
+        data.resize(16, 0);
+        let mut prg = vec![0; 0x8000];
+        prg[..program.len()].copy_from_slice(program);
+        prg[0x7ffa..].copy_from_slice(&[0x06, 0x80, 0x00, 0x80, 0x06, 0x80]);
+        data.extend(prg);
+        Rom::from_bytes(&data).unwrap()
+    }
+}
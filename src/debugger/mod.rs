@@ -0,0 +1,368 @@
+pub mod cdl;
+pub mod condition;
+pub mod event_stream;
+pub mod events;
+pub mod heatmap;
+pub mod listing;
+pub mod profiler;
+pub mod repl;
+pub mod symbols;
+pub mod uninit;
+
+pub use self::condition::Condition;
+use self::{symbols::SymbolTable, uninit::UninitializedRead};
+use crate::cpu::{AccessKind, Cpu, CpuError, MemoryAccess};
+use std::{collections::BTreeMap, ops::RangeInclusive};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BreakReason {
+    // このアドレスの命令を実行する直前で止まった
+    Breakpoint(u16),
+    // 直前の命令がウォッチしているアドレスにアクセスした
+    Watchpoint(MemoryAccess),
+    // 直前の命令が書き込む前のWRAMを読んだ
+    UninitializedRead(UninitializedRead),
+    // 条件式が成り立った。値は何番目の条件か
+    Condition(usize),
+    // ステップ実行が最後まで終わった
+    Step,
+    // 命令を実行できなかった
+    Error(CpuError),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    ReadWrite,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Watchpoint {
+    pub range: RangeInclusive<u16>,
+    pub kind: WatchKind,
+}
+
+impl Watchpoint {
+    fn matches(&self, access: &MemoryAccess) -> bool {
+        let kind = match (self.kind, access.kind) {
+            (_, AccessKind::Execute) => false,
+            (WatchKind::Read, AccessKind::Read) => true,
+            (WatchKind::Write, AccessKind::Write) => true,
+            (WatchKind::ReadWrite, _) => true,
+            _ => false,
+        };
+        kind && self.range.contains(&access.addr)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Debugger {
+    // 条件付きブレークポイントは条件が成り立つときだけ止まる
+    breakpoints: BTreeMap<u16, Option<Condition>>,
+    watchpoints: Vec<Watchpoint>,
+    conditions: Vec<Condition>,
+    symbols: SymbolTable,
+}
+
+impl Debugger {
+    pub fn add_breakpoint(&mut self, addr: u16) {
+        self.breakpoints.insert(addr, None);
+    }
+
+    pub fn add_conditional_breakpoint(&mut self, addr: u16, condition: Condition) {
+        self.breakpoints.insert(addr, Some(condition));
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u16) -> bool {
+        self.breakpoints.remove(&addr).is_some()
+    }
+
+    pub fn breakpoints(&self) -> impl Iterator<Item = (u16, Option<&Condition>)> + '_ {
+        self.breakpoints
+            .iter()
+            .map(|(addr, condition)| (*addr, condition.as_ref()))
+    }
+
+    pub fn add_watchpoint(&mut self, range: RangeInclusive<u16>, kind: WatchKind) {
+        self.watchpoints.push(Watchpoint { range, kind });
+    }
+
+    pub fn remove_watchpoint(&mut self, index: usize) -> Option<Watchpoint> {
+        if index < self.watchpoints.len() {
+            Some(self.watchpoints.remove(index))
+        } else {
+            None
+        }
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    // アドレスに関係なく、命令を実行するたびに評価する条件
+    pub fn add_condition(&mut self, condition: Condition) {
+        self.conditions.push(condition);
+    }
+
+    pub fn remove_condition(&mut self, index: usize) -> Option<Condition> {
+        if index < self.conditions.len() {
+            Some(self.conditions.remove(index))
+        } else {
+            None
+        }
+    }
+
+    pub fn conditions(&self) -> &[Condition] {
+        &self.conditions
+    }
+
+    pub fn set_symbols(&mut self, symbols: SymbolTable) {
+        self.symbols = symbols;
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        &self.symbols
+    }
+
+    pub fn clear(&mut self) {
+        self.breakpoints.clear();
+        self.watchpoints.clear();
+        self.conditions.clear();
+    }
+
+    // 1命令実行した後に呼ぶ。PCは次に実行する命令のアドレスになっている
+    pub fn check(&self, cpu: &Cpu) -> Option<BreakReason> {
+        for access in cpu.accesses() {
+            if self.watchpoints.iter().any(|w| w.matches(access)) {
+                return Some(BreakReason::Watchpoint(*access));
+            }
+        }
+        let pc = cpu.registers().program_counter;
+        match self.breakpoints.get(&pc) {
+            Some(None) => return Some(BreakReason::Breakpoint(pc)),
+            Some(Some(condition)) if condition.evaluate(cpu) => {
+                return Some(BreakReason::Breakpoint(pc))
+            }
+            _ => {}
+        }
+        self.conditions
+            .iter()
+            .position(|c| c.evaluate(cpu))
+            .map(BreakReason::Condition)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{uninit::UninitializedRead, BreakReason, Condition, WatchKind};
+    use crate::{
+        cpu::{AccessKind, MemoryAccess},
+        nes::Nes,
+        rom::{Rom, RomHeader},
+    };
+    use std::{fs::File, io::BufReader};
+
+    #[test]
+    fn test_breakpoint() {
+        let mut nes = prepare();
+        nes.debugger().add_breakpoint(0x8004);
+
+        assert_eq!(nes.run_until_break(), BreakReason::Breakpoint(0x8004));
+        assert_eq!(nes.registers().program_counter, 0x8004);
+
+        // 止まったアドレスからもう一度実行しても同じところで止まり続けない
+        nes.debugger().add_breakpoint(0x8009);
+        assert_eq!(nes.run_until_break(), BreakReason::Breakpoint(0x8009));
+
+        assert!(nes.debugger().remove_breakpoint(0x8004));
+        assert!(!nes.debugger().remove_breakpoint(0x8004));
+    }
+
+    #[test]
+    fn test_conditional_breakpoint() {
+        let mut nes = prepare_subroutine();
+        // 0x8010 に来たときはXが0なので止まらない
+        let debugger = nes.debugger();
+        debugger.add_conditional_breakpoint(0x8010, Condition::parse("X != 0").unwrap());
+        debugger.add_conditional_breakpoint(0x8021, Condition::parse("X == 1").unwrap());
+        assert_eq!(nes.run_until_break(), BreakReason::Breakpoint(0x8021));
+        assert_eq!(nes.registers().index_x, 0x01);
+    }
+
+    #[test]
+    fn test_condition() {
+        let mut nes = prepare_subroutine();
+        nes.debugger()
+            .add_condition(Condition::parse("X >= 2 && SP < 0xff").unwrap());
+        assert_eq!(nes.run_until_break(), BreakReason::Condition(0));
+        assert_eq!(nes.registers().program_counter, 0x8014);
+        assert_eq!(nes.registers().index_x, 0x02);
+    }
+
+    #[test]
+    fn test_write_watchpoint() {
+        let mut nes = prepare();
+        nes.debugger()
+            .add_watchpoint(0x2000..=0x2007, WatchKind::Write);
+
+        let reason = nes.run_until_break();
+        assert_eq!(
+            reason,
+            BreakReason::Watchpoint(MemoryAccess {
+                addr: 0x2000,
+                value: 0x00,
+                kind: AccessKind::Write,
+            })
+        );
+        // STA $2000 の次で止まる
+        assert_eq!(nes.registers().program_counter, 0x8009);
+    }
+
+    #[test]
+    fn test_uninitialized_read() {
+        let mut nes = prepare();
+        nes.set_uninit_detection(true);
+        // $0300: STA $0020 / LDA $0020,X / LDA $0010,X / JMP $0300
+        let program = [
+            0x8d, 0x20, 0x00, 0xbd, 0x20, 0x00, 0xbd, 0x10, 0x00, 0x4c, 0x00, 0x03,
+        ];
+        for (i, b) in program.iter().enumerate() {
+            nes.poke(0x0300 + i as u16, *b);
+        }
+        let mut registers = nes.registers().clone();
+        registers.program_counter = 0x0300;
+        nes.set_registers(registers);
+
+        let reason = nes.run_until_break();
+        assert_eq!(
+            reason,
+            BreakReason::UninitializedRead(UninitializedRead {
+                pc: 0x0306,
+                addr: 0x0010,
+                value: 0x00,
+            })
+        );
+        // 2回目は止まらない
+        nes.debugger().add_breakpoint(0x0306);
+        assert_eq!(nes.run_until_break(), BreakReason::Breakpoint(0x0306));
+        assert_eq!(nes.step_into(), BreakReason::Step);
+    }
+
+    #[test]
+    fn test_uninitialized_reads_in_one_instruction() {
+        let mut nes = prepare();
+        // $0300: LDA $0010,X。書き込んでから検出を始めるので、命令のバイトも書き込む前になる
+        for (i, b) in [0xbd, 0x10, 0x00].iter().enumerate() {
+            nes.poke(0x0300 + i as u16, *b);
+        }
+        nes.set_uninit_detection(true);
+        let mut registers = nes.registers().clone();
+        registers.program_counter = 0x0300;
+        nes.set_registers(registers);
+
+        let first = UninitializedRead {
+            pc: 0x0300,
+            addr: 0x0300,
+            value: 0xbd,
+        };
+        assert_eq!(nes.step_into(), BreakReason::UninitializedRead(first));
+        // 止まるのは最初の1つでも、残りも取り出せる
+        let reads = nes.take_uninitialized_reads();
+        assert_eq!(reads.len(), 4);
+        assert_eq!(reads[0], first);
+        assert_eq!(reads[3].addr, 0x0010);
+    }
+
+    #[test]
+    fn test_read_watchpoint() {
+        let mut nes = prepare();
+        // 命令の読み込みではなくパレットのデータを読んだときだけ止まる
+        nes.debugger()
+            .add_watchpoint(0x8000..=0xffff, WatchKind::Read);
+
+        let reason = nes.run_until_break();
+        assert_eq!(
+            reason,
+            BreakReason::Watchpoint(MemoryAccess {
+                addr: 0x8051,
+                value: 0x0f,
+                kind: AccessKind::Read,
+            })
+        );
+    }
+
+    #[test]
+    fn test_step() {
+        let mut nes = prepare();
+        nes.debugger().add_breakpoint(0x8001);
+        assert_eq!(nes.step_into(), BreakReason::Step);
+        assert_eq!(nes.registers().program_counter, 0x8001);
+        assert_eq!(nes.step_into(), BreakReason::Step);
+        assert_eq!(nes.registers().program_counter, 0x8003);
+    }
+
+    #[test]
+    fn test_step_over() {
+        let mut nes = prepare_subroutine();
+        // JSRはサブルーチンから戻ってくるまで実行する
+        assert_eq!(nes.step_over(), BreakReason::Step);
+        assert_eq!(nes.registers().program_counter, 0x8003);
+        assert_eq!(nes.registers().index_x, 0x02);
+
+        // JSR以外は1命令だけ
+        assert_eq!(nes.step_over(), BreakReason::Step);
+        assert_eq!(nes.registers().program_counter, 0x8004);
+    }
+
+    #[test]
+    fn test_step_over_breakpoint() {
+        let mut nes = prepare_subroutine();
+        nes.debugger().add_breakpoint(0x8020);
+        assert_eq!(nes.step_over(), BreakReason::Breakpoint(0x8020));
+    }
+
+    #[test]
+    fn test_step_out() {
+        let mut nes = prepare_subroutine();
+        nes.step_into();
+        assert_eq!(nes.registers().program_counter, 0x8010);
+
+        // 中でさらにサブルーチンを呼んでいても今のサブルーチンから抜けるまで実行する
+        assert_eq!(nes.step_out(), BreakReason::Step);
+        assert_eq!(nes.registers().program_counter, 0x8003);
+        assert_eq!(nes.registers().index_x, 0x02);
+    }
+
+    fn prepare_subroutine() -> Nes {
+        let mut program = vec![0; 0x8000];
+        // 0x8000: JSR $8010, INX, INX
+        program[0x0000..0x0005].copy_from_slice(&[0x20, 0x10, 0x80, 0xe8, 0xe8]);
+        // 0x8010: JSR $8020, INX, RTS
+        program[0x0010..0x0015].copy_from_slice(&[0x20, 0x20, 0x80, 0xe8, 0x60]);
+        // 0x8020: INX, RTS
+        program[0x0020..0x0022].copy_from_slice(&[0xe8, 0x60]);
+        program[0x7ffc] = 0x00;
+        program[0x7ffd] = 0x80;
+
+        let mut nes = Nes::new();
+        nes.set_rom(Rom {
+            header: RomHeader::default(),
+            trainer: None,
+            program: program.into(),
+            character: Default::default(),
+            hint_screen: None,
+        })
+        .unwrap();
+        nes.reset().unwrap();
+        nes
+    }
+
+    fn prepare() -> Nes {
+        let mut reader = BufReader::new(File::open("./tests/rom/hello_world.nes").unwrap());
+        let mut nes = Nes::new();
+        nes.set_rom(Rom::load(&mut reader).unwrap()).unwrap();
+        nes.reset().unwrap();
+        nes
+    }
+}
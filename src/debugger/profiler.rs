@@ -0,0 +1,142 @@
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProfileEntry {
+    // 命令のアドレスか、サブルーチンの先頭アドレス。サブルーチンの外ならNone
+    pub address: Option<u16>,
+    pub cycles: u64,
+    // 実行した命令の数
+    pub instructions: u64,
+}
+
+// 命令ごとにかかったクロック数を、PCごととサブルーチンごとに集計する。
+// サブルーチンの集計には、そこから呼んだ別のサブルーチンの分は含めない
+#[derive(Debug, Default)]
+pub struct Profiler {
+    by_address: HashMap<u16, (u64, u64)>,
+    by_routine: HashMap<Option<u16>, (u64, u64)>,
+    total_cycles: u64,
+}
+
+impl Profiler {
+    // routineは命令を実行する直前にいたサブルーチンの先頭
+    pub fn record(&mut self, pc: u16, routine: Option<u16>, cycles: u64) {
+        for (c, n) in [
+            self.by_address.entry(pc).or_default(),
+            self.by_routine.entry(routine).or_default(),
+        ] {
+            *c += cycles;
+            *n += 1;
+        }
+        self.total_cycles += cycles;
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.total_cycles
+    }
+
+    // クロック数が多い順
+    pub fn hot_addresses(&self) -> Vec<ProfileEntry> {
+        sorted(
+            self.by_address
+                .iter()
+                .map(|(addr, counts)| (Some(*addr), *counts)),
+        )
+    }
+
+    // クロック数が多い順
+    pub fn hot_routines(&self) -> Vec<ProfileEntry> {
+        sorted(
+            self.by_routine
+                .iter()
+                .map(|(addr, counts)| (*addr, *counts)),
+        )
+    }
+
+    // 上位limit個のサブルーチンを表にする
+    pub fn report(&self, limit: usize) -> String {
+        let mut lines = vec!["routine     cycles      %  instructions".to_string()];
+        for entry in self.hot_routines().iter().take(limit) {
+            let name = match entry.address {
+                Some(addr) => format!("${:04X}", addr),
+                None => "(top)".to_string(),
+            };
+            let percent = entry.cycles as f64 * 100.0 / self.total_cycles.max(1) as f64;
+            lines.push(format!(
+                "{:<7} {:>10} {:>6.2} {:>13}",
+                name, entry.cycles, percent, entry.instructions
+            ));
+        }
+        lines.join("\n")
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+fn sorted(entries: impl Iterator<Item = (Option<u16>, (u64, u64))>) -> Vec<ProfileEntry> {
+    let mut entries: Vec<ProfileEntry> = entries
+        .map(|(address, (cycles, instructions))| ProfileEntry {
+            address,
+            cycles,
+            instructions,
+        })
+        .collect();
+    // 同じクロック数ならアドレス順にして結果を安定させる
+    entries.sort_by(|a, b| b.cycles.cmp(&a.cycles).then(a.address.cmp(&b.address)));
+    entries
+}
+
+#[cfg(test)]
+mod test {
+    use super::{ProfileEntry, Profiler};
+
+    #[test]
+    fn test_record() {
+        let mut profiler = Profiler::default();
+        profiler.record(0x8000, None, 6);
+        profiler.record(0x8010, Some(0x8010), 2);
+        profiler.record(0x8011, Some(0x8010), 6);
+        profiler.record(0x8010, Some(0x8010), 2);
+
+        assert_eq!(profiler.total_cycles(), 16);
+        assert_eq!(
+            profiler.hot_routines(),
+            vec![
+                ProfileEntry {
+                    address: Some(0x8010),
+                    cycles: 10,
+                    instructions: 3,
+                },
+                ProfileEntry {
+                    address: None,
+                    cycles: 6,
+                    instructions: 1,
+                },
+            ]
+        );
+        assert_eq!(
+            profiler.hot_addresses()[..2],
+            [
+                ProfileEntry {
+                    address: Some(0x8000),
+                    cycles: 6,
+                    instructions: 1,
+                },
+                ProfileEntry {
+                    address: Some(0x8011),
+                    cycles: 6,
+                    instructions: 1,
+                },
+            ]
+        );
+        assert_eq!(
+            profiler.report(1),
+            "routine     cycles      %  instructions\n$8010           10  62.50             3"
+        );
+
+        profiler.clear();
+        assert!(profiler.hot_routines().is_empty());
+    }
+}
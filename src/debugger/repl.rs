@@ -0,0 +1,460 @@
+use super::{BreakReason, Condition, WatchKind};
+use crate::{
+    cpu::{disassembler::disassemble_range, AccessKind},
+    nes::{MemorySpace, Nes},
+};
+use std::{error::Error, result::Result};
+
+const DEFAULT_MEMORY_LEN: u16 = 0x40;
+const DEFAULT_DISASSEMBLE_COUNT: usize = 10;
+const DEFAULT_PROFILE_LIMIT: usize = 20;
+
+pub const HELP: &str = "\
+step [N]                   execute N instructions (s)
+next                       step over JSR (n)
+finish                     run until the current subroutine returns
+continue                   run until a break (c)
+bp [ADDR [COND]]           add a breakpoint, or list them
+del ADDR                   delete a breakpoint
+watch [ADDR[-END] [r|w|rw]]  add a watchpoint, or list them
+unwatch N                  delete the Nth watchpoint
+break [COND]               break when COND holds, or list conditions
+mem ADDR [LEN]             dump memory (x)
+dis [ADDR] [COUNT]         disassemble from ADDR or PC (d)
+regs                       show registers (r)
+bt                         show the call stack
+profile on|off|report [N]  profile cycles per subroutine
+quit                       quit (q)";
+
+// デバッグモードで1行分のコマンドを実行した結果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Output {
+    Text(String),
+    Quit,
+}
+
+// 数値は16進数。$や0xを付けてもいい
+pub fn parse_addr(s: &str) -> Result<u16, Box<dyn Error>> {
+    let digits = s
+        .strip_prefix('$')
+        .or_else(|| s.strip_prefix("0x"))
+        .unwrap_or(s);
+    u16::from_str_radix(digits, 16).map_err(|_| format!("Invalid address: {}", s).into())
+}
+
+// ラベルの名前でもいい
+fn resolve(nes: &Nes, s: &str) -> Result<u16, Box<dyn Error>> {
+    match nes.symbols().address(s) {
+        Some(addr) => Ok(addr),
+        None => parse_addr(s),
+    }
+}
+
+fn parse_count(s: Option<&str>, default: usize) -> Result<usize, Box<dyn Error>> {
+    match s {
+        Some(s) => s
+            .parse()
+            .map_err(|_| format!("Invalid count: {}", s).into()),
+        None => Ok(default),
+    }
+}
+
+pub fn execute(nes: &mut Nes, line: &str) -> Result<Output, Box<dyn Error>> {
+    let mut args = line.split_whitespace();
+    let command = match args.next() {
+        Some(command) => command,
+        None => return Ok(Output::Text(String::new())),
+    };
+    let text = match command {
+        "step" | "s" => {
+            let count = parse_count(args.next(), 1)?;
+            let mut reason = BreakReason::Step;
+            for _ in 0..count {
+                reason = nes.step_into();
+                if reason != BreakReason::Step {
+                    break;
+                }
+            }
+            stopped(nes, reason)
+        }
+        "next" | "n" => {
+            let reason = nes.step_over();
+            stopped(nes, reason)
+        }
+        "finish" => {
+            let reason = nes.step_out();
+            stopped(nes, reason)
+        }
+        "continue" | "c" => {
+            let reason = nes.run_until_break();
+            stopped(nes, reason)
+        }
+        "bp" => match args.next() {
+            Some(addr) => {
+                let addr = resolve(nes, addr)?;
+                let condition: Vec<&str> = args.collect();
+                if condition.is_empty() {
+                    nes.debugger().add_breakpoint(addr);
+                } else {
+                    let condition = Condition::parse(&condition.join(" "))?;
+                    nes.debugger().add_conditional_breakpoint(addr, condition);
+                }
+                format!("Breakpoint at ${:04X}", addr)
+            }
+            None => {
+                let lines: Vec<String> = nes
+                    .debugger()
+                    .breakpoints()
+                    .map(|(addr, condition)| match condition {
+                        Some(condition) => format!("${:04X} if {}", addr, condition),
+                        None => format!("${:04X}", addr),
+                    })
+                    .collect();
+                lines.join("\n")
+            }
+        },
+        "del" => {
+            let addr = resolve(nes, args.next().ok_or("Missing address.")?)?;
+            if !nes.debugger().remove_breakpoint(addr) {
+                return Err(format!("No breakpoint at ${:04X}", addr).into());
+            }
+            format!("Deleted breakpoint at ${:04X}", addr)
+        }
+        "watch" => match args.next() {
+            Some(range) => {
+                let (start, end) = match range.split_once('-') {
+                    Some((start, end)) => (resolve(nes, start)?, resolve(nes, end)?),
+                    None => (resolve(nes, range)?, resolve(nes, range)?),
+                };
+                let kind = match args.next() {
+                    Some("r") => WatchKind::Read,
+                    Some("w") | None => WatchKind::Write,
+                    Some("rw") => WatchKind::ReadWrite,
+                    Some(kind) => return Err(format!("Unknown watch kind: {}", kind).into()),
+                };
+                nes.debugger().add_watchpoint(start..=end, kind);
+                format!("Watchpoint at ${:04X}-${:04X} ({:?})", start, end, kind)
+            }
+            None => {
+                let lines: Vec<String> = nes
+                    .debugger()
+                    .watchpoints()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, w)| {
+                        format!(
+                            "{}: ${:04X}-${:04X} ({:?})",
+                            i,
+                            w.range.start(),
+                            w.range.end(),
+                            w.kind
+                        )
+                    })
+                    .collect();
+                lines.join("\n")
+            }
+        },
+        "unwatch" => {
+            let index = parse_count(args.next(), 0)?;
+            nes.debugger()
+                .remove_watchpoint(index)
+                .ok_or_else(|| format!("No watchpoint: {}", index))?;
+            format!("Deleted watchpoint {}", index)
+        }
+        "break" => {
+            let condition: Vec<&str> = args.collect();
+            if condition.is_empty() {
+                let lines: Vec<String> = nes
+                    .debugger()
+                    .conditions()
+                    .iter()
+                    .enumerate()
+                    .map(|(i, c)| format!("{}: {}", i, c))
+                    .collect();
+                lines.join("\n")
+            } else {
+                let condition = Condition::parse(&condition.join(" "))?;
+                let text = format!("Break if {}", condition);
+                nes.debugger().add_condition(condition);
+                text
+            }
+        }
+        "mem" | "x" => {
+            let start = resolve(nes, args.next().ok_or("Missing address.")?)?;
+            let len = match args.next() {
+                Some(len) => parse_addr(len)?.max(1),
+                None => DEFAULT_MEMORY_LEN,
+            };
+            let end = start.saturating_add(len - 1);
+            nes.hexdump(MemorySpace::Cpu, start..=end)
+        }
+        "dis" | "d" => {
+            let start = match args.next() {
+                Some(addr) => resolve(nes, addr)?,
+                None => nes.registers().program_counter,
+            };
+            let count = parse_count(args.next(), DEFAULT_DISASSEMBLE_COUNT)?;
+            disassemble_lines(nes, start, count)
+        }
+        "regs" | "r" => registers(nes),
+        "bt" => backtrace(nes),
+        "profile" => match args.next() {
+            Some("on") => {
+                nes.set_profiling(true);
+                "Profiling started".to_string()
+            }
+            Some("off") => {
+                nes.set_profiling(false);
+                "Profiling stopped".to_string()
+            }
+            Some("report") => {
+                let limit = parse_count(args.next(), DEFAULT_PROFILE_LIMIT)?;
+                nes.profiler().ok_or("Profiling is off.")?.report(limit)
+            }
+            _ => return Err("usage: profile on|off|report [N]".into()),
+        },
+        "help" | "h" => HELP.to_string(),
+        "quit" | "q" => return Ok(Output::Quit),
+        _ => return Err(format!("Unknown command: {}", command).into()),
+    };
+    Ok(Output::Text(text))
+}
+
+fn disassemble_lines(nes: &Nes, start: u16, count: usize) -> String {
+    // 1命令は最大3バイトなので、count命令分が必ず入る範囲を逆アセンブルしてから切る
+    let end = start.saturating_add((count * 3) as u16);
+    let symbols = nes.symbols();
+    let mut lines = Vec::new();
+    for d in disassemble_range(start, end, |addr| nes.peek(addr).unwrap_or(0))
+        .iter()
+        .take(count)
+    {
+        if let Some(label) = symbols.label(d.address) {
+            lines.push(format!("{}:", label));
+        }
+        let assembly = d.to_string_with_labels(|addr| symbols.label(addr));
+        lines.push(format!("{:04X}  {}", d.address, assembly));
+    }
+    lines.join("\n")
+}
+
+// ラベルがあれば付けたアドレス
+fn describe(nes: &Nes, addr: u16) -> String {
+    match nes.symbols().label(addr) {
+        Some(label) => format!("${:04X} <{}>", addr, label),
+        None => format!("${:04X}", addr),
+    }
+}
+
+fn registers(nes: &Nes) -> String {
+    let registers = nes.registers();
+    format!(
+        "PC:{:04X} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+        registers.program_counter,
+        registers.accumulator,
+        registers.index_x,
+        registers.index_y,
+        u8::from(&registers.status),
+        registers.stack_pointer,
+        nes.cycles(),
+    )
+}
+
+// 止まった理由と、次に実行する命令
+fn stopped(nes: &mut Nes, reason: BreakReason) -> String {
+    let reason = match reason {
+        BreakReason::Breakpoint(addr) => format!("Breakpoint at {}", describe(nes, addr)),
+        BreakReason::Watchpoint(access) => {
+            let kind = match access.kind {
+                AccessKind::Read => "Read",
+                _ => "Write",
+            };
+            format!("{} ${:02X} at ${:04X}", kind, access.value, access.addr)
+        }
+        BreakReason::UninitializedRead(read) => read.to_string(),
+        BreakReason::Condition(index) => format!("Condition {} hit", index),
+        BreakReason::Step => String::new(),
+        BreakReason::Error(err) => format!("Stopped: {}", err),
+    };
+    let current = format!(
+        "{}\n{}",
+        registers(nes),
+        disassemble_lines(nes, nes.registers().program_counter, 1)
+    );
+    let mut lines: Vec<String> = nes
+        .take_stack_warnings()
+        .iter()
+        .map(|w| format!("warning: {}", w))
+        .collect();
+    if !reason.is_empty() {
+        lines.push(reason);
+    }
+    lines.push(current);
+    lines.join("\n")
+}
+
+// 内側から順に、呼び出されたサブルーチンと呼び出したJSRのアドレス
+fn backtrace(nes: &Nes) -> String {
+    let lines: Vec<String> = nes
+        .call_stack()
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, frame)| {
+            format!(
+                "#{} {} called from {}",
+                i,
+                describe(nes, frame.target),
+                describe(nes, frame.call_site)
+            )
+        })
+        .collect();
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{execute, Output};
+    use crate::{
+        debugger::symbols::SymbolTable,
+        nes::Nes,
+        rom::{Rom, RomHeader},
+    };
+    use std::{fs::File, io::BufReader};
+
+    #[test]
+    fn test_execute() {
+        let mut nes = prepare();
+        let mut run = |line: &str| match execute(&mut nes, line).unwrap() {
+            Output::Text(text) => text,
+            Output::Quit => "quit".to_string(),
+        };
+
+        assert_eq!(run("regs"), "PC:8000 A:00 X:00 Y:00 P:24 SP:FD CYC:7");
+        assert_eq!(
+            run("step 2"),
+            "PC:8003 A:00 X:FF Y:00 P:A4 SP:FD CYC:11\n8003  TXS"
+        );
+        assert_eq!(run("dis 8000 3"), "8000  SEI\n8001  LDX #$FF\n8003  TXS");
+        assert_eq!(run("bp $8006 A == 0"), "Breakpoint at $8006");
+        assert_eq!(run("bp"), "$8006 if A == 0");
+        assert_eq!(
+            run("c"),
+            "Breakpoint at $8006\nPC:8006 A:00 X:FF Y:00 P:26 SP:FF CYC:15\n8006  STA $2000"
+        );
+        assert_eq!(run("watch 2000-2007"), "Watchpoint at $2000-$2007 (Write)");
+        assert!(run("c").starts_with("Write $00 at $2000\nPC:8009"));
+        assert_eq!(run("unwatch 0"), "Deleted watchpoint 0");
+        assert_eq!(run("del 8006"), "Deleted breakpoint at $8006");
+        assert_eq!(
+            run("mem 8000 4"),
+            "8000: 78 A2 FF 9A                                      x..."
+        );
+        assert_eq!(run("quit"), "quit");
+    }
+
+    #[test]
+    fn test_backtrace() {
+        let mut nes = prepare_subroutine();
+        execute(&mut nes, "step 3").unwrap();
+        assert_eq!(
+            execute(&mut nes, "bt").unwrap(),
+            Output::Text("#0 $8020 called from $8010\n#1 $8010 called from $8000".to_string())
+        );
+
+        // 戻り先を書き換えてからRTSすると警告が出る
+        nes.poke(0x01fa, 0x10);
+        let text = match execute(&mut nes, "step").unwrap() {
+            Output::Text(text) => text,
+            Output::Quit => unreachable!(),
+        };
+        assert!(text.starts_with(
+            "warning: RTS at $8021 returned to $8011 without a matching JSR\nPC:8011"
+        ));
+    }
+
+    #[test]
+    fn test_symbols() {
+        let mut nes = prepare_subroutine();
+        let mut symbols = SymbolTable::default();
+        symbols.insert(0x8010, "outer");
+        symbols.insert(0x8020, "inner");
+        nes.debugger().set_symbols(symbols);
+
+        assert_eq!(
+            execute(&mut nes, "dis outer 1").unwrap(),
+            Output::Text("outer:\n8010  JSR inner".to_string())
+        );
+        execute(&mut nes, "bp inner").unwrap();
+        execute(&mut nes, "c").unwrap();
+        assert_eq!(
+            execute(&mut nes, "bt").unwrap(),
+            Output::Text(
+                "#0 $8020 <inner> called from $8010 <outer>\n#1 $8010 <outer> called from $8000"
+                    .to_string()
+            )
+        );
+    }
+
+    #[test]
+    fn test_profile() {
+        let mut nes = prepare_subroutine();
+        execute(&mut nes, "profile on").unwrap();
+        execute(&mut nes, "step 4").unwrap();
+        assert_eq!(
+            execute(&mut nes, "profile report").unwrap(),
+            Output::Text(
+                [
+                    "routine     cycles      %  instructions",
+                    "$8020            8  40.00             2",
+                    "(top)            6  30.00             1",
+                    "$8010            6  30.00             1",
+                ]
+                .join("\n")
+            )
+        );
+    }
+
+    #[test]
+    fn test_execute_error() {
+        let mut nes = prepare();
+        let mut error = |line: &str| execute(&mut nes, line).unwrap_err().to_string();
+        assert_eq!(error("foo"), "Unknown command: foo");
+        assert_eq!(error("bp zz"), "Invalid address: zz");
+        assert_eq!(error("bp 8000 A =="), "Unexpected end of expression.");
+        assert_eq!(error("del 8000"), "No breakpoint at $8000");
+        assert_eq!(error("mem"), "Missing address.");
+    }
+
+    fn prepare_subroutine() -> Nes {
+        let mut program = vec![0; 0x8000];
+        // 0x8000: JSR $8010
+        program[0x0000..0x0003].copy_from_slice(&[0x20, 0x10, 0x80]);
+        // 0x8010: JSR $8020
+        program[0x0010..0x0013].copy_from_slice(&[0x20, 0x20, 0x80]);
+        // 0x8020: INX, RTS
+        program[0x0020..0x0022].copy_from_slice(&[0xe8, 0x60]);
+        program[0x7ffc] = 0x00;
+        program[0x7ffd] = 0x80;
+
+        let mut nes = Nes::new();
+        nes.set_rom(Rom {
+            header: RomHeader::default(),
+            trainer: None,
+            program: program.into(),
+            character: Default::default(),
+            hint_screen: None,
+        })
+        .unwrap();
+        nes.power_cycle().unwrap();
+        nes
+    }
+
+    fn prepare() -> Nes {
+        let mut reader = BufReader::new(File::open("./tests/rom/hello_world.nes").unwrap());
+        let mut nes = Nes::new();
+        nes.set_rom(Rom::load(&mut reader).unwrap()).unwrap();
+        nes.power_cycle().unwrap();
+        nes
+    }
+}
@@ -0,0 +1,138 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    error::Error,
+    fs,
+    path::Path,
+    result::Result,
+};
+
+// アドレスとラベルの対応。FCEUXの.nlとcc65(ld65 --dbgfile)の.dbgを読める
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SymbolTable {
+    labels: BTreeMap<u16, String>,
+    addresses: HashMap<String, u16>,
+}
+
+impl SymbolTable {
+    // 拡張子で形式を決める
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path)?;
+        let mut symbols = Self::default();
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("dbg") => symbols.load_dbg(&source)?,
+            _ => symbols.load_nl(&source)?,
+        }
+        Ok(symbols)
+    }
+
+    // $C000#reset#コメント の形式。1つのアドレスに同じ行で複数書くことはない
+    pub fn load_nl(&mut self, source: &str) -> Result<(), Box<dyn Error>> {
+        for line in source.lines().filter(|l| !l.trim().is_empty()) {
+            let mut fields = line.split('#');
+            let addr = fields.next().unwrap_or("").trim();
+            let name = fields.next().unwrap_or("").trim();
+            let addr = addr
+                .strip_prefix('$')
+                .and_then(|a| u16::from_str_radix(a, 16).ok())
+                .ok_or_else(|| format!("Invalid symbol line: {}", line))?;
+            if !name.is_empty() {
+                self.insert(addr, name);
+            }
+        }
+        Ok(())
+    }
+
+    // sym id=0,name="reset",addrsize=absolute,scope=0,def=1,val=0x8000,type=lab のような行だけ使う
+    pub fn load_dbg(&mut self, source: &str) -> Result<(), Box<dyn Error>> {
+        for line in source.lines() {
+            let attributes = match line
+                .strip_prefix("sym\t")
+                .or_else(|| line.strip_prefix("sym "))
+            {
+                Some(attributes) => attributes,
+                None => continue,
+            };
+            let mut name = None;
+            let mut value = None;
+            let mut is_label = false;
+            for attribute in attributes.split(',') {
+                match attribute.split_once('=') {
+                    Some(("name", v)) => name = Some(v.trim_matches('"')),
+                    Some(("val", v)) => {
+                        let v = v.trim_start_matches("0x");
+                        value = Some(
+                            u16::from_str_radix(v, 16)
+                                .map_err(|_| format!("Invalid symbol line: {}", line))?,
+                        );
+                    }
+                    Some(("type", "lab")) => is_label = true,
+                    _ => {}
+                }
+            }
+            // 定数(type=equ)はアドレスではないので使わない
+            if let (true, Some(name), Some(value)) = (is_label, name, value) {
+                self.insert(value, name);
+            }
+        }
+        Ok(())
+    }
+
+    // 同じアドレスに複数のラベルがあるときは最初のものを表示に使う
+    pub fn insert(&mut self, addr: u16, name: &str) {
+        self.labels.entry(addr).or_insert_with(|| name.to_string());
+        self.addresses.insert(name.to_string(), addr);
+    }
+
+    pub fn label(&self, addr: u16) -> Option<&str> {
+        self.labels.get(&addr).map(String::as_str)
+    }
+
+    pub fn address(&self, name: &str) -> Option<u16> {
+        self.addresses.get(name).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.labels.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::SymbolTable;
+
+    #[test]
+    fn test_load_nl() {
+        let mut symbols = SymbolTable::default();
+        symbols
+            .load_nl("$8000#reset#エントリポイント\n$0300#player_x#\n$0301##\n")
+            .unwrap();
+        assert_eq!(symbols.label(0x8000), Some("reset"));
+        assert_eq!(symbols.label(0x0300), Some("player_x"));
+        assert_eq!(symbols.label(0x0301), None);
+        assert_eq!(symbols.address("reset"), Some(0x8000));
+        assert_eq!(symbols.len(), 2);
+
+        assert!(symbols.load_nl("8000#reset#").is_err());
+    }
+
+    #[test]
+    fn test_load_dbg() {
+        let mut symbols = SymbolTable::default();
+        symbols
+            .load_dbg(
+                "version\tmajor=2,minor=0\n\
+                 sym\tid=0,name=\"nmi_handler\",addrsize=absolute,scope=0,def=1,val=0x8050,seg=0,type=lab\n\
+                 sym\tid=1,name=\"PPUCTRL\",addrsize=absolute,scope=0,def=2,val=0x2000,type=equ\n\
+                 sym\tid=2,name=\"player_x\",addrsize=zeropage,scope=0,def=3,val=0x10,seg=1,type=lab\n",
+            )
+            .unwrap();
+        assert_eq!(symbols.label(0x8050), Some("nmi_handler"));
+        assert_eq!(symbols.label(0x0010), Some("player_x"));
+        assert_eq!(symbols.label(0x2000), None);
+    }
+}
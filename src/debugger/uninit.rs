@@ -0,0 +1,148 @@
+use crate::{
+    cpu::{AccessKind, MemoryAccess},
+    ram::WRAM_SIZE,
+};
+use std::fmt;
+
+// 書き込む前のWRAMを読んだところ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UninitializedRead {
+    // 読んだ命令のアドレス
+    pub pc: u16,
+    pub addr: u16,
+    pub value: u8,
+}
+
+impl fmt::Display for UninitializedRead {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Read of uninitialized ${:04X} (${:02X}) at ${:04X}",
+            self.addr, self.value, self.pc
+        )
+    }
+}
+
+// 電源を入れてからWRAMのどこに書き込んだかを覚えて、書き込む前に読んだところを見つける。
+// 電源を入れたときの中身に頼ったバグを探すためのもの。同じアドレスは1回だけ知らせる
+#[derive(Debug, Clone)]
+pub struct UninitDetector {
+    written: Vec<bool>,
+    reported: Vec<bool>,
+}
+
+impl UninitDetector {
+    pub fn new() -> Self {
+        Self {
+            written: vec![false; WRAM_SIZE],
+            reported: vec![false; WRAM_SIZE],
+        }
+    }
+
+    // 電源を入れ直したときに呼ぶ
+    pub fn clear(&mut self) {
+        self.written.fill(false);
+        self.reported.fill(false);
+    }
+
+    // デバッガから書き換えたときなど、命令を通さずに書き込んだもの
+    pub fn mark_written(&mut self, addr: u16) {
+        if let Some(i) = index(addr) {
+            self.written[i] = true;
+        }
+    }
+
+    pub fn is_written(&self, addr: u16) -> bool {
+        index(addr).is_none_or(|i| self.written[i])
+    }
+
+    // 1命令分のアクセスを順に見る。読み書きする命令は読んだ時点ではまだ書いていない
+    pub fn record(&mut self, pc: u16, accesses: &[MemoryAccess]) -> Vec<UninitializedRead> {
+        let mut reads = Vec::new();
+        for access in accesses {
+            let i = match index(access.addr) {
+                Some(i) => i,
+                None => continue,
+            };
+            match access.kind {
+                AccessKind::Write => self.written[i] = true,
+                // WRAMに置いたコードを実行するのも読み込みと同じ
+                AccessKind::Read | AccessKind::Execute => {
+                    if !self.written[i] && !self.reported[i] {
+                        self.reported[i] = true;
+                        reads.push(UninitializedRead {
+                            pc,
+                            addr: access.addr,
+                            value: access.value,
+                        });
+                    }
+                }
+            }
+        }
+        reads
+    }
+}
+
+impl Default for UninitDetector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// $0000〜$1FFFはWRAMのミラー
+fn index(addr: u16) -> Option<usize> {
+    if addr < 0x2000 {
+        Some(addr as usize % WRAM_SIZE)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{UninitDetector, UninitializedRead};
+    use crate::cpu::{AccessKind, MemoryAccess};
+
+    #[test]
+    fn test_record() {
+        let mut detector = UninitDetector::new();
+        let access = |addr, value, kind| MemoryAccess { addr, value, kind };
+        // INC $0810 はミラーの$0010を読んでから書く
+        let reads = detector.record(
+            0x8000,
+            &[
+                access(0x8000, 0xee, AccessKind::Execute),
+                access(0x0810, 0x12, AccessKind::Read),
+                access(0x0810, 0x13, AccessKind::Write),
+                access(0x6000, 0x00, AccessKind::Read),
+            ],
+        );
+        assert_eq!(
+            reads,
+            vec![UninitializedRead {
+                pc: 0x8000,
+                addr: 0x0810,
+                value: 0x12,
+            }]
+        );
+        assert_eq!(
+            reads[0].to_string(),
+            "Read of uninitialized $0810 ($12) at $8000"
+        );
+        assert!(detector.is_written(0x0010));
+        assert!(detector.is_written(0x6000));
+
+        // 同じアドレスは2回知らせない
+        let read = access(0x0020, 0x00, AccessKind::Read);
+        assert_eq!(detector.record(0x8003, &[read]).len(), 1);
+        assert!(detector.record(0x8006, &[read]).is_empty());
+        detector.mark_written(0x0030);
+        assert!(detector
+            .record(0x8009, &[access(0x0030, 0x00, AccessKind::Read)])
+            .is_empty());
+
+        detector.clear();
+        assert!(!detector.is_written(0x0010));
+        assert_eq!(detector.record(0x800c, &[read]).len(), 1);
+    }
+}
@@ -0,0 +1,160 @@
+use core::fmt;
+
+// 1文字が4ビットになる。並び順がそのまま値
+const LETTERS: &[u8; 16] = b"APZLGITYEOXUKSVN";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GameGenieError {
+    InvalidLength(usize),
+    InvalidLetter(char),
+}
+
+impl fmt::Display for GameGenieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::InvalidLength(len) => {
+                write!(f, "Game Genie code must be 6 or 8 letters, got {}.", len)
+            }
+            Self::InvalidLetter(c) => write!(f, "Invalid Game Genie letter '{}'.", c),
+        }
+    }
+}
+
+impl core::error::Error for GameGenieError {}
+
+// ROMの1バイトを読んだときだけ値を差し替える。8文字のコードはcompareと一致したときだけ
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GameGenieCode {
+    pub addr: u16,
+    pub value: u8,
+    pub compare: Option<u8>,
+}
+
+impl GameGenieCode {
+    // 大文字小文字は区別しない
+    pub fn decode(code: &str) -> Result<Self, GameGenieError> {
+        let mut n = [0u8; 8];
+        let mut len = 0;
+        for c in code.chars() {
+            let upper = c.to_ascii_uppercase();
+            let digit = LETTERS
+                .iter()
+                .position(|&l| l as char == upper)
+                .ok_or(GameGenieError::InvalidLetter(c))?;
+            if len < n.len() {
+                n[len] = digit as u8;
+            }
+            len += 1;
+        }
+        if len != 6 && len != 8 {
+            return Err(GameGenieError::InvalidLength(len));
+        }
+
+        let addr = 0x8000
+            | ((n[3] & 7) as u16) << 12
+            | ((n[5] & 7) as u16) << 8
+            | ((n[4] & 8) as u16) << 8
+            | ((n[2] & 7) as u16) << 4
+            | ((n[1] & 8) as u16) << 4
+            | (n[4] & 7) as u16
+            | (n[3] & 8) as u16;
+        let (value, compare) = if len == 6 {
+            let value = (n[1] & 7) << 4 | (n[0] & 8) << 4 | (n[0] & 7) | (n[5] & 8);
+            (value, None)
+        } else {
+            let value = (n[1] & 7) << 4 | (n[0] & 8) << 4 | (n[0] & 7) | (n[7] & 8);
+            let compare = (n[7] & 7) << 4 | (n[6] & 8) << 4 | (n[6] & 7) | (n[5] & 8);
+            (value, Some(compare))
+        };
+        Ok(Self {
+            addr,
+            value,
+            compare,
+        })
+    }
+
+    // ROMから読んだ値を差し替えるならその値
+    pub fn apply(&self, addr: u16, original: u8) -> Option<u8> {
+        if addr != self.addr || self.compare.is_some_and(|c| c != original) {
+            return None;
+        }
+        Some(self.value)
+    }
+}
+
+impl fmt::Display for GameGenieCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.compare {
+            Some(compare) => write!(
+                f,
+                "${:04X} = ${:02X} if ${:02X}",
+                self.addr, self.value, compare
+            ),
+            None => write!(f, "${:04X} = ${:02X}", self.addr, self.value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{GameGenieCode, GameGenieError};
+
+    #[test]
+    fn test_decode_6_letters() {
+        let code = GameGenieCode::decode("GOSSIP").unwrap();
+        assert_eq!(
+            code,
+            GameGenieCode {
+                addr: 0xd1dd,
+                value: 0x14,
+                compare: None
+            }
+        );
+        assert_eq!(code.to_string(), "$D1DD = $14");
+        assert_eq!(GameGenieCode::decode("gossip"), Ok(code));
+    }
+
+    #[test]
+    fn test_decode_8_letters() {
+        let code = GameGenieCode::decode("ZEXPYGLA").unwrap();
+        assert_eq!(
+            code,
+            GameGenieCode {
+                addr: 0x94a7,
+                value: 0x02,
+                compare: Some(0x03)
+            }
+        );
+        assert_eq!(code.to_string(), "$94A7 = $02 if $03");
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        assert_eq!(
+            GameGenieCode::decode("GOSSI"),
+            Err(GameGenieError::InvalidLength(5))
+        );
+        assert_eq!(
+            GameGenieCode::decode("GOSSIPAAA"),
+            Err(GameGenieError::InvalidLength(9))
+        );
+        assert_eq!(
+            GameGenieCode::decode("GOSSIB"),
+            Err(GameGenieError::InvalidLetter('B'))
+        );
+        assert_eq!(
+            GameGenieError::InvalidLetter('B').to_string(),
+            "Invalid Game Genie letter 'B'."
+        );
+    }
+
+    #[test]
+    fn test_apply() {
+        let code = GameGenieCode::decode("ZEXPYGLA").unwrap();
+        assert_eq!(code.apply(0x94a7, 0x03), Some(0x02));
+        assert_eq!(code.apply(0x94a7, 0x04), None);
+        assert_eq!(code.apply(0x94a8, 0x03), None);
+        let code = GameGenieCode::decode("GOSSIP").unwrap();
+        assert_eq!(code.apply(0xd1dd, 0xff), Some(0x14));
+    }
+}
@@ -0,0 +1,479 @@
+use crate::{
+    cpu::{register::Status, AccessKind, CpuError},
+    debugger::{BreakReason, WatchKind},
+    nes::Nes,
+};
+use std::{
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream},
+};
+
+// 6502にはgdbの標準のレジスタ定義が無いので、A X Y P SP を1バイトずつ、
+// PCを2バイト(リトルエンディアン)の順に並べたものをレジスタ一式とする
+const REGISTER_SIZES: [usize; 6] = [1, 1, 1, 1, 1, 2];
+
+// continue中にこの命令数ごとにgdbからの割り込みを確認する
+const INTERRUPT_CHECK_INTERVAL: u64 = 1000;
+
+pub trait Connection: Read + Write {
+    // 実行中にgdbから割り込み(Ctrl-C)が来ていればtrue
+    fn interrupted(&mut self) -> bool {
+        false
+    }
+}
+
+impl Connection for TcpStream {
+    fn interrupted(&mut self) -> bool {
+        let mut buf = [0];
+        if self.set_nonblocking(true).is_err() {
+            return false;
+        }
+        let interrupted = matches!(self.peek(&mut buf), Ok(1) if buf[0] == 0x03);
+        if interrupted {
+            let _ = self.read(&mut buf);
+        }
+        let _ = self.set_nonblocking(false);
+        interrupted
+    }
+}
+
+// gdbのリモートシリアルプロトコルで1つの接続を処理する
+pub struct GdbStub<C: Connection> {
+    conn: C,
+}
+
+impl<C: Connection> GdbStub<C> {
+    pub fn new(conn: C) -> Self {
+        Self { conn }
+    }
+
+    pub fn into_inner(self) -> C {
+        self.conn
+    }
+
+    // gdbがデタッチするか接続が切れるまでパケットを処理する
+    pub fn serve(&mut self, nes: &mut Nes) -> io::Result<()> {
+        while let Some(packet) = self.read_packet()? {
+            match packet.as_str() {
+                "D" => return self.write_packet("OK"),
+                "k" => return Ok(()),
+                _ => {
+                    let reply = self.handle(nes, &packet)?;
+                    self.write_packet(&reply)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, nes: &mut Nes, packet: &str) -> io::Result<String> {
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        let reply = match command {
+            "?" => "S05".to_string(),
+            "g" => read_registers(nes),
+            "G" => ok_or_error(write_registers(nes, args)),
+            "p" => parse_hex(args)
+                .and_then(|n| read_register(nes, n as usize))
+                .unwrap_or_else(|| "E01".to_string()),
+            "P" => ok_or_error(write_register(nes, args)),
+            "m" => read_memory(nes, args).unwrap_or_else(|| "E01".to_string()),
+            "M" => ok_or_error(write_memory(nes, args)),
+            "c" => self.resume(nes, false)?,
+            "s" => self.resume(nes, true)?,
+            "Z" => ok_or_error(set_breakpoint(nes, args, true)),
+            "z" => ok_or_error(set_breakpoint(nes, args, false)),
+            "H" => "OK".to_string(),
+            "q" if args.starts_with("Supported") => "PacketSize=1000".to_string(),
+            "q" if args == "Attached" => "1".to_string(),
+            // 対応していないパケットには空で返す決まり
+            _ => String::new(),
+        };
+        Ok(reply)
+    }
+
+    fn resume(&mut self, nes: &mut Nes, step: bool) -> io::Result<String> {
+        if step {
+            return Ok(stop_reply(Some(nes.step_into())));
+        }
+        let mut steps = 0u64;
+        let reason = loop {
+            if let Some(reason) = nes.step_debug() {
+                break Some(reason);
+            }
+            steps += 1;
+            if steps.is_multiple_of(INTERRUPT_CHECK_INTERVAL) && self.conn.interrupted() {
+                break None;
+            }
+        };
+        Ok(stop_reply(reason))
+    }
+
+    // $<data>#<checksum> を1つ読む。チェックサムが合わなければ再送してもらう
+    fn read_packet(&mut self) -> io::Result<Option<String>> {
+        loop {
+            // パケットの外にあるackや割り込みは読み飛ばす
+            loop {
+                match self.read_byte()? {
+                    Some(b'$') => break,
+                    Some(_) => {}
+                    None => return Ok(None),
+                }
+            }
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    Some(b'#') => break,
+                    Some(b) => data.push(b),
+                    None => return Ok(None),
+                }
+            }
+            let mut checksum = [0; 2];
+            self.conn.read_exact(&mut checksum)?;
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|s| u8::from_str_radix(s, 16).ok());
+            if expected == Some(checksum_of(&data)) {
+                self.conn.write_all(b"+")?;
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+            self.conn.write_all(b"-")?;
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut buf = [0];
+        match self.conn.read(&mut buf)? {
+            0 => Ok(None),
+            _ => Ok(Some(buf[0])),
+        }
+    }
+
+    fn write_packet(&mut self, data: &str) -> io::Result<()> {
+        write!(self.conn, "${}#{:02x}", data, checksum_of(data.as_bytes()))?;
+        self.conn.flush()
+    }
+}
+
+// 待ち受けているlistenerに最初に接続してきたgdbの相手をする
+pub fn listen(listener: TcpListener, nes: &mut Nes) -> io::Result<()> {
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    GdbStub::new(stream).serve(nes)
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, b| sum.wrapping_add(*b))
+}
+
+fn stop_reply(reason: Option<BreakReason>) -> String {
+    match reason {
+        Some(BreakReason::Watchpoint(access)) => {
+            let kind = match access.kind {
+                AccessKind::Read => "rwatch",
+                _ => "watch",
+            };
+            format!("T05{}:{:04x};", kind, access.addr)
+        }
+        // 実行できない命令はSIGILL、つながっていないアドレスへのアクセスはSIGSEGV
+        Some(BreakReason::Error(
+            CpuError::UnimplementedOpcode { .. } | CpuError::Halted { .. },
+        )) => "S04".to_string(),
+        Some(BreakReason::Error(CpuError::Bus(_))) => "S0b".to_string(),
+        Some(_) => "S05".to_string(),
+        // gdbから割り込まれた(SIGINT)
+        None => "S02".to_string(),
+    }
+}
+
+fn ok_or_error(result: Option<()>) -> String {
+    match result {
+        Some(()) => "OK".to_string(),
+        None => "E01".to_string(),
+    }
+}
+
+fn parse_hex(s: &str) -> Option<u32> {
+    u32::from_str_radix(s, 16).ok()
+}
+
+pub(crate) fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+pub(crate) fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn register_values(nes: &Nes) -> [u16; 6] {
+    let registers = nes.registers();
+    [
+        registers.accumulator as u16,
+        registers.index_x as u16,
+        registers.index_y as u16,
+        u8::from(&registers.status) as u16,
+        registers.stack_pointer as u16,
+        registers.program_counter,
+    ]
+}
+
+fn read_register(nes: &Nes, n: usize) -> Option<String> {
+    let value = *register_values(nes).get(n)?;
+    Some(encode_hex(&value.to_le_bytes()[..REGISTER_SIZES[n]]))
+}
+
+fn read_registers(nes: &Nes) -> String {
+    (0..REGISTER_SIZES.len())
+        .filter_map(|n| read_register(nes, n))
+        .collect()
+}
+
+fn set_register(nes: &mut Nes, n: usize, value: u16) {
+    let mut registers = nes.registers().clone();
+    match n {
+        0 => registers.accumulator = value as u8,
+        1 => registers.index_x = value as u8,
+        2 => registers.index_y = value as u8,
+        3 => registers.status = Status::from(value as u8),
+        4 => registers.stack_pointer = value as u8,
+        _ => registers.program_counter = value,
+    }
+    nes.set_registers(registers);
+}
+
+fn decode_register(n: usize, hex: &str) -> Option<u16> {
+    let bytes = decode_hex(hex)?;
+    if bytes.len() != *REGISTER_SIZES.get(n)? {
+        return None;
+    }
+    Some(
+        bytes
+            .iter()
+            .rev()
+            .fold(0, |value, b| (value << 8) | *b as u16),
+    )
+}
+
+fn write_register(nes: &mut Nes, args: &str) -> Option<()> {
+    let (n, hex) = args.split_once('=')?;
+    let n = parse_hex(n)? as usize;
+    let value = decode_register(n, hex)?;
+    set_register(nes, n, value);
+    Some(())
+}
+
+fn write_registers(nes: &mut Nes, args: &str) -> Option<()> {
+    let mut values = Vec::new();
+    let mut rest = args;
+    for (n, size) in REGISTER_SIZES.iter().enumerate() {
+        let hex = rest.get(..size * 2)?;
+        values.push(decode_register(n, hex)?);
+        rest = &rest[size * 2..];
+    }
+    for (n, value) in values.into_iter().enumerate() {
+        set_register(nes, n, value);
+    }
+    Some(())
+}
+
+fn parse_range(args: &str) -> Option<(u16, u16)> {
+    let (addr, len) = args.split_once(',')?;
+    Some((parse_hex(addr)? as u16, parse_hex(len)? as u16))
+}
+
+// 読めないアドレスがあればそこまでを返す
+fn read_memory(nes: &Nes, args: &str) -> Option<String> {
+    let (addr, len) = parse_range(args)?;
+    let bytes: Vec<u8> = (0..len)
+        .map_while(|i| nes.peek(addr.wrapping_add(i)))
+        .collect();
+    if bytes.is_empty() && len > 0 {
+        return None;
+    }
+    Some(encode_hex(&bytes))
+}
+
+fn write_memory(nes: &mut Nes, args: &str) -> Option<()> {
+    let (range, data) = args.split_once(':')?;
+    let (addr, len) = parse_range(range)?;
+    let bytes = decode_hex(data)?;
+    if bytes.len() != len as usize {
+        return None;
+    }
+    for (i, value) in bytes.into_iter().enumerate() {
+        if !nes.poke(addr.wrapping_add(i as u16), value) {
+            return None;
+        }
+    }
+    Some(())
+}
+
+// Z0/Z1: ブレークポイント, Z2: 書き込み, Z3: 読み込み, Z4: 読み書きのウォッチポイント
+fn set_breakpoint(nes: &mut Nes, args: &str, insert: bool) -> Option<()> {
+    let mut fields = args.split(',');
+    let kind = fields.next()?;
+    let addr = parse_hex(fields.next()?)? as u16;
+    let len = parse_hex(fields.next()?)?.max(1) as u16;
+    let watch_kind = match kind {
+        "0" | "1" => {
+            if insert {
+                nes.debugger().add_breakpoint(addr);
+            } else {
+                nes.debugger().remove_breakpoint(addr);
+            }
+            return Some(());
+        }
+        "2" => WatchKind::Write,
+        "3" => WatchKind::Read,
+        "4" => WatchKind::ReadWrite,
+        _ => return None,
+    };
+    let range = addr..=addr.wrapping_add(len - 1);
+    let debugger = nes.debugger();
+    if insert {
+        debugger.add_watchpoint(range, watch_kind);
+    } else {
+        let index = debugger
+            .watchpoints()
+            .iter()
+            .position(|w| w.range == range && w.kind == watch_kind)?;
+        debugger.remove_watchpoint(index);
+    }
+    Some(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{checksum_of, Connection, GdbStub};
+    use crate::{nes::Nes, rom::Rom};
+    use std::{
+        fs::File,
+        io::{self, BufReader, Cursor, Read, Write},
+    };
+
+    struct MockConnection {
+        input: Cursor<Vec<u8>>,
+        output: Vec<u8>,
+    }
+
+    impl Read for MockConnection {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            self.input.read(buf)
+        }
+    }
+
+    impl Write for MockConnection {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.output.write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl Connection for MockConnection {}
+
+    #[test]
+    fn test_registers_and_memory() {
+        let mut nes = prepare();
+        let replies = serve(
+            &mut nes,
+            &[
+                "qSupported:multiprocess+",
+                "?",
+                "g",
+                "m8000,3",
+                "M0010,2:abcd",
+                "m0010,2",
+                "m2000,1",
+                "P0=3f",
+                "p0",
+                "Gaa0102240010c0",
+                "vMustReplyEmpty",
+                "D",
+            ],
+        );
+        assert_eq!(
+            replies,
+            vec![
+                "PacketSize=1000",
+                "S05",
+                "00000024fd0080",
+                "78a2ff",
+                "OK",
+                "abcd",
+                "E01",
+                "OK",
+                "3f",
+                "OK",
+                "",
+                "OK",
+            ]
+        );
+        let registers = nes.registers();
+        assert_eq!(registers.accumulator, 0xaa);
+        assert_eq!(registers.stack_pointer, 0x00);
+        assert_eq!(registers.program_counter, 0xc010);
+    }
+
+    #[test]
+    fn test_breakpoint_and_step() {
+        let mut nes = prepare();
+        let replies = serve(
+            &mut nes,
+            &["Z0,8004,1", "c", "s", "z0,8004,1", "Z2,2000,8", "c", "k"],
+        );
+        assert_eq!(
+            replies,
+            vec!["OK", "S05", "S05", "OK", "OK", "T05watch:2000;"]
+        );
+        assert_eq!(nes.registers().program_counter, 0x8009);
+    }
+
+    #[test]
+    fn test_bad_checksum() {
+        let mut nes = prepare();
+        let mut input = b"$?#00".to_vec();
+        input.extend(encode("?"));
+        let mut stub = GdbStub::new(MockConnection {
+            input: Cursor::new(input),
+            output: vec![],
+        });
+        stub.serve(&mut nes).unwrap();
+        assert!(stub.into_inner().output.starts_with(b"-+$S05#b8"));
+    }
+
+    fn encode(data: &str) -> Vec<u8> {
+        format!("${}#{:02x}", data, checksum_of(data.as_bytes())).into_bytes()
+    }
+
+    fn serve(nes: &mut Nes, packets: &[&str]) -> Vec<String> {
+        let input = packets.iter().flat_map(|p| encode(p)).collect();
+        let mut stub = GdbStub::new(MockConnection {
+            input: Cursor::new(input),
+            output: vec![],
+        });
+        stub.serve(nes).unwrap();
+
+        let output = String::from_utf8(stub.into_inner().output).unwrap();
+        output
+            .split('$')
+            .skip(1)
+            .map(|packet| packet.split('#').next().unwrap().to_string())
+            .collect()
+    }
+
+    fn prepare() -> Nes {
+        let mut reader = BufReader::new(File::open("./tests/rom/hello_world.nes").unwrap());
+        let mut nes = Nes::new();
+        nes.set_rom(Rom::load(&mut reader).unwrap()).unwrap();
+        nes.power_cycle().unwrap();
+        nes
+    }
+}
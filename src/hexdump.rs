@@ -0,0 +1,69 @@
+use alloc::{
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+
+// 1行16バイトでダンプする。読めないところは--にする
+pub fn hexdump(start: u16, data: &[Option<u8>]) -> String {
+    let mut lines = Vec::new();
+    let offset = (start & 0x000f) as usize;
+    let first_line = start & 0xfff0;
+
+    // 行の先頭が16の倍数になるように前を空ける
+    let padded: Vec<Option<Option<u8>>> = (0..offset)
+        .map(|_| None)
+        .chain(data.iter().map(|b| Some(*b)))
+        .collect();
+
+    for (i, chunk) in padded.chunks(16).enumerate() {
+        let addr = first_line.wrapping_add((i * 16) as u16);
+        let hex: Vec<String> = chunk
+            .iter()
+            .map(|b| match b {
+                Some(Some(b)) => format!("{:02X}", b),
+                Some(None) => "--".to_string(),
+                None => "  ".to_string(),
+            })
+            .collect();
+        let ascii: String = chunk
+            .iter()
+            .map(|b| match b {
+                Some(Some(b)) if b.is_ascii_graphic() || *b == b' ' => *b as char,
+                None => ' ',
+                _ => '.',
+            })
+            .collect();
+        lines.push(format!("{:04X}: {:<47}  {}", addr, hex.join(" "), ascii));
+    }
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod test {
+    use super::hexdump;
+
+    #[test]
+    fn test_hexdump() {
+        let data: Vec<Option<u8>> = b"HELLO, WORLD!\x00\x01\xff\x7e"
+            .iter()
+            .map(|b| Some(*b))
+            .collect();
+        assert_eq!(
+            hexdump(0x8061, &data),
+            [
+                "8060:    48 45 4C 4C 4F 2C 20 57 4F 52 4C 44 21 00 01   HELLO, WORLD!..",
+                "8070: FF 7E                                            .~",
+            ]
+            .join("\n")
+        );
+    }
+
+    #[test]
+    fn test_hexdump_unreadable() {
+        assert_eq!(
+            hexdump(0x2000, &[None, Some(0x41)]),
+            "2000: -- 41                                            .A"
+        );
+    }
+}
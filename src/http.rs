@@ -0,0 +1,208 @@
+use crate::{nes::Nes, state_json};
+use std::{
+    error::Error,
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, ToSocketAddrs},
+    time::Duration,
+};
+
+// 1つのリクエストを待つ時間。止まったクライアントでエミュレーションを止めないように
+const READ_TIMEOUT: Duration = Duration::from_secs(1);
+// これより大きい本文は読まない。ステートのJSONは20KB程度
+const MAX_BODY: usize = 1 << 20;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Response {
+    pub status: u16,
+    pub content_type: &'static str,
+    pub body: Vec<u8>,
+}
+
+impl Response {
+    fn new(status: u16, content_type: &'static str, body: Vec<u8>) -> Self {
+        Self {
+            status,
+            content_type,
+            body,
+        }
+    }
+
+    fn text(status: u16, message: &str) -> Self {
+        Self::new(status, "text/plain", format!("{}\n", message).into_bytes())
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            413 => "Payload Too Large",
+            500 => "Internal Server Error",
+            501 => "Not Implemented",
+            _ => "Error",
+        }
+    }
+}
+
+// 動いているNesを外から見るための小さなHTTPサーバー。メインループから毎回pollを呼ぶ。
+// 1回のpollで待っているリクエストを全部処理する
+#[derive(Debug)]
+pub struct HttpServer {
+    listener: TcpListener,
+}
+
+impl HttpServer {
+    pub fn bind<A: ToSocketAddrs>(addr: A) -> io::Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        listener.set_nonblocking(true)?;
+        Ok(Self { listener })
+    }
+
+    pub fn poll(&self, nes: &mut Nes) -> io::Result<()> {
+        loop {
+            let stream = match self.listener.accept() {
+                Ok((stream, _)) => stream,
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(err) => return Err(err),
+            };
+            stream.set_nonblocking(false)?;
+            stream.set_read_timeout(Some(READ_TIMEOUT))?;
+            // 1つの接続の失敗はそのクライアントだけの問題
+            if let Err(err) = serve(BufReader::new(stream.try_clone()?), stream, nes) {
+                log::warn!("http: {}", err);
+            }
+        }
+    }
+}
+
+// リクエストを1つ読んで返事を書く。接続は毎回閉じる
+pub fn serve<R: BufRead, W: Write>(mut reader: R, mut writer: W, nes: &mut Nes) -> io::Result<()> {
+    let response = match read_request(&mut reader)? {
+        Ok((method, path, body)) => handle(nes, &method, &path, &body),
+        Err(response) => response,
+    };
+    write!(
+        writer,
+        "HTTP/1.1 {} {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.reason(),
+        response.content_type,
+        response.body.len()
+    )?;
+    writer.write_all(&response.body)?;
+    writer.flush()
+}
+
+// /state はGETでJSONのステートを返し、PUTで読み込む。
+// 映像とコントローラーはPPUと入力ができるまで501を返す
+pub fn handle(nes: &mut Nes, method: &str, path: &str, body: &[u8]) -> Response {
+    let path = path.split('?').next().unwrap_or(path);
+    match (method, path) {
+        ("GET", "/state") => match state_json::to_json(&nes.save_state()) {
+            Ok(json) => Response::new(200, "application/json", json.into_bytes()),
+            Err(err) => Response::text(500, &err.to_string()),
+        },
+        ("PUT", "/state") | ("POST", "/state") => match load_json(nes, body) {
+            Ok(()) => Response::text(200, "OK"),
+            Err(err) => Response::text(400, &err.to_string()),
+        },
+        (_, "/state") => Response::text(405, "Use GET or PUT."),
+        (_, "/frame.png") => Response::text(501, "Frames are not rendered yet."),
+        (_, "/input") => Response::text(501, "Controller input is not supported yet."),
+        _ => Response::text(404, "Not found."),
+    }
+}
+
+fn load_json(nes: &mut Nes, body: &[u8]) -> Result<(), Box<dyn Error>> {
+    let state = state_json::from_json(std::str::from_utf8(body)?)?;
+    nes.load_state(&state)
+}
+
+type Request = (String, String, Vec<u8>);
+
+// 読めたら(メソッド, パス, 本文)。おかしなリクエストにはそのまま返す返事
+fn read_request<R: BufRead>(reader: &mut R) -> io::Result<Result<Request, Response>> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    let mut parts = line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => return Ok(Err(Response::text(400, "Invalid request line."))),
+    };
+    let mut length = 0;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 || header.trim().is_empty() {
+            break;
+        }
+        let (name, value) = match header.find(':') {
+            Some(i) => (&header[..i], header[i + 1..].trim()),
+            None => continue,
+        };
+        if name.eq_ignore_ascii_case("content-length") {
+            length = match value.parse() {
+                Ok(length) => length,
+                Err(_) => return Ok(Err(Response::text(400, "Invalid Content-Length."))),
+            };
+        }
+    }
+    if length > MAX_BODY {
+        return Ok(Err(Response::text(413, "Request body is too large.")));
+    }
+    let mut body = vec![0; length];
+    reader.read_exact(&mut body)?;
+    Ok(Ok((method, path, body)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{handle, serve};
+    use crate::{nes::Nes, rom::Rom};
+
+    #[test]
+    fn test_state() {
+        let mut nes = prepare();
+        let response = handle(&mut nes, "GET", "/state", b"");
+        assert_eq!(response.status, 200);
+        assert_eq!(response.content_type, "application/json");
+        let state = response.body;
+
+        nes.step().unwrap();
+        nes.poke(0x0010, 0x12);
+        let request = format!(
+            "PUT /state HTTP/1.1\r\nHost: localhost\r\nContent-Length: {}\r\n\r\n{}",
+            state.len(),
+            String::from_utf8(state.clone()).unwrap()
+        );
+        let mut output = vec![];
+        serve(request.as_bytes(), &mut output, &mut nes).unwrap();
+        assert!(output.starts_with(b"HTTP/1.1 200 OK\r\n"));
+        assert_eq!(nes.peek(0x0010), Some(0x00));
+        assert_eq!(handle(&mut nes, "GET", "/state", b"").body, state);
+    }
+
+    #[test]
+    fn test_errors() {
+        let mut nes = prepare();
+        assert_eq!(handle(&mut nes, "PUT", "/state", b"{}").status, 400);
+        assert_eq!(handle(&mut nes, "DELETE", "/state", b"").status, 405);
+        assert_eq!(handle(&mut nes, "GET", "/frame.png", b"").status, 501);
+        assert_eq!(handle(&mut nes, "POST", "/input", b"").status, 501);
+        assert_eq!(handle(&mut nes, "GET", "/", b"").status, 404);
+
+        let mut output = vec![];
+        serve(&b"garbage\r\n\r\n"[..], &mut output, &mut nes).unwrap();
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.starts_with("HTTP/1.1 400 Bad Request\r\n"));
+        assert!(output.ends_with("\r\n\r\nInvalid request line.\n"));
+    }
+
+    fn prepare() -> Nes {
+        let mut nes = Nes::new();
+        nes.set_rom(Rom::open("./tests/rom/hello_world.nes").unwrap())
+            .unwrap();
+        nes.power_cycle().unwrap();
+        nes
+    }
+}
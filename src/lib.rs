@@ -0,0 +1,51 @@
+// stdが無いときはCPUのコアだけをno_std + allocでビルドする
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+pub mod cheat;
+pub mod cheat_search;
+pub mod checksum;
+#[cfg(feature = "std")]
+pub mod config;
+pub mod cpu;
+#[cfg(feature = "debugger")]
+pub mod debugger;
+pub mod game_genie;
+#[cfg(feature = "debugger")]
+pub mod gdb;
+pub mod hexdump;
+#[cfg(feature = "http")]
+pub mod http;
+#[cfg(feature = "std")]
+pub mod nes;
+#[cfg(feature = "std")]
+pub mod patch;
+pub mod png;
+pub mod ram;
+pub mod region;
+#[cfg(feature = "debugger")]
+pub mod remote;
+#[cfg(feature = "std")]
+pub mod rewind;
+#[cfg(feature = "std")]
+pub mod rom;
+pub mod rom_data;
+#[cfg(feature = "std")]
+pub mod rom_db;
+#[cfg(feature = "scripting")]
+pub mod script;
+#[cfg(feature = "std")]
+pub mod state;
+#[cfg(feature = "std")]
+pub mod state_diff;
+#[cfg(feature = "std")]
+pub mod state_json;
+#[cfg(feature = "std")]
+pub mod state_slot;
+#[cfg(feature = "std")]
+pub mod testing;
+pub mod tile_sheet;
+
+#[cfg(feature = "std")]
+pub use crate::{nes::Nes, rom::Rom};
@@ -1,17 +1,972 @@
-use nes::Nes;
-use rom::Rom;
-use std::{fs::File, io::BufReader};
+use env_logger::Env;
+#[cfg(feature = "http")]
+use nes::http::HttpServer;
+#[cfg(feature = "scripting")]
+use nes::script::Script;
+use nes::{
+    config::Config,
+    cpu::{tracer::Tracer, CpuError},
+    debugger::{
+        cdl::CodeDataLogger,
+        event_stream::{Category, EventStream},
+        listing::listing,
+        repl::{self, Output},
+        symbols::SymbolTable,
+    },
+    gdb, patch, png,
+    ram::RamPattern,
+    region::Region,
+    remote,
+    rewind::RewindBuffer,
+    rom_db::RomDatabase,
+    state::StateError,
+    state_diff, state_json,
+    state_slot::StateSlots,
+    testing::{lockstep, nestest},
+    tile_sheet, Nes, Rom,
+};
+use std::{
+    env,
+    error::Error,
+    fs::{self, File},
+    io::{self, BufRead, BufWriter, Write},
+    net::TcpListener,
+    path::Path,
+    process,
+    sync::mpsc::{self, Receiver},
+    thread::{self, sleep},
+    time,
+};
 
-mod cpu;
-mod nes;
-mod ram;
-mod rom;
+const DEFAULT_ROM: &str = "./tests/rom/hello_world.nes";
+const DEFAULT_STATE_DIR: &str = "./states";
+const DEFAULT_CONFIG_PATH: &str = "./nes.toml";
+const BATTERY_FLUSH_INTERVAL: time::Duration = time::Duration::from_secs(10);
+const REWIND_CAPACITY: usize = 600;
 
 fn main() {
-    let file = File::open("./tests/rom/hello_world.nes").unwrap();
-    let rom = Rom::load(&mut BufReader::new(file)).unwrap();
+    // ROMの情報を表示するだけで実行はしない
+    if env::args().nth(1).as_deref() == Some("info") {
+        let path = env::args().nth(2).unwrap_or_else(|| usage());
+        print_info(&path).unwrap_or_else(|err| {
+            eprintln!("Failed to load {}: {}", path, err);
+            process::exit(1);
+        });
+        return;
+    }
+    // nestest.logとトレースを比べる
+    if env::args().nth(1).as_deref() == Some("nestest") {
+        let rom_path = env::args().nth(2).unwrap_or_else(|| usage());
+        let log_path = env::args().nth(3).unwrap_or_else(|| usage());
+        let mut nes = Nes::new();
+        let rom = Rom::open(&rom_path).unwrap_or_else(|err| {
+            eprintln!("Failed to load {}: {}", rom_path, err);
+            process::exit(1);
+        });
+        nes.set_rom(rom).unwrap_or_else(|err| {
+            eprintln!("Failed to load {}: {}", rom_path, err);
+            process::exit(1);
+        });
+        let log = fs::read_to_string(&log_path).unwrap_or_else(|err| {
+            eprintln!("Failed to read {}: {}", log_path, err);
+            process::exit(1);
+        });
+        match nestest::run(&mut nes, &log) {
+            Ok(lines) => println!("All {} lines matched.", lines),
+            Err(divergence) => {
+                println!("{}", divergence);
+                process::exit(1);
+            }
+        }
+        return;
+    }
 
+    // 同じROMを2つの設定で同時に実行して、最初に食い違ったところを表示する
+    if env::args().nth(1).as_deref() == Some("lockstep") {
+        let matched = run_lockstep(env::args().skip(2)).unwrap_or_else(|err| {
+            eprintln!("Failed to compare: {}", err);
+            process::exit(1);
+        });
+        if !matched {
+            process::exit(1);
+        }
+        return;
+    }
+
+    // 同じ設定の2つを並べて実行して、コアが決定的かを確かめる
+    if env::args().nth(1).as_deref() == Some("self-check") {
+        let matched = run_self_check(env::args().skip(2)).unwrap_or_else(|err| {
+            eprintln!("Failed to check: {}", err);
+            process::exit(1);
+        });
+        if !matched {
+            process::exit(1);
+        }
+        return;
+    }
+
+    // 画面を出さずに決まった数だけ実行してトレースを書き出す
+    if env::args().nth(1).as_deref() == Some("trace") {
+        run_trace(env::args().skip(2)).unwrap_or_else(|err| {
+            eprintln!("Failed to trace: {}", err);
+            process::exit(1);
+        });
+        return;
+    }
+    // パターンテーブルをPNGに書き出す
+    if env::args().nth(1).as_deref() == Some("chr") {
+        let rom_path = env::args().nth(2).unwrap_or_else(|| usage());
+        let png_path = env::args().nth(3).unwrap_or_else(|| usage());
+        export_chr(&rom_path, &png_path).unwrap_or_else(|err| {
+            eprintln!("Failed to export {}: {}", rom_path, err);
+            process::exit(1);
+        });
+        return;
+    }
+    // ROMを逆アセンブルして表示する
+    if env::args().nth(1).as_deref() == Some("disasm") {
+        print_disasm(env::args().skip(2)).unwrap_or_else(|err| {
+            eprintln!("Failed to disassemble: {}", err);
+            process::exit(1);
+        });
+        return;
+    }
+    // 2つのステートの違いを表示する。違いがあれば終了コードは1
+    if env::args().nth(1).as_deref() == Some("diff") {
+        let left = env::args().nth(2).unwrap_or_else(|| usage());
+        let right = env::args().nth(3).unwrap_or_else(|| usage());
+        let identical = print_diff(&left, &right).unwrap_or_else(|err| {
+            eprintln!("Failed to compare states: {}", err);
+            process::exit(1);
+        });
+        if !identical {
+            process::exit(1);
+        }
+        return;
+    }
+
+    // ステートをJSONにして表示する。importはJSONからステートを作る
+    if env::args().nth(1).as_deref() == Some("export-state") {
+        let path = env::args().nth(2).unwrap_or_else(|| usage());
+        let json = fs::read(&path)
+            .map_err(Into::into)
+            .and_then(|state| state_json::to_json(&state));
+        match json {
+            Ok(json) => println!("{}", json),
+            Err(err) => {
+                eprintln!("Failed to export {}: {}", path, err);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+    if env::args().nth(1).as_deref() == Some("import-state") {
+        let json_path = env::args().nth(2).unwrap_or_else(|| usage());
+        let state_path = env::args().nth(3).unwrap_or_else(|| usage());
+        import_state(&json_path, &state_path).unwrap_or_else(|err| {
+            eprintln!("Failed to import {}: {}", json_path, err);
+            process::exit(1);
+        });
+        return;
+    }
+
+    let mut rom_path = DEFAULT_ROM.to_string();
+    let mut config_path = DEFAULT_CONFIG_PATH.to_string();
+    let mut state_dir = None;
+    let mut resume = false;
+    let mut trace_path = None;
+    let mut events_path = None;
+    let mut event_categories = Category::ALL.to_vec();
+    let mut gdb_addr = None;
+    let mut remote_addr = None;
+    let mut http_addr: Option<String> = None;
+    let mut debug = false;
+    let mut cdl_path = None;
+    let mut heatmap_path = None;
+    let mut script_path = None;
+    let mut symbols_path = None;
+    let mut db_path = None;
+    let mut patch_path = None;
+    let mut differential = false;
+    let mut coverage = false;
+    let mut uninit = false;
+    let mut watch = false;
+    let mut log_level = None;
+    let mut bench_seconds = None;
+    let mut region = None;
+    let mut ram_init = None;
+    let mut cheats = Vec::new();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--config" => config_path = args.next().unwrap_or_else(|| usage()),
+            "--state-dir" => state_dir = Some(args.next().unwrap_or_else(|| usage())),
+            "--resume" => resume = true,
+            "--trace" => trace_path = Some(args.next().unwrap_or_else(|| usage())),
+            "--events" => events_path = Some(args.next().unwrap_or_else(|| usage())),
+            "--event-categories" => {
+                let list = args.next().unwrap_or_else(|| usage());
+                event_categories = Category::parse_list(&list).unwrap_or_else(|err| {
+                    eprintln!("{}", err);
+                    process::exit(1);
+                });
+            }
+            "--gdb" => gdb_addr = Some(args.next().unwrap_or_else(|| usage())),
+            "--remote" => remote_addr = Some(args.next().unwrap_or_else(|| usage())),
+            "--http" => http_addr = Some(args.next().unwrap_or_else(|| usage())),
+            "--debug" => debug = true,
+            "--cdl" => cdl_path = Some(args.next().unwrap_or_else(|| usage())),
+            "--heatmap" => heatmap_path = Some(args.next().unwrap_or_else(|| usage())),
+            "--script" => script_path = Some(args.next().unwrap_or_else(|| usage())),
+            "--symbols" => symbols_path = Some(args.next().unwrap_or_else(|| usage())),
+            "--db" => db_path = Some(args.next().unwrap_or_else(|| usage())),
+            "--patch" => patch_path = Some(args.next().unwrap_or_else(|| usage())),
+            "--differential" => differential = true,
+            "--coverage" => coverage = true,
+            "--uninit" => uninit = true,
+            "--watch" => watch = true,
+            "--log-level" => log_level = Some(args.next().unwrap_or_else(|| usage())),
+            "--region" => {
+                let name = args.next().unwrap_or_else(|| usage());
+                region = Some(Region::from_name(&name).unwrap_or_else(|| usage()));
+            }
+            "--ram-init" => ram_init = Some(args.next().unwrap_or_else(|| usage())),
+            "--cheat" => cheats.push(args.next().unwrap_or_else(|| usage())),
+            "--bench" => {
+                let seconds = args.next().unwrap_or_else(|| usage());
+                bench_seconds = Some(seconds.parse::<f64>().unwrap_or_else(|_| usage()));
+            }
+            _ if arg.starts_with("--") => usage(),
+            _ => rom_path = arg,
+        }
+    }
+
+    // コマンドラインで指定しなかったものは設定ファイルから
+    let config = Config::load_or_create(&config_path).unwrap_or_else(|err| {
+        eprintln!("Failed to load {}: {}", config_path, err);
+        process::exit(1);
+    });
+    let region = region.or_else(|| config.region());
+    let state_dir = state_dir
+        .or(config.state_dir)
+        .unwrap_or_else(|| DEFAULT_STATE_DIR.to_string());
+    let ram_init = ram_init.or(config.ram_init);
+    let log_level = log_level.or(config.log_level);
+    let cheats: Vec<String> = config.cheats.into_iter().chain(cheats).collect();
+
+    init_logger(log_level.as_deref());
+    let ram_pattern = ram_init.map(|name| parse_ram_pattern(&name).unwrap_or_else(|| usage()));
+
+    #[cfg(feature = "scripting")]
+    let mut script = script_path.map(|path| {
+        fs::read_to_string(&path)
+            .map_err(Into::into)
+            .and_then(|source| Script::load(&source))
+            .unwrap_or_else(|err| {
+                eprintln!("Failed to load {}: {}", path, err);
+                process::exit(1);
+            })
+    });
+    #[cfg(not(feature = "scripting"))]
+    if script_path.is_some() {
+        eprintln!("Scripting is disabled in this build.");
+        process::exit(1);
+    }
+    #[cfg(feature = "http")]
+    let http = http_addr.map(|addr| {
+        HttpServer::bind(addr.as_str()).unwrap_or_else(|err| {
+            eprintln!("Failed to listen on {}: {}", addr, err);
+            process::exit(1);
+        })
+    });
+    #[cfg(not(feature = "http"))]
+    if http_addr.is_some() {
+        eprintln!("HTTP is disabled in this build.");
+        process::exit(1);
+    }
+
+    let mut rom = load_rom(&rom_path, patch_path.as_deref(), watch).unwrap_or_else(|err| {
+        eprintln!("Failed to load {}: {}", rom_path, err);
+        process::exit(1);
+    });
+    // データベースにあればヘッダをそちらに合わせる
+    if let Some(path) = db_path {
+        let db = RomDatabase::load(&path).unwrap_or_else(|err| {
+            eprintln!("Failed to load {}: {}", path, err);
+            process::exit(1);
+        });
+        match db.lookup(&rom) {
+            Some(entry) => {
+                log::info!("found in database: {}", entry.title);
+                entry.apply(&mut rom);
+            }
+            None => log::info!("not found in database: crc32={:08X}", rom.crc32()),
+        }
+    }
+    let slots = StateSlots::new(state_dir, &rom);
+
+    let sav_path = match &config.save_dir {
+        Some(dir) => {
+            fs::create_dir_all(dir).unwrap_or_else(|err| {
+                eprintln!("Failed to create {}: {}", dir, err);
+                process::exit(1);
+            });
+            let name = Path::new(&rom_path).with_extension("sav");
+            let name = name.file_name().unwrap_or_else(|| {
+                eprintln!("Invalid ROM path for save_dir: {}", rom_path);
+                process::exit(1);
+            });
+            Path::new(dir).join(name)
+        }
+        None => Path::new(&rom_path).with_extension("sav"),
+    };
+
+    // 前回の.cdlがあればそれに追記する
+    let cdl = cdl_path.as_ref().map(|path| {
+        let (prg_len, chr_len) = (rom.program.len(), rom.character.len());
+        match fs::read(path) {
+            Ok(data) => CodeDataLogger::from_bytes(&data, prg_len, chr_len).unwrap_or_else(|err| {
+                // ROMを作り直して大きさが変わったときなど。前回の分は使わずに始め直す
+                log::warn!("Ignoring {}: {}", path, err);
+                CodeDataLogger::new(prg_len, chr_len)
+            }),
+            Err(_) => CodeDataLogger::new(prg_len, chr_len),
+        }
+    });
+
+    let mut nes = Nes::new();
+    nes.set_rom(rom).unwrap_or_else(|err| {
+        eprintln!("Failed to load {}: {}", rom_path, err);
+        process::exit(1);
+    });
+    if let Some(region) = region {
+        nes.set_region(region);
+    }
+    if let Some(pattern) = ram_pattern {
+        nes.init_ram(pattern);
+    }
+    for code in &cheats {
+        match nes.add_cheat(code) {
+            Ok(cheat) => log::info!("cheat {}: {}", code, cheat),
+            Err(err) => {
+                eprintln!("{}", err);
+                process::exit(1);
+            }
+        }
+    }
+    nes.set_code_data_logger(cdl);
+    nes.set_heatmap(heatmap_path.is_some());
+    nes.set_opcode_coverage(coverage);
+    nes.set_uninit_detection(uninit);
+    nes.set_differential(differential);
+    let symbols = symbols_path.map(|path| {
+        SymbolTable::load(&path).unwrap_or_else(|err| {
+            eprintln!("Failed to load {}: {}", path, err);
+            process::exit(1);
+        })
+    });
+    let tracer = match trace_path.as_deref() {
+        Some("-") => Some(Tracer::new(io::stdout())),
+        Some(path) => Some(Tracer::new(create_file(path))),
+        None => None,
+    };
+    if let Some(mut tracer) = tracer {
+        tracer.set_symbols(symbols.clone());
+        nes.set_tracer(Some(tracer));
+    }
+    if let Some(symbols) = symbols {
+        nes.debugger().set_symbols(symbols);
+    }
+    let events: Option<Box<dyn Write + Send>> = match events_path.as_deref() {
+        Some("-") => Some(Box::new(io::stdout())),
+        Some(path) => Some(Box::new(create_file(path))),
+        None => None,
+    };
+    if let Some(out) = events {
+        nes.set_event_stream(Some(EventStream::new(out, &event_categories)));
+    }
+    if nes.battery_ram().is_some() && sav_path.exists() {
+        // 読めない.savは使わずに、電源を入れたときの中身で始める
+        let loaded = fs::read(&sav_path)
+            .map_err(Into::into)
+            .and_then(|data| nes.load_battery_ram(&data));
+        if let Err(err) = loaded {
+            log::error!("Ignoring {}: {}", sav_path.display(), err);
+        }
+    }
+    nes.power_cycle().unwrap_or_else(|err| {
+        log::error!("Failed to reset: {}", err);
+        process::exit(1);
+    });
+    if resume && slots.auto_path().exists() {
+        // 読み込みに失敗してもNesは変わらないので、電源を入れたところから始める
+        if let Err(err) = slots.load_auto(&mut nes) {
+            let path = slots.auto_path();
+            match err.downcast_ref::<StateError>() {
+                Some(err) => log::error!("Ignoring {}: {}", path.display(), err),
+                None => log::error!("Failed to read {}: {}", path.display(), err),
+            }
+        }
+    }
+
+    // 待たずに実行して速さを測るだけ。セーブなどはしない
+    if let Some(seconds) = bench_seconds {
+        match nes.run_benchmark(seconds) {
+            Ok(result) => println!("{}", result),
+            Err(err) => {
+                log::error!("{}", err);
+                process::exit(1);
+            }
+        }
+        return;
+    }
+
+    // gdbから操作するときは自分では実行を進めない
+    if let Some(addr) = gdb_addr {
+        let listener = TcpListener::bind(addr.as_str()).unwrap_or_else(|err| {
+            eprintln!("Failed to listen on {}: {}", addr, err);
+            process::exit(1);
+        });
+        log::info!("waiting for gdb on {}", addr);
+        // 接続が切れたときなど。そこまでの結果は書き出す
+        if let Err(err) = gdb::listen(listener, &mut nes) {
+            log::error!("gdb: {}", err);
+        }
+        shutdown(
+            &nes,
+            Some(&slots),
+            &sav_path,
+            &mut None,
+            cdl_path.as_deref(),
+            heatmap_path.as_deref(),
+        );
+        return;
+    }
+    // ビルドスクリプトなどからJSONの命令で操作する。quitが来たら終わる
+    if let Some(addr) = remote_addr {
+        let listener = TcpListener::bind(addr.as_str()).unwrap_or_else(|err| {
+            eprintln!("Failed to listen on {}: {}", addr, err);
+            process::exit(1);
+        });
+        log::info!("waiting for commands on {}", addr);
+        if let Err(err) = remote::listen(listener, &mut nes) {
+            log::error!("remote: {}", err);
+        }
+        shutdown(
+            &nes,
+            Some(&slots),
+            &sav_path,
+            &mut None,
+            cdl_path.as_deref(),
+            heatmap_path.as_deref(),
+        );
+        return;
+    }
+    if debug {
+        debug_repl(&mut nes);
+        shutdown(
+            &nes,
+            Some(&slots),
+            &sav_path,
+            &mut None,
+            cdl_path.as_deref(),
+            heatmap_path.as_deref(),
+        );
+        return;
+    }
+
+    let commands = spawn_command_reader();
+    let mut saved_battery_ram = nes.battery_ram();
+    let mut last_flush = time::Instant::now();
+    // フレームの始まりごとに1つ積む。rで巻き戻している間は進めない
+    let mut rewind = RewindBuffer::new(REWIND_CAPACITY);
+    let mut rewinding = false;
+    rewind.push(nes.save_state());
+    let mut rom_modified = modified_time(&rom_path);
+    loop {
+        // JAMで止まっている間と巻き戻している間は進めずに、ステートの読み込みなどの命令だけを待つ
+        let halted = nes.halted() || rewinding;
+        let frame = nes.frame_count();
+        #[cfg(feature = "scripting")]
+        let stepped = match &mut script {
+            _ if halted => Ok(0),
+            Some(s) => s.step(&mut nes).or_else(|err| {
+                // CPUが止まったのはスクリプトのせいではない
+                if nes.halted() {
+                    log::error!("{}", err);
+                    return Ok(0);
+                }
+                // エラーが出たスクリプトはそれ以降動かさない
+                log::error!("script error: {}", err);
+                script = None;
+                Ok(0)
+            }),
+            None => nes.step(),
+        };
+        #[cfg(not(feature = "scripting"))]
+        let stepped = if halted { Ok(0) } else { nes.step() };
+        let clock = match stepped {
+            Ok(clock) => clock,
+            Err(err @ CpuError::Halted { .. }) => {
+                log::error!("{}", err);
+                0
+            }
+            Err(err) => {
+                // これ以上は進められないので、残すものだけ書き出して終わる
+                log::error!("{}", err);
+                // 続きから始めると同じエラーになるので、自動セーブはしない
+                shutdown(
+                    &nes,
+                    None,
+                    &sav_path,
+                    &mut saved_battery_ram,
+                    cdl_path.as_deref(),
+                    heatmap_path.as_deref(),
+                );
+                process::exit(1);
+            }
+        };
+        log::debug!(target: "nes::cpu", "clock: {}", clock);
+        if nes.frame_count() != frame {
+            rewind.push(nes.save_state());
+        }
+        nes.dump_registers();
+        for warning in nes.take_stack_warnings() {
+            log::warn!(target: "nes::cpu", "{}", warning);
+        }
+        for read in nes.take_uninitialized_reads() {
+            log::warn!(target: "nes::cpu", "{}", read);
+        }
+
+        while let Ok(command) = commands.try_recv() {
+            if command.trim() == "q" {
+                shutdown(
+                    &nes,
+                    Some(&slots),
+                    &sav_path,
+                    &mut saved_battery_ram,
+                    cdl_path.as_deref(),
+                    heatmap_path.as_deref(),
+                );
+                return;
+            }
+            handle_command(&command, &mut nes, &slots, &mut rewind, &mut rewinding);
+        }
+        #[cfg(feature = "http")]
+        if let Some(http) = &http {
+            if let Err(err) = http.poll(&mut nes) {
+                log::warn!("http: {}", err);
+            }
+        }
+        // ビルドし直されたROMに差し替えて電源を入れ直す。チートやトレースはそのまま
+        if watch && modified_time(&rom_path) != rom_modified {
+            rom_modified = modified_time(&rom_path);
+            match reload_rom(&mut nes, &rom_path, patch_path.as_deref(), watch) {
+                Ok(()) => {
+                    log::info!("reloaded {}", rom_path);
+                    rewind.clear();
+                    rewind.push(nes.save_state());
+                    rewinding = false;
+                }
+                // 書き込みの途中なら、書き終わったときにもう一度読む
+                Err(err) => log::warn!("Failed to reload {}: {}", rom_path, err),
+            }
+        }
+        if last_flush.elapsed() >= BATTERY_FLUSH_INTERVAL {
+            flush_battery_ram(&nes, &sav_path, &mut saved_battery_ram);
+            last_flush = time::Instant::now();
+        }
+        sleep(time::Duration::from_millis(500));
+    }
+}
+
+// --log-levelはRUST_LOGと同じ書き方(debug、nes::ppu=trace など)。どちらも無ければinfo
+fn init_logger(filter: Option<&str>) {
+    let mut builder = env_logger::Builder::from_env(Env::default().default_filter_or("info"));
+    if let Some(filter) = filter {
+        builder.parse_filters(filter);
+    }
+    builder.init();
+}
+
+// シードの無いrandomは時刻から決めて、同じ状態を再現できるようにログに出す
+fn parse_ram_pattern(name: &str) -> Option<RamPattern> {
+    if name != "random" {
+        return RamPattern::from_name(name);
+    }
+    let seed = time::SystemTime::now()
+        .duration_since(time::UNIX_EPOCH)
+        .map_or(0, |d| d.as_nanos() as u64);
+    log::info!("RAM initialized with --ram-init random:{}", seed);
+    Some(RamPattern::Random(seed))
+}
+
+fn print_info(path: &str) -> Result<(), Box<dyn Error>> {
+    let data = Rom::read_file(path)?;
+    let rom = Rom::load_unchecked(&mut &data[..])?;
+    print!("{}", rom.describe());
+    Ok(())
+}
+
+// 数を指定しなければ1000命令。途中で止まってもそこまでのトレースは残す
+fn run_trace<I: Iterator<Item = String>>(mut args: I) -> Result<(), Box<dyn Error>> {
+    let mut positional = Vec::new();
+    let mut instructions = None;
+    let mut frames = None;
+    while let Some(arg) = args.next() {
+        let mut count = || {
+            args.next()
+                .and_then(|n| n.parse::<u64>().ok())
+                .unwrap_or_else(|| usage())
+        };
+        match arg.as_str() {
+            "--instructions" => instructions = Some(count()),
+            "--frames" => frames = Some(count()),
+            _ if arg.starts_with("--") => usage(),
+            _ => positional.push(arg),
+        }
+    }
+    let (rom_path, out_path) = match positional.as_slice() {
+        [rom_path, out_path] if instructions.is_none() || frames.is_none() => (rom_path, out_path),
+        _ => usage(),
+    };
+    let out: Box<dyn Write + Send> = match out_path.as_str() {
+        "-" => Box::new(io::stdout()),
+        path => Box::new(BufWriter::new(File::create(path)?)),
+    };
+    let mut nes = Nes::new();
+    nes.set_rom(Rom::open(rom_path)?)?;
+    nes.power_cycle()?;
+    nes.set_tracer(Some(Tracer::new(out)));
+    let result = match frames {
+        Some(frames) => (0..frames).try_for_each(|_| nes.step_frame()),
+        None => (0..instructions.unwrap_or(1000)).try_for_each(|_| nes.step().map(|_| ())),
+    };
+    // ファイルに書き切ってからエラーを返す
+    nes.set_tracer(None);
+    Ok(result?)
+}
+
+// 設定は "region=pal,decode-cache=off" のように書く。数を指定しなければ10万命令。
+// 食い違わなければtrue
+fn run_lockstep<I: Iterator<Item = String>>(mut args: I) -> Result<bool, Box<dyn Error>> {
+    let mut positional = Vec::new();
+    let mut instructions = 100_000;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--instructions" => {
+                instructions = args
+                    .next()
+                    .and_then(|n| n.parse::<u64>().ok())
+                    .unwrap_or_else(|| usage())
+            }
+            _ if arg.starts_with("--") => usage(),
+            _ => positional.push(arg),
+        }
+    }
+    let (rom_path, left, right) = match positional.as_slice() {
+        [rom_path, left, right] => (rom_path, left, right),
+        _ => usage(),
+    };
+    let mut left = lockstep::CoreConfig::parse(left)?.build(Rom::open(rom_path)?)?;
+    let mut right = lockstep::CoreConfig::parse(right)?.build(Rom::open(rom_path)?)?;
+    match lockstep::run(&mut left, &mut right, instructions) {
+        Ok(count) => {
+            println!("All {} instructions matched.", count);
+            Ok(true)
+        }
+        Err(divergence) => {
+            println!("{}", divergence);
+            Ok(false)
+        }
+    }
+}
+
+// 設定を省けば既定のまま。数を指定しなければ600フレーム。食い違わなければtrue
+fn run_self_check<I: Iterator<Item = String>>(mut args: I) -> Result<bool, Box<dyn Error>> {
+    let mut positional = Vec::new();
+    let mut frames = 600;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--frames" => {
+                frames = args
+                    .next()
+                    .and_then(|n| n.parse::<u64>().ok())
+                    .unwrap_or_else(|| usage())
+            }
+            _ if arg.starts_with("--") => usage(),
+            _ => positional.push(arg),
+        }
+    }
+    let (rom_path, config) = match positional.as_slice() {
+        [rom_path] => (rom_path, "default"),
+        [rom_path, config] => (rom_path, config.as_str()),
+        _ => usage(),
+    };
+    let config = lockstep::CoreConfig::parse(config)?;
+    let mut left = config.build(Rom::open(rom_path)?)?;
+    let mut right = config.build(Rom::open(rom_path)?)?;
+    match lockstep::run_frames(&mut left, &mut right, frames) {
+        Ok(count) => {
+            println!("All {} frames matched.", count);
+            Ok(true)
+        }
+        Err(divergence) => {
+            println!("{}", divergence);
+            Ok(false)
+        }
+    }
+}
+
+// 色はグレースケール。上が$0000、下が$1000のパターンテーブル
+fn export_chr(rom_path: &str, png_path: &str) -> Result<(), Box<dyn Error>> {
     let mut nes = Nes::new();
-    nes.set_rom(rom);
-    nes.run();
+    nes.set_rom(Rom::open(rom_path)?)?;
+    let sheet = nes.tile_sheet();
+    let rgba = sheet.to_rgba(&tile_sheet::GRAYSCALE);
+    let png = png::encode_rgba(sheet.width as u32, sheet.height as u32, &rgba);
+    fs::write(png_path, png)?;
+    Ok(())
+}
+
+// 範囲を指定しなければPRG ROM全体。--cdlがあれば実行されたところも命令にする
+fn print_disasm<I: Iterator<Item = String>>(mut args: I) -> Result<(), Box<dyn Error>> {
+    let mut positional = Vec::new();
+    let mut cdl_path = None;
+    let mut symbols_path = None;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--cdl" => cdl_path = Some(args.next().unwrap_or_else(|| usage())),
+            "--symbols" => symbols_path = Some(args.next().unwrap_or_else(|| usage())),
+            _ if arg.starts_with("--") => usage(),
+            _ => positional.push(arg),
+        }
+    }
+    let (path, range) = match positional.as_slice() {
+        [path] => (path, 0x8000..=0xffff),
+        [path, start, end] => (path, repl::parse_addr(start)?..=repl::parse_addr(end)?),
+        _ => usage(),
+    };
+    let rom = Rom::load_unchecked(&mut &Rom::read_file(path)?[..])?;
+    let cdl = match cdl_path {
+        Some(cdl_path) => Some(CodeDataLogger::from_bytes(
+            &fs::read(cdl_path)?,
+            rom.program.len(),
+            rom.character.len(),
+        )?),
+        None => None,
+    };
+    let symbols = match symbols_path {
+        Some(symbols_path) => Some(SymbolTable::load(symbols_path)?),
+        None => None,
+    };
+    print!("{}", listing(&rom, range, cdl.as_ref(), symbols.as_ref()));
+    Ok(())
+}
+
+// 違いが無ければtrue
+fn print_diff(left: &str, right: &str) -> Result<bool, Box<dyn Error>> {
+    let differences = state_diff::diff_states(&fs::read(left)?, &fs::read(right)?)?;
+    for difference in &differences {
+        println!("{}", difference);
+    }
+    if differences.is_empty() {
+        println!("States are identical.");
+    }
+    Ok(differences.is_empty())
+}
+
+fn import_state(json_path: &str, state_path: &str) -> Result<(), Box<dyn Error>> {
+    let state = state_json::from_json(&fs::read_to_string(json_path)?)?;
+    fs::write(state_path, state)?;
+    Ok(())
+}
+
+// パッチがあれば当ててから読む。--watchのときはファイルが作り直されるので、
+// メモリマップせずに中身をコピーする
+fn load_rom(path: &str, patch_path: Option<&str>, watch: bool) -> Result<Rom, Box<dyn Error>> {
+    let patch_path = match patch_path {
+        Some(patch_path) => patch_path,
+        None if watch => return Ok(Rom::from_bytes(&Rom::read_file(path)?)?),
+        None => return Ok(Rom::open(path)?),
+    };
+    let data = patch::apply(&fs::read(patch_path)?, &Rom::read_file(path)?)?;
+    Ok(Rom::from_bytes(&data)?)
+}
+
+fn reload_rom(
+    nes: &mut Nes,
+    path: &str,
+    patch_path: Option<&str>,
+    watch: bool,
+) -> Result<(), Box<dyn Error>> {
+    nes.set_rom(load_rom(path, patch_path, watch)?)?;
+    nes.power_cycle()?;
+    Ok(())
+}
+
+fn modified_time(path: &str) -> Option<time::SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+// 終わるときに残すものを全部書き出す。書き出すものを増やしたらここに足す。
+// slotsを渡すと--resumeで続きから始めるためのステートも書き出す
+fn shutdown(
+    nes: &Nes,
+    slots: Option<&StateSlots>,
+    sav_path: &Path,
+    saved_battery_ram: &mut Option<Vec<u8>>,
+    cdl_path: Option<&str>,
+    heatmap_path: Option<&str>,
+) {
+    flush_battery_ram(nes, sav_path, saved_battery_ram);
+    if let Some(slots) = slots {
+        // 書き出せなくても、残りは書き出す
+        if let Err(err) = slots.save_auto(nes) {
+            log::error!("Failed to save the state: {}", err);
+        }
+    }
+    save_cdl(nes, cdl_path);
+    save_heatmap(nes, heatmap_path);
+    print_coverage(nes);
+}
+
+// 起動するときに書き出す先を作る。作れなければ終わる
+fn create_file(path: &str) -> File {
+    File::create(path).unwrap_or_else(|err| {
+        eprintln!("Failed to create {}: {}", path, err);
+        process::exit(1);
+    })
+}
+
+// 前回書き出したときから変わっていれば.savに書き出す
+fn flush_battery_ram(nes: &Nes, path: &Path, saved: &mut Option<Vec<u8>>) {
+    let current = nes.battery_ram();
+    if let Some(data) = &current {
+        if current != *saved {
+            // 書き出せなければ次にまた試す
+            match fs::write(path, data) {
+                Ok(()) => *saved = current,
+                Err(err) => log::error!("Failed to write {}: {}", path.display(), err),
+            }
+        }
+    }
+}
+
+fn save_cdl(nes: &Nes, path: Option<&str>) {
+    if let (Some(cdl), Some(path)) = (nes.code_data_logger(), path) {
+        // shutdownの途中なので、失敗しても残りは書き出す
+        let written = File::create(path)
+            .map_err(Into::into)
+            .and_then(|mut file| cdl.write(&mut file));
+        if let Err(err) = written {
+            log::error!("Failed to write {}: {}", path, err);
+        }
+    }
+}
+
+// .pngならPNG、それ以外はCSV
+fn save_heatmap(nes: &Nes, path: Option<&str>) {
+    if let (Some(heatmap), Some(path)) = (nes.heatmap(), path) {
+        let written = if path.ends_with(".png") {
+            fs::write(path, heatmap.to_png())
+        } else {
+            File::create(path).and_then(|mut file| heatmap.write_csv(&mut file))
+        };
+        if let Err(err) = written {
+            log::error!("Failed to write {}: {}", path, err);
+        }
+    }
+}
+
+fn print_coverage(nes: &Nes) {
+    if let Some(coverage) = nes.opcode_coverage() {
+        eprint!("{}", coverage.report());
+    }
+}
+
+fn usage() -> ! {
+    eprintln!(
+        "usage: nes [--config FILE] [--state-dir DIR] [--resume] [--trace FILE] [--events FILE] [--event-categories LIST] [--gdb ADDR] [--remote ADDR] [--http ADDR] [--debug] [--cdl FILE] [--heatmap FILE] [--script FILE] [--symbols FILE] [--db FILE] [--patch FILE] [--differential] [--coverage] [--uninit] [--watch] [--log-level FILTER] [--bench SECONDS] [--region ntsc|pal|dendy] [--ram-init zero|ff|alternating|random[:SEED]] [--cheat GENIE|ADDR:VALUE]... [ROM]\n       nes info ROM\n       nes nestest ROM LOG\n       nes diff STATE STATE\n       nes export-state STATE\n       nes import-state JSON STATE\n       nes lockstep ROM CONFIG CONFIG [--instructions N]\n       nes self-check ROM [CONFIG] [--frames N]\n       nes trace ROM OUTPUT [--instructions N | --frames N]\n       nes chr ROM PNG\n       nes disasm ROM [START END] [--cdl FILE] [--symbols FILE]"
+    );
+    process::exit(1);
+}
+
+// 止まった状態でプロンプトを出して、デバッガのコマンドを1行ずつ実行する
+fn debug_repl(nes: &mut Nes) {
+    let stdin = io::stdin();
+    let mut lines = stdin.lock().lines();
+    loop {
+        print!("(nes) ");
+        io::stdout().flush().unwrap();
+        let line = match lines.next() {
+            Some(Ok(line)) => line,
+            _ => return,
+        };
+        match repl::execute(nes, &line) {
+            Ok(Output::Text(text)) if text.is_empty() => {}
+            Ok(Output::Text(text)) => println!("{}", text),
+            Ok(Output::Quit) => return,
+            Err(err) => println!("{}", err),
+        }
+    }
+}
+
+// 標準入力から1行ずつコマンドを受け取る。ウィンドウが無いのでこれをホットキーの代わりにする
+fn spawn_command_reader() -> Receiver<String> {
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for line in io::stdin().lock().lines().map_while(Result::ok) {
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+    receiver
+}
+
+// s0〜s9: クイックセーブ, l0〜l9: クイックロード, r: 1フレーム巻き戻して止める,
+// resume: 巻き戻したところから再開, q: 終了
+fn handle_command(
+    command: &str,
+    nes: &mut Nes,
+    slots: &StateSlots,
+    rewind: &mut RewindBuffer,
+    rewinding: &mut bool,
+) {
+    let command = command.trim();
+    let slot = command.get(1..).and_then(|s| s.parse::<u8>().ok());
+    let result = match (command.get(..1), slot) {
+        (Some("s"), Some(slot)) => slots.save(slot, nes),
+        (Some("l"), Some(slot)) => slots.load(slot, nes),
+        _ if command == "reset" => nes.reset().map_err(Into::into),
+        _ if command == "power" => nes.power_cycle().map_err(Into::into),
+        // 続けて押すとさらに1フレームずつ戻る
+        _ if command == "r" => match rewind.pop() {
+            Some(state) => {
+                *rewinding = true;
+                nes.load_state(&state)
+            }
+            None => Err("Nothing to rewind.".into()),
+        },
+        _ if command == "resume" => {
+            *rewinding = false;
+            // 再開したところからまた積み直す
+            rewind.push(nes.save_state());
+            Ok(())
+        }
+        // +CODEでチートを追加、-CODEで削除
+        (Some("+"), _) => nes.add_cheat(&command[1..]).map(|_| ()).map_err(Into::into),
+        (Some("-"), _) => match nes.remove_cheat(&command[1..]) {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(format!("Cheat {} is not added.", &command[1..]).into()),
+            Err(err) => Err(err.into()),
+        },
+        _ => Err(format!("Unknown command: {}", command).into()),
+    };
+    match result {
+        Ok(()) => log::info!("{}", command),
+        Err(err) => log::error!("{}", err),
+    }
 }
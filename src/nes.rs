@@ -1,40 +1,1203 @@
-use crate::{cpu::Cpu, ram::Ram, rom::Rom};
-use std::{cell::RefCell, rc::Rc, thread::sleep, time};
+#[cfg(feature = "debugger")]
+use crate::debugger::{
+    cdl::CodeDataLogger,
+    event_stream::EventStream,
+    events::EventLog,
+    heatmap::Heatmap,
+    profiler::Profiler,
+    symbols::SymbolTable,
+    uninit::{UninitDetector, UninitializedRead},
+    BreakReason, Debugger,
+};
+use crate::{
+    cheat::{Cheat, CheatError},
+    cheat_search::{CheatSearch, SearchFilter},
+    checksum::fnv1a_64,
+    cpu::{
+        bus::Expansion,
+        call_stack::{CallFrame, StackWarning},
+        coverage::OpcodeCoverage,
+        register::Registers,
+        tracer::{self, Tracer},
+        Cpu, CpuError, MemoryAccess, StepResult,
+    },
+    hexdump::hexdump,
+    ram::{RamPattern, PRG_RAM_SIZE, WRAM_SIZE},
+    region::Region,
+    rom::{Rom, RomError, TvSystem},
+    state::{SaveState, StateError, SECTION_CPU, SECTION_PRG_RAM, SECTION_WRAM},
+    testing::reference,
+    tile_sheet::TileSheet,
+};
+use std::{error::Error, fmt, ops::RangeInclusive, result::Result, sync::Arc, thread::sleep, time};
+
+#[cfg(feature = "debugger")]
+const RTS_OPCODE: u8 = 0x60;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MemorySpace {
+    // CPUから見えるアドレス空間
+    Cpu,
+    // PPUの$0000〜$1FFF(パターンテーブル)。PPUがまだ無いのでCHR ROMをそのまま読む
+    Chr,
+}
+
+// Nesを借りられないところ(スクリプトのコールバックなど)からRAMとROMを読み書きするための写し。
+// 書き換えた内容はNes::apply_memory_viewで戻す
+#[derive(Debug, Clone)]
+pub struct MemoryView {
+    wram: Vec<u8>,
+    prg_ram: Vec<u8>,
+    rom: Option<Arc<Rom>>,
+}
+
+impl MemoryView {
+    pub fn peek(&self, addr: u16) -> Option<u8> {
+        match addr {
+            0x0000..=0x07ff => Some(self.wram[addr as usize]),
+            0x6000..=0x7fff => Some(self.prg_ram[(addr - 0x6000) as usize]),
+            0x8000..=0xffff => self
+                .rom
+                .as_ref()
+                .filter(|rom| !rom.program.is_empty())
+                .map(|rom| rom.program[(addr - 0x8000) as usize % rom.program.len()]),
+            _ => None,
+        }
+    }
+
+    pub fn poke(&mut self, addr: u16, value: u8) -> bool {
+        match addr {
+            0x0000..=0x07ff => self.wram[addr as usize] = value,
+            0x6000..=0x7fff => self.prg_ram[(addr - 0x6000) as usize] = value,
+            _ => return false,
+        }
+        true
+    }
+}
+
+// run_benchmarkで測った結果
+#[derive(Debug, Clone, PartialEq)]
+pub struct BenchmarkResult {
+    pub frames: u64,
+    pub instructions: u64,
+    pub elapsed: time::Duration,
+}
+
+impl BenchmarkResult {
+    pub fn frames_per_sec(&self) -> f64 {
+        self.frames as f64 / self.elapsed.as_secs_f64()
+    }
+
+    pub fn instructions_per_sec(&self) -> f64 {
+        self.instructions as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+impl fmt::Display for BenchmarkResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} frames, {} instructions in {:.2}s ({:.1} frames/s, {:.0} instructions/s)",
+            self.frames,
+            self.instructions,
+            self.elapsed.as_secs_f64(),
+            self.frames_per_sec(),
+            self.instructions_per_sec()
+        )
+    }
+}
+
+// VBlankが始まったときに渡す。映像と音声はPPUとAPUができたらここに足す
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Frame {
+    // 電源を入れてから何フレーム目か。0から数える
+    pub number: u64,
+    pub cycles: u64,
+}
+
+struct FrameCallback(Box<dyn FnMut(&Frame) + Send>);
+
+impl fmt::Debug for FrameCallback {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("FrameCallback")
+    }
+}
+
+// 次のスキャンラインに入ったときに渡す。スクロールやバンクはPPUとマッパーができたらここに足す
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Scanline {
+    // それまでに終わったフレームの数。frame_countと同じ
+    pub frame: u64,
+    pub line: u16,
+    pub cycles: u64,
+}
+
+struct ScanlineCallback(Box<dyn FnMut(&Scanline) + Send>);
+
+impl fmt::Debug for ScanlineCallback {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ScanlineCallback")
+    }
+}
 
 #[derive(Debug)]
 pub struct Nes {
     cpu: Cpu,
-    wram: Ram,
-    rom: Option<Rc<Rom>>,
+    rom: Option<Arc<Rom>>,
+    tracer: Option<Tracer>,
+    #[cfg(feature = "debugger")]
+    event_stream: Option<EventStream>,
+    #[cfg(feature = "debugger")]
+    debugger: Debugger,
+    #[cfg(feature = "debugger")]
+    profiler: Option<Profiler>,
+    #[cfg(feature = "debugger")]
+    cdl: Option<CodeDataLogger>,
+    #[cfg(feature = "debugger")]
+    event_log: Option<EventLog>,
+    #[cfg(feature = "debugger")]
+    heatmap: Option<Heatmap>,
+    coverage: Option<OpcodeCoverage>,
+    #[cfg(feature = "debugger")]
+    uninit: Option<UninitDetector>,
+    #[cfg(feature = "debugger")]
+    uninit_reads: Vec<UninitializedRead>,
+    differential: bool,
+    frame: u64,
+    on_frame: Option<FrameCallback>,
+    on_scanline: Option<ScanlineCallback>,
+    // power_cycleでWRAMを埋め直すのに使う
+    ram_pattern: RamPattern,
 }
 
 impl Nes {
     pub fn new() -> Self {
-        let wram = Rc::new(RefCell::new(vec![0; 0x800]));
-        let cpu = Cpu::new(wram.clone());
-
         Self {
-            cpu,
-            wram,
+            cpu: Cpu::new(),
             rom: None,
+            tracer: None,
+            #[cfg(feature = "debugger")]
+            event_stream: None,
+            #[cfg(feature = "debugger")]
+            debugger: Debugger::default(),
+            #[cfg(feature = "debugger")]
+            profiler: None,
+            #[cfg(feature = "debugger")]
+            cdl: None,
+            #[cfg(feature = "debugger")]
+            event_log: None,
+            #[cfg(feature = "debugger")]
+            heatmap: None,
+            coverage: None,
+            #[cfg(feature = "debugger")]
+            uninit: None,
+            #[cfg(feature = "debugger")]
+            uninit_reads: Vec::new(),
+            differential: false,
+            frame: 0,
+            on_frame: None,
+            on_scanline: None,
+            ram_pattern: RamPattern::default(),
         }
     }
 
-    pub fn set_rom(&mut self, rom: Rom) {
-        let program = Rc::new(rom.program.clone());
-        self.rom = Some(Rc::new(rom));
-        self.cpu.set_rom(Some(program));
+    // 対応していないマッパーなどのROMはエラーにして、今のROMはそのまま残す
+    pub fn set_rom(&mut self, rom: Rom) -> Result<(), RomError> {
+        rom.header.check_supported()?;
+        // トレーナーはPRG RAMの$7000〜$71FFに置く。512バイトを超える分は捨てる
+        if let Some(trainer) = &rom.trainer {
+            let len = trainer.len().min(0x200);
+            self.cpu.bus_mut().prg_ram_mut()[0x1000..0x1000 + len].copy_from_slice(&trainer[..len]);
+        }
+        // ヘッダで地域が指定されていればそれに合わせる。どちらでも動くものはNTSCにする
+        self.cpu.set_region(match rom.header.tv_system {
+            TvSystem::Pal => Region::Pal,
+            TvSystem::Dendy => Region::Dendy,
+            TvSystem::Ntsc | TvSystem::MultiRegion => Region::Ntsc,
+        });
+        // PRGはコピーせずにCPUと共有する
+        self.cpu.set_rom(Some(rom.program.clone()));
+        self.rom = Some(Arc::new(rom));
+        Ok(())
+    }
+
+    // リセットボタン。RAMはそのまま残る
+    pub fn reset(&mut self) -> Result<(), CpuError> {
+        self.cpu.reset()
+    }
+
+    // 電源を入れ直す。WRAMはinit_ramのパターンで埋め直す。PRG RAMはバッテリーやトレーナーのために残す
+    pub fn power_cycle(&mut self) -> Result<(), CpuError> {
+        self.init_ram(self.ram_pattern);
+        #[cfg(feature = "debugger")]
+        if let Some(uninit) = &mut self.uninit {
+            uninit.clear();
+        }
+        self.cpu.bus_mut().apply_freezes();
+        // フレームは電源を入れてから数える。リセットでは数え直さない
+        self.frame = 0;
+        self.cpu.power_on()
+    }
+
+    // 電源を入れたときのWRAMの中身を埋める。resetの前に呼ぶ
+    pub fn init_ram(&mut self, pattern: RamPattern) {
+        pattern.fill(self.cpu.bus_mut().ram_mut());
+        self.ram_pattern = pattern;
+    }
+
+    // Game Genieのコードか "0075:09" のような固定するアドレスと値。実行中に追加や削除をしてもよい
+    pub fn add_cheat(&mut self, code: &str) -> Result<Cheat, CheatError> {
+        let cheat = Cheat::parse(code)?;
+        self.cpu.bus_mut().add_cheat(cheat);
+        Ok(cheat)
+    }
+
+    // 入っていなければfalse
+    pub fn remove_cheat(&mut self, code: &str) -> Result<bool, CheatError> {
+        let cheat = Cheat::parse(code)?;
+        Ok(self.cpu.bus_mut().remove_cheat(cheat))
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        self.cpu.bus().cheats()
+    }
+
+    // 今のRAMの値から、チートに使うアドレスの検索を始める
+    pub fn start_cheat_search(&self) -> CheatSearch {
+        CheatSearch::new(self.cpu.bus())
+    }
+
+    // 今のRAMの値で候補を絞り込む。残った候補の数を返す
+    pub fn filter_cheat_search(&self, search: &mut CheatSearch, filter: SearchFilter) -> usize {
+        search.filter(self.cpu.bus(), filter)
     }
 
-    pub fn run(&mut self) {
-        self.cpu.reset();
+    pub fn region(&self) -> Region {
+        self.cpu.region()
+    }
+
+    // set_romでヘッダから決めた地域を上書きする
+    pub fn set_region(&mut self, region: Region) {
+        self.cpu.set_region(region);
+    }
+
+    // $4018〜$5FFFに独自の周辺機器をつなぐ
+    pub fn set_expansion(&mut self, expansion: Option<Box<dyn Expansion>>) {
+        self.cpu.bus_mut().set_expansion(expansion);
+    }
+
+    pub fn set_tracer(&mut self, tracer: Option<Tracer>) {
+        self.tracer = tracer;
+    }
+
+    // 実行したopcodeを記録するかどうか。有効にするたびに数え直す
+    pub fn set_opcode_coverage(&mut self, enabled: bool) {
+        self.coverage = if enabled {
+            Some(OpcodeCoverage::new())
+        } else {
+            None
+        };
+    }
+
+    pub fn opcode_coverage(&self) -> Option<&OpcodeCoverage> {
+        self.coverage.as_ref()
+    }
+
+    // 命令のデコード結果を使い回すかどうか。既定では使い回す
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.cpu.set_decode_cache(enabled);
+    }
+
+    // 1命令ごとに参照実装でも実行して、結果が違ったらDifferentialMismatchのエラーにする。遅いのでデバッグ用
+    pub fn set_differential(&mut self, enabled: bool) {
+        self.differential = enabled;
+    }
+
+    // 1命令実行してかかったクロック数を返す
+    pub fn step(&mut self) -> Result<u8, CpuError> {
+        Ok(self.step_instruction()?.cycles)
+    }
+
+    // フレームが終わるたびに呼ばれる。step_frameで待たなくてもよくなる
+    pub fn on_frame<F: FnMut(&Frame) + Send + 'static>(&mut self, callback: F) {
+        self.on_frame = Some(FrameCallback(Box::new(callback)));
+    }
+
+    pub fn clear_on_frame(&mut self) {
+        self.on_frame = None;
+    }
+
+    // スキャンラインが変わるたびに呼ばれる。1命令で2ライン進むことは無いので、どのラインも1回ずつ
+    pub fn on_scanline<F: FnMut(&Scanline) + Send + 'static>(&mut self, callback: F) {
+        self.on_scanline = Some(ScanlineCallback(Box::new(callback)));
+    }
+
+    pub fn clear_on_scanline(&mut self) {
+        self.on_scanline = None;
+    }
+
+    // 1命令実行して、何を実行したかを返す
+    pub fn step_instruction(&mut self) -> Result<StepResult, CpuError> {
+        // 止まっているときはトレースなども残さない
+        if self.cpu.halted() {
+            return self.cpu.run();
+        }
+        if let Some(tracer) = &mut self.tracer {
+            tracer.trace(&self.cpu);
+        }
+        let pc = self.cpu.registers().program_counter;
+        #[cfg(feature = "debugger")]
+        let routine = self.cpu.call_stack().last().map(|frame| frame.target);
+        let position = self.cpu.ppu_position();
+        let expected = if self.differential {
+            let cpu = &self.cpu;
+            reference::execute(cpu.registers(), |addr| cpu.peek(addr).unwrap_or(0))
+        } else {
+            None
+        };
+        let result = self.cpu.run();
+        if let Some(coverage) = &mut self.coverage {
+            match result {
+                Ok(result) => coverage.record(result.opcode),
+                Err(CpuError::UnimplementedOpcode { opcode, .. }) => {
+                    coverage.record_unsupported(opcode)
+                }
+                Err(_) => {}
+            }
+        }
+        let result = result?;
+        let clock = result.cycles;
+        if let Some(expected) = expected {
+            let compared =
+                reference::compare(&expected, self.cpu.registers(), self.cpu.accesses(), clock);
+            if let Err(detail) = compared {
+                return Err(CpuError::DifferentialMismatch { addr: pc, detail });
+            }
+        }
+        #[cfg(feature = "debugger")]
+        if let Some(stream) = &mut self.event_stream {
+            stream.instruction(&result, self.cpu.registers(), self.cpu.cycles());
+            stream.io(self.cpu.accesses(), self.cpu.ppu_position());
+        }
+        #[cfg(feature = "debugger")]
+        if let Some(profiler) = &mut self.profiler {
+            profiler.record(pc, routine, clock as u64);
+        }
+        #[cfg(feature = "debugger")]
+        if let Some(cdl) = &mut self.cdl {
+            cdl.log(self.cpu.accesses());
+        }
+        #[cfg(feature = "debugger")]
+        if let Some(heatmap) = &mut self.heatmap {
+            heatmap.record(self.cpu.accesses());
+        }
+        #[cfg(feature = "debugger")]
+        if let Some(uninit) = &mut self.uninit {
+            let reads = uninit.record(pc, self.cpu.accesses());
+            self.uninit_reads.extend(reads);
+        }
+        #[cfg(feature = "debugger")]
+        if let Some(event_log) = &mut self.event_log {
+            let region = self.cpu.region();
+            event_log.record(
+                self.cpu.accesses(),
+                region,
+                position,
+                self.cpu.ppu_position(),
+            );
+        }
+        if self.entered_vblank(position) {
+            let frame = Frame {
+                number: self.frame,
+                cycles: self.cpu.cycles(),
+            };
+            self.frame += 1;
+            #[cfg(feature = "debugger")]
+            if let Some(stream) = &mut self.event_stream {
+                stream.frame(frame.number, frame.cycles);
+            }
+            if let Some(FrameCallback(callback)) = &mut self.on_frame {
+                callback(&frame);
+            }
+        }
+        let (line, _) = self.cpu.ppu_position();
+        if line != position.0 {
+            let scanline = Scanline {
+                frame: self.frame,
+                line,
+                cycles: self.cpu.cycles(),
+            };
+            #[cfg(feature = "debugger")]
+            if let Some(stream) = &mut self.event_stream {
+                stream.scanline(scanline.frame, scanline.line, scanline.cycles);
+            }
+            if let Some(ScanlineCallback(callback)) = &mut self.on_scanline {
+                callback(&scanline);
+            }
+        }
+        Ok(result)
+    }
+
+    // 前の命令の位置から、今の命令でVBlankの最初のラインに入ったか
+    fn entered_vblank(&self, before: (u16, u16)) -> bool {
+        let (line, _) = self.cpu.ppu_position();
+        line != before.0 && line == self.cpu.region().vblank_scanline()
+    }
+
+    // 次のVBlankが始まるまで実行する
+    pub fn step_frame(&mut self) -> Result<(), CpuError> {
+        loop {
+            let position = self.cpu.ppu_position();
+            self.step()?;
+            if self.entered_vblank(position) {
+                return Ok(());
+            }
+        }
+    }
+
+    // JAMで止まっていればtrue。リセットすると動き出す
+    pub fn halted(&self) -> bool {
+        self.cpu.halted()
+    }
+
+    pub fn frame_count(&self) -> u64 {
+        self.frame
+    }
+
+    pub fn registers(&self) -> &Registers {
+        self.cpu.registers()
+    }
+
+    // 次に実行する命令のトレースの行。--traceで書き出すものと同じ
+    pub fn trace_line(&self) -> String {
+        tracer::trace_line(&self.cpu)
+    }
+
+    pub fn cycles(&self) -> u64 {
+        self.cpu.cycles()
+    }
+
+    pub fn set_registers(&mut self, registers: Registers) {
+        self.cpu.set_registers(registers);
+    }
+
+    // 副作用なしでCPUのアドレス空間を読む。I/Oレジスタなど読めないところはNone
+    pub fn peek(&self, addr: u16) -> Option<u8> {
+        self.cpu.peek(addr)
+    }
+
+    pub fn memory_view(&self) -> MemoryView {
+        let bus = self.cpu.bus();
+        MemoryView {
+            wram: bus.ram().to_vec(),
+            prg_ram: bus.prg_ram().to_vec(),
+            rom: self.rom.clone(),
+        }
+    }
+
+    // memory_viewで取った写しに書き込まれた内容をRAMに戻す
+    pub fn apply_memory_view(&mut self, view: &MemoryView) {
+        let bus = self.cpu.bus_mut();
+        bus.ram_mut().copy_from_slice(&view.wram);
+        bus.prg_ram_mut().copy_from_slice(&view.prg_ram);
+    }
+
+    // 直前に実行した命令でのメモリアクセス
+    pub fn accesses(&self) -> &[MemoryAccess] {
+        self.cpu.accesses()
+    }
+
+    pub fn ppu_position(&self) -> (u16, u16) {
+        self.cpu.ppu_position()
+    }
+
+    // RAMを直接書き換える。書き込めないアドレスならfalse
+    pub fn poke(&mut self, addr: u16, value: u8) -> bool {
+        #[cfg(feature = "debugger")]
+        if let Some(uninit) = &mut self.uninit {
+            uninit.mark_written(addr);
+        }
+        self.cpu.poke(addr, value)
+    }
+
+    pub fn peek_range(&self, space: MemorySpace, range: RangeInclusive<u16>) -> Vec<Option<u8>> {
+        match space {
+            MemorySpace::Cpu => range.map(|addr| self.cpu.peek(addr)).collect(),
+            MemorySpace::Chr => range
+                .map(|addr| {
+                    self.rom
+                        .as_ref()
+                        .and_then(|rom| rom.character.get(addr as usize).copied())
+                })
+                .collect(),
+        }
+    }
+
+    // PPUから見た$0000〜$1FFFの2枚のパターンテーブルを縦に並べる。CHR RAMなどで読めないところは0
+    pub fn tile_sheet(&self) -> TileSheet {
+        let chr: Vec<u8> = self
+            .peek_range(MemorySpace::Chr, 0x0000..=0x1fff)
+            .into_iter()
+            .map(|b| b.unwrap_or(0))
+            .collect();
+        TileSheet::decode(&chr)
+    }
+
+    pub fn hexdump(&self, space: MemorySpace, range: RangeInclusive<u16>) -> String {
+        let start = *range.start();
+        hexdump(start, &self.peek_range(space, range))
+    }
+
+    // 外側から順に並んだ、今実行中のサブルーチンの呼び出し
+    pub fn call_stack(&self) -> &[CallFrame] {
+        self.cpu.call_stack()
+    }
+
+    pub fn take_stack_warnings(&mut self) -> Vec<StackWarning> {
+        self.cpu.take_stack_warnings()
+    }
+
+    // 実行できない命令などで止まったらそのエラーを返す
+    pub fn run(&mut self) -> Result<(), CpuError> {
+        self.power_cycle()?;
 
         loop {
-            let clock = self.cpu.run();
-            println!("#################################################");
-            println!("clock: {}", clock);
+            let clock = self.step()?;
+            log::debug!(target: "nes::cpu", "clock: {}", clock);
             self.cpu.dump_registers();
             sleep(time::Duration::from_millis(500));
         }
     }
+
+    pub fn dump_registers(&self) {
+        self.cpu.dump_registers();
+    }
+
+    // 待たずにseconds秒実行して、何フレーム何命令進んだかを返す
+    pub fn run_benchmark(&mut self, seconds: f64) -> Result<BenchmarkResult, CpuError> {
+        let duration = time::Duration::from_secs_f64(seconds);
+        let start = time::Instant::now();
+        let mut frames = 0;
+        let mut instructions = 0;
+        loop {
+            // 時計を見るのは重いのでたまにだけ
+            if instructions % 1024 == 0 && start.elapsed() >= duration {
+                break;
+            }
+            let (line_before, _) = self.cpu.ppu_position();
+            self.step()?;
+            let (line, _) = self.cpu.ppu_position();
+            if line != line_before && line == self.cpu.region().vblank_scanline() {
+                frames += 1;
+            }
+            instructions += 1;
+        }
+        Ok(BenchmarkResult {
+            frames,
+            instructions,
+            elapsed: start.elapsed(),
+        })
+    }
+
+    // バッテリーバックアップされている$6000〜$7FFFの中身。バッテリーが無ければNone
+    pub fn battery_ram(&self) -> Option<Vec<u8>> {
+        match &self.rom {
+            Some(rom) if rom.has_battery() => Some(self.cpu.bus().prg_ram().to_vec()),
+            _ => None,
+        }
+    }
+
+    pub fn load_battery_ram(&mut self, data: &[u8]) -> Result<(), Box<dyn Error>> {
+        let prg_ram = self.cpu.bus_mut().prg_ram_mut();
+        if data.len() != prg_ram.len() {
+            return Err("Invalid battery RAM size.".into());
+        }
+        prg_ram.copy_from_slice(data);
+        Ok(())
+    }
+
+    pub fn save_state(&self) -> Vec<u8> {
+        let bus = self.cpu.bus();
+        let mut state = SaveState::new();
+        state.insert(
+            SECTION_CPU,
+            bincode::serialize(&self.cpu).expect("Failed to serialize state."),
+        );
+        state.insert(SECTION_WRAM, bus.ram().to_vec());
+        state.insert(SECTION_PRG_RAM, bus.prg_ram().to_vec());
+        state.to_bytes()
+    }
+
+    // エミュレートしている状態全体のハッシュ。2つの実行が同じ状態にいるかの比較に使う
+    pub fn state_hash(&self) -> u64 {
+        fnv1a_64(&self.save_state())
+    }
+
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), Box<dyn Error>> {
+        // 古い形式はここで今の形式に直る
+        let state = SaveState::from_bytes(state)?;
+        let cpu: Cpu = bincode::deserialize(state.get(SECTION_CPU)?)
+            .map_err(|_| StateError::InvalidSection(SECTION_CPU))?;
+        let wram = state.get(SECTION_WRAM)?;
+        if wram.len() != WRAM_SIZE {
+            return Err(StateError::InvalidSection(SECTION_WRAM).into());
+        }
+        let prg_ram = state.get(SECTION_PRG_RAM)?;
+        if prg_ram.len() != PRG_RAM_SIZE {
+            return Err(StateError::InvalidSection(SECTION_PRG_RAM).into());
+        }
+
+        self.cpu.restore(cpu);
+        let bus = self.cpu.bus_mut();
+        bus.ram_mut().copy_from_slice(wram);
+        bus.prg_ram_mut().copy_from_slice(prg_ram);
+        // チートはステートに含めないので、固定している値を書き直す
+        bus.apply_freezes();
+        Ok(())
+    }
+}
+
+// デバッガから使うもの
+#[cfg(feature = "debugger")]
+impl Nes {
+    // 命令やフレームなどを1行1つのJSONで書き出す
+    pub fn set_event_stream(&mut self, stream: Option<EventStream>) {
+        self.event_stream = stream;
+    }
+
+    // プロファイルを取るかどうか。有効にするたびに集計をやり直す
+    pub fn set_profiling(&mut self, enabled: bool) {
+        self.profiler = if enabled {
+            Some(Profiler::default())
+        } else {
+            None
+        };
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    pub fn set_code_data_logger(&mut self, cdl: Option<CodeDataLogger>) {
+        self.cdl = cdl;
+    }
+
+    pub fn code_data_logger(&self) -> Option<&CodeDataLogger> {
+        self.cdl.as_ref()
+    }
+
+    // フレームごとのレジスタ書き込みを記録するかどうか
+    pub fn set_event_logging(&mut self, enabled: bool) {
+        self.event_log = if enabled {
+            Some(EventLog::default())
+        } else {
+            None
+        };
+    }
+
+    pub fn event_log(&self) -> Option<&EventLog> {
+        self.event_log.as_ref()
+    }
+
+    // アドレスごとのアクセス回数を数えるかどうか。有効にするたびに数え直す
+    pub fn set_heatmap(&mut self, enabled: bool) {
+        self.heatmap = if enabled { Some(Heatmap::new()) } else { None };
+    }
+
+    pub fn heatmap(&self) -> Option<&Heatmap> {
+        self.heatmap.as_ref()
+    }
+
+    // 書き込む前のWRAMを読んだら知らせる。有効にした時点ではどこにも書き込んでいないことにする
+    pub fn set_uninit_detection(&mut self, enabled: bool) {
+        self.uninit = if enabled {
+            Some(UninitDetector::new())
+        } else {
+            None
+        };
+        self.uninit_reads.clear();
+    }
+
+    // 前回呼んでから見つかった、書き込む前のWRAMの読み込み
+    pub fn take_uninitialized_reads(&mut self) -> Vec<UninitializedRead> {
+        std::mem::take(&mut self.uninit_reads)
+    }
+
+    pub fn symbols(&self) -> &SymbolTable {
+        self.debugger.symbols()
+    }
+
+    pub fn debugger(&mut self) -> &mut Debugger {
+        &mut self.debugger
+    }
+
+    // 1命令実行して、ブレークポイントかウォッチポイントに引っかかったらその理由を返す
+    // 命令を実行できなかったときと、書き込む前のWRAMを読んだときもその理由で止まる
+    pub fn step_debug(&mut self) -> Option<BreakReason> {
+        let reported = self.uninit_reads.len();
+        if let Err(err) = self.step() {
+            return Some(BreakReason::Error(err));
+        }
+        if let Some(read) = self.uninit_reads.get(reported).copied() {
+            return Some(BreakReason::UninitializedRead(read));
+        }
+        self.debugger.check(&self.cpu)
+    }
+
+    // ブレークポイントに関係なく1命令だけ実行する
+    pub fn step_into(&mut self) -> BreakReason {
+        match self.step_debug() {
+            Some(
+                reason @ (BreakReason::Watchpoint(_)
+                | BreakReason::UninitializedRead(_)
+                | BreakReason::Error(_)),
+            ) => reason,
+            _ => BreakReason::Step,
+        }
+    }
+
+    // JSRならサブルーチンから戻ってくるまで実行する。それ以外は1命令だけ
+    pub fn step_over(&mut self) -> BreakReason {
+        let depth = self.cpu.call_depth();
+        if let Some(
+            reason @ (BreakReason::Watchpoint(_)
+            | BreakReason::UninitializedRead(_)
+            | BreakReason::Error(_)),
+        ) = self.step_debug()
+        {
+            return reason;
+        }
+        while self.cpu.call_depth() > depth {
+            if let Some(reason) = self.step_debug() {
+                return reason;
+            }
+        }
+        BreakReason::Step
+    }
+
+    // 今いるサブルーチンからRTSで戻るまで実行する。
+    // JSRを見ていなくて呼び出し元が分からないときは最初のRTSで止まる
+    pub fn step_out(&mut self) -> BreakReason {
+        let depth = self.cpu.call_depth();
+        loop {
+            let reason = self.step_debug();
+            if let Some(BreakReason::Error(err)) = reason {
+                return BreakReason::Error(err);
+            }
+            let returned = match self.cpu.accesses().first() {
+                Some(access) if depth == 0 => access.value == RTS_OPCODE,
+                _ => self.cpu.call_depth() < depth,
+            };
+            if returned {
+                return reason.unwrap_or(BreakReason::Step);
+            }
+            if let Some(reason) = reason {
+                return reason;
+            }
+        }
+    }
+
+    // ブレークポイントかウォッチポイントに引っかかるまで実行する。
+    // 今のPCにブレークポイントがあっても最初の1命令は実行する
+    pub fn run_until_break(&mut self) -> BreakReason {
+        loop {
+            if let Some(reason) = self.step_debug() {
+                return reason;
+            }
+        }
+    }
+}
+
+impl Default for Nes {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{MemorySpace, Nes};
+    use crate::{
+        cheat_search::SearchFilter,
+        cpu::CpuError,
+        ram::RamPattern,
+        region::Region,
+        rom::{Rom, RomError, TvSystem},
+    };
+    use std::{
+        fs::File,
+        io::BufReader,
+        sync::{Arc, Mutex},
+    };
+
+    #[test]
+    fn test_send() {
+        // バックグラウンドのスレッドやサーバーで動かせるように
+        fn assert_send<T: Send>() {}
+        assert_send::<Nes>();
+    }
+
+    #[test]
+    fn test_run_in_thread() {
+        let mut nes = prepare();
+        nes.on_frame(|_| {});
+        let nes = std::thread::spawn(move || {
+            nes.step_frame().unwrap();
+            nes
+        })
+        .join()
+        .unwrap();
+        assert_eq!(nes.frame_count(), 1);
+    }
+
+    #[test]
+    fn test_save_and_load_state() {
+        let mut nes = prepare();
+        for _ in 0..10 {
+            nes.step().unwrap();
+        }
+        let state = nes.save_state();
+        let registers = nes.cpu.get_registers().clone();
+        let wram = nes.cpu.bus().ram().to_vec();
+
+        for _ in 0..10 {
+            nes.step().unwrap();
+        }
+        nes.cpu.bus_mut().ram_mut()[0x0010] = 0xaa;
+        assert_ne!(*nes.cpu.get_registers(), registers);
+
+        nes.load_state(&state).unwrap();
+        assert_eq!(*nes.cpu.get_registers(), registers);
+        assert_eq!(nes.cpu.bus().ram(), &wram[..]);
+    }
+
+    #[test]
+    fn test_load_state_unsupported_version() {
+        let mut nes = prepare();
+        let mut state = nes.save_state();
+        state[4] = 0xff;
+        let err = nes.load_state(&state).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("State version 255 was written by nes"));
+        let err = nes.load_state(&2u32.to_le_bytes()).unwrap_err();
+        assert_eq!("Unsupported state version: 2", err.to_string());
+    }
+
+    #[test]
+    fn test_state_hash() {
+        let mut nes = prepare();
+        let mut other = prepare();
+        assert_eq!(nes.state_hash(), other.state_hash());
+
+        nes.step().unwrap();
+        assert_ne!(nes.state_hash(), other.state_hash());
+
+        other.step().unwrap();
+        assert_eq!(nes.state_hash(), other.state_hash());
+
+        other.cpu.bus_mut().ram_mut()[0x0100] = 0x01;
+        assert_ne!(nes.state_hash(), other.state_hash());
+    }
+
+    #[test]
+    fn test_peek_range() {
+        let nes = prepare();
+        assert_eq!(
+            nes.peek_range(MemorySpace::Cpu, 0x8000..=0x8002),
+            vec![Some(0x78), Some(0xa2), Some(0xff)]
+        );
+        assert_eq!(
+            nes.peek_range(MemorySpace::Cpu, 0x2001..=0x2002),
+            vec![None, None]
+        );
+        assert_eq!(
+            nes.peek_range(MemorySpace::Chr, 0x0410..=0x0411),
+            vec![Some(0x3c), Some(0x3c)]
+        );
+        assert_eq!(
+            nes.peek_range(MemorySpace::Chr, 0x2000..=0x2000),
+            vec![None]
+        );
+        assert_eq!(
+            nes.hexdump(MemorySpace::Cpu, 0x8061..=0x8063),
+            "8060:    48 45 4C                                       HEL"
+        );
+    }
+
+    #[test]
+    fn test_battery_ram() {
+        let mut nes = prepare();
+        assert_eq!(nes.battery_ram(), None);
+
+        let mut rom = (**nes.rom.as_ref().unwrap()).clone();
+        rom.header.has_battery = true;
+        nes.set_rom(rom).unwrap();
+        let mut data = vec![0; 0x2000];
+        data[0x0123] = 0x45;
+        nes.load_battery_ram(&data).unwrap();
+        assert_eq!(nes.battery_ram(), Some(data));
+
+        let err = nes.load_battery_ram(&[0; 0x10]).unwrap_err();
+        assert_eq!("Invalid battery RAM size.", err.to_string());
+    }
+
+    #[test]
+    fn test_differential() {
+        let mut nes = prepare();
+        nes.set_differential(true);
+        for _ in 0..100 {
+            nes.step().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_trainer() {
+        let mut nes = prepare();
+        let mut rom = (**nes.rom.as_ref().unwrap()).clone();
+        rom.trainer = Some(vec![0x12; 0x200]);
+        nes.set_rom(rom).unwrap();
+        assert_eq!(nes.peek(0x6fff), Some(0x00));
+        assert_eq!(nes.peek(0x7000), Some(0x12));
+        assert_eq!(nes.peek(0x71ff), Some(0x12));
+        assert_eq!(nes.peek(0x7200), Some(0x00));
+
+        let mut rom = (**nes.rom.as_ref().unwrap()).clone();
+        rom.trainer = Some(vec![0x34; 0x300]);
+        nes.set_rom(rom).unwrap();
+        assert_eq!(nes.peek(0x71ff), Some(0x34));
+        assert_eq!(nes.peek(0x7200), Some(0x00));
+    }
+
+    #[test]
+    fn test_init_ram() {
+        let mut nes = prepare();
+        nes.init_ram(RamPattern::Ones);
+        assert_eq!(nes.peek(0x0000), Some(0xff));
+        assert_eq!(nes.peek(0x07ff), Some(0xff));
+        // PRG RAMはそのまま
+        assert_eq!(nes.peek(0x6000), Some(0x00));
+    }
+
+    #[test]
+    fn test_region() {
+        let mut nes = prepare();
+        assert_eq!(nes.region(), Region::Ntsc);
+        let mut rom = (**nes.rom.as_ref().unwrap()).clone();
+        rom.header.tv_system = TvSystem::Pal;
+        nes.set_rom(rom).unwrap();
+        assert_eq!(nes.region(), Region::Pal);
+    }
+
+    #[test]
+    fn test_run_benchmark() {
+        let mut nes = prepare();
+        let cycles = nes.cycles();
+        let result = nes.run_benchmark(0.01).unwrap();
+        assert!(result.instructions > 0);
+        assert!(result.elapsed.as_secs_f64() >= 0.01);
+        // 1フレームは341×262ドット = 29780.67クロック
+        let frames = (nes.cycles() - cycles) * 3 / (341 * 262);
+        assert!(result.frames.abs_diff(frames) <= 1);
+    }
+
+    #[test]
+    fn test_cheat() {
+        let mut nes = prepare();
+        let original = nes.peek(0xd1dd);
+        let code = nes.add_cheat("GOSSIP").unwrap();
+        assert_eq!(nes.peek(0xd1dd), Some(0x14));
+        assert_eq!(nes.cheats(), &[code]);
+        assert_eq!(nes.remove_cheat("GOSSIP"), Ok(true));
+        assert_eq!(nes.peek(0xd1dd), original);
+        assert!(nes.cheats().is_empty());
+        assert!(nes.add_cheat("XYZ").is_err());
+
+        // ステートを読み込んでも固定した値のまま
+        let state = nes.save_state();
+        nes.add_cheat("0010:5a").unwrap();
+        assert_eq!(nes.peek(0x0010), Some(0x5a));
+        nes.load_state(&state).unwrap();
+        assert_eq!(nes.peek(0x0010), Some(0x5a));
+    }
+
+    #[test]
+    fn test_cheat_search() {
+        let mut nes = prepare();
+        nes.poke(0x0075, 3);
+        let mut search = nes.start_cheat_search();
+        nes.poke(0x0075, 2);
+        nes.filter_cheat_search(&mut search, SearchFilter::ChangedBy(-1));
+        assert_eq!(search.candidates().collect::<Vec<_>>(), vec![(0x0075, 2)]);
+    }
+
+    #[test]
+    fn test_step_instruction() {
+        let mut nes = prepare();
+        let pc = nes.registers().program_counter;
+        let opcode = nes.peek(pc).unwrap();
+        let cycles = nes.cycles();
+        let result = nes.step_instruction().unwrap();
+        assert_eq!(result.pc_before, pc);
+        assert_eq!(result.opcode, opcode);
+        assert_eq!(result.cycles as u64, nes.cycles() - cycles);
+        assert!(!result.interrupt);
+    }
+
+    #[test]
+    fn test_tile_sheet() {
+        let nes = prepare();
+        let sheet = nes.tile_sheet();
+        assert_eq!((sheet.width, sheet.height), (128, 256));
+        // $0410からの"A"のタイル($41)は5行目の2枚目
+        let top = 4 * 8 * 128 + 8;
+        assert_eq!(&sheet.pixels[top..top + 8], &[0, 0, 3, 3, 3, 1, 0, 0]);
+    }
+
+    #[test]
+    fn test_on_frame() {
+        let mut nes = prepare();
+        let frames = Arc::new(Mutex::new(Vec::new()));
+        let received = Arc::clone(&frames);
+        nes.on_frame(move |frame| received.lock().unwrap().push(*frame));
+        nes.step_frame().unwrap();
+        nes.step_frame().unwrap();
+        let frames = frames.lock().unwrap();
+        assert_eq!(frames.len(), 2);
+        assert_eq!(frames[0].number, 0);
+        assert_eq!(frames[1].number, 1);
+        assert_eq!(frames[1].cycles, nes.cycles());
+        assert_eq!(nes.frame_count(), 2);
+    }
+
+    #[test]
+    fn test_on_scanline() {
+        let mut nes = prepare();
+        let lines = Arc::new(Mutex::new(Vec::new()));
+        let received = Arc::clone(&lines);
+        nes.on_scanline(move |scanline| received.lock().unwrap().push(*scanline));
+        nes.step_frame().unwrap();
+        let lines = lines.lock().unwrap();
+        let vblank_scanline = Region::Ntsc.vblank_scanline();
+        assert_eq!(lines.len(), vblank_scanline as usize);
+        assert_eq!(lines[0].line, 1);
+        assert_eq!(lines[0].frame, 0);
+        let last = lines.last().unwrap();
+        assert_eq!(last.line, vblank_scanline);
+        assert_eq!(last.frame, 1);
+        assert_eq!(last.cycles, nes.cycles());
+
+        nes.clear_on_scanline();
+        nes.step_frame().unwrap();
+        assert_eq!(lines.len(), vblank_scanline as usize);
+    }
+
+    #[test]
+    fn test_set_rom_unsupported() {
+        let mut nes = prepare();
+        let mut rom = (**nes.rom.as_ref().unwrap()).clone();
+        rom.header.mapper = 4;
+        assert!(matches!(
+            nes.set_rom(rom),
+            Err(RomError::UnsupportedMapper(4))
+        ));
+    }
+
+    #[test]
+    fn test_step_unimplemented_opcode() {
+        let mut nes = prepare();
+        nes.set_opcode_coverage(true);
+        nes.step().unwrap();
+        let mut registers = nes.registers().clone();
+        registers.program_counter = 0x0300;
+        nes.set_registers(registers);
+        nes.poke(0x0300, 0xff);
+        let err = nes.step().unwrap_err();
+        assert_eq!(
+            err,
+            CpuError::UnimplementedOpcode {
+                addr: 0x0300,
+                opcode: 0xff
+            }
+        );
+        assert_eq!(err.to_string(), "Opcode $FF at $0300 is not implemented.");
+        let coverage = nes.opcode_coverage().unwrap();
+        assert_eq!(coverage.executed(0x78), 1);
+        assert_eq!(coverage.unsupported(), vec![(0xff, 1)]);
+    }
+
+    #[test]
+    fn test_power_cycle() {
+        let mut nes = prepare();
+        nes.init_ram(RamPattern::Ones);
+        nes.poke(0x0010, 0x12);
+        nes.poke(0x6000, 0x34);
+        nes.add_cheat("0075:09").unwrap();
+        nes.step_frame().unwrap();
+        assert_eq!(nes.frame_count(), 1);
+
+        // リセットではRAMもフレームの数も変わらない
+        nes.reset().unwrap();
+        assert_eq!(nes.peek(0x0010), Some(0x12));
+        assert_eq!(nes.frame_count(), 1);
+        assert_eq!(nes.registers().program_counter, 0x8000);
+
+        nes.power_cycle().unwrap();
+        assert_eq!(nes.frame_count(), 0);
+        assert_eq!(nes.peek(0x0010), Some(0xff));
+        assert_eq!(nes.peek(0x0075), Some(0x09));
+        assert_eq!(nes.peek(0x6000), Some(0x34));
+        assert_eq!(nes.cycles(), 7);
+    }
+
+    #[test]
+    fn test_halted() {
+        let mut nes = prepare();
+        let mut registers = nes.registers().clone();
+        registers.program_counter = 0x0300;
+        nes.set_registers(registers);
+        nes.poke(0x0300, 0x12);
+        let err = nes.step().unwrap_err();
+        assert_eq!(err.to_string(), "CPU halted by opcode $12 at $0300.");
+        assert!(nes.halted());
+        // ステートから戻しても止まる
+        let state = nes.save_state();
+        nes.reset().unwrap();
+        assert!(!nes.halted());
+        nes.step().unwrap();
+        nes.load_state(&state).unwrap();
+        assert!(nes.step().is_err());
+        assert!(nes.halted());
+    }
+
+    #[test]
+    fn test_load_state_after_halt() {
+        let mut nes = prepare();
+        let state = nes.save_state();
+        let mut registers = nes.registers().clone();
+        registers.program_counter = 0x0300;
+        nes.set_registers(registers);
+        nes.poke(0x0300, 0x12);
+        assert!(nes.step().is_err());
+        assert!(nes.halted());
+        // 止まる前のステートを読み込めば動き出す
+        nes.load_state(&state).unwrap();
+        assert!(!nes.halted());
+        nes.step().unwrap();
+    }
+
+    #[test]
+    fn test_step_frame() {
+        let mut nes = prepare();
+        nes.step_frame().unwrap();
+        assert_eq!(nes.ppu_position().0, Region::Ntsc.vblank_scanline());
+    }
+
+    fn prepare() -> Nes {
+        let mut reader = BufReader::new(File::open("./tests/rom/hello_world.nes").unwrap());
+        let mut nes = Nes::new();
+        nes.set_rom(Rom::load(&mut reader).unwrap()).unwrap();
+        nes.reset().unwrap();
+        nes
+    }
 }
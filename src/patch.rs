@@ -0,0 +1,264 @@
+use crate::checksum::crc32;
+use std::{error::Error, result::Result};
+
+// 先頭のマジックナンバーでIPSかBPSかを決めて、ヘッダを含めたROMのファイルに当てる
+pub fn apply(patch: &[u8], source: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    if patch.starts_with(b"PATCH") {
+        apply_ips(patch, source)
+    } else if patch.starts_with(b"BPS1") {
+        apply_bps(patch, source)
+    } else {
+        Err("Unknown patch format.".into())
+    }
+}
+
+// "PATCH" の後に、3バイトのオフセットと2バイトの長さとデータが "EOF" まで続く。
+// 長さが0のときはRLEで、2バイトの個数と1バイトの値になる
+fn apply_ips(patch: &[u8], source: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    let mut target = source.to_vec();
+    let mut reader = PatchReader::new(&patch[5..]);
+    loop {
+        let offset = reader.bytes(3)?;
+        if offset == b"EOF" {
+            break;
+        }
+        let offset =
+            ((offset[0] as usize) << 16) | ((offset[1] as usize) << 8) | offset[2] as usize;
+        let size = reader.u16_be()? as usize;
+        let data = if size == 0 {
+            let count = reader.u16_be()? as usize;
+            vec![reader.byte()?; count]
+        } else {
+            reader.bytes(size)?.to_vec()
+        };
+        if target.len() < offset + data.len() {
+            target.resize(offset + data.len(), 0);
+        }
+        target[offset..offset + data.len()].copy_from_slice(&data);
+    }
+    // EOFの後ろに3バイトあれば切り詰める長さ
+    if let Ok(size) = reader.bytes(3) {
+        target.truncate(((size[0] as usize) << 16) | ((size[1] as usize) << 8) | size[2] as usize);
+    }
+    Ok(target)
+}
+
+// "BPS1" の後に元と結果のサイズ、メタデータ、コマンドが続き、最後の12バイトは
+// 元、結果、パッチ自身のCRC32
+fn apply_bps(patch: &[u8], source: &[u8]) -> Result<Vec<u8>, Box<dyn Error>> {
+    if patch.len() < 16 {
+        return Err("Patch is truncated.".into());
+    }
+    let footer = &patch[patch.len() - 12..];
+    let crc =
+        |i: usize| u32::from_le_bytes([footer[i], footer[i + 1], footer[i + 2], footer[i + 3]]);
+    if crc32(&patch[..patch.len() - 4]) != crc(8) {
+        return Err("Patch checksum mismatch.".into());
+    }
+    if crc32(source) != crc(0) {
+        return Err("Patch does not match this ROM.".into());
+    }
+
+    let mut reader = PatchReader::new(&patch[4..patch.len() - 12]);
+    let source_size = reader.number()?;
+    let target_size = reader.number()?;
+    let metadata_size = reader.number()?;
+    reader.bytes(metadata_size)?;
+    if source_size != source.len() {
+        return Err("Patch does not match this ROM.".into());
+    }
+
+    // ヘッダの大きさは壊れていることもあるので、先に確保しない
+    let mut target = Vec::new();
+    let mut source_offset = 0;
+    let mut target_offset = 0;
+    while !reader.is_empty() {
+        let data = reader.number()?;
+        let length = (data >> 2) + 1;
+        // ヘッダの大きさを超えて書き出すパッチは壊れている
+        if target
+            .len()
+            .checked_add(length)
+            .is_none_or(|end| end > target_size)
+        {
+            return Err("Patch writes past the end of the output.".into());
+        }
+        match data & 0x03 {
+            // SourceRead: 元の同じ位置からコピー
+            0 => target.extend_from_slice(source_range(source, target.len(), length)?),
+            // TargetRead: パッチの中のデータ
+            1 => target.extend_from_slice(reader.bytes(length)?),
+            // SourceCopy: 元の好きな位置からコピー
+            2 => {
+                source_offset = relative(source_offset, reader.number()?)?;
+                target.extend_from_slice(source_range(source, source_offset, length)?);
+                source_offset += length;
+            }
+            // TargetCopy: 書き出したところからコピー。重なっていてもよい
+            _ => {
+                target_offset = relative(target_offset, reader.number()?)?;
+                for _ in 0..length {
+                    let b = *target
+                        .get(target_offset)
+                        .ok_or("Patch reads outside of the output.")?;
+                    target.push(b);
+                    target_offset += 1;
+                }
+            }
+        }
+    }
+
+    if target.len() != target_size || crc32(&target) != crc(4) {
+        return Err("Patched ROM checksum mismatch.".into());
+    }
+    Ok(target)
+}
+
+fn source_range(source: &[u8], start: usize, length: usize) -> Result<&[u8], Box<dyn Error>> {
+    start
+        .checked_add(length)
+        .and_then(|end| source.get(start..end))
+        .ok_or_else(|| "Patch reads outside of the ROM.".into())
+}
+
+// 最下位ビットが符号で残りが絶対値
+fn relative(offset: usize, data: usize) -> Result<usize, Box<dyn Error>> {
+    let delta = data >> 1;
+    let offset = if data & 1 == 0 {
+        offset.checked_add(delta)
+    } else {
+        offset.checked_sub(delta)
+    };
+    offset.ok_or_else(|| "Invalid relative offset in patch.".into())
+}
+
+struct PatchReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> PatchReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.data.is_empty()
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Box<dyn Error>> {
+        if self.data.len() < len {
+            return Err("Patch is truncated.".into());
+        }
+        let (bytes, rest) = self.data.split_at(len);
+        self.data = rest;
+        Ok(bytes)
+    }
+
+    fn byte(&mut self) -> Result<u8, Box<dyn Error>> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16_be(&mut self) -> Result<u16, Box<dyn Error>> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    // BPSの可変長の数値。7ビットずつで最上位ビットが立っていたら終わり
+    fn number(&mut self) -> Result<usize, Box<dyn Error>> {
+        let mut data: usize = 0;
+        let mut shift: usize = 1;
+        loop {
+            let x = self.byte()?;
+            data = ((x & 0x7f) as usize)
+                .checked_mul(shift)
+                .and_then(|value| data.checked_add(value))
+                .ok_or("Invalid number in patch.")?;
+            if x & 0x80 != 0 {
+                return Ok(data);
+            }
+            shift = shift.checked_shl(7).ok_or("Invalid number in patch.")?;
+            data = data.checked_add(shift).ok_or("Invalid number in patch.")?;
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::apply;
+    use crate::checksum::crc32;
+
+    #[test]
+    fn test_ips() {
+        let source = vec![0; 8];
+        let mut patch = b"PATCH".to_vec();
+        // 2バイト目から2バイト書き換える
+        patch.extend(&[0x00, 0x00, 0x02, 0x00, 0x02, 0xaa, 0xbb]);
+        // RLEで末尾を延ばす
+        patch.extend(&[0x00, 0x00, 0x07, 0x00, 0x00, 0x00, 0x03, 0xcc]);
+        patch.extend(b"EOF");
+        assert_eq!(
+            apply(&patch, &source).unwrap(),
+            vec![0, 0, 0xaa, 0xbb, 0, 0, 0, 0xcc, 0xcc, 0xcc]
+        );
+
+        patch.extend(&[0x00, 0x00, 0x04]);
+        assert_eq!(apply(&patch, &source).unwrap(), vec![0, 0, 0xaa, 0xbb]);
+
+        assert!(apply(b"PATCH\x00\x00", &source).is_err());
+        assert!(apply(b"XXXX", &source).is_err());
+    }
+
+    #[test]
+    fn test_bps() {
+        let source = b"ABCDEFGH".to_vec();
+        let target = b"ABxyCDCDCD".to_vec();
+        let mut patch = b"BPS1".to_vec();
+        // 元8バイト、結果10バイト、メタデータ無し
+        patch.extend(&[0x88, 0x8a, 0x80]);
+        // SourceRead 2
+        patch.push(0x80 | ((2 - 1) << 2));
+        // TargetRead 2 "xy"
+        patch.extend(&[0x80 | ((2 - 1) << 2) | 1, b'x', b'y']);
+        // SourceCopy 2 from +2
+        patch.extend(&[0x80 | ((2 - 1) << 2) | 2, 0x80 | (2 << 1)]);
+        // TargetCopy 4 from +4 (重なる)
+        patch.extend(&[0x80 | ((4 - 1) << 2) | 3, 0x80 | (4 << 1)]);
+        patch.extend(&crc32(&source).to_le_bytes());
+        patch.extend(&crc32(&target).to_le_bytes());
+        patch.extend(&crc32(&patch).to_le_bytes());
+        assert_eq!(apply(&patch, &source).unwrap(), target);
+
+        let err = apply(&patch, b"ABCDEFGX").unwrap_err();
+        assert_eq!("Patch does not match this ROM.", err.to_string());
+
+        let last = patch.len() - 1;
+        patch[last] ^= 0xff;
+        let err = apply(&patch, &source).unwrap_err();
+        assert_eq!("Patch checksum mismatch.", err.to_string());
+    }
+
+    #[test]
+    fn test_bps_broken() {
+        let source = b"ABCDEFGH".to_vec();
+        let bps = |target_size: &[u8], actions: &[u8]| {
+            let mut patch = b"BPS1".to_vec();
+            patch.push(0x88);
+            patch.extend(target_size);
+            patch.push(0x80);
+            patch.extend(actions);
+            patch.extend(&crc32(&source).to_le_bytes());
+            patch.extend(&[0; 4]);
+            patch.extend(&crc32(&patch).to_le_bytes());
+            patch
+        };
+        // 結果が2^56バイト近くあると書いてあっても先に確保しない
+        let huge = bps(&[0x7f, 0x7f, 0x7f, 0x7f, 0x7f, 0x7f, 0x7f, 0x80], &[0x80]);
+        let err = apply(&huge, &source).unwrap_err();
+        assert_eq!("Patched ROM checksum mismatch.", err.to_string());
+
+        // 結果4バイトなのにTargetReadで1バイト書いてからTargetCopyで8バイト書く
+        let over = bps(&[0x84], &[0x81, b'x', 0x80 | ((8 - 1) << 2) | 3, 0x80]);
+        let err = apply(&over, &source).unwrap_err();
+        assert_eq!("Patch writes past the end of the output.", err.to_string());
+    }
+}
@@ -0,0 +1,94 @@
+use crate::checksum::{adler32, crc32};
+use alloc::{vec, vec::Vec};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+// 無圧縮のdeflateブロックに入る最大のバイト数
+const STORED_BLOCK_SIZE: usize = 0xffff;
+
+// RGBA 8bitのPNGにする。圧縮はしない
+pub fn encode_rgba(width: u32, height: u32, rgba: &[u8]) -> Vec<u8> {
+    assert_eq!(
+        rgba.len(),
+        width as usize * height as usize * 4,
+        "Pixel data does not match the image size."
+    );
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&width.to_be_bytes());
+    header.extend_from_slice(&height.to_be_bytes());
+    // ビット深度8、RGBA、deflate、フィルタ0、インターレース無し
+    header.extend_from_slice(&[8, 6, 0, 0, 0]);
+
+    // 各行の先頭にフィルタの種類(0: なし)を付ける
+    let mut raw = Vec::with_capacity(rgba.len() + height as usize);
+    for row in rgba
+        .chunks(width as usize * 4)
+        .filter(|row| !row.is_empty())
+    {
+        raw.push(0);
+        raw.extend_from_slice(row);
+    }
+
+    let mut png = SIGNATURE.to_vec();
+    write_chunk(&mut png, b"IHDR", &header);
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(STORED_BLOCK_SIZE).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xff, 0xff]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::{encode_rgba, zlib_stored};
+
+    #[test]
+    fn test_encode_rgba() {
+        let png = encode_rgba(2, 1, &[255, 0, 0, 255, 0, 0, 255, 255]);
+        assert_eq!(&png[..8], b"\x89PNG\r\n\x1a\n");
+        assert_eq!(&png[12..16], b"IHDR");
+        assert_eq!(&png[16..29], &[0, 0, 0, 2, 0, 0, 0, 1, 8, 6, 0, 0, 0]);
+        // IENDはいつも同じ
+        assert_eq!(
+            &png[png.len() - 12..],
+            &[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xae, 0x42, 0x60, 0x82]
+        );
+    }
+
+    #[test]
+    fn test_zlib_stored() {
+        let data = vec![0x5a; 0x10000];
+        let out = zlib_stored(&data);
+        // 0xFFFFバイトと1バイトの2ブロック
+        assert_eq!(out.len(), 2 + 5 + 0xffff + 5 + 1 + 4);
+        assert_eq!(&out[2..7], &[0x00, 0xff, 0xff, 0x00, 0x00]);
+        assert_eq!(
+            &out[7 + 0xffff..7 + 0xffff + 5],
+            &[0x01, 0x01, 0x00, 0xfe, 0xff]
+        );
+    }
+}
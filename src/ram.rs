@@ -1,3 +1,95 @@
-use std::{cell::RefCell, rc::Rc};
+// 本体のRAM($0000〜$07FF)とカートリッジのPRG RAM($6000〜$7FFF)の大きさ
+pub const WRAM_SIZE: usize = 0x800;
+pub const PRG_RAM_SIZE: usize = 0x2000;
 
-pub type Ram = Rc<RefCell<Vec<u8>>>;
+// 電源を入れたときのRAMの中身。実機では不定で、これによって動きが変わるゲームがある
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum RamPattern {
+    #[default]
+    Zero,
+    Ones,
+    // FCEUXと同じく$00を4バイト、$FFを4バイトの繰り返し
+    Alternating,
+    // 同じシードなら同じ中身になる
+    Random(u64),
+}
+
+impl RamPattern {
+    // zero, ff, alternating, random:SEED
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "zero" => Some(Self::Zero),
+            "ff" => Some(Self::Ones),
+            "alternating" => Some(Self::Alternating),
+            _ => {
+                let seed = name.strip_prefix("random:")?;
+                seed.parse().ok().map(Self::Random)
+            }
+        }
+    }
+
+    pub fn fill(&self, ram: &mut [u8]) {
+        match self {
+            Self::Zero => ram.fill(0x00),
+            Self::Ones => ram.fill(0xff),
+            Self::Alternating => {
+                for (i, b) in ram.iter_mut().enumerate() {
+                    *b = if i & 0x04 == 0 { 0x00 } else { 0xff };
+                }
+            }
+            Self::Random(seed) => {
+                // xorshift64。状態が0だと0しか出ないので、XORして0になったシードも0以外にする
+                let mut state = match seed ^ 0x9e37_79b9_7f4a_7c15 {
+                    0 => 0x9e37_79b9_7f4a_7c15,
+                    state => state,
+                };
+                for b in ram.iter_mut() {
+                    state ^= state << 13;
+                    state ^= state >> 7;
+                    state ^= state << 17;
+                    *b = (state >> 32) as u8;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RamPattern;
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(RamPattern::from_name("zero"), Some(RamPattern::Zero));
+        assert_eq!(RamPattern::from_name("ff"), Some(RamPattern::Ones));
+        assert_eq!(
+            RamPattern::from_name("alternating"),
+            Some(RamPattern::Alternating)
+        );
+        assert_eq!(
+            RamPattern::from_name("random:42"),
+            Some(RamPattern::Random(42))
+        );
+        assert_eq!(RamPattern::from_name("random:x"), None);
+        assert_eq!(RamPattern::from_name("random"), None);
+    }
+
+    #[test]
+    fn test_fill() {
+        let mut ram = [0x12; 10];
+        RamPattern::Alternating.fill(&mut ram);
+        assert_eq!(ram, [0, 0, 0, 0, 0xff, 0xff, 0xff, 0xff, 0, 0]);
+        RamPattern::Ones.fill(&mut ram);
+        assert_eq!(ram, [0xff; 10]);
+
+        let mut other = [0; 10];
+        RamPattern::Random(0).fill(&mut ram);
+        RamPattern::Random(0).fill(&mut other);
+        assert_eq!(ram, other);
+        assert_ne!(ram, [0; 10]);
+        RamPattern::Random(1).fill(&mut other);
+        assert_ne!(ram, other);
+        RamPattern::Random(0x9e37_79b9_7f4a_7c15).fill(&mut ram);
+        assert_ne!(ram, [0; 10]);
+    }
+}
@@ -0,0 +1,102 @@
+use core::fmt;
+
+// 本体の地域。CPUとPPUのクロックの比と1フレームのスキャンライン数が違う
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Region {
+    #[default]
+    Ntsc,
+    Pal,
+    // PALのテレビ向けのファミコン互換機
+    Dendy,
+}
+
+impl Region {
+    // --regionなどで使う名前から
+    pub fn from_name(name: &str) -> Option<Self> {
+        [Self::Ntsc, Self::Pal, Self::Dendy]
+            .iter()
+            .copied()
+            .find(|region| name.eq_ignore_ascii_case(region.name()))
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Ntsc => "NTSC",
+            Self::Pal => "PAL",
+            Self::Dendy => "Dendy",
+        }
+    }
+
+    // CPU 1クロックで進むPPUのドット数を(分子, 分母)で。PALだけ3.2
+    pub fn dots_per_cycle(&self) -> (u64, u64) {
+        match self {
+            Self::Ntsc | Self::Dendy => (3, 1),
+            Self::Pal => (16, 5),
+        }
+    }
+
+    // VBlankも含めた1フレームのスキャンライン数
+    pub fn scanlines(&self) -> u16 {
+        match self {
+            Self::Ntsc => 262,
+            Self::Pal | Self::Dendy => 312,
+        }
+    }
+
+    // VBlankが始まるスキャンライン。ここに来たら1フレーム終わったことにする。
+    // DendyはPALと同じ312ラインだが、VBlankの前に50ライン待つ
+    pub fn vblank_scanline(&self) -> u16 {
+        match self {
+            Self::Ntsc | Self::Pal => 241,
+            Self::Dendy => 291,
+        }
+    }
+
+    // CPUのクロック周波数(Hz)
+    pub fn cpu_clock(&self) -> u32 {
+        match self {
+            Self::Ntsc => 1_789_773,
+            Self::Pal => 1_662_607,
+            Self::Dendy => 1_773_448,
+        }
+    }
+
+    // 1秒あたりのフレーム数
+    pub fn frame_rate(&self) -> f64 {
+        match self {
+            Self::Ntsc => 60.0988,
+            Self::Pal | Self::Dendy => 50.0070,
+        }
+    }
+}
+
+impl fmt::Display for Region {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Region;
+
+    #[test]
+    fn test_from_name() {
+        assert_eq!(Region::from_name("ntsc"), Some(Region::Ntsc));
+        assert_eq!(Region::from_name("PAL"), Some(Region::Pal));
+        assert_eq!(Region::from_name("Dendy"), Some(Region::Dendy));
+        assert_eq!(Region::from_name("secam"), None);
+    }
+
+    #[test]
+    fn test_frame_timing() {
+        // 1フレームのクロック数がおおよそ周波数÷フレームレートになる
+        for region in [Region::Ntsc, Region::Pal, Region::Dendy] {
+            let (numerator, denominator) = region.dots_per_cycle();
+            let dots = 341 * region.scanlines() as u64;
+            let cycles = (dots * denominator / numerator) as f64;
+            let expected = region.cpu_clock() as f64 / region.frame_rate();
+            assert!((cycles - expected).abs() < 10.0, "{}", region);
+        }
+    }
+}
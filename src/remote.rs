@@ -0,0 +1,156 @@
+use crate::{
+    gdb::{decode_hex, encode_hex},
+    nes::Nes,
+    rom::Rom,
+};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::{
+    error::Error,
+    io::{self, BufRead, BufReader, Write},
+    net::TcpListener,
+};
+
+// 1行に1つのJSONで命令を受け取り、1行のJSONで返事をする
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+#[serde(tag = "command", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Command {
+    // iNESファイルの中身を16進の文字列にしたもの。読み込んだら電源を入れ直す
+    Load { rom: String },
+    Reset,
+    PowerCycle,
+    // 指定したフレーム数だけ進める
+    Frames { count: u64 },
+    Peek { addr: u16, len: u16 },
+    Press { buttons: Vec<String> },
+    Screenshot,
+    Quit,
+}
+
+// 接続が切れたらfalse、quitが来たらtrueを返す
+pub fn serve<R: BufRead, W: Write>(reader: R, mut writer: W, nes: &mut Nes) -> io::Result<bool> {
+    for line in reader.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let command = serde_json::from_str::<Command>(&line);
+        let reply = match &command {
+            Ok(command) => handle(nes, command).unwrap_or_else(|err| error_reply(err.to_string())),
+            Err(err) => error_reply(format!("Invalid command: {}", err)),
+        };
+        writeln!(writer, "{}", reply)?;
+        writer.flush()?;
+        if command.ok() == Some(Command::Quit) {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+// 待ち受けているlistenerで、quitが来るまで接続を1つずつ処理する
+pub fn listen(listener: TcpListener, nes: &mut Nes) -> io::Result<()> {
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let quit = stream.set_nodelay(true).and_then(|()| {
+            let reader = BufReader::new(stream.try_clone()?);
+            serve(reader, &stream, nes)
+        });
+        match quit {
+            Ok(true) => break,
+            Ok(false) => {}
+            // 1つの接続の失敗はそのクライアントだけの問題なので、次の接続を待つ
+            Err(err) => log::warn!("remote: {}", err),
+        }
+    }
+    Ok(())
+}
+
+fn handle(nes: &mut Nes, command: &Command) -> Result<Value, Box<dyn Error>> {
+    match command {
+        Command::Load { rom } => {
+            let data = decode_hex(rom).ok_or("ROM is not a hex string.")?;
+            nes.set_rom(Rom::from_bytes(&data)?)?;
+            nes.power_cycle()?;
+        }
+        Command::Reset => nes.reset()?,
+        Command::PowerCycle => nes.power_cycle()?,
+        Command::Frames { count } => {
+            for _ in 0..*count {
+                nes.step_frame()?;
+            }
+            return Ok(json!({ "ok": true, "frame": nes.frame_count() }));
+        }
+        Command::Peek { addr, len } => {
+            let data: Vec<u8> = (0..*len)
+                .map(|i| nes.peek(addr.wrapping_add(i)).unwrap_or(0))
+                .collect();
+            return Ok(json!({ "ok": true, "data": encode_hex(&data) }));
+        }
+        // コントローラーとPPUはまだ無い
+        Command::Press { .. } => return Err("Controller input is not supported yet.".into()),
+        Command::Screenshot => return Err("Screenshots are not supported yet.".into()),
+        Command::Quit => {}
+    }
+    Ok(json!({ "ok": true }))
+}
+
+fn error_reply(message: String) -> Value {
+    json!({ "ok": false, "error": message })
+}
+
+#[cfg(test)]
+mod test {
+    use super::serve;
+    use crate::{gdb::encode_hex, nes::Nes};
+    use serde_json::Value;
+    use std::fs;
+
+    #[test]
+    fn test_serve() {
+        let rom = encode_hex(&fs::read("./tests/rom/hello_world.nes").unwrap());
+        let input = format!(
+            "{{\"command\":\"load\",\"rom\":\"{}\"}}\n\
+             {{\"command\":\"frames\",\"count\":2}}\n\
+             {{\"command\":\"peek\",\"addr\":65532,\"len\":2}}\n\
+             {{\"command\":\"quit\"}}\n\
+             {{\"command\":\"reset\"}}\n",
+            rom
+        );
+        let replies = run(&input, true);
+        assert_eq!(replies.len(), 4);
+        assert_eq!(replies[0]["ok"], true);
+        assert_eq!(replies[1]["frame"], 2);
+        assert_eq!(replies[2]["data"], "0080");
+    }
+
+    #[test]
+    fn test_errors() {
+        let replies = run(
+            "{\"command\":\"load\",\"rom\":\"xyz\"}\n\
+             {\"command\":\"press\",\"buttons\":[\"a\"]}\n\
+             {\"command\":\"jump\"}\n",
+            false,
+        );
+        assert_eq!(replies[0]["error"], "ROM is not a hex string.");
+        assert_eq!(
+            replies[1]["error"],
+            "Controller input is not supported yet."
+        );
+        assert_eq!(replies[2]["ok"], false);
+    }
+
+    fn run(input: &str, quit: bool) -> Vec<Value> {
+        let mut nes = Nes::new();
+        let mut output = vec![];
+        assert_eq!(
+            serve(input.as_bytes(), &mut output, &mut nes).unwrap(),
+            quit
+        );
+        String::from_utf8(output)
+            .unwrap()
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect()
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::VecDeque;
+
+// 巻き戻し用のステート履歴。最新のステートだけそのまま持ち、
+// それより前は1つ新しいステートとの差分(XORしてゼロの連続を詰めたもの)で持つ
+#[derive(Debug)]
+pub struct RewindBuffer {
+    latest: Option<Vec<u8>>,
+    deltas: VecDeque<Vec<u8>>,
+    capacity: usize,
+}
+
+impl RewindBuffer {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            latest: None,
+            deltas: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn push(&mut self, state: Vec<u8>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Some(latest) = self.latest.take() {
+            self.deltas.push_back(encode_delta(&state, &latest));
+            if self.deltas.len() >= self.capacity {
+                self.deltas.pop_front();
+            }
+        }
+        self.latest = Some(state);
+    }
+
+    // 最後にpushしたステートを取り出す
+    pub fn pop(&mut self) -> Option<Vec<u8>> {
+        let latest = self.latest.take()?;
+        self.latest = self.deltas.pop_back().map(|d| decode_delta(&latest, &d));
+        Some(latest)
+    }
+
+    pub fn len(&self) -> usize {
+        match self.latest {
+            Some(_) => self.deltas.len() + 1,
+            None => 0,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    pub fn memory_usage(&self) -> usize {
+        let latest = self.latest.as_ref().map_or(0, |s| s.len());
+        latest + self.deltas.iter().map(|d| d.len()).sum::<usize>()
+    }
+}
+
+// baseからtargetを復元するための差分を作る。
+// 形式: targetの長さ, (ゼロの長さ, リテラルの長さ, リテラル)の繰り返し。長さはすべてLEB128
+pub fn encode_delta(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let xored: Vec<u8> = target
+        .iter()
+        .enumerate()
+        .map(|(i, b)| b ^ base.get(i).copied().unwrap_or(0))
+        .collect();
+
+    let mut delta = Vec::new();
+    write_varint(&mut delta, target.len());
+
+    let mut i = 0;
+    while i < xored.len() {
+        let zero_start = i;
+        while i < xored.len() && xored[i] == 0 {
+            i += 1;
+        }
+        let literal_start = i;
+        while i < xored.len() && xored[i] != 0 {
+            i += 1;
+        }
+        write_varint(&mut delta, literal_start - zero_start);
+        write_varint(&mut delta, i - literal_start);
+        delta.extend_from_slice(&xored[literal_start..i]);
+    }
+    delta
+}
+
+pub fn decode_delta(base: &[u8], delta: &[u8]) -> Vec<u8> {
+    let mut pos = 0;
+    let len = read_varint(delta, &mut pos);
+    let mut target: Vec<u8> = (0..len)
+        .map(|i| base.get(i).copied().unwrap_or(0))
+        .collect();
+
+    let mut i = 0;
+    while pos < delta.len() {
+        i += read_varint(delta, &mut pos);
+        let literal_len = read_varint(delta, &mut pos);
+        for b in &delta[pos..pos + literal_len] {
+            target[i] ^= b;
+            i += 1;
+        }
+        pos += literal_len;
+    }
+    target
+}
+
+fn write_varint(buf: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        buf.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+fn read_varint(buf: &[u8], pos: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let b = buf[*pos];
+        *pos += 1;
+        value |= ((b & 0x7f) as usize) << shift;
+        if b & 0x80 == 0 {
+            return value;
+        }
+        shift += 7;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode_delta, encode_delta, RewindBuffer};
+
+    #[test]
+    fn test_delta() {
+        let base = vec![0x11; 0x1000];
+        let mut target = base.clone();
+        target[0x0010] = 0x22;
+        target[0x0800] = 0x33;
+        target[0x0801] = 0x44;
+
+        let delta = encode_delta(&base, &target);
+        assert!(delta.len() < 16);
+        assert_eq!(decode_delta(&base, &delta), target);
+    }
+
+    #[test]
+    fn test_delta_different_length() {
+        let base = vec![0x01, 0x02, 0x03];
+        let longer = vec![0x01, 0x02, 0x03, 0x04, 0x05];
+        let shorter = vec![0x01];
+
+        assert_eq!(decode_delta(&base, &encode_delta(&base, &longer)), longer);
+        assert_eq!(decode_delta(&base, &encode_delta(&base, &shorter)), shorter);
+    }
+
+    #[test]
+    fn test_rewind_buffer() {
+        let mut buffer = RewindBuffer::new(3);
+        assert!(buffer.is_empty());
+
+        for i in 0..5 {
+            buffer.push(state(i));
+        }
+        assert_eq!(buffer.len(), 3);
+        assert!(buffer.memory_usage() < 0x100 + 32);
+
+        assert_eq!(buffer.pop(), Some(state(4)));
+        assert_eq!(buffer.pop(), Some(state(3)));
+        assert_eq!(buffer.pop(), Some(state(2)));
+        assert_eq!(buffer.pop(), None);
+        assert!(buffer.is_empty());
+    }
+
+    fn state(i: u8) -> Vec<u8> {
+        let mut state = vec![0xaa; 0x100];
+        state[0x80] = i;
+        state
+    }
+}
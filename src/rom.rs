@@ -1,31 +1,661 @@
-use std::{error::Error, io::Read, result::Result};
+use crate::{
+    checksum::{crc32, to_hex, update_crc32, Sha1},
+    rom_data::RomData,
+};
+#[cfg(feature = "mmap")]
+use memmap2::Mmap;
+#[cfg(feature = "zip-archive")]
+use std::io::Seek;
+#[cfg(feature = "mmap")]
+use std::sync::Arc;
+use std::{
+    convert::TryFrom,
+    error::Error,
+    fmt,
+    fs::File,
+    io::{self, BufReader, Read},
+    path::Path,
+    result::Result,
+};
 
-#[derive(Debug, PartialEq, Eq)]
+// ROMを読み込めなかった理由
+#[derive(Debug)]
+pub enum RomError {
+    // 先頭が"NES\x1a"ではない
+    BadMagic,
+    TruncatedHeader,
+    TruncatedTrainer {
+        expected: usize,
+        got: usize,
+    },
+    TruncatedPrg {
+        expected: usize,
+        got: usize,
+    },
+    TruncatedChr {
+        expected: usize,
+        got: usize,
+    },
+    UnsupportedMapper(u16),
+    UnsupportedConsole(ConsoleType),
+    // ZIPの中に.nesが無い
+    NoRomInArchive,
+    // zip-archiveを無効にしてビルドしたときにZIPを渡された
+    ArchiveUnsupported,
+    #[cfg(feature = "zip-archive")]
+    Archive(zip::result::ZipError),
+    Io(io::Error),
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::BadMagic => write!(f, "Invalid header constant."),
+            Self::TruncatedHeader => write!(f, "ROM is too short to contain a header."),
+            Self::TruncatedTrainer { expected, got } => write!(
+                f,
+                "Trainer is truncated: expected {} bytes, got {}.",
+                expected, got
+            ),
+            Self::TruncatedPrg { expected, got } => write!(
+                f,
+                "PRG ROM is truncated: expected {} bytes, got {}.",
+                expected, got
+            ),
+            Self::TruncatedChr { expected, got } => write!(
+                f,
+                "CHR ROM is truncated: expected {} bytes, got {}.",
+                expected, got
+            ),
+            Self::UnsupportedMapper(mapper) => write!(f, "Mapper {} is not supported.", mapper),
+            Self::UnsupportedConsole(console) => write!(f, "{} ROMs are not supported.", console),
+            Self::NoRomInArchive => write!(f, "No .nes file found in the archive."),
+            Self::ArchiveUnsupported => write!(f, "ZIP support is disabled in this build."),
+            #[cfg(feature = "zip-archive")]
+            Self::Archive(err) => write!(f, "Failed to read archive: {}", err),
+            Self::Io(err) => write!(f, "Failed to read ROM: {}", err),
+        }
+    }
+}
+
+impl Error for RomError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            #[cfg(feature = "zip-archive")]
+            Self::Archive(err) => Some(err),
+            Self::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for RomError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+#[cfg(feature = "zip-archive")]
+impl From<zip::result::ZipError> for RomError {
+    fn from(err: zip::result::ZipError) -> Self {
+        Self::Archive(err)
+    }
+}
+
+// 今のところNROMしか動かない
+const SUPPORTED_MAPPERS: [u16; 1] = [0];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Mirroring {
+    #[default]
+    Horizontal,
+    Vertical,
+    // カートリッジにVRAMが載っていて4画面とも別々
+    FourScreen,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TvSystem {
+    #[default]
+    Ntsc,
+    Pal,
+    // どちらでも動く
+    MultiRegion,
+    Dendy,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConsoleType {
+    #[default]
+    Nes,
+    VsSystem,
+    // CHRの後ろにヒント画面用の8KBのROMが付いている
+    PlayChoice10,
+    // NES 2.0の13バイト目で指定される互換機など
+    Extended(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum HeaderFormat {
+    #[default]
+    INes,
+    Nes2,
+}
+
+impl fmt::Display for TvSystem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Ntsc => write!(f, "NTSC"),
+            Self::Pal => write!(f, "PAL"),
+            Self::MultiRegion => write!(f, "Multi-region"),
+            Self::Dendy => write!(f, "Dendy"),
+        }
+    }
+}
+
+impl fmt::Display for ConsoleType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Nes => write!(f, "NES"),
+            Self::VsSystem => write!(f, "VS System"),
+            Self::PlayChoice10 => write!(f, "PlayChoice-10"),
+            Self::Extended(n) => write!(f, "Extended console type {}", n),
+        }
+    }
+}
+
+impl fmt::Display for HeaderFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::INes => write!(f, "iNES"),
+            Self::Nes2 => write!(f, "NES 2.0"),
+        }
+    }
+}
+
+// iNES 5バイト目は16KB、NES 2.0で拡張しても12ビットまで
+const MAX_PRG_ROM_SIZE: usize = 0xfff * 0x4000;
+
+pub const TRAINER_SIZE: usize = 0x200;
+pub const HINT_SCREEN_SIZE: usize = 0x2000;
+
+// iNES/NES 2.0ヘッダの中身
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RomHeader {
+    pub format: HeaderFormat,
+    // バイト数
+    pub prg_rom_size: usize,
+    pub chr_rom_size: usize,
+    pub mapper: u16,
+    // NES 2.0のみ。iNESでは0
+    pub submapper: u8,
+    pub mirroring: Mirroring,
+    pub has_battery: bool,
+    // PRGの前に512バイトのトレーナーがある
+    pub has_trainer: bool,
+    // バイト数。nvramはバッテリーバックアップされている分
+    pub prg_ram_size: usize,
+    pub prg_nvram_size: usize,
+    pub chr_ram_size: usize,
+    pub chr_nvram_size: usize,
+    pub tv_system: TvSystem,
+    pub console: ConsoleType,
+}
+
+impl RomHeader {
+    pub fn parse(header: &[u8; 16]) -> Result<Self, RomError> {
+        if header[0] != 0x4e || header[1] != 0x45 || header[2] != 0x53 || header[3] != 0x1a {
+            return Err(RomError::BadMagic);
+        }
+        // NES 2.0のサイズが明らかにおかしいときはiNESとして読む
+        let header = match header[7] & 0x0c {
+            0x08 => Self::parse_nes2(header).unwrap_or_else(|| Self::parse_ines(header)),
+            _ => Self::parse_ines(header),
+        };
+        Ok(header)
+    }
+
+    // このエミュレータで動かせるかどうか
+    pub fn check_supported(&self) -> Result<(), RomError> {
+        if !SUPPORTED_MAPPERS.contains(&self.mapper) {
+            return Err(RomError::UnsupportedMapper(self.mapper));
+        }
+        // PlayChoice-10はヒント画面を使わなければ普通のNESとして動く
+        if !matches!(self.console, ConsoleType::Nes | ConsoleType::PlayChoice10) {
+            return Err(RomError::UnsupportedConsole(self.console));
+        }
+        Ok(())
+    }
+
+    fn parse_ines(header: &[u8; 16]) -> Self {
+        // 古いツールが12〜15バイト目に"DiskDude!"などを書き込んでいるものは
+        // 7バイト目以降が信用できないので使わない
+        let clean = header[12..16].iter().all(|b| *b == 0);
+        let (flags7, flags8, flags9) = if clean {
+            (header[7], header[8], header[9])
+        } else {
+            (0, 0, 0)
+        };
+
+        let has_battery = header[6] & 0x02 != 0;
+        // 0でも8KBあるものとして扱うのが普通
+        let prg_ram_size = flags8.max(1) as usize * 0x2000;
+        let chr_rom_size = header[5] as usize * 0x2000;
+        Self {
+            format: HeaderFormat::INes,
+            prg_rom_size: header[4] as usize * 0x4000,
+            chr_rom_size,
+            mapper: ((flags7 & 0xf0) | (header[6] >> 4)) as u16,
+            submapper: 0,
+            mirroring: mirroring(header[6]),
+            has_battery,
+            has_trainer: header[6] & 0x04 != 0,
+            prg_ram_size: if has_battery { 0 } else { prg_ram_size },
+            prg_nvram_size: if has_battery { prg_ram_size } else { 0 },
+            chr_ram_size: if chr_rom_size == 0 { 0x2000 } else { 0 },
+            chr_nvram_size: 0,
+            tv_system: if flags9 & 0x01 != 0 {
+                TvSystem::Pal
+            } else {
+                TvSystem::Ntsc
+            },
+            console: if flags7 & 0x01 != 0 {
+                ConsoleType::VsSystem
+            } else if flags7 & 0x02 != 0 {
+                ConsoleType::PlayChoice10
+            } else {
+                ConsoleType::Nes
+            },
+        }
+    }
+
+    fn parse_nes2(header: &[u8; 16]) -> Option<Self> {
+        let prg_rom_size = nes2_rom_size(header[4], header[9] & 0x0f, 0x4000)?;
+        let chr_rom_size = nes2_rom_size(header[5], header[9] >> 4, 0x2000)?;
+        if prg_rom_size > MAX_PRG_ROM_SIZE || chr_rom_size > MAX_PRG_ROM_SIZE {
+            return None;
+        }
+        Some(Self {
+            format: HeaderFormat::Nes2,
+            prg_rom_size,
+            chr_rom_size,
+            mapper: ((header[8] as u16 & 0x0f) << 8)
+                | (header[7] & 0xf0) as u16
+                | (header[6] >> 4) as u16,
+            submapper: header[8] >> 4,
+            mirroring: mirroring(header[6]),
+            has_battery: header[6] & 0x02 != 0,
+            has_trainer: header[6] & 0x04 != 0,
+            prg_ram_size: nes2_ram_size(header[10] & 0x0f),
+            prg_nvram_size: nes2_ram_size(header[10] >> 4),
+            chr_ram_size: nes2_ram_size(header[11] & 0x0f),
+            chr_nvram_size: nes2_ram_size(header[11] >> 4),
+            tv_system: match header[12] & 0x03 {
+                0 => TvSystem::Ntsc,
+                1 => TvSystem::Pal,
+                2 => TvSystem::MultiRegion,
+                _ => TvSystem::Dendy,
+            },
+            console: match header[7] & 0x03 {
+                0 => ConsoleType::Nes,
+                1 => ConsoleType::VsSystem,
+                2 => ConsoleType::PlayChoice10,
+                _ => match header[13] & 0x0f {
+                    0 => ConsoleType::Nes,
+                    1 => ConsoleType::VsSystem,
+                    2 => ConsoleType::PlayChoice10,
+                    n => ConsoleType::Extended(n),
+                },
+            },
+        })
+    }
+}
+
+// よく使われるマッパーの名前
+pub fn mapper_name(mapper: u16) -> Option<&'static str> {
+    let name = match mapper {
+        0 => "NROM",
+        1 => "MMC1",
+        2 => "UxROM",
+        3 => "CNROM",
+        4 => "MMC3",
+        5 => "MMC5",
+        7 => "AxROM",
+        9 => "MMC2",
+        10 => "MMC4",
+        11 => "Color Dreams",
+        19 => "Namco 163",
+        21 | 23 | 25 => "VRC2/VRC4",
+        24 | 26 => "VRC6",
+        66 => "GxROM",
+        69 => "Sunsoft FME-7",
+        71 => "Camerica",
+        85 => "VRC7",
+        _ => return None,
+    };
+    Some(name)
+}
+
+fn format_size(size: usize) -> String {
+    if size != 0 && size.is_multiple_of(1024) {
+        format!("{} KB", size / 1024)
+    } else {
+        format!("{} bytes", size)
+    }
+}
+
+fn read_header<R: Read>(reader: &mut R) -> Result<RomHeader, RomError> {
+    let mut header = [0; 16];
+    reader
+        .read_exact(&mut header)
+        .map_err(|err| match err.kind() {
+            io::ErrorKind::UnexpectedEof => RomError::TruncatedHeader,
+            _ => RomError::Io(err),
+        })?;
+    RomHeader::parse(&header)
+}
+
+fn mirroring(flags6: u8) -> Mirroring {
+    if flags6 & 0x08 != 0 {
+        Mirroring::FourScreen
+    } else if flags6 & 0x01 != 0 {
+        Mirroring::Vertical
+    } else {
+        Mirroring::Horizontal
+    }
+}
+
+// 上位4ビットが0xFのときは EEEEEEMM で 2^E * (MM*2+1) バイト
+fn nes2_rom_size(lsb: u8, msb: u8, unit: usize) -> Option<usize> {
+    if msb == 0x0f {
+        let multiplier = (lsb & 0x03) as usize * 2 + 1;
+        1usize
+            .checked_shl((lsb >> 2) as u32)?
+            .checked_mul(multiplier)
+    } else {
+        Some((((msb as usize) << 8) | lsb as usize) * unit)
+    }
+}
+
+// 0なら無し、それ以外は64 << nバイト
+fn nes2_ram_size(shift: u8) -> usize {
+    match shift {
+        0 => 0,
+        n => 64 << n,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Rom {
-    pub program: Vec<u8>,
-    pub character: Vec<u8>,
+    pub header: RomHeader,
+    // 起動時に$7000〜$71FFへ置かれる512バイト
+    pub trainer: Option<Vec<u8>>,
+    pub program: RomData,
+    pub character: RomData,
+    // PlayChoice-10のヒント画面(INST-ROM)。エミュレーションには使わない
+    pub hint_screen: Option<Vec<u8>>,
 }
 
 impl Rom {
-    pub fn load<R: Read>(reader: &mut R) -> Result<Self, Box<dyn Error>> {
-        let mut header = [0; 16];
-        reader.read_exact(&mut header)?;
-        if header[0] != 0x4e || header[1] != 0x45 || header[2] != 0x53 || header[3] != 0x1a {
-            return Err("Invalid header constant.".into());
+    pub fn load<R: Read>(reader: &mut R) -> Result<Self, RomError> {
+        let header = read_header(reader)?;
+        header.check_supported()?;
+        Self::load_body(reader, header)
+    }
+
+    // 動かせないROMでもそのまま読む。中身を調べるときに使う
+    pub fn load_unchecked<R: Read>(reader: &mut R) -> Result<Self, RomError> {
+        let header = read_header(reader)?;
+        Self::load_body(reader, header)
+    }
+
+    fn load_body<R: Read>(reader: &mut R, header: RomHeader) -> Result<Self, RomError> {
+        let trainer = if header.has_trainer {
+            Some(read_section(reader, TRAINER_SIZE, |expected, got| {
+                RomError::TruncatedTrainer { expected, got }
+            })?)
+        } else {
+            None
+        };
+        let program = read_section(reader, header.prg_rom_size, |expected, got| {
+            RomError::TruncatedPrg { expected, got }
+        })?;
+        let character = read_section(reader, header.chr_rom_size, |expected, got| {
+            RomError::TruncatedChr { expected, got }
+        })?;
+        // ヒント画面のROMの後ろのPROMは使わない。吸い出されていないものもある
+        let hint_screen = match header.console {
+            ConsoleType::PlayChoice10 => {
+                let mut data = Vec::new();
+                reader
+                    .take(HINT_SCREEN_SIZE as u64)
+                    .read_to_end(&mut data)?;
+                Some(data).filter(|d| d.len() == HINT_SCREEN_SIZE)
+            }
+            _ => None,
+        };
+
+        Ok(Self {
+            header,
+            trainer,
+            program: program.into(),
+            character: character.into(),
+            hint_screen,
+        })
+    }
+
+    // ファイルの中身をコピーせず、PRGとCHRはdataの一部をそのまま使う
+    pub fn from_data(data: RomData) -> Result<Self, RomError> {
+        let mut reader = &data[..];
+        let header = read_header(&mut reader)?;
+        header.check_supported()?;
+        let mut offset = data.len() - reader.len();
+        let mut section = |expected: usize, truncated: fn(usize, usize) -> RomError| {
+            let got = expected.min(data.len() - offset);
+            if got != expected {
+                return Err(truncated(expected, got));
+            }
+            offset += expected;
+            Ok(data.slice(offset - expected..offset))
+        };
+        let trainer = if header.has_trainer {
+            Some(
+                section(TRAINER_SIZE, |expected, got| RomError::TruncatedTrainer {
+                    expected,
+                    got,
+                })?
+                .to_vec(),
+            )
+        } else {
+            None
+        };
+        let program = section(header.prg_rom_size, |expected, got| {
+            RomError::TruncatedPrg { expected, got }
+        })?;
+        let character = section(header.chr_rom_size, |expected, got| {
+            RomError::TruncatedChr { expected, got }
+        })?;
+        let hint_screen = match header.console {
+            ConsoleType::PlayChoice10 => data
+                .get(offset..offset + HINT_SCREEN_SIZE)
+                .map(|data| data.to_vec()),
+            _ => None,
+        };
+
+        Ok(Self {
+            header,
+            trainer,
+            program,
+            character,
+            hint_screen,
+        })
+    }
+
+    // 拡張子が.zipなら中の最初の.nesを読む
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self, RomError> {
+        #[cfg(feature = "mmap")]
+        if !is_zip(path.as_ref()) {
+            return Self::map(path);
         }
-        let mut program: Vec<u8> = vec![0; (header[4] as usize) * 0x4000];
-        let mut character: Vec<u8> = vec![0; (header[5] as usize) * 0x2000];
-        reader.read_exact(&mut program)?;
-        reader.read_exact(&mut character)?;
+        Self::from_data(Self::read_file(path)?.into())
+    }
 
-        Ok(Self { program, character })
+    // ファイルをメモリマップして読む。大きいROMでも読み込みが速い。
+    // 使っている間にファイルを書き換えたり切り詰めたりしてはいけない。
+    // ビルドし直されるROMを読むときはread_fileでコピーする
+    #[cfg(feature = "mmap")]
+    pub fn map<P: AsRef<Path>>(path: P) -> Result<Self, RomError> {
+        let file = File::open(path)?;
+        // 書き換えられると読んでいる途中の中身が変わったりSIGBUSになったりするので、
+        // 呼び出す側が書き換えられないファイルにだけ使う
+        let mmap = unsafe { Mmap::map(&file)? };
+        Self::from_data(RomData::new(Arc::new(mmap)))
+    }
+
+    // パッチを当てるときなど、ヘッダを含めたファイルの中身がそのまま欲しいときに使う
+    pub fn read_file<P: AsRef<Path>>(path: P) -> Result<Vec<u8>, RomError> {
+        let path = path.as_ref();
+        let mut reader = BufReader::new(File::open(path)?);
+        if !is_zip(path) {
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data)?;
+            return Ok(data);
+        }
+        #[cfg(feature = "zip-archive")]
+        return read_zip(reader);
+        #[cfg(not(feature = "zip-archive"))]
+        Err(RomError::ArchiveUnsupported)
+    }
+
+    #[cfg(feature = "zip-archive")]
+    pub fn load_zip<R: Read + Seek>(reader: R) -> Result<Self, RomError> {
+        Self::from_data(read_zip(reader)?.into())
+    }
+
+    // ファイルを使わずに読む。include_bytes!したものやwasmから渡されたものなど
+    pub fn from_bytes(data: &[u8]) -> Result<Self, RomError> {
+        Self::load(&mut &data[..])
+    }
+
+    pub fn has_battery(&self) -> bool {
+        self.header.has_battery
+    }
+
+    // infoサブコマンドで表示するもの
+    pub fn describe(&self) -> String {
+        let header = &self.header;
+        let mapper = match mapper_name(header.mapper) {
+            Some(name) => format!("{} ({})", header.mapper, name),
+            None => header.mapper.to_string(),
+        };
+        let supported = match header.check_supported() {
+            Ok(()) => "yes".to_string(),
+            Err(err) => format!("no ({})", err),
+        };
+        let yes_no = |b: bool| if b { "yes" } else { "no" };
+        let lines = [
+            ("Format", header.format.to_string()),
+            ("Mapper", mapper),
+            ("Submapper", header.submapper.to_string()),
+            ("PRG ROM", format_size(header.prg_rom_size)),
+            ("CHR ROM", format_size(header.chr_rom_size)),
+            ("PRG RAM", format_size(header.prg_ram_size)),
+            ("PRG NVRAM", format_size(header.prg_nvram_size)),
+            ("CHR RAM", format_size(header.chr_ram_size)),
+            ("CHR NVRAM", format_size(header.chr_nvram_size)),
+            ("Mirroring", format!("{:?}", header.mirroring)),
+            ("Battery", yes_no(header.has_battery).to_string()),
+            ("Trainer", yes_no(header.has_trainer).to_string()),
+            ("TV system", header.tv_system.to_string()),
+            ("Console", header.console.to_string()),
+            ("CRC32", format!("{:08X}", self.crc32())),
+            ("SHA-1", to_hex(&self.sha1()).to_uppercase()),
+            ("Supported", supported),
+        ];
+        lines
+            .iter()
+            .map(|(name, value)| format!("{:<10} {}\n", format!("{}:", name), value))
+            .collect()
+    }
+
+    // PRGとCHRを通したCRC32。ヘッダは含めない
+    pub fn crc32(&self) -> u32 {
+        update_crc32(crc32(&self.program), &self.character)
+    }
+
+    // PRGとCHRを通したSHA-1
+    pub fn sha1(&self) -> [u8; 20] {
+        let mut sha1 = Sha1::new();
+        sha1.update(&self.program);
+        sha1.update(&self.character);
+        sha1.finish()
+    }
+}
+
+fn is_zip(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| e.eq_ignore_ascii_case("zip"))
+}
+
+#[cfg(feature = "zip-archive")]
+fn read_zip<R: Read + Seek>(reader: R) -> Result<Vec<u8>, RomError> {
+    let mut archive = zip::ZipArchive::new(reader)?;
+    // ZIPの中の並び順で最初のもの
+    let mut index = None;
+    for i in 0..archive.len() {
+        let file = archive.by_index_raw(i)?;
+        let is_rom = Path::new(file.name())
+            .extension()
+            .and_then(|e| e.to_str())
+            .is_some_and(|e| e.eq_ignore_ascii_case("nes"));
+        if is_rom {
+            index = Some(i);
+            break;
+        }
+    }
+    let index = index.ok_or(RomError::NoRomInArchive)?;
+    let mut data = Vec::new();
+    archive.by_index(index)?.read_to_end(&mut data)?;
+    Ok(data)
+}
+
+impl TryFrom<Vec<u8>> for Rom {
+    type Error = RomError;
+
+    fn try_from(data: Vec<u8>) -> Result<Self, Self::Error> {
+        Self::from_data(data.into())
+    }
+}
+
+impl TryFrom<&[u8]> for Rom {
+    type Error = RomError;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        Self::from_bytes(data)
     }
 }
 
+// 途中で終わっていたら何バイト読めたかをエラーにする
+fn read_section<R, F>(reader: &mut R, expected: usize, truncated: F) -> Result<Vec<u8>, RomError>
+where
+    R: Read,
+    F: FnOnce(usize, usize) -> RomError,
+{
+    let mut data = Vec::with_capacity(expected);
+    reader.take(expected as u64).read_to_end(&mut data)?;
+    if data.len() != expected {
+        return Err(truncated(expected, data.len()));
+    }
+    Ok(data)
+}
+
 #[cfg(test)]
 mod test {
-    use super::Rom;
+    use super::{ConsoleType, HeaderFormat, Mirroring, Rom, RomError, RomHeader, TvSystem};
+    use crate::checksum::sha1;
     use std::{
+        convert::TryFrom,
         fs::File,
         io::{BufReader, Cursor},
     };
@@ -33,7 +663,262 @@ mod test {
     #[test]
     fn test_load() {
         let mut reader = BufReader::new(File::open("./tests/rom/hello_world.nes").unwrap());
-        let _ = Rom::load(&mut reader).unwrap();
+        let rom = Rom::load(&mut reader).unwrap();
+        assert!(!rom.has_battery());
+        assert_eq!(rom.header.mapper, 0);
+        assert_eq!(rom.header.prg_rom_size, 0x8000);
+        assert_eq!(rom.program.len(), 0x8000);
+        assert_eq!(rom.character.len(), rom.header.chr_rom_size);
+    }
+
+    #[test]
+    fn test_from_bytes() {
+        let data = include_bytes!("../tests/rom/hello_world.nes");
+        let mut reader = BufReader::new(File::open("./tests/rom/hello_world.nes").unwrap());
+        let rom = Rom::load(&mut reader).unwrap();
+        assert_eq!(Rom::from_bytes(data).unwrap(), rom);
+        assert_eq!(Rom::try_from(data.to_vec()).unwrap(), rom);
+        assert!(matches!(
+            Rom::try_from(&data[..0x100]),
+            Err(RomError::TruncatedPrg { .. })
+        ));
+    }
+
+    #[test]
+    fn test_from_data() {
+        let data = include_bytes!("../tests/rom/hello_world.nes").to_vec();
+        let rom = Rom::from_data(data.clone().into()).unwrap();
+        assert_eq!(rom, Rom::from_bytes(&data).unwrap());
+        assert_eq!(&rom.program[..], &data[0x10..0x8010]);
+
+        let mut data = vec![0x4e, 0x45, 0x53, 0x1a, 0x02, 0x01];
+        data.resize(16, 0);
+        data.extend(vec![0; 0x9000]);
+        assert!(matches!(
+            Rom::from_data(data.into()),
+            Err(RomError::TruncatedChr {
+                expected: 0x2000,
+                got: 0x1000
+            })
+        ));
+    }
+
+    #[cfg(feature = "mmap")]
+    #[test]
+    fn test_map() {
+        let rom = Rom::map("./tests/rom/hello_world.nes").unwrap();
+        assert_eq!(rom, Rom::open("./tests/rom/hello_world.nes").unwrap());
+        assert!(matches!(
+            Rom::map("./tests/rom/missing.nes"),
+            Err(RomError::Io(_))
+        ));
+    }
+
+    #[cfg(feature = "zip-archive")]
+    #[test]
+    fn test_load_zip() {
+        use std::io::Write;
+        use zip::{write::FileOptions, ZipWriter};
+
+        let data = include_bytes!("../tests/rom/hello_world.nes");
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        writer
+            .start_file("README.txt", FileOptions::default())
+            .unwrap();
+        writer.write_all(b"hello").unwrap();
+        writer
+            .start_file("roms/Hello World.NES", FileOptions::default())
+            .unwrap();
+        writer.write_all(data).unwrap();
+        let archive = writer.finish().unwrap().into_inner();
+
+        let rom = Rom::load_zip(Cursor::new(archive)).unwrap();
+        assert_eq!(rom, Rom::from_bytes(data).unwrap());
+
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        writer
+            .start_file("README.txt", FileOptions::default())
+            .unwrap();
+        let archive = writer.finish().unwrap().into_inner();
+        assert!(matches!(
+            Rom::load_zip(Cursor::new(archive)),
+            Err(RomError::NoRomInArchive)
+        ));
+    }
+
+    #[test]
+    fn test_open() {
+        let rom = Rom::open("./tests/rom/hello_world.nes").unwrap();
+        assert_eq!(rom.header.prg_rom_size, 0x8000);
+        assert!(matches!(
+            Rom::open("./tests/rom/missing.nes"),
+            Err(RomError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_header() {
+        let header = RomHeader::parse(&[
+            0x4e, 0x45, 0x53, 0x1a, 0x08, 0x10, 0x41, 0x10, 0x01, 0x01, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00,
+        ])
+        .unwrap();
+        assert_eq!(
+            header,
+            RomHeader {
+                format: HeaderFormat::INes,
+                prg_rom_size: 0x20000,
+                chr_rom_size: 0x20000,
+                mapper: 0x14,
+                submapper: 0,
+                mirroring: Mirroring::Vertical,
+                has_battery: false,
+                has_trainer: false,
+                prg_ram_size: 0x2000,
+                prg_nvram_size: 0,
+                chr_ram_size: 0,
+                chr_nvram_size: 0,
+                tv_system: TvSystem::Pal,
+                console: ConsoleType::Nes,
+            }
+        );
+
+        let header = RomHeader::parse(&[
+            0x4e, 0x45, 0x53, 0x1a, 0x02, 0x01, 0x1e, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00,
+        ])
+        .unwrap();
+        assert_eq!(header.mapper, 1);
+        assert_eq!(header.mirroring, Mirroring::FourScreen);
+        assert!(header.has_battery);
+        assert!(header.has_trainer);
+        assert_eq!(header.prg_nvram_size, 0x2000);
+    }
+
+    #[test]
+    fn test_parse_nes2_header() {
+        let header = RomHeader::parse(&[
+            0x4e, 0x45, 0x53, 0x1a, 0x00, 0x00, 0x12, 0x48, 0x51, 0x01, 0x70, 0x07, 0x02, 0x00,
+            0x00, 0x00,
+        ])
+        .unwrap();
+        assert_eq!(
+            header,
+            RomHeader {
+                format: HeaderFormat::Nes2,
+                prg_rom_size: 0x100 * 0x4000,
+                chr_rom_size: 0,
+                mapper: 0x141,
+                submapper: 5,
+                mirroring: Mirroring::Horizontal,
+                has_battery: true,
+                has_trainer: false,
+                prg_ram_size: 0,
+                prg_nvram_size: 0x2000,
+                chr_ram_size: 0x2000,
+                chr_nvram_size: 0,
+                tv_system: TvSystem::MultiRegion,
+                console: ConsoleType::Nes,
+            }
+        );
+
+        // 指数表記: 2^10 * 3
+        let header = RomHeader::parse(&[
+            0x4e, 0x45, 0x53, 0x1a, 0x29, 0x00, 0x00, 0x08, 0x00, 0x0f, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00,
+        ])
+        .unwrap();
+        assert_eq!(header.prg_rom_size, 3 * 1024);
+
+        // 大きすぎるサイズならiNESとして読む
+        let header = RomHeader::parse(&[
+            0x4e, 0x45, 0x53, 0x1a, 0xfc, 0x00, 0x00, 0x08, 0x00, 0x0f, 0x00, 0x00, 0x00, 0x00,
+            0x00, 0x00,
+        ])
+        .unwrap();
+        assert_eq!(header.format, HeaderFormat::INes);
+        assert_eq!(header.prg_rom_size, 0xfc * 0x4000);
+    }
+
+    #[test]
+    fn test_parse_header_with_garbage() {
+        // "DiskDude!" が書き込まれていると7バイト目の上位4ビットは無視する
+        let mut header = [0; 16];
+        header[..4].copy_from_slice(b"NES\x1a");
+        header[6] = 0x40;
+        header[7..16].copy_from_slice(b"DiskDude!");
+        assert_eq!(RomHeader::parse(&header).unwrap().mapper, 4);
+    }
+
+    #[test]
+    fn test_load_trainer() {
+        let mut data = vec![0x4e, 0x45, 0x53, 0x1a, 0x01, 0x00, 0x04];
+        data.resize(16, 0);
+        data.extend(vec![0xaa; 0x200]);
+        data.extend(vec![0x55; 0x4000]);
+        let rom = Rom::load(&mut Cursor::new(data)).unwrap();
+        assert_eq!(rom.trainer, Some(vec![0xaa; 0x200]));
+        assert_eq!(rom.program.to_vec(), vec![0x55; 0x4000]);
+    }
+
+    #[test]
+    fn test_load_console_type() {
+        let mut data = vec![0x4e, 0x45, 0x53, 0x1a, 0x01, 0x00, 0x00, 0x01];
+        data.resize(16, 0);
+        data.extend(vec![0; 0x4000]);
+        let err = Rom::load(&mut Cursor::new(data.clone())).unwrap_err();
+        assert!(matches!(
+            err,
+            RomError::UnsupportedConsole(ConsoleType::VsSystem)
+        ));
+        assert_eq!("VS System ROMs are not supported.", err.to_string());
+
+        // PlayChoice-10のヒント画面はPRGとCHRには混ぜない
+        data[7] = 0x02;
+        data.extend(vec![0xaa; 0x2000]);
+        data.extend(vec![0xbb; 0x20]);
+        let rom = Rom::load(&mut Cursor::new(data.clone())).unwrap();
+        assert_eq!(rom.header.console, ConsoleType::PlayChoice10);
+        assert_eq!(rom.program.to_vec(), vec![0; 0x4000]);
+        assert_eq!(rom.hint_screen, Some(vec![0xaa; 0x2000]));
+
+        // NES 2.0の拡張コンソール
+        data[7] = 0x0b;
+        data[13] = 0x03;
+        let err = Rom::load(&mut Cursor::new(data)).unwrap_err();
+        assert!(matches!(
+            err,
+            RomError::UnsupportedConsole(ConsoleType::Extended(3))
+        ));
+    }
+
+    #[test]
+    fn test_describe() {
+        let rom = Rom::from_bytes(include_bytes!("../tests/rom/hello_world.nes")).unwrap();
+        let info = rom.describe();
+        assert!(info.contains("Mapper:    0 (NROM)\n"));
+        assert!(info.contains("PRG ROM:   32 KB\n"));
+        assert!(info.contains("CRC32:     4400FF8F\n"));
+        assert!(info.ends_with("Supported: yes\n"));
+
+        let mut data = include_bytes!("../tests/rom/hello_world.nes").to_vec();
+        data[6] |= 0x40;
+        assert!(Rom::from_bytes(&data).is_err());
+        let rom = Rom::load_unchecked(&mut &data[..]).unwrap();
+        assert!(rom
+            .describe()
+            .contains("Supported: no (Mapper 4 is not supported.)\n"));
+    }
+
+    #[test]
+    fn test_checksum() {
+        let mut reader = BufReader::new(File::open("./tests/rom/hello_world.nes").unwrap());
+        let rom = Rom::load(&mut reader).unwrap();
+        assert_eq!(rom.crc32(), 0x4400_ff8f);
+
+        let mut data = rom.program.to_vec();
+        data.extend_from_slice(&rom.character);
+        assert_eq!(rom.sha1(), sha1(&data));
     }
 
     #[test]
@@ -43,6 +928,48 @@ mod test {
             0x00, 0x01,
         ]);
         let err = Rom::load(&mut reader).unwrap_err();
+        assert!(matches!(err, RomError::BadMagic));
         assert_eq!("Invalid header constant.", err.to_string());
+
+        let err = Rom::load(&mut Cursor::new(b"NES\x1a")).unwrap_err();
+        assert!(matches!(err, RomError::TruncatedHeader));
+    }
+
+    #[test]
+    fn test_load_truncated() {
+        let mut data = vec![0x4e, 0x45, 0x53, 0x1a, 0x02, 0x01];
+        data.resize(16, 0);
+        data.extend(vec![0; 0x5000]);
+        let err = Rom::load(&mut Cursor::new(data.clone())).unwrap_err();
+        assert!(matches!(
+            err,
+            RomError::TruncatedPrg {
+                expected: 0x8000,
+                got: 0x5000
+            }
+        ));
+        assert_eq!(
+            "PRG ROM is truncated: expected 32768 bytes, got 20480.",
+            err.to_string()
+        );
+
+        data.extend(vec![0; 0x4000]);
+        let err = Rom::load(&mut Cursor::new(data)).unwrap_err();
+        assert!(matches!(
+            err,
+            RomError::TruncatedChr {
+                expected: 0x2000,
+                got: 0x1000
+            }
+        ));
+    }
+
+    #[test]
+    fn test_load_unsupported_mapper() {
+        let mut data = vec![0x4e, 0x45, 0x53, 0x1a, 0x02, 0x01, 0x40];
+        data.resize(16, 0);
+        let err = Rom::load(&mut Cursor::new(data)).unwrap_err();
+        assert!(matches!(err, RomError::UnsupportedMapper(4)));
+        assert_eq!("Mapper 4 is not supported.", err.to_string());
     }
 }
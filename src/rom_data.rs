@@ -0,0 +1,96 @@
+use alloc::{sync::Arc, vec::Vec};
+use core::{fmt, ops::Deref, ops::Range};
+
+// ROMの中身の一部を共有して持つ。元はVec<u8>でもメモリマップしたファイルでもよい
+#[derive(Clone)]
+pub struct RomData {
+    source: Arc<dyn AsRef<[u8]> + Send + Sync>,
+    range: Range<usize>,
+}
+
+impl RomData {
+    pub fn new(source: Arc<dyn AsRef<[u8]> + Send + Sync>) -> Self {
+        let len = (*source).as_ref().len();
+        Self {
+            source,
+            range: 0..len,
+        }
+    }
+
+    // コピーせずに一部を切り出す。範囲はこのRomDataの先頭からの位置
+    pub fn slice(&self, range: Range<usize>) -> Self {
+        assert!(
+            range.start <= range.end && range.end <= self.len(),
+            "Range is out of bounds."
+        );
+        Self {
+            source: Arc::clone(&self.source),
+            range: self.range.start + range.start..self.range.start + range.end,
+        }
+    }
+}
+
+impl Deref for RomData {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &(*self.source).as_ref()[self.range.clone()]
+    }
+}
+
+impl AsRef<[u8]> for RomData {
+    fn as_ref(&self) -> &[u8] {
+        self
+    }
+}
+
+impl From<Vec<u8>> for RomData {
+    fn from(data: Vec<u8>) -> Self {
+        Self::new(Arc::new(data))
+    }
+}
+
+impl Default for RomData {
+    fn default() -> Self {
+        Vec::new().into()
+    }
+}
+
+impl PartialEq for RomData {
+    fn eq(&self, other: &Self) -> bool {
+        **self == **other
+    }
+}
+
+impl Eq for RomData {}
+
+// 中身は大きいので長さだけ出す
+impl fmt::Debug for RomData {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "RomData({} bytes)", self.len())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::RomData;
+
+    #[test]
+    fn test_slice() {
+        let data = RomData::from((0..=255).collect::<Vec<u8>>());
+        assert_eq!(data.len(), 256);
+        let slice = data.slice(0x10..0x20);
+        assert_eq!(slice.len(), 0x10);
+        assert_eq!(slice[0], 0x10);
+        let nested = slice.slice(4..8);
+        assert_eq!(&nested[..], &[0x14, 0x15, 0x16, 0x17]);
+        assert_eq!(nested, RomData::from(vec![0x14, 0x15, 0x16, 0x17]));
+        assert_eq!(format!("{:?}", nested), "RomData(4 bytes)");
+    }
+
+    #[test]
+    #[should_panic(expected = "Range is out of bounds.")]
+    fn test_slice_out_of_bounds() {
+        RomData::from(vec![0; 4]).slice(2..5);
+    }
+}
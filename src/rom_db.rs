@@ -0,0 +1,258 @@
+use crate::{
+    checksum::to_hex,
+    rom::{Mirroring, Rom, TvSystem},
+};
+use std::{collections::HashMap, error::Error, fs, path::Path, result::Result};
+
+// データベースに書かれていたもの。書かれていないものはNone
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DbEntry {
+    pub title: String,
+    pub crc32: u32,
+    pub sha1: Option<String>,
+    pub mapper: Option<u16>,
+    pub submapper: Option<u8>,
+    pub mirroring: Option<Mirroring>,
+    pub has_battery: Option<bool>,
+    pub prg_ram_size: Option<usize>,
+    pub prg_nvram_size: Option<usize>,
+    pub chr_ram_size: Option<usize>,
+    pub chr_nvram_size: Option<usize>,
+    pub tv_system: Option<TvSystem>,
+}
+
+// PRGとCHRのCRC32からタイトルと正しいヘッダの内容を引く。
+// NES 2.0 XML(nes20db.xml)とNo-IntroのDATを読める
+#[derive(Debug, Clone, Default)]
+pub struct RomDatabase {
+    entries: HashMap<u32, DbEntry>,
+}
+
+impl RomDatabase {
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, Box<dyn Error>> {
+        Self::parse(&fs::read_to_string(path)?)
+    }
+
+    pub fn parse(source: &str) -> Result<Self, Box<dyn Error>> {
+        let mut entries = HashMap::new();
+        let mut rest = source;
+        while let Some(start) = rest.find("<game") {
+            let end = rest[start..]
+                .find("</game>")
+                .ok_or("Unterminated game element.")?;
+            let game = &rest[start..start + end];
+            rest = &rest[start + end + "</game>".len()..];
+            if let Some(entry) = parse_game(game)? {
+                entries.insert(entry.crc32, entry);
+            }
+        }
+        Ok(Self { entries })
+    }
+
+    // SHA-1が書かれていればそれも一致するものだけ
+    pub fn lookup(&self, rom: &Rom) -> Option<&DbEntry> {
+        let entry = self.entries.get(&rom.crc32())?;
+        match &entry.sha1 {
+            Some(sha1) if !sha1.eq_ignore_ascii_case(&to_hex(&rom.sha1())) => None,
+            _ => Some(entry),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+impl DbEntry {
+    // ヘッダが間違っているROMを直す
+    pub fn apply(&self, rom: &mut Rom) {
+        let header = &mut rom.header;
+        if let Some(mapper) = self.mapper {
+            header.mapper = mapper;
+        }
+        if let Some(submapper) = self.submapper {
+            header.submapper = submapper;
+        }
+        if let Some(mirroring) = self.mirroring {
+            header.mirroring = mirroring;
+        }
+        if let Some(has_battery) = self.has_battery {
+            header.has_battery = has_battery;
+        }
+        if let Some(size) = self.prg_ram_size {
+            header.prg_ram_size = size;
+        }
+        if let Some(size) = self.prg_nvram_size {
+            header.prg_nvram_size = size;
+        }
+        if let Some(size) = self.chr_ram_size {
+            header.chr_ram_size = size;
+        }
+        if let Some(size) = self.chr_nvram_size {
+            header.chr_nvram_size = size;
+        }
+        if let Some(tv_system) = self.tv_system {
+            header.tv_system = tv_system;
+        }
+    }
+}
+
+// <game>...</game> 1つ分。ROM全体のCRC32が無いものは使わない
+fn parse_game(game: &str) -> Result<Option<DbEntry>, Box<dyn Error>> {
+    let mut entry = DbEntry::default();
+    let mut crc32 = None;
+
+    // No-Introは<game name="...">、NES 2.0 XMLはコメントにファイル名が書いてある
+    let game_tag = &game[..game.find('>').unwrap_or(game.len())];
+    if let Some(name) = attribute(game_tag, "name") {
+        entry.title = name.to_string();
+    } else if let Some(start) = game.find("<!--") {
+        let comment = &game[start + 4..];
+        let comment = &comment[..comment.find("-->").unwrap_or(comment.len())];
+        let title = comment.trim();
+        entry.title = title.strip_suffix(".nes").unwrap_or(title).to_string();
+    }
+
+    for tag in game.split('<').skip(1) {
+        let (name, attributes) = tag.split_once(char::is_whitespace).unwrap_or((tag, ""));
+        let size = || attribute(attributes, "size").and_then(|s| s.parse().ok());
+        match name {
+            "rom" => {
+                let crc = attribute(attributes, "crc32").or_else(|| attribute(attributes, "crc"));
+                if let Some(crc) = crc {
+                    crc32 = Some(
+                        u32::from_str_radix(crc, 16)
+                            .map_err(|_| format!("Invalid CRC32: {}", crc))?,
+                    );
+                }
+                entry.sha1 = attribute(attributes, "sha1").map(str::to_string);
+            }
+            "pcb" => {
+                entry.mapper = attribute(attributes, "mapper").and_then(|m| m.parse().ok());
+                entry.submapper = attribute(attributes, "submapper").and_then(|m| m.parse().ok());
+                entry.mirroring = match attribute(attributes, "mirroring") {
+                    Some("H") => Some(Mirroring::Horizontal),
+                    Some("V") => Some(Mirroring::Vertical),
+                    Some("4") => Some(Mirroring::FourScreen),
+                    _ => None,
+                };
+                entry.has_battery = attribute(attributes, "battery").map(|b| b == "1");
+            }
+            "prgram" => entry.prg_ram_size = size(),
+            "prgnvram" => entry.prg_nvram_size = size(),
+            "chrram" => entry.chr_ram_size = size(),
+            "chrnvram" => entry.chr_nvram_size = size(),
+            "console" => {
+                entry.tv_system = match attribute(attributes, "region") {
+                    Some("0") => Some(TvSystem::Ntsc),
+                    Some("1") => Some(TvSystem::Pal),
+                    Some("2") => Some(TvSystem::MultiRegion),
+                    Some("3") => Some(TvSystem::Dendy),
+                    _ => None,
+                };
+            }
+            _ => {}
+        }
+    }
+
+    Ok(crc32.map(|crc32| DbEntry { crc32, ..entry }))
+}
+
+// name="value" の値。最初に見つかったものを返す
+fn attribute<'a>(tag: &'a str, name: &str) -> Option<&'a str> {
+    let mut rest = tag;
+    while let Some(start) = rest.find(name) {
+        let preceded = rest[..start]
+            .chars()
+            .last()
+            .is_none_or(|c| c.is_whitespace());
+        let after = &rest[start + name.len()..];
+        if preceded {
+            if let Some(value) = after.strip_prefix("=\"") {
+                return value.find('"').map(|end| &value[..end]);
+            }
+        }
+        rest = after;
+    }
+    None
+}
+
+#[cfg(test)]
+mod test {
+    use super::RomDatabase;
+    use crate::{
+        checksum::to_hex,
+        rom::{Mirroring, Rom},
+    };
+    use std::{fs::File, io::BufReader};
+
+    #[test]
+    fn test_nes20db() {
+        let mut rom = load_rom();
+        let source = format!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>
+<nes20db date="2024-01-01">
+	<game>
+		<!-- Hello World.nes -->
+		<prgrom size="32768" crc32="00000000"/>
+		<rom size="40960" crc32="{:08X}" sha1="{}"/>
+		<prgram size="8192"/>
+		<pcb mapper="0" submapper="0" mirroring="V" battery="1"/>
+		<console type="0" region="0"/>
+	</game>
+	<game>
+		<!-- Other.nes -->
+		<rom size="40960" crc32="12345678"/>
+	</game>
+</nes20db>"#,
+            rom.crc32(),
+            to_hex(&rom.sha1()).to_uppercase()
+        );
+        let db = RomDatabase::parse(&source).unwrap();
+        assert_eq!(db.len(), 2);
+
+        let entry = db.lookup(&rom).unwrap().clone();
+        assert_eq!(entry.title, "Hello World");
+        assert_eq!(entry.prg_ram_size, Some(0x2000));
+
+        entry.apply(&mut rom);
+        assert_eq!(rom.header.mirroring, Mirroring::Vertical);
+        assert!(rom.has_battery());
+
+        // SHA-1が違えば別のROM
+        let mut program = rom.program.to_vec();
+        program[0] ^= 0xff;
+        rom.program = program.into();
+        assert!(db.lookup(&rom).is_none());
+    }
+
+    #[test]
+    fn test_no_intro() {
+        let rom = load_rom();
+        let source = format!(
+            r#"<datafile>
+	<game name="Hello World (Japan)">
+		<description>Hello World (Japan)</description>
+		<rom name="Hello World (Japan).nes" size="40960" crc="{:08x}"/>
+	</game>
+</datafile>"#,
+            rom.crc32()
+        );
+        let db = RomDatabase::parse(&source).unwrap();
+        let entry = db.lookup(&rom).unwrap();
+        assert_eq!(entry.title, "Hello World (Japan)");
+        assert_eq!(entry.mapper, None);
+
+        assert!(RomDatabase::parse("<game><rom crc32=\"xyz\"/></game>").is_err());
+        assert!(RomDatabase::parse("<game>").is_err());
+    }
+
+    fn load_rom() -> Rom {
+        let mut reader = BufReader::new(File::open("./tests/rom/hello_world.nes").unwrap());
+        Rom::load(&mut reader).unwrap()
+    }
+}
@@ -0,0 +1,327 @@
+use crate::{
+    cpu::{register::Registers, AccessKind},
+    nes::{MemoryView, Nes},
+};
+use rhai::{Dynamic, Engine, FnPtr, AST};
+use std::{cell::RefCell, error::Error, ops::RangeInclusive, rc::Rc, result::Result};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct OverlayText {
+    pub x: i64,
+    pub y: i64,
+    pub text: String,
+}
+
+#[derive(Debug, Clone)]
+enum Event {
+    Frame,
+    Scanline(u16),
+    Execute(u16),
+    Read(RangeInclusive<u16>),
+    Write(RangeInclusive<u16>),
+}
+
+#[derive(Debug, Clone)]
+struct Hook {
+    event: Event,
+    callback: FnPtr,
+}
+
+// スクリプトから呼ばれる関数と共有する状態
+#[derive(Debug, Default)]
+struct State {
+    hooks: Vec<Hook>,
+    // コールバックを呼んでいる間だけSome
+    memory: Option<MemoryView>,
+    registers: Registers,
+    registers_changed: bool,
+    drawing: Vec<OverlayText>,
+    overlay: Vec<OverlayText>,
+    frame: u64,
+}
+
+// Rhaiで書いたスクリプト。トップレベルでon_frameなどを呼んでコールバックを登録しておくと、
+// stepで1命令実行するたびに条件に合うものが呼ばれる。
+//
+//   on_frame(|frame| { draw_text(8, 8, `frame ${frame}`); });
+//   on_scanline(100, |line| { ... });
+//   on_exec(0x8000, || { ... });
+//   on_read(0x0300, 0x03ff, |addr, value| { ... });
+//   on_write(0x2000, 0x2007, |addr, value| { ... });
+//
+// コールバックの中では peek(addr), poke(addr, value), reg("a"), set_reg("a", value),
+// draw_text(x, y, text) が使える
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    state: Rc<RefCell<State>>,
+}
+
+impl Script {
+    pub fn load(source: &str) -> Result<Self, Box<dyn Error>> {
+        let state = Rc::new(RefCell::new(State::default()));
+        let engine = create_engine(&state);
+        let ast = engine.compile(source)?;
+        engine.run_ast(&ast)?;
+        Ok(Self { engine, ast, state })
+    }
+
+    // 1命令実行して、その間に起きたイベントのコールバックを呼ぶ
+    pub fn step(&mut self, nes: &mut Nes) -> Result<u8, Box<dyn Error>> {
+        let pc = nes.registers().program_counter;
+        let (line_before, _) = nes.ppu_position();
+        let clock = nes.step()?;
+        let (line, _) = nes.ppu_position();
+        let new_line = line != line_before;
+        let vblank_scanline = nes.region().vblank_scanline();
+
+        let mut calls = Vec::new();
+        {
+            let mut state = self.state.borrow_mut();
+            if new_line && line == vblank_scanline {
+                state.frame += 1;
+                state.overlay = std::mem::take(&mut state.drawing);
+            }
+            for hook in &state.hooks {
+                let callback = hook.callback.clone();
+                match &hook.event {
+                    Event::Frame if new_line && line == vblank_scanline => {
+                        calls.push((callback, vec![Dynamic::from(state.frame as i64)]));
+                    }
+                    Event::Scanline(target) if new_line && line == *target => {
+                        calls.push((callback, vec![Dynamic::from(line as i64)]));
+                    }
+                    Event::Execute(addr) if *addr == pc => calls.push((callback, vec![])),
+                    Event::Read(range) | Event::Write(range) => {
+                        let kind = match hook.event {
+                            Event::Read(_) => AccessKind::Read,
+                            _ => AccessKind::Write,
+                        };
+                        for access in nes.accesses() {
+                            if access.kind == kind && range.contains(&access.addr) {
+                                let args = vec![
+                                    Dynamic::from(access.addr as i64),
+                                    Dynamic::from(access.value as i64),
+                                ];
+                                calls.push((callback.clone(), args));
+                            }
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+        if calls.is_empty() {
+            return Ok(clock);
+        }
+
+        {
+            let mut state = self.state.borrow_mut();
+            state.memory = Some(nes.memory_view());
+            state.registers = nes.registers().clone();
+            state.registers_changed = false;
+        }
+        let mut result = Ok(());
+        for (callback, args) in calls {
+            if let Err(err) = callback.call::<Dynamic>(&self.engine, &self.ast, args) {
+                result = Err(err);
+                break;
+            }
+        }
+        let mut state = self.state.borrow_mut();
+        if let Some(memory) = state.memory.take() {
+            nes.apply_memory_view(&memory);
+        }
+        if state.registers_changed {
+            nes.set_registers(state.registers.clone());
+        }
+        result?;
+        Ok(clock)
+    }
+
+    // 最後に終わったフレームで描かれた文字。フロントエンドが画面に重ねて表示する
+    pub fn overlay(&self) -> Vec<OverlayText> {
+        self.state.borrow().overlay.clone()
+    }
+}
+
+fn create_engine(state: &Rc<RefCell<State>>) -> Engine {
+    let mut engine = Engine::new();
+
+    let add_hook = |state: &Rc<RefCell<State>>| {
+        let state = state.clone();
+        move |event: Event, callback: FnPtr| {
+            state.borrow_mut().hooks.push(Hook { event, callback });
+        }
+    };
+    let hook = add_hook(state);
+    engine.register_fn("on_frame", move |f: FnPtr| hook(Event::Frame, f));
+    let hook = add_hook(state);
+    engine.register_fn("on_scanline", move |line: i64, f: FnPtr| {
+        hook(Event::Scanline(line as u16), f)
+    });
+    let hook = add_hook(state);
+    engine.register_fn("on_exec", move |addr: i64, f: FnPtr| {
+        hook(Event::Execute(addr as u16), f)
+    });
+    let hook = add_hook(state);
+    engine.register_fn("on_read", move |start: i64, end: i64, f: FnPtr| {
+        hook(Event::Read(start as u16..=end as u16), f)
+    });
+    let hook = add_hook(state);
+    engine.register_fn("on_write", move |start: i64, end: i64, f: FnPtr| {
+        hook(Event::Write(start as u16..=end as u16), f)
+    });
+
+    // 読めないところとコールバックの外では0
+    let s = state.clone();
+    engine.register_fn("peek", move |addr: i64| {
+        let state = s.borrow();
+        let value = state.memory.as_ref().and_then(|m| m.peek(addr as u16));
+        value.unwrap_or(0) as i64
+    });
+    let s = state.clone();
+    engine.register_fn("poke", move |addr: i64, value: i64| {
+        let mut state = s.borrow_mut();
+        state
+            .memory
+            .as_mut()
+            .is_some_and(|m| m.poke(addr as u16, value as u8))
+    });
+    let s = state.clone();
+    engine.register_fn("reg", move |name: &str| {
+        let registers = &s.borrow().registers;
+        match name.to_ascii_lowercase().as_str() {
+            "a" => registers.accumulator as i64,
+            "x" => registers.index_x as i64,
+            "y" => registers.index_y as i64,
+            "sp" => registers.stack_pointer as i64,
+            "p" => u8::from(&registers.status) as i64,
+            "pc" => registers.program_counter as i64,
+            _ => -1,
+        }
+    });
+    let s = state.clone();
+    engine.register_fn("set_reg", move |name: &str, value: i64| {
+        let mut state = s.borrow_mut();
+        let registers = &mut state.registers;
+        match name.to_ascii_lowercase().as_str() {
+            "a" => registers.accumulator = value as u8,
+            "x" => registers.index_x = value as u8,
+            "y" => registers.index_y = value as u8,
+            "sp" => registers.stack_pointer = value as u8,
+            "p" => registers.status = (value as u8).into(),
+            "pc" => registers.program_counter = value as u16,
+            _ => return false,
+        }
+        state.registers_changed = true;
+        true
+    });
+    let s = state.clone();
+    engine.register_fn("draw_text", move |x: i64, y: i64, text: &str| {
+        s.borrow_mut().drawing.push(OverlayText {
+            x,
+            y,
+            text: text.to_string(),
+        });
+    });
+
+    engine
+}
+
+#[cfg(test)]
+mod test {
+    use super::{OverlayText, Script};
+    use crate::{nes::Nes, rom::Rom};
+    use std::{fs::File, io::BufReader};
+
+    #[test]
+    fn test_memory_hooks() {
+        let mut nes = prepare();
+        let mut script = Script::load(
+            r#"
+            on_write(0x2000, 0x2007, |addr, value| {
+                poke(0x0300 + (addr & 0x07), value + 1);
+            });
+            on_read(0x8000, 0xffff, |addr, value| {
+                poke(0x0310, peek(0x0310) + 1);
+            });
+            "#,
+        )
+        .unwrap();
+        for _ in 0..20 {
+            script.step(&mut nes).unwrap();
+        }
+        assert_eq!(nes.peek(0x0300), Some(0x01));
+        assert_eq!(nes.peek(0x0301), Some(0x01));
+        assert!(nes.peek(0x0310).unwrap() > 0);
+    }
+
+    #[test]
+    fn test_exec_hook_and_registers() {
+        let mut nes = prepare();
+        let mut script = Script::load(
+            r#"
+            on_exec(0x8003, || {
+                poke(0x0000, reg("x"));
+                set_reg("a", 0x42);
+            });
+            "#,
+        )
+        .unwrap();
+        for _ in 0..3 {
+            script.step(&mut nes).unwrap();
+        }
+        assert_eq!(nes.peek(0x0000), Some(0xff));
+        assert_eq!(nes.registers().accumulator, 0x42);
+    }
+
+    #[test]
+    fn test_frame_hook() {
+        let mut nes = prepare();
+        let mut script = Script::load(
+            r#"
+            on_scanline(10, |line| { poke(0x0000, line); });
+            on_frame(|frame| { draw_text(8, 16, `frame ${frame}`); });
+            on_frame(|frame| { poke(0x0001, frame); });
+            "#,
+        )
+        .unwrap();
+        // 最初のVBlankは241*341/3クロックあたり。1フレームは約29781クロック
+        while nes.cycles() < 30000 {
+            script.step(&mut nes).unwrap();
+        }
+        assert_eq!(nes.peek(0x0000), Some(10));
+        assert_eq!(nes.peek(0x0001), Some(1));
+
+        while nes.cycles() < 60000 {
+            script.step(&mut nes).unwrap();
+        }
+        assert_eq!(nes.peek(0x0001), Some(2));
+        assert_eq!(
+            script.overlay(),
+            vec![OverlayText {
+                x: 8,
+                y: 16,
+                text: "frame 1".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_error() {
+        assert!(Script::load("on_frame(").is_err());
+
+        let mut nes = prepare();
+        let mut script = Script::load("on_exec(0x8000, || { undefined_function(); });").unwrap();
+        assert!(script.step(&mut nes).is_err());
+    }
+
+    fn prepare() -> Nes {
+        let mut reader = BufReader::new(File::open("./tests/rom/hello_world.nes").unwrap());
+        let mut nes = Nes::new();
+        nes.set_rom(Rom::load(&mut reader).unwrap()).unwrap();
+        nes.reset().unwrap();
+        nes
+    }
+}
@@ -0,0 +1,254 @@
+use crate::cpu::Cpu;
+use std::{convert::TryInto, error::Error, fmt, result::Result};
+
+const MAGIC: &[u8; 4] = b"NESS";
+// セーブステートの形式を変えたら上げる。3まではマジックもセクションも無いbincodeのタプル
+pub const STATE_VERSION: u32 = 4;
+const LEGACY_VERSION: u32 = 3;
+
+pub const SECTION_CPU: [u8; 4] = *b"CPU ";
+pub const SECTION_WRAM: [u8; 4] = *b"WRAM";
+pub const SECTION_PRG_RAM: [u8; 4] = *b"PRAM";
+
+#[derive(Debug)]
+pub enum StateError {
+    NotAState,
+    Truncated,
+    // 古すぎて読めない
+    UnsupportedVersion(u32),
+    // これより新しいビルドで書き出されたもの
+    NewerVersion { version: u32, core_version: String },
+    MissingSection([u8; 4]),
+    InvalidSection([u8; 4]),
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::NotAState => f.write_str("Not a save state."),
+            Self::Truncated => f.write_str("Save state is truncated."),
+            Self::UnsupportedVersion(version) => {
+                write!(f, "Unsupported state version: {}", version)
+            }
+            Self::NewerVersion {
+                version,
+                core_version,
+            } => write!(
+                f,
+                "State version {} was written by nes {} and is newer than this build.",
+                version, core_version
+            ),
+            Self::MissingSection(tag) => {
+                write!(
+                    f,
+                    "Missing {} section.",
+                    String::from_utf8_lossy(tag).trim()
+                )
+            }
+            Self::InvalidSection(tag) => {
+                write!(
+                    f,
+                    "Invalid {} section.",
+                    String::from_utf8_lossy(tag).trim()
+                )
+            }
+        }
+    }
+}
+
+impl Error for StateError {}
+
+// マジック、形式のバージョン、書き出したビルドのバージョンに続いて、
+// 4文字のタグ、長さ(u32 LE)、中身のセクションが並ぶ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SaveState {
+    pub version: u32,
+    pub core_version: String,
+    sections: Vec<([u8; 4], Vec<u8>)>,
+}
+
+impl SaveState {
+    pub fn new() -> Self {
+        Self {
+            version: STATE_VERSION,
+            core_version: env!("CARGO_PKG_VERSION").to_string(),
+            sections: Vec::new(),
+        }
+    }
+
+    pub fn insert(&mut self, tag: [u8; 4], data: Vec<u8>) {
+        self.sections.retain(|(t, _)| *t != tag);
+        self.sections.push((tag, data));
+    }
+
+    pub fn get(&self, tag: [u8; 4]) -> Result<&[u8], StateError> {
+        self.sections
+            .iter()
+            .find(|(t, _)| *t == tag)
+            .map(|(_, data)| &data[..])
+            .ok_or(StateError::MissingSection(tag))
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut data = MAGIC.to_vec();
+        data.extend_from_slice(&self.version.to_le_bytes());
+        data.push(self.core_version.len() as u8);
+        data.extend_from_slice(self.core_version.as_bytes());
+        for (tag, section) in &self.sections {
+            data.extend_from_slice(tag);
+            data.extend_from_slice(&(section.len() as u32).to_le_bytes());
+            data.extend_from_slice(section);
+        }
+        data
+    }
+
+    // 古い形式は今の形式に直して読む
+    pub fn from_bytes(data: &[u8]) -> Result<Self, StateError> {
+        if !data.starts_with(MAGIC) {
+            return migrate_legacy(data);
+        }
+        let mut reader = Reader(&data[MAGIC.len()..]);
+        let version = reader.u32()?;
+        let len = reader.u8()? as usize;
+        let core_version = String::from_utf8_lossy(reader.take(len)?).into_owned();
+        if version > STATE_VERSION {
+            return Err(StateError::NewerVersion {
+                version,
+                core_version,
+            });
+        }
+        if version < STATE_VERSION {
+            return Err(StateError::UnsupportedVersion(version));
+        }
+
+        let mut state = Self {
+            version,
+            core_version,
+            sections: Vec::new(),
+        };
+        while !reader.0.is_empty() {
+            let tag: [u8; 4] = reader.take(4)?.try_into().unwrap();
+            let len = reader.u32()? as usize;
+            state.insert(tag, reader.take(len)?.to_vec());
+        }
+        Ok(state)
+    }
+}
+
+impl Default for SaveState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// バージョン3はbincodeの (u32, Cpu, Vec<u8>, Vec<u8>)
+fn migrate_legacy(data: &[u8]) -> Result<SaveState, StateError> {
+    let version = match data.get(..4) {
+        Some(bytes) => u32::from_le_bytes(bytes.try_into().unwrap()),
+        None => return Err(StateError::NotAState),
+    };
+    if version > LEGACY_VERSION {
+        return Err(StateError::NotAState);
+    }
+    if version < LEGACY_VERSION {
+        return Err(StateError::UnsupportedVersion(version));
+    }
+    let (_, cpu, wram, prg_ram): (u32, Cpu, Vec<u8>, Vec<u8>) =
+        bincode::deserialize(data).map_err(|_| StateError::InvalidSection(SECTION_CPU))?;
+    let mut state = SaveState::new();
+    state.insert(
+        SECTION_CPU,
+        bincode::serialize(&cpu).expect("Failed to serialize state."),
+    );
+    state.insert(SECTION_WRAM, wram);
+    state.insert(SECTION_PRG_RAM, prg_ram);
+    Ok(state)
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], StateError> {
+        if self.0.len() < len {
+            return Err(StateError::Truncated);
+        }
+        let (head, tail) = self.0.split_at(len);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, StateError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, StateError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{SaveState, StateError, SECTION_CPU, SECTION_WRAM, STATE_VERSION};
+    use crate::cpu::Cpu;
+
+    #[test]
+    fn test_round_trip() {
+        let mut state = SaveState::new();
+        state.insert(SECTION_WRAM, vec![1, 2, 3]);
+        state.insert(SECTION_CPU, vec![]);
+        let data = state.to_bytes();
+        assert!(data.starts_with(b"NESS"));
+        let loaded = SaveState::from_bytes(&data).unwrap();
+        assert_eq!(loaded, state);
+        assert_eq!(loaded.get(SECTION_WRAM).unwrap(), &[1, 2, 3]);
+        assert!(matches!(
+            loaded.get(*b"PPU "),
+            Err(StateError::MissingSection(_))
+        ));
+
+        assert!(matches!(
+            SaveState::from_bytes(&data[..data.len() - 1]),
+            Err(StateError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn test_versions() {
+        let mut data = SaveState::new().to_bytes();
+        data[4..8].copy_from_slice(&(STATE_VERSION + 1).to_le_bytes());
+        let err = SaveState::from_bytes(&data).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "State version {} was written by nes {} and is newer than this build.",
+                STATE_VERSION + 1,
+                env!("CARGO_PKG_VERSION")
+            )
+        );
+
+        assert_eq!(
+            SaveState::from_bytes(&2u32.to_le_bytes())
+                .unwrap_err()
+                .to_string(),
+            "Unsupported state version: 2"
+        );
+        assert!(matches!(
+            SaveState::from_bytes(b"garbage"),
+            Err(StateError::NotAState)
+        ));
+    }
+
+    #[test]
+    fn test_migrate_legacy() {
+        let cpu = Cpu::new();
+        let legacy =
+            bincode::serialize(&(3u32, &cpu, vec![0x12u8; 0x800], vec![0u8; 0x2000])).unwrap();
+        let state = SaveState::from_bytes(&legacy).unwrap();
+        assert_eq!(state.version, STATE_VERSION);
+        assert_eq!(state.get(SECTION_WRAM).unwrap(), &[0x12; 0x800][..]);
+        assert_eq!(
+            state.get(SECTION_CPU).unwrap(),
+            &bincode::serialize(&cpu).unwrap()[..]
+        );
+    }
+}
@@ -0,0 +1,197 @@
+use crate::{cpu::register::Registers, Nes};
+use std::{error::Error, fmt, ops::Range, result::Result};
+
+// 比べるメモリの範囲。PPUはまだ無いのでCPUから見えるRAMだけ
+const MEMORY_RANGES: [(&str, Range<u16>); 2] =
+    [("WRAM", 0x0000..0x0800), ("PRG RAM", 0x6000..0x8000)];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Difference {
+    Register {
+        name: &'static str,
+        left: u16,
+        right: u16,
+    },
+    Cycles {
+        left: u64,
+        right: u64,
+    },
+    // 連続して違っているところをまとめたもの
+    Memory {
+        space: &'static str,
+        addr: u16,
+        left: Vec<u8>,
+        right: Vec<u8>,
+    },
+}
+
+impl fmt::Display for Difference {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Register { name, left, right } if *name == "PC" => {
+                write!(f, "{}: {:04X} != {:04X}", name, left, right)
+            }
+            Self::Register { name, left, right } => {
+                write!(f, "{}: {:02X} != {:02X}", name, left, right)
+            }
+            Self::Cycles { left, right } => write!(f, "CYC: {} != {}", left, right),
+            Self::Memory {
+                space,
+                addr,
+                left,
+                right,
+            } => {
+                let end = *addr as usize + left.len() - 1;
+                write!(
+                    f,
+                    "{} ${:04X}-${:04X}: {} != {}",
+                    space,
+                    addr,
+                    end,
+                    hex(left),
+                    hex(right)
+                )
+            }
+        }
+    }
+}
+
+// 2つのNesの状態を比べる。同じなら空
+pub fn diff(left: &Nes, right: &Nes) -> Vec<Difference> {
+    let mut differences = diff_registers(left.registers(), right.registers());
+    if left.cycles() != right.cycles() {
+        differences.push(Difference::Cycles {
+            left: left.cycles(),
+            right: right.cycles(),
+        });
+    }
+    for (space, range) in MEMORY_RANGES.iter() {
+        differences.extend(diff_memory(space, range.clone(), left, right));
+    }
+    differences
+}
+
+// save_stateで書き出したもの同士を比べる。ROMはつながっていなくてよい
+pub fn diff_states(left: &[u8], right: &[u8]) -> Result<Vec<Difference>, Box<dyn Error>> {
+    let mut left_nes = Nes::new();
+    left_nes.load_state(left)?;
+    let mut right_nes = Nes::new();
+    right_nes.load_state(right)?;
+    Ok(diff(&left_nes, &right_nes))
+}
+
+fn diff_registers(left: &Registers, right: &Registers) -> Vec<Difference> {
+    let fields = |r: &Registers| {
+        [
+            ("A", r.accumulator as u16),
+            ("X", r.index_x as u16),
+            ("Y", r.index_y as u16),
+            ("P", u8::from(&r.status) as u16),
+            ("SP", r.stack_pointer as u16),
+            ("PC", r.program_counter),
+        ]
+    };
+    fields(left)
+        .iter()
+        .zip(fields(right).iter())
+        .filter(|(l, r)| l.1 != r.1)
+        .map(|(l, r)| Difference::Register {
+            name: l.0,
+            left: l.1,
+            right: r.1,
+        })
+        .collect()
+}
+
+fn diff_memory(space: &'static str, range: Range<u16>, left: &Nes, right: &Nes) -> Vec<Difference> {
+    let mut differences = Vec::new();
+    let mut current: Option<Difference> = None;
+    for addr in range {
+        let (l, r) = (left.peek(addr), right.peek(addr));
+        if l == r {
+            differences.extend(current.take());
+            continue;
+        }
+        let (l, r) = (l.unwrap_or(0), r.unwrap_or(0));
+        match &mut current {
+            Some(Difference::Memory { left, right, .. }) => {
+                left.push(l);
+                right.push(r);
+            }
+            _ => {
+                current = Some(Difference::Memory {
+                    space,
+                    addr,
+                    left: vec![l],
+                    right: vec![r],
+                })
+            }
+        }
+    }
+    differences.extend(current);
+    differences
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|b| format!("{:02X}", b))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{diff, diff_states, Difference};
+    use crate::{rom::Rom, Nes};
+
+    #[test]
+    fn test_diff() {
+        let left = prepare();
+        let mut right = prepare();
+        assert!(diff(&left, &right).is_empty());
+
+        right.step().unwrap();
+        right.poke(0x0010, 0x01);
+        right.poke(0x0011, 0x02);
+        right.poke(0x6000, 0x03);
+        let differences = diff(&left, &right);
+        let lines: Vec<String> = differences.iter().map(|d| d.to_string()).collect();
+        assert!(lines.iter().any(|l| l.starts_with("PC: ")));
+        assert!(lines.iter().any(|l| l.starts_with("CYC: ")));
+        assert!(lines.contains(&"WRAM $0010-$0011: 00 00 != 01 02".to_string()));
+        assert!(lines.contains(&"PRG RAM $6000-$6000: 00 != 03".to_string()));
+        assert!(differences.contains(&Difference::Memory {
+            space: "PRG RAM",
+            addr: 0x6000,
+            left: vec![0x00],
+            right: vec![0x03],
+        }));
+    }
+
+    #[test]
+    fn test_diff_states() {
+        let mut nes = prepare();
+        let left = nes.save_state();
+        nes.poke(0x0123, 0xff);
+        let right = nes.save_state();
+        assert_eq!(
+            diff_states(&left, &right).unwrap(),
+            vec![Difference::Memory {
+                space: "WRAM",
+                addr: 0x0123,
+                left: vec![0x00],
+                right: vec![0xff],
+            }]
+        );
+        assert!(diff_states(&left, b"broken").is_err());
+    }
+
+    fn prepare() -> Nes {
+        let mut nes = Nes::new();
+        nes.set_rom(Rom::open("./tests/rom/hello_world.nes").unwrap())
+            .unwrap();
+        nes.reset().unwrap();
+        nes
+    }
+}
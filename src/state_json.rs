@@ -0,0 +1,208 @@
+use crate::{
+    cpu::{register::Registers, Cpu},
+    ram::{PRG_RAM_SIZE, WRAM_SIZE},
+    state::{SaveState, StateError, SECTION_CPU, SECTION_PRG_RAM, SECTION_WRAM, STATE_VERSION},
+};
+use serde::{Deserialize, Serialize};
+use std::{error::Error, result::Result};
+
+const BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+// セーブステートをRustの型に頼らずに読み書きするための形。メモリはbase64。
+// PPUとAPUはまだ無いので、できたらここにフィールドを足す
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JsonState {
+    pub version: u32,
+    // 手で組み立てたものには無くてよい
+    #[serde(default)]
+    pub core_version: String,
+    pub cpu: JsonCpu,
+    pub wram: String,
+    pub prg_ram: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct JsonCpu {
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub sp: u8,
+    pub pc: u16,
+    pub cycles: u64,
+}
+
+// save_stateで書き出したものをJSONにする
+pub fn to_json(state: &[u8]) -> Result<String, Box<dyn Error>> {
+    let state = SaveState::from_bytes(state)?;
+    let cpu: Cpu = bincode::deserialize(state.get(SECTION_CPU)?)
+        .map_err(|_| StateError::InvalidSection(SECTION_CPU))?;
+    let registers = cpu.registers();
+    let json = JsonState {
+        version: state.version,
+        core_version: state.core_version.clone(),
+        cpu: JsonCpu {
+            a: registers.accumulator,
+            x: registers.index_x,
+            y: registers.index_y,
+            p: u8::from(&registers.status),
+            sp: registers.stack_pointer,
+            pc: registers.program_counter,
+            cycles: cpu.cycles(),
+        },
+        wram: encode_base64(state.get(SECTION_WRAM)?),
+        prg_ram: encode_base64(state.get(SECTION_PRG_RAM)?),
+    };
+    Ok(serde_json::to_string_pretty(&json)?)
+}
+
+// JSONをload_stateで読める形に戻す
+pub fn from_json(json: &str) -> Result<Vec<u8>, Box<dyn Error>> {
+    let json: JsonState = serde_json::from_str(json)?;
+    if json.version > STATE_VERSION {
+        return Err(StateError::NewerVersion {
+            version: json.version,
+            core_version: json.core_version,
+        }
+        .into());
+    }
+    if json.version < STATE_VERSION {
+        return Err(StateError::UnsupportedVersion(json.version).into());
+    }
+    let memory = |data: &str, len: usize, tag: [u8; 4]| match decode_base64(data) {
+        Some(data) if data.len() == len => Ok(data),
+        _ => Err(StateError::InvalidSection(tag)),
+    };
+    let wram = memory(&json.wram, WRAM_SIZE, SECTION_WRAM)?;
+    let prg_ram = memory(&json.prg_ram, PRG_RAM_SIZE, SECTION_PRG_RAM)?;
+
+    let mut cpu = Cpu::new();
+    cpu.set_registers(Registers {
+        accumulator: json.cpu.a,
+        index_x: json.cpu.x,
+        index_y: json.cpu.y,
+        stack_pointer: json.cpu.sp,
+        status: json.cpu.p.into(),
+        program_counter: json.cpu.pc,
+    });
+    cpu.set_cycles(json.cpu.cycles);
+    let mut state = SaveState::new();
+    state.insert(
+        SECTION_CPU,
+        bincode::serialize(&cpu).expect("Failed to serialize state."),
+    );
+    state.insert(SECTION_WRAM, wram);
+    state.insert(SECTION_PRG_RAM, prg_ram);
+    Ok(state.to_bytes())
+}
+
+fn encode_base64(data: &[u8]) -> String {
+    let mut text = String::with_capacity(data.len().div_ceil(3) * 4);
+    for chunk in data.chunks(3) {
+        let bits = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |acc, (i, b)| acc | (*b as u32) << (16 - i * 8));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                text.push(BASE64[(bits >> (18 - i * 6)) as usize & 0x3f] as char);
+            } else {
+                text.push('=');
+            }
+        }
+    }
+    text
+}
+
+// 改行などの空白は読み飛ばす。おかしな文字や長さならNone
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let text: Vec<u8> = text.bytes().filter(|b| !b.is_ascii_whitespace()).collect();
+    if !text.len().is_multiple_of(4) {
+        return None;
+    }
+    let mut data = Vec::with_capacity(text.len() / 4 * 3);
+    for (n, chunk) in text.chunks(4).enumerate() {
+        let padding = chunk.iter().rev().take_while(|b| **b == b'=').count();
+        if padding > 2 || (padding > 0 && n != text.len() / 4 - 1) {
+            return None;
+        }
+        let mut bits = 0u32;
+        for (i, b) in chunk[..4 - padding].iter().enumerate() {
+            let value = BASE64.iter().position(|c| c == b)? as u32;
+            bits |= value << (18 - i * 6);
+        }
+        data.extend_from_slice(&bits.to_be_bytes()[1..4 - padding]);
+    }
+    Some(data)
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode_base64, encode_base64, from_json, to_json, JsonState};
+    use crate::{rom::Rom, state::STATE_VERSION, Nes};
+
+    #[test]
+    fn test_base64() {
+        let cases: [(&[u8], &str); 4] = [
+            (b"", ""),
+            (b"f", "Zg=="),
+            (b"fo", "Zm8="),
+            (b"foobar", "Zm9vYmFy"),
+        ];
+        for (data, text) in cases.iter() {
+            assert_eq!(encode_base64(data), *text);
+            assert_eq!(decode_base64(text).as_deref(), Some(*data));
+        }
+        assert_eq!(decode_base64("Zm9v\nYmFy").unwrap(), b"foobar");
+        assert_eq!(decode_base64("Zg="), None);
+        assert_eq!(decode_base64("Zg==Zm8="), None);
+        assert_eq!(decode_base64("Z!=="), None);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let mut nes = Nes::new();
+        nes.set_rom(Rom::open("./tests/rom/hello_world.nes").unwrap())
+            .unwrap();
+        nes.power_cycle().unwrap();
+        for _ in 0..100 {
+            nes.step().unwrap();
+        }
+        nes.poke(0x0123, 0x45);
+        nes.poke(0x6001, 0x67);
+        let state = nes.save_state();
+
+        let json = to_json(&state).unwrap();
+        let parsed: JsonState = serde_json::from_str(&json).unwrap();
+        assert_eq!(parsed.version, STATE_VERSION);
+        assert_eq!(parsed.cpu.pc, nes.registers().program_counter);
+        assert_eq!(parsed.cpu.cycles, nes.cycles());
+        assert_eq!(from_json(&json).unwrap(), state);
+    }
+
+    #[test]
+    fn test_from_json() {
+        let wram = encode_base64(&[0x12; 0x800]);
+        let prg_ram = encode_base64(&[0; 0x2000]);
+        let json = format!(
+            "{{\"version\":{},\"cpu\":{{\"a\":1,\"x\":2,\"y\":3,\"p\":36,\"sp\":253,\"pc\":49152,\"cycles\":7}},\
+             \"wram\":\"{}\",\"prg_ram\":\"{}\"}}",
+            STATE_VERSION, wram, prg_ram
+        );
+        let mut nes = Nes::new();
+        nes.load_state(&from_json(&json).unwrap()).unwrap();
+        assert_eq!(nes.registers().index_y, 3);
+        assert_eq!(nes.registers().program_counter, 0xc000);
+        assert_eq!(u8::from(&nes.registers().status), 0x24);
+        assert_eq!(nes.peek(0x07ff), Some(0x12));
+
+        let short = json.replace(&wram, "AAAA");
+        assert_eq!(
+            from_json(&short).unwrap_err().to_string(),
+            "Invalid WRAM section."
+        );
+        assert!(from_json(&json.replace("\"cycles\"", "\"clock\"")).is_err());
+    }
+}
@@ -0,0 +1,131 @@
+use crate::{nes::Nes, rom::Rom};
+use std::{
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+    result::Result,
+};
+
+pub const SLOT_COUNT: u8 = 10;
+
+// ゲームごとにステートを保存するスロット。ファイル名はROMのCRC32で区別する
+#[derive(Debug)]
+pub struct StateSlots {
+    dir: PathBuf,
+    rom_crc32: u32,
+}
+
+impl StateSlots {
+    pub fn new<P: Into<PathBuf>>(dir: P, rom: &Rom) -> Self {
+        Self {
+            dir: dir.into(),
+            rom_crc32: rom.crc32(),
+        }
+    }
+
+    pub fn path(&self, slot: u8) -> PathBuf {
+        self.dir.join(format!("{:08x}.ss{}", self.rom_crc32, slot))
+    }
+
+    // 終了時に自動で保存するステート。番号付きのスロットとは別に持つ
+    pub fn auto_path(&self) -> PathBuf {
+        self.dir.join(format!("{:08x}.auto", self.rom_crc32))
+    }
+
+    pub fn save(&self, slot: u8, nes: &Nes) -> Result<(), Box<dyn Error>> {
+        check_slot(slot)?;
+        self.save_to(&self.path(slot), nes)
+    }
+
+    pub fn load(&self, slot: u8, nes: &mut Nes) -> Result<(), Box<dyn Error>> {
+        check_slot(slot)?;
+        load_from(&self.path(slot), nes)
+    }
+
+    pub fn save_auto(&self, nes: &Nes) -> Result<(), Box<dyn Error>> {
+        self.save_to(&self.auto_path(), nes)
+    }
+
+    pub fn load_auto(&self, nes: &mut Nes) -> Result<(), Box<dyn Error>> {
+        load_from(&self.auto_path(), nes)
+    }
+
+    fn save_to(&self, path: &Path, nes: &Nes) -> Result<(), Box<dyn Error>> {
+        fs::create_dir_all(&self.dir)?;
+        fs::write(path, nes.save_state())?;
+        Ok(())
+    }
+}
+
+fn load_from(path: &Path, nes: &mut Nes) -> Result<(), Box<dyn Error>> {
+    let state = fs::read(path)?;
+    nes.load_state(&state)
+}
+
+fn check_slot(slot: u8) -> Result<(), Box<dyn Error>> {
+    if slot >= SLOT_COUNT {
+        return Err(format!("Invalid slot: {}", slot).into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::StateSlots;
+    use crate::{nes::Nes, rom::Rom};
+    use std::{env, fs, fs::File, io::BufReader};
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = env::temp_dir().join(format!("nes-state-slot-test-{}", std::process::id()));
+        let rom = load_rom();
+        let slots = StateSlots::new(&dir, &rom);
+        assert_eq!(slots.path(3), dir.join("4400ff8f.ss3"));
+
+        let mut nes = Nes::new();
+        nes.set_rom(rom).unwrap();
+        nes.reset().unwrap();
+        for _ in 0..5 {
+            nes.step().unwrap();
+        }
+        slots.save(3, &nes).unwrap();
+        let state = nes.save_state();
+
+        let mut other = Nes::new();
+        other.set_rom(load_rom()).unwrap();
+        slots.load(3, &mut other).unwrap();
+        assert_eq!(other.save_state(), state);
+
+        assert!(slots.load(4, &mut other).is_err());
+        let err = slots.save(10, &nes).unwrap_err();
+        assert_eq!("Invalid slot: 10", err.to_string());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_save_and_load_auto() {
+        let dir = env::temp_dir().join(format!("nes-state-auto-test-{}", std::process::id()));
+        let rom = load_rom();
+        let slots = StateSlots::new(&dir, &rom);
+        assert_eq!(slots.auto_path(), dir.join("4400ff8f.auto"));
+
+        let mut nes = Nes::new();
+        nes.set_rom(rom).unwrap();
+        nes.reset().unwrap();
+        nes.step().unwrap();
+        slots.save_auto(&nes).unwrap();
+
+        let mut other = Nes::new();
+        other.set_rom(load_rom()).unwrap();
+        slots.load_auto(&mut other).unwrap();
+        assert_eq!(other.state_hash(), nes.state_hash());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    fn load_rom() -> Rom {
+        let mut reader = BufReader::new(File::open("./tests/rom/hello_world.nes").unwrap());
+        Rom::load(&mut reader).unwrap()
+    }
+}
@@ -0,0 +1,164 @@
+use crate::Nes;
+use std::fmt;
+
+const STATUS: u16 = 0x6000;
+const MAGIC: u16 = 0x6001;
+const MESSAGE: u16 = 0x6004;
+// $6001〜$6003にこれが書かれていたら$6000の値は信用できる
+const MAGIC_BYTES: [u8; 3] = [0xde, 0xb0, 0x61];
+const RUNNING: u8 = 0x80;
+const NEEDS_RESET: u8 = 0x81;
+// リセットを頼まれてから押すまで100ミリ秒以上待つ
+const RESET_DELAY: u64 = 1_789_773 / 10;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Outcome {
+    Passed(String),
+    Failed { code: u8, message: String },
+    // 決めたクロック数までに終わらなかった
+    Timeout(String),
+    // 未実装の命令などでエミュレータが止まった
+    Crashed(String),
+}
+
+impl Outcome {
+    pub fn is_passed(&self) -> bool {
+        matches!(self, Self::Passed(_))
+    }
+}
+
+impl fmt::Display for Outcome {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Passed(message) => write!(f, "Passed\n{}", message),
+            Self::Failed { code, message } => write!(f, "Failed with code {}\n{}", code, message),
+            Self::Timeout(message) => write!(f, "Timed out\n{}", message),
+            Self::Crashed(message) => write!(f, "Crashed: {}", message),
+        }
+    }
+}
+
+// blarggのテストROMを動かして、$6000に結果が書かれるまで待つ
+pub fn run(nes: &mut Nes, max_cycles: u64) -> Outcome {
+    if let Err(err) = nes.reset() {
+        return Outcome::Crashed(err.to_string());
+    }
+    let mut reset_at = None;
+    while nes.cycles() < max_cycles {
+        if let Err(err) = nes.step() {
+            return Outcome::Crashed(err.to_string());
+        }
+        if !has_magic(nes) {
+            continue;
+        }
+        match nes.peek(STATUS).unwrap_or(RUNNING) {
+            RUNNING => {}
+            NEEDS_RESET => {
+                let at = *reset_at.get_or_insert(nes.cycles() + RESET_DELAY);
+                if nes.cycles() >= at {
+                    reset_at = None;
+                    if let Err(err) = nes.reset() {
+                        return Outcome::Crashed(err.to_string());
+                    }
+                }
+            }
+            0 => return Outcome::Passed(message(nes)),
+            code => {
+                return Outcome::Failed {
+                    code,
+                    message: message(nes),
+                }
+            }
+        }
+    }
+    Outcome::Timeout(message(nes))
+}
+
+fn has_magic(nes: &Nes) -> bool {
+    (0..3).all(|i| nes.peek(MAGIC + i) == Some(MAGIC_BYTES[i as usize]))
+}
+
+// $6004からの0で終わる文字列
+fn message(nes: &Nes) -> String {
+    let bytes: Vec<u8> = (MESSAGE..0x8000)
+        .map_while(|addr| nes.peek(addr).filter(|b| *b != 0))
+        .collect();
+    String::from_utf8_lossy(&bytes).trim_end().to_string()
+}
+
+#[cfg(test)]
+mod test {
+    use super::{run, Outcome};
+    use crate::{
+        rom::{Rom, RomHeader},
+        Nes,
+    };
+
+    #[test]
+    fn test_run() {
+        let mut nes = prepare(0x00);
+        assert_eq!(run(&mut nes, 100_000), Outcome::Passed("OK".to_string()));
+
+        let mut nes = prepare(0x03);
+        let outcome = run(&mut nes, 100_000);
+        assert_eq!(
+            outcome,
+            Outcome::Failed {
+                code: 3,
+                message: "OK".to_string()
+            }
+        );
+        assert_eq!(outcome.to_string(), "Failed with code 3\nOK");
+
+        // 結果を書かずに止まるもの
+        let mut nes = prepare(0x80);
+        assert_eq!(run(&mut nes, 1_000), Outcome::Timeout("OK".to_string()));
+    }
+
+    #[test]
+    fn test_run_crashed() {
+        let mut nes = Nes::new();
+        let mut program = vec![0xea; 0x8000];
+        program[0x7ffc] = 0x00;
+        program[0x7ffd] = 0x80;
+        nes.set_rom(rom(program)).unwrap();
+        assert!(matches!(run(&mut nes, 1_000), Outcome::Crashed(_)));
+    }
+
+    // 実行中の印とメッセージを書いてから結果を書き、その場で止まるROM
+    fn prepare(result: u8) -> Nes {
+        let mut program = Vec::new();
+        let mut store = |value: u8, addr: u16| {
+            // LDA #value, STA addr
+            program.extend(&[0xa9, value, 0x8d, addr as u8, (addr >> 8) as u8]);
+        };
+        store(0x80, 0x6000);
+        store(0xde, 0x6001);
+        store(0xb0, 0x6002);
+        store(0x61, 0x6003);
+        store(b'O', 0x6004);
+        store(b'K', 0x6005);
+        store(b'\n', 0x6006);
+        store(result, 0x6000);
+        // JMP 自分自身
+        let addr = 0x8000 + program.len() as u16;
+        program.extend(&[0x4c, addr as u8, (addr >> 8) as u8]);
+        program.resize(0x8000, 0);
+        program[0x7ffc] = 0x00;
+        program[0x7ffd] = 0x80;
+
+        let mut nes = Nes::new();
+        nes.set_rom(rom(program)).unwrap();
+        nes
+    }
+
+    fn rom(program: Vec<u8>) -> Rom {
+        Rom {
+            header: RomHeader::default(),
+            trainer: None,
+            program: program.into(),
+            character: Default::default(),
+            hint_screen: None,
+        }
+    }
+}
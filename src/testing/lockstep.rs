@@ -0,0 +1,310 @@
+use crate::{
+    cpu::{AccessKind, MemoryAccess},
+    ram::RamPattern,
+    region::Region,
+    rom::Rom,
+    state_diff::{self, Difference},
+    Nes,
+};
+use std::{error::Error, fmt, result::Result, thread};
+
+// 食い違ったときに表示する直前の行数
+const CONTEXT_LINES: usize = 3;
+
+// 比べる設定の片方。指定していないものはROMのヘッダや既定値のまま
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CoreConfig {
+    pub region: Option<Region>,
+    pub ram_init: Option<RamPattern>,
+    pub decode_cache: Option<bool>,
+    pub cheats: Vec<String>,
+}
+
+impl CoreConfig {
+    // "region=pal,ram-init=random:1,decode-cache=off,cheat=0075:09" のような書き方。
+    // "default" か空なら何も変えない
+    pub fn parse(spec: &str) -> Result<Self, Box<dyn Error>> {
+        let mut config = Self::default();
+        for item in spec.split(',').map(str::trim) {
+            if item.is_empty() || item == "default" {
+                continue;
+            }
+            let (key, value) = match item.find('=') {
+                Some(i) => (&item[..i], &item[i + 1..]),
+                None => return Err(format!("Invalid setting: {}.", item).into()),
+            };
+            match key {
+                "region" => {
+                    let region = Region::from_name(value)
+                        .ok_or_else(|| format!("Unknown region: {}.", value))?;
+                    config.region = Some(region);
+                }
+                "ram-init" => {
+                    let pattern = RamPattern::from_name(value)
+                        .ok_or_else(|| format!("Unknown RAM pattern: {}.", value))?;
+                    config.ram_init = Some(pattern);
+                }
+                "decode-cache" => {
+                    config.decode_cache = match value {
+                        "on" => Some(true),
+                        "off" => Some(false),
+                        _ => return Err(format!("Invalid decode-cache: {}.", value).into()),
+                    }
+                }
+                "cheat" => config.cheats.push(value.to_string()),
+                _ => return Err(format!("Unknown setting: {}.", key).into()),
+            }
+        }
+        Ok(config)
+    }
+
+    // ROMを差して設定を反映し、電源を入れたNesを作る
+    pub fn build(&self, rom: Rom) -> Result<Nes, Box<dyn Error>> {
+        let mut nes = Nes::new();
+        nes.set_rom(rom)?;
+        if let Some(region) = self.region {
+            nes.set_region(region);
+        }
+        if let Some(pattern) = self.ram_init {
+            nes.init_ram(pattern);
+        }
+        if let Some(enabled) = self.decode_cache {
+            nes.set_decode_cache(enabled);
+        }
+        for code in &self.cheats {
+            nes.add_cheat(code)?;
+        }
+        nes.power_cycle()?;
+        Ok(nes)
+    }
+}
+
+// 2つの実行が最初に食い違ったところ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    // それまでに一致して実行できた命令の数
+    pub instruction: u64,
+    pub left: String,
+    pub right: String,
+    // 直前の一致していた行
+    pub context: Vec<String>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Diverged at instruction {}:", self.instruction)?;
+        for line in &self.context {
+            writeln!(f, "  {}", line)?;
+        }
+        writeln!(f, "< {}", self.left)?;
+        write!(f, "> {}", self.right)
+    }
+}
+
+// 2つのNesを1命令ずつ交互に進めて、トレースの行か命令のメモリアクセスが食い違ったら止まる。
+// 最後まで一致したら実行した命令数を返す。両方が同じエラーで止まったときもそこまでの数を返す
+pub fn run(left: &mut Nes, right: &mut Nes, instructions: u64) -> Result<u64, Divergence> {
+    let mut context = Vec::new();
+    for i in 0..instructions {
+        let (left_line, right_line) = (left.trace_line(), right.trace_line());
+        let diverged = |left_line: String, right_line: String, context: Vec<String>| Divergence {
+            instruction: i,
+            left: left_line,
+            right: right_line,
+            context,
+        };
+        // トレースの行にはオペランドの指すメモリの値も出る
+        if left_line != right_line {
+            return Err(diverged(left_line, right_line, context));
+        }
+        match (left.step(), right.step()) {
+            (Ok(_), Ok(_)) => {}
+            (Err(l), Err(r)) if l == r => return Ok(i),
+            (l, r) => {
+                let describe = |result: Result<u8, _>| match result {
+                    Ok(_) => left_line.clone(),
+                    Err(err) => format!("{} CPU error: {}", left_line, err),
+                };
+                return Err(diverged(describe(l), describe(r), context));
+            }
+        }
+        // スタックから戻したアドレスなど、トレースの行に出ない読み書きも比べる
+        if left.accesses() != right.accesses() {
+            return Err(diverged(
+                format!("{} {}", left_line, accesses(left.accesses())),
+                format!("{} {}", right_line, accesses(right.accesses())),
+                context,
+            ));
+        }
+        if context.len() == CONTEXT_LINES {
+            context.remove(0);
+        }
+        context.push(left_line);
+    }
+    Ok(instructions)
+}
+
+// フレームの終わりに状態のハッシュが食い違ったところ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FrameDivergence {
+    // それまでに一致して実行できたフレームの数
+    pub frame: u64,
+    pub left_hash: u64,
+    pub right_hash: u64,
+    pub differences: Vec<Difference>,
+    // そのフレームをやり直して見つけた、最初に食い違った命令。
+    // トレースとアクセスに出ない状態だけが違うときはNone
+    pub instruction: Option<Box<Divergence>>,
+}
+
+impl fmt::Display for FrameDivergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "Diverged in frame {}: {:016x} != {:016x}",
+            self.frame, self.left_hash, self.right_hash
+        )?;
+        for difference in &self.differences {
+            write!(f, "\n  {}", difference)?;
+        }
+        if let Some(divergence) = &self.instruction {
+            write!(f, "\n{}", divergence)?;
+        }
+        Ok(())
+    }
+}
+
+// 2つのNesを別々のスレッドで1フレームずつ進めて、フレームごとに状態のハッシュを比べる。
+// 同じ設定で作ったものが食い違えば、コアが決定的でなくなっている。
+// 最後まで一致したら実行したフレーム数を返す。両方が同じエラーで止まったときもそこまでの数を返す
+pub fn run_frames(left: &mut Nes, right: &mut Nes, frames: u64) -> Result<u64, FrameDivergence> {
+    for frame in 0..frames {
+        let (left_state, right_state) = (left.save_state(), right.save_state());
+        let cycles = left.cycles().max(right.cycles());
+        let (left_result, right_result) = thread::scope(|scope| {
+            let handle = scope.spawn(|| left.step_frame());
+            let right_result = right.step_frame();
+            (
+                handle.join().expect("Emulation thread panicked."),
+                right_result,
+            )
+        });
+        let (left_hash, right_hash) = (left.state_hash(), right.state_hash());
+        if left_result == right_result && left_hash == right_hash {
+            if left_result.is_err() {
+                return Ok(frame);
+            }
+            continue;
+        }
+        let differences = state_diff::diff(left, right);
+        // フレームの最初に戻して命令ごとに比べ直す。1命令は1サイクル以上かかる
+        let instructions = left.cycles().max(right.cycles()) - cycles + 1;
+        let instruction = match (left.load_state(&left_state), right.load_state(&right_state)) {
+            (Ok(()), Ok(())) => run(left, right, instructions).err().map(Box::new),
+            _ => None,
+        };
+        return Err(FrameDivergence {
+            frame,
+            left_hash,
+            right_hash,
+            differences,
+            instruction,
+        });
+    }
+    Ok(frames)
+}
+
+fn accesses(accesses: &[MemoryAccess]) -> String {
+    accesses
+        .iter()
+        .filter(|access| access.kind != AccessKind::Execute)
+        .map(|access| {
+            let kind = if access.kind == AccessKind::Write {
+                "W"
+            } else {
+                "R"
+            };
+            format!("{} ${:04X}={:02X}", kind, access.addr, access.value)
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+#[cfg(test)]
+mod test {
+    use super::{run, run_frames, CoreConfig};
+    use crate::{ram::RamPattern, region::Region, rom::Rom, Nes};
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(CoreConfig::parse("default").unwrap(), CoreConfig::default());
+        let config =
+            CoreConfig::parse("region=pal, ram-init=ff,decode-cache=off,cheat=0075:09").unwrap();
+        assert_eq!(config.region, Some(Region::Pal));
+        assert_eq!(config.ram_init, Some(RamPattern::Ones));
+        assert_eq!(config.decode_cache, Some(false));
+        assert_eq!(config.cheats, vec!["0075:09"]);
+
+        assert_eq!(
+            CoreConfig::parse("mapper=4").unwrap_err().to_string(),
+            "Unknown setting: mapper."
+        );
+        assert!(CoreConfig::parse("region").is_err());
+        assert!(CoreConfig::parse("decode-cache=maybe").is_err());
+    }
+
+    #[test]
+    fn test_run() {
+        // デコードのキャッシュの有無では変わらない
+        let mut left = prepare("default");
+        let mut right = prepare("decode-cache=off");
+        assert_eq!(run(&mut left, &mut right, 500), Ok(500));
+    }
+
+    #[test]
+    fn test_divergence() {
+        // $0300: LDA $0010,X / STA $0011 / JMP $0300
+        let program = [0xbd, 0x10, 0x00, 0x8d, 0x11, 0x00, 0x4c, 0x00, 0x03];
+        let mut left = prepare("default");
+        let mut right = prepare("ram-init=ff");
+        for nes in [&mut left, &mut right].iter_mut() {
+            for (i, b) in program.iter().enumerate() {
+                nes.poke(0x0300 + i as u16, *b);
+            }
+            let mut registers = nes.registers().clone();
+            registers.program_counter = 0x0300;
+            nes.set_registers(registers);
+        }
+        let divergence = run(&mut left, &mut right, 10).unwrap_err();
+        assert_eq!(divergence.instruction, 0);
+        assert!(divergence.left.contains("LDA $0010,X @ 0010 = 00"));
+        assert!(divergence.right.contains("LDA $0010,X @ 0010 = FF"));
+        assert!(divergence
+            .to_string()
+            .starts_with("Diverged at instruction 0:\n< 0300"));
+    }
+
+    #[test]
+    fn test_run_frames() {
+        let mut left = prepare("default");
+        let mut right = prepare("default");
+        assert_eq!(run_frames(&mut left, &mut right, 3), Ok(3));
+        assert_eq!(left.state_hash(), right.state_hash());
+
+        // 片方だけ書き換えると、そのフレームで見つかる
+        right.poke(0x0010, 0x12);
+        let divergence = run_frames(&mut left, &mut right, 3).unwrap_err();
+        assert_eq!(divergence.frame, 0);
+        assert_ne!(divergence.left_hash, divergence.right_hash);
+        assert!(divergence
+            .differences
+            .iter()
+            .any(|d| d.to_string().starts_with("WRAM $0010")));
+        assert!(divergence.to_string().starts_with("Diverged in frame 0: "));
+    }
+
+    fn prepare(spec: &str) -> Nes {
+        let rom = Rom::open("./tests/rom/hello_world.nes").unwrap();
+        CoreConfig::parse(spec).unwrap().build(rom).unwrap()
+    }
+}
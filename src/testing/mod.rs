@@ -0,0 +1,6 @@
+// テストROMやテストベクタでCPUなどの正しさを確かめるためのもの
+pub mod blargg;
+pub mod lockstep;
+pub mod nestest;
+pub mod reference;
+pub mod single_step;
@@ -0,0 +1,135 @@
+use crate::{cpu::register::Registers, Nes};
+use std::fmt;
+
+// nestest.nesの自動テストの入り口。PPUが無くても動く
+pub const START_ADDRESS: u16 = 0xc000;
+// 食い違ったときに表示する直前の行数
+const CONTEXT_LINES: usize = 3;
+
+// ログと最初に食い違ったところ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Divergence {
+    // 1始まりの行番号
+    pub line: usize,
+    pub expected: String,
+    pub actual: String,
+    // 直前の一致していた行
+    pub context: Vec<String>,
+}
+
+impl fmt::Display for Divergence {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Trace diverged at line {}:", self.line)?;
+        for line in &self.context {
+            writeln!(f, "  {}", line)?;
+        }
+        writeln!(f, "- {}", self.expected)?;
+        write!(f, "+ {}", self.actual)
+    }
+}
+
+// $C000から実行してトレースをnestest.logと1行ずつ比べる。全部一致したら行数を返す
+pub fn run(nes: &mut Nes, expected_log: &str) -> Result<usize, Divergence> {
+    if let Err(err) = nes.power_cycle() {
+        return Err(Divergence {
+            line: 1,
+            expected: expected_log.lines().next().unwrap_or("").to_string(),
+            actual: format!("CPU error: {}", err),
+            context: Vec::new(),
+        });
+    }
+    nes.set_registers(Registers {
+        program_counter: START_ADDRESS,
+        stack_pointer: 0xfd,
+        status: 0x24.into(),
+        ..Registers::default()
+    });
+
+    let mut context = Vec::new();
+    for (i, expected) in expected_log.lines().enumerate() {
+        let expected = expected.trim_end();
+        let actual = nes.trace_line();
+        if actual != expected {
+            return Err(Divergence {
+                line: i + 1,
+                expected: expected.to_string(),
+                actual,
+                context,
+            });
+        }
+        // 未実装の命令などで止まったら、そこで食い違ったことにする
+        if let Err(err) = nes.step() {
+            return Err(Divergence {
+                line: i + 2,
+                expected: expected_log.lines().nth(i + 1).unwrap_or("").to_string(),
+                actual: format!("CPU error: {}", err),
+                context: vec![actual],
+            });
+        }
+        if context.len() == CONTEXT_LINES {
+            context.remove(0);
+        }
+        context.push(actual);
+    }
+    Ok(expected_log.lines().count())
+}
+
+#[cfg(test)]
+mod test {
+    use super::{run, Divergence};
+    use crate::{
+        rom::{Rom, RomHeader},
+        Nes,
+    };
+
+    #[test]
+    fn test_run() {
+        let mut nes = prepare();
+        let log = "\
+C000  A2 05     LDX #$05                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+C002  E8        INX                             A:00 X:05 Y:00 P:24 SP:FD PPU:  0, 27 CYC:9
+C003  A0 00     LDY #$00                        A:00 X:06 Y:00 P:24 SP:FD PPU:  0, 33 CYC:11
+";
+        assert_eq!(run(&mut nes, log), Ok(3));
+
+        let log = log.replace("X:06", "X:07");
+        let divergence = run(&mut nes, &log).unwrap_err();
+        assert_eq!(divergence.line, 3);
+        assert_eq!(divergence.context.len(), 2);
+        assert!(divergence.actual.contains("X:06"));
+        assert!(divergence
+            .to_string()
+            .starts_with("Trace diverged at line 3:\n"));
+    }
+
+    #[test]
+    fn test_run_unimplemented() {
+        let mut nes = prepare();
+        let log = "\
+C000  A2 05     LDX #$05                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+C002  E8        INX                             A:00 X:05 Y:00 P:24 SP:FD PPU:  0, 27 CYC:9
+C003  A0 00     LDY #$00                        A:00 X:06 Y:00 P:24 SP:FD PPU:  0, 33 CYC:11
+C005  EA        NOP                             A:00 X:06 Y:00 P:26 SP:FD PPU:  0, 39 CYC:13
+C006  EA        NOP                             A:00 X:06 Y:00 P:26 SP:FD PPU:  0, 45 CYC:15
+";
+        let Divergence { line, actual, .. } = run(&mut nes, log).unwrap_err();
+        assert_eq!(line, 5);
+        assert!(actual.starts_with("CPU error"));
+    }
+
+    // nestest.nesと同じく16KBのPRGを$8000と$C000の両方から見せる
+    fn prepare() -> Nes {
+        let mut program = vec![0xea; 0x4000];
+        program[..5].copy_from_slice(&[0xa2, 0x05, 0xe8, 0xa0, 0x00]);
+        let mut nes = Nes::new();
+        nes.set_rom(Rom {
+            header: RomHeader::default(),
+            trainer: None,
+            program: program.into(),
+            character: Default::default(),
+            hint_screen: None,
+        })
+        .unwrap();
+        nes
+    }
+}
@@ -0,0 +1,745 @@
+use crate::cpu::{register::Registers, AccessKind, MemoryAccess};
+use std::collections::HashMap;
+
+// 速さを気にせず仕様をそのまま書いた6502。本体のCPUと同じ命令を実行させて、
+// 結果が食い違ったらデコード表などの間違いとして見つける。BCDは無い(NESのCPUと同じ)
+
+const CARRY: u8 = 0x01;
+const ZERO: u8 = 0x02;
+const IRQ: u8 = 0x04;
+const DECIMAL: u8 = 0x08;
+const BREAK: u8 = 0x10;
+const RESERVED: u8 = 0x20;
+const OVERFLOW: u8 = 0x40;
+const NEGATIVE: u8 = 0x80;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Adc,
+    And,
+    Asl,
+    Bcc,
+    Bcs,
+    Beq,
+    Bit,
+    Bmi,
+    Bne,
+    Bpl,
+    Brk,
+    Bvc,
+    Bvs,
+    Clc,
+    Cld,
+    Cli,
+    Clv,
+    Cmp,
+    Cpx,
+    Cpy,
+    Dec,
+    Dex,
+    Dey,
+    Eor,
+    Inc,
+    Inx,
+    Iny,
+    Jmp,
+    Jsr,
+    Lda,
+    Ldx,
+    Ldy,
+    Lsr,
+    Nop,
+    Ora,
+    Pha,
+    Php,
+    Pla,
+    Plp,
+    Rol,
+    Ror,
+    Rti,
+    Rts,
+    Sbc,
+    Sec,
+    Sed,
+    Sei,
+    Sta,
+    Stx,
+    Sty,
+    Tax,
+    Tay,
+    Tsx,
+    Txa,
+    Txs,
+    Tya,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Implied,
+    Accumulator,
+    Immediate,
+    ZeroPage,
+    ZeroPageX,
+    ZeroPageY,
+    Absolute,
+    AbsoluteX,
+    AbsoluteY,
+    Indirect,
+    IndirectX,
+    IndirectY,
+    Relative,
+}
+
+// 公式の命令だけ。(命令, アドレッシング, 基本のクロック数)
+fn decode(opcode: u8) -> Option<(Op, Mode, u8)> {
+    use self::{Mode::*, Op::*};
+    let decoded = match opcode {
+        0x69 => (Adc, Immediate, 2),
+        0x65 => (Adc, ZeroPage, 3),
+        0x75 => (Adc, ZeroPageX, 4),
+        0x6d => (Adc, Absolute, 4),
+        0x7d => (Adc, AbsoluteX, 4),
+        0x79 => (Adc, AbsoluteY, 4),
+        0x61 => (Adc, IndirectX, 6),
+        0x71 => (Adc, IndirectY, 5),
+        0x29 => (And, Immediate, 2),
+        0x25 => (And, ZeroPage, 3),
+        0x35 => (And, ZeroPageX, 4),
+        0x2d => (And, Absolute, 4),
+        0x3d => (And, AbsoluteX, 4),
+        0x39 => (And, AbsoluteY, 4),
+        0x21 => (And, IndirectX, 6),
+        0x31 => (And, IndirectY, 5),
+        0x0a => (Asl, Accumulator, 2),
+        0x06 => (Asl, ZeroPage, 5),
+        0x16 => (Asl, ZeroPageX, 6),
+        0x0e => (Asl, Absolute, 6),
+        0x1e => (Asl, AbsoluteX, 7),
+        0x90 => (Bcc, Relative, 2),
+        0xb0 => (Bcs, Relative, 2),
+        0xf0 => (Beq, Relative, 2),
+        0x24 => (Bit, ZeroPage, 3),
+        0x2c => (Bit, Absolute, 4),
+        0x30 => (Bmi, Relative, 2),
+        0xd0 => (Bne, Relative, 2),
+        0x10 => (Bpl, Relative, 2),
+        0x00 => (Brk, Implied, 7),
+        0x50 => (Bvc, Relative, 2),
+        0x70 => (Bvs, Relative, 2),
+        0x18 => (Clc, Implied, 2),
+        0xd8 => (Cld, Implied, 2),
+        0x58 => (Cli, Implied, 2),
+        0xb8 => (Clv, Implied, 2),
+        0xc9 => (Cmp, Immediate, 2),
+        0xc5 => (Cmp, ZeroPage, 3),
+        0xd5 => (Cmp, ZeroPageX, 4),
+        0xcd => (Cmp, Absolute, 4),
+        0xdd => (Cmp, AbsoluteX, 4),
+        0xd9 => (Cmp, AbsoluteY, 4),
+        0xc1 => (Cmp, IndirectX, 6),
+        0xd1 => (Cmp, IndirectY, 5),
+        0xe0 => (Cpx, Immediate, 2),
+        0xe4 => (Cpx, ZeroPage, 3),
+        0xec => (Cpx, Absolute, 4),
+        0xc0 => (Cpy, Immediate, 2),
+        0xc4 => (Cpy, ZeroPage, 3),
+        0xcc => (Cpy, Absolute, 4),
+        0xc6 => (Dec, ZeroPage, 5),
+        0xd6 => (Dec, ZeroPageX, 6),
+        0xce => (Dec, Absolute, 6),
+        0xde => (Dec, AbsoluteX, 7),
+        0xca => (Dex, Implied, 2),
+        0x88 => (Dey, Implied, 2),
+        0x49 => (Eor, Immediate, 2),
+        0x45 => (Eor, ZeroPage, 3),
+        0x55 => (Eor, ZeroPageX, 4),
+        0x4d => (Eor, Absolute, 4),
+        0x5d => (Eor, AbsoluteX, 4),
+        0x59 => (Eor, AbsoluteY, 4),
+        0x41 => (Eor, IndirectX, 6),
+        0x51 => (Eor, IndirectY, 5),
+        0xe6 => (Inc, ZeroPage, 5),
+        0xf6 => (Inc, ZeroPageX, 6),
+        0xee => (Inc, Absolute, 6),
+        0xfe => (Inc, AbsoluteX, 7),
+        0xe8 => (Inx, Implied, 2),
+        0xc8 => (Iny, Implied, 2),
+        0x4c => (Jmp, Absolute, 3),
+        0x6c => (Jmp, Indirect, 5),
+        0x20 => (Jsr, Absolute, 6),
+        0xa9 => (Lda, Immediate, 2),
+        0xa5 => (Lda, ZeroPage, 3),
+        0xb5 => (Lda, ZeroPageX, 4),
+        0xad => (Lda, Absolute, 4),
+        0xbd => (Lda, AbsoluteX, 4),
+        0xb9 => (Lda, AbsoluteY, 4),
+        0xa1 => (Lda, IndirectX, 6),
+        0xb1 => (Lda, IndirectY, 5),
+        0xa2 => (Ldx, Immediate, 2),
+        0xa6 => (Ldx, ZeroPage, 3),
+        0xb6 => (Ldx, ZeroPageY, 4),
+        0xae => (Ldx, Absolute, 4),
+        0xbe => (Ldx, AbsoluteY, 4),
+        0xa0 => (Ldy, Immediate, 2),
+        0xa4 => (Ldy, ZeroPage, 3),
+        0xb4 => (Ldy, ZeroPageX, 4),
+        0xac => (Ldy, Absolute, 4),
+        0xbc => (Ldy, AbsoluteX, 4),
+        0x4a => (Lsr, Accumulator, 2),
+        0x46 => (Lsr, ZeroPage, 5),
+        0x56 => (Lsr, ZeroPageX, 6),
+        0x4e => (Lsr, Absolute, 6),
+        0x5e => (Lsr, AbsoluteX, 7),
+        0xea => (Nop, Implied, 2),
+        0x09 => (Ora, Immediate, 2),
+        0x05 => (Ora, ZeroPage, 3),
+        0x15 => (Ora, ZeroPageX, 4),
+        0x0d => (Ora, Absolute, 4),
+        0x1d => (Ora, AbsoluteX, 4),
+        0x19 => (Ora, AbsoluteY, 4),
+        0x01 => (Ora, IndirectX, 6),
+        0x11 => (Ora, IndirectY, 5),
+        0x48 => (Pha, Implied, 3),
+        0x08 => (Php, Implied, 3),
+        0x68 => (Pla, Implied, 4),
+        0x28 => (Plp, Implied, 4),
+        0x2a => (Rol, Accumulator, 2),
+        0x26 => (Rol, ZeroPage, 5),
+        0x36 => (Rol, ZeroPageX, 6),
+        0x2e => (Rol, Absolute, 6),
+        0x3e => (Rol, AbsoluteX, 7),
+        0x6a => (Ror, Accumulator, 2),
+        0x66 => (Ror, ZeroPage, 5),
+        0x76 => (Ror, ZeroPageX, 6),
+        0x6e => (Ror, Absolute, 6),
+        0x7e => (Ror, AbsoluteX, 7),
+        0x40 => (Rti, Implied, 6),
+        0x60 => (Rts, Implied, 6),
+        0xe9 => (Sbc, Immediate, 2),
+        0xe5 => (Sbc, ZeroPage, 3),
+        0xf5 => (Sbc, ZeroPageX, 4),
+        0xed => (Sbc, Absolute, 4),
+        0xfd => (Sbc, AbsoluteX, 4),
+        0xf9 => (Sbc, AbsoluteY, 4),
+        0xe1 => (Sbc, IndirectX, 6),
+        0xf1 => (Sbc, IndirectY, 5),
+        0x38 => (Sec, Implied, 2),
+        0xf8 => (Sed, Implied, 2),
+        0x78 => (Sei, Implied, 2),
+        0x85 => (Sta, ZeroPage, 3),
+        0x95 => (Sta, ZeroPageX, 4),
+        0x8d => (Sta, Absolute, 4),
+        0x9d => (Sta, AbsoluteX, 5),
+        0x99 => (Sta, AbsoluteY, 5),
+        0x81 => (Sta, IndirectX, 6),
+        0x91 => (Sta, IndirectY, 6),
+        0x86 => (Stx, ZeroPage, 3),
+        0x96 => (Stx, ZeroPageY, 4),
+        0x8e => (Stx, Absolute, 4),
+        0x84 => (Sty, ZeroPage, 3),
+        0x94 => (Sty, ZeroPageX, 4),
+        0x8c => (Sty, Absolute, 4),
+        0xaa => (Tax, Implied, 2),
+        0xa8 => (Tay, Implied, 2),
+        0xba => (Tsx, Implied, 2),
+        0x8a => (Txa, Implied, 2),
+        0x9a => (Txs, Implied, 2),
+        0x98 => (Tya, Implied, 2),
+        _ => return None,
+    };
+    Some(decoded)
+}
+
+// 1命令実行した結果
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Outcome {
+    pub registers: Registers,
+    // 書き込んだ順
+    pub writes: Vec<(u16, u8)>,
+    pub cycles: u8,
+}
+
+struct Machine<F> {
+    a: u8,
+    x: u8,
+    y: u8,
+    s: u8,
+    p: u8,
+    pc: u16,
+    peek: F,
+    // この命令の中で書き込んだ値。読むときはこちらを優先する
+    memory: HashMap<u16, u8>,
+    writes: Vec<(u16, u8)>,
+    cycles: u8,
+}
+
+impl<F: Fn(u16) -> u8> Machine<F> {
+    fn read(&self, addr: u16) -> u8 {
+        match self.memory.get(&addr) {
+            Some(value) => *value,
+            None => (self.peek)(addr),
+        }
+    }
+
+    fn write(&mut self, addr: u16, value: u8) {
+        self.memory.insert(addr, value);
+        self.writes.push((addr, value));
+    }
+
+    fn fetch(&mut self) -> u8 {
+        let value = self.read(self.pc);
+        self.pc = self.pc.wrapping_add(1);
+        value
+    }
+
+    fn fetch_word(&mut self) -> u16 {
+        let lower = self.fetch() as u16;
+        let upper = self.fetch() as u16;
+        lower | (upper << 8)
+    }
+
+    fn push(&mut self, value: u8) {
+        self.write(0x0100 | self.s as u16, value);
+        self.s = self.s.wrapping_sub(1);
+    }
+
+    fn pull(&mut self) -> u8 {
+        self.s = self.s.wrapping_add(1);
+        self.read(0x0100 | self.s as u16)
+    }
+
+    fn set_flag(&mut self, flag: u8, on: bool) {
+        if on {
+            self.p |= flag;
+        } else {
+            self.p &= !flag;
+        }
+    }
+
+    fn set_nz(&mut self, value: u8) {
+        self.set_flag(ZERO, value == 0);
+        self.set_flag(NEGATIVE, value & 0x80 != 0);
+    }
+
+    // オペランドのアドレスと、ページをまたいだかどうか
+    fn address(&mut self, mode: Mode) -> (u16, bool) {
+        match mode {
+            Mode::Immediate => {
+                let addr = self.pc;
+                self.pc = self.pc.wrapping_add(1);
+                (addr, false)
+            }
+            Mode::ZeroPage => (self.fetch() as u16, false),
+            Mode::ZeroPageX => (self.fetch().wrapping_add(self.x) as u16, false),
+            Mode::ZeroPageY => (self.fetch().wrapping_add(self.y) as u16, false),
+            Mode::Absolute => (self.fetch_word(), false),
+            Mode::AbsoluteX => {
+                let base = self.fetch_word();
+                let addr = base.wrapping_add(self.x as u16);
+                (addr, base & 0xff00 != addr & 0xff00)
+            }
+            Mode::AbsoluteY => {
+                let base = self.fetch_word();
+                let addr = base.wrapping_add(self.y as u16);
+                (addr, base & 0xff00 != addr & 0xff00)
+            }
+            Mode::Indirect => {
+                // ページをまたがずに上位バイトを読む
+                let pointer = self.fetch_word();
+                let lower = self.read(pointer) as u16;
+                let upper = self.read((pointer & 0xff00) | (pointer.wrapping_add(1) & 0x00ff));
+                (lower | ((upper as u16) << 8), false)
+            }
+            Mode::IndirectX => {
+                let pointer = self.fetch().wrapping_add(self.x);
+                let lower = self.read(pointer as u16) as u16;
+                let upper = self.read(pointer.wrapping_add(1) as u16) as u16;
+                (lower | (upper << 8), false)
+            }
+            Mode::IndirectY => {
+                let pointer = self.fetch();
+                let lower = self.read(pointer as u16) as u16;
+                let upper = self.read(pointer.wrapping_add(1) as u16) as u16;
+                let base = lower | (upper << 8);
+                let addr = base.wrapping_add(self.y as u16);
+                (addr, base & 0xff00 != addr & 0xff00)
+            }
+            Mode::Relative => {
+                let offset = self.fetch() as i8;
+                let addr = self.pc.wrapping_add(offset as u16);
+                (addr, self.pc & 0xff00 != addr & 0xff00)
+            }
+            Mode::Implied | Mode::Accumulator => (0, false),
+        }
+    }
+
+    fn branch(&mut self, condition: bool, addr: u16, page_crossed: bool) {
+        if condition {
+            self.pc = addr;
+            self.cycles += if page_crossed { 2 } else { 1 };
+        }
+    }
+
+    fn compare(&mut self, register: u8, value: u8) {
+        self.set_flag(CARRY, register >= value);
+        self.set_nz(register.wrapping_sub(value));
+    }
+
+    fn add(&mut self, value: u8) {
+        let sum = self.a as u16 + value as u16 + (self.p & CARRY) as u16;
+        let result = sum as u8;
+        self.set_flag(CARRY, sum > 0xff);
+        self.set_flag(OVERFLOW, (self.a ^ result) & (value ^ result) & 0x80 != 0);
+        self.a = result;
+        self.set_nz(result);
+    }
+
+    // シフトとローテートはAかメモリのどちらかを書き換える
+    fn modify(&mut self, mode: Mode, addr: u16, f: impl Fn(u8, bool) -> (u8, bool)) {
+        let value = match mode {
+            Mode::Accumulator => self.a,
+            _ => self.read(addr),
+        };
+        let (result, carry) = f(value, self.p & CARRY != 0);
+        self.set_flag(CARRY, carry);
+        self.set_nz(result);
+        match mode {
+            Mode::Accumulator => self.a = result,
+            _ => self.write(addr, result),
+        }
+    }
+
+    fn execute(&mut self, op: Op, mode: Mode) {
+        let (addr, page_crossed) = self.address(mode);
+        // 読むだけの命令はページをまたぐと1クロック増える
+        if page_crossed
+            && matches!(
+                op,
+                Op::Adc
+                    | Op::And
+                    | Op::Cmp
+                    | Op::Eor
+                    | Op::Lda
+                    | Op::Ldx
+                    | Op::Ldy
+                    | Op::Ora
+                    | Op::Sbc
+            )
+        {
+            self.cycles += 1;
+        }
+        match op {
+            Op::Adc => {
+                let value = self.read(addr);
+                self.add(value);
+            }
+            Op::Sbc => {
+                let value = self.read(addr);
+                self.add(!value);
+            }
+            Op::And => {
+                self.a &= self.read(addr);
+                self.set_nz(self.a);
+            }
+            Op::Ora => {
+                self.a |= self.read(addr);
+                self.set_nz(self.a);
+            }
+            Op::Eor => {
+                self.a ^= self.read(addr);
+                self.set_nz(self.a);
+            }
+            Op::Asl => self.modify(mode, addr, |v, _| (v << 1, v & 0x80 != 0)),
+            Op::Lsr => self.modify(mode, addr, |v, _| (v >> 1, v & 0x01 != 0)),
+            Op::Rol => self.modify(mode, addr, |v, c| ((v << 1) | c as u8, v & 0x80 != 0)),
+            Op::Ror => self.modify(mode, addr, |v, c| {
+                ((v >> 1) | ((c as u8) << 7), v & 0x01 != 0)
+            }),
+            Op::Bcc => self.branch(self.p & CARRY == 0, addr, page_crossed),
+            Op::Bcs => self.branch(self.p & CARRY != 0, addr, page_crossed),
+            Op::Bne => self.branch(self.p & ZERO == 0, addr, page_crossed),
+            Op::Beq => self.branch(self.p & ZERO != 0, addr, page_crossed),
+            Op::Bpl => self.branch(self.p & NEGATIVE == 0, addr, page_crossed),
+            Op::Bmi => self.branch(self.p & NEGATIVE != 0, addr, page_crossed),
+            Op::Bvc => self.branch(self.p & OVERFLOW == 0, addr, page_crossed),
+            Op::Bvs => self.branch(self.p & OVERFLOW != 0, addr, page_crossed),
+            Op::Bit => {
+                let value = self.read(addr);
+                self.set_flag(ZERO, self.a & value == 0);
+                self.set_flag(OVERFLOW, value & 0x40 != 0);
+                self.set_flag(NEGATIVE, value & 0x80 != 0);
+            }
+            Op::Brk => {
+                // BRKの次のバイトは読み飛ばす
+                let return_addr = self.pc.wrapping_add(1);
+                self.push((return_addr >> 8) as u8);
+                self.push(return_addr as u8);
+                self.push(self.p | BREAK | RESERVED);
+                self.p |= IRQ;
+                self.pc = self.read(0xfffe) as u16 | ((self.read(0xffff) as u16) << 8);
+            }
+            Op::Clc => self.p &= !CARRY,
+            Op::Cld => self.p &= !DECIMAL,
+            Op::Cli => self.p &= !IRQ,
+            Op::Clv => self.p &= !OVERFLOW,
+            Op::Sec => self.p |= CARRY,
+            Op::Sed => self.p |= DECIMAL,
+            Op::Sei => self.p |= IRQ,
+            Op::Cmp => {
+                let value = self.read(addr);
+                self.compare(self.a, value);
+            }
+            Op::Cpx => {
+                let value = self.read(addr);
+                self.compare(self.x, value);
+            }
+            Op::Cpy => {
+                let value = self.read(addr);
+                self.compare(self.y, value);
+            }
+            Op::Dec => {
+                let value = self.read(addr).wrapping_sub(1);
+                self.write(addr, value);
+                self.set_nz(value);
+            }
+            Op::Inc => {
+                let value = self.read(addr).wrapping_add(1);
+                self.write(addr, value);
+                self.set_nz(value);
+            }
+            Op::Dex => {
+                self.x = self.x.wrapping_sub(1);
+                self.set_nz(self.x);
+            }
+            Op::Dey => {
+                self.y = self.y.wrapping_sub(1);
+                self.set_nz(self.y);
+            }
+            Op::Inx => {
+                self.x = self.x.wrapping_add(1);
+                self.set_nz(self.x);
+            }
+            Op::Iny => {
+                self.y = self.y.wrapping_add(1);
+                self.set_nz(self.y);
+            }
+            Op::Jmp => self.pc = addr,
+            Op::Jsr => {
+                let return_addr = self.pc.wrapping_sub(1);
+                self.push((return_addr >> 8) as u8);
+                self.push(return_addr as u8);
+                self.pc = addr;
+            }
+            Op::Rts => {
+                let lower = self.pull() as u16;
+                let upper = self.pull() as u16;
+                self.pc = (lower | (upper << 8)).wrapping_add(1);
+            }
+            Op::Rti => {
+                self.p = (self.pull() & !BREAK) | RESERVED;
+                let lower = self.pull() as u16;
+                let upper = self.pull() as u16;
+                self.pc = lower | (upper << 8);
+            }
+            Op::Lda => {
+                self.a = self.read(addr);
+                self.set_nz(self.a);
+            }
+            Op::Ldx => {
+                self.x = self.read(addr);
+                self.set_nz(self.x);
+            }
+            Op::Ldy => {
+                self.y = self.read(addr);
+                self.set_nz(self.y);
+            }
+            Op::Sta => self.write(addr, self.a),
+            Op::Stx => self.write(addr, self.x),
+            Op::Sty => self.write(addr, self.y),
+            Op::Nop => {}
+            Op::Pha => self.push(self.a),
+            Op::Php => self.push(self.p | BREAK | RESERVED),
+            Op::Pla => {
+                self.a = self.pull();
+                self.set_nz(self.a);
+            }
+            Op::Plp => self.p = (self.pull() & !BREAK) | RESERVED,
+            Op::Tax => {
+                self.x = self.a;
+                self.set_nz(self.x);
+            }
+            Op::Tay => {
+                self.y = self.a;
+                self.set_nz(self.y);
+            }
+            Op::Tsx => {
+                self.x = self.s;
+                self.set_nz(self.x);
+            }
+            Op::Txa => {
+                self.a = self.x;
+                self.set_nz(self.a);
+            }
+            Op::Tya => {
+                self.a = self.y;
+                self.set_nz(self.a);
+            }
+            // フラグは変わらない
+            Op::Txs => self.s = self.x,
+        }
+    }
+}
+
+// peekで読める今のメモリの上で、PCにある命令を1つ実行する。メモリは書き換えずに
+// 書き込みを結果として返す。非公式の命令はNone
+pub fn execute<F: Fn(u16) -> u8>(registers: &Registers, peek: F) -> Option<Outcome> {
+    let (op, mode, cycles) = decode(peek(registers.program_counter))?;
+    let mut machine = Machine {
+        a: registers.accumulator,
+        x: registers.index_x,
+        y: registers.index_y,
+        s: registers.stack_pointer,
+        p: u8::from(&registers.status),
+        pc: registers.program_counter.wrapping_add(1),
+        peek,
+        memory: HashMap::new(),
+        writes: Vec::new(),
+        cycles,
+    };
+    machine.execute(op, mode);
+    Some(Outcome {
+        registers: Registers {
+            accumulator: machine.a,
+            index_x: machine.x,
+            index_y: machine.y,
+            stack_pointer: machine.s,
+            status: machine.p.into(),
+            program_counter: machine.pc,
+        },
+        writes: machine.writes,
+        cycles: machine.cycles,
+    })
+}
+
+// 本体のCPUで実行した結果と比べる。食い違いがあればその説明を返す
+pub fn compare(
+    expected: &Outcome,
+    registers: &Registers,
+    accesses: &[MemoryAccess],
+    cycles: u8,
+) -> Result<(), String> {
+    let mut errors = Vec::new();
+    if expected.registers != *registers {
+        errors.push(format!(
+            "registers: expected {:?}, got {:?}",
+            expected.registers, registers
+        ));
+    }
+    let writes: Vec<(u16, u8)> = accesses
+        .iter()
+        .filter(|a| a.kind == AccessKind::Write)
+        .map(|a| (a.addr, a.value))
+        .collect();
+    if expected.writes != writes {
+        errors.push(format!(
+            "writes: expected {:02X?}, got {:02X?}",
+            expected.writes, writes
+        ));
+    }
+    if expected.cycles != cycles {
+        errors.push(format!(
+            "cycles: expected {}, got {}",
+            expected.cycles, cycles
+        ));
+    }
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors.join(", "))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{compare, execute};
+    use crate::cpu::{bus::TestBus, register::Registers, Cpu};
+
+    #[test]
+    fn test_execute() {
+        let mut memory = vec![0; 0x10000];
+        // ADC #$50
+        memory[0x8000..0x8002].copy_from_slice(&[0x69, 0x50]);
+        let registers = Registers {
+            accumulator: 0x50,
+            program_counter: 0x8000,
+            ..Registers::default()
+        };
+        let outcome = execute(&registers, |addr| memory[addr as usize]).unwrap();
+        assert_eq!(outcome.registers.accumulator, 0xa0);
+        assert_eq!(u8::from(&outcome.registers.status), 0xe0);
+        assert_eq!(outcome.registers.program_counter, 0x8002);
+        assert_eq!(outcome.cycles, 2);
+
+        // INC $10 は同じ命令の中で読んだ値に書き込む
+        memory[0x8000..0x8002].copy_from_slice(&[0xe6, 0x10]);
+        memory[0x10] = 0xff;
+        let outcome = execute(&registers, |addr| memory[addr as usize]).unwrap();
+        assert_eq!(outcome.writes, vec![(0x0010, 0x00)]);
+        assert!(outcome.registers.status.zero);
+
+        // 非公式の命令
+        memory[0x8000] = 0x02;
+        assert_eq!(execute(&registers, |addr| memory[addr as usize]), None);
+    }
+
+    #[test]
+    fn test_compare_with_cpu() {
+        // 本体で実装済みの命令は一致する
+        let programs: [&[u8]; 6] = [
+            &[0xa9, 0x80],
+            &[0xa2, 0x00],
+            &[0x20, 0x34, 0x12],
+            &[0x8d, 0x00, 0x02],
+            &[0xbd, 0xff, 0x01],
+            &[0xd0, 0x10],
+        ];
+        for program in programs.iter() {
+            let mut cpu = Cpu::with_bus(TestBus::default());
+            for (i, b) in program.iter().enumerate() {
+                cpu.poke(0x8000 + i as u16, *b);
+            }
+            cpu.set_registers(Registers {
+                accumulator: 0x12,
+                index_x: 0x05,
+                stack_pointer: 0xfd,
+                program_counter: 0x8000,
+                ..Registers::default()
+            });
+            let expected = execute(cpu.registers(), |addr| cpu.peek(addr).unwrap()).unwrap();
+            let cycles = cpu.run().unwrap().cycles;
+            assert_eq!(
+                compare(&expected, cpu.registers(), cpu.accesses(), cycles),
+                Ok(()),
+                "{:02X?}",
+                program
+            );
+        }
+    }
+
+    #[test]
+    fn test_compare_mismatch() {
+        let mut cpu = Cpu::with_bus(TestBus::default());
+        cpu.poke(0x8000, 0xe8);
+        cpu.set_registers(Registers {
+            program_counter: 0x8000,
+            ..Registers::default()
+        });
+        let mut expected = execute(cpu.registers(), |addr| cpu.peek(addr).unwrap()).unwrap();
+        expected.cycles = 3;
+        let cycles = cpu.run().unwrap().cycles;
+        assert_eq!(
+            compare(&expected, cpu.registers(), cpu.accesses(), cycles),
+            Err("cycles: expected 3, got 2".to_string())
+        );
+    }
+}
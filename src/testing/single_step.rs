@@ -0,0 +1,157 @@
+use crate::cpu::{bus::TestBus, register::Registers, AccessKind, Cpu};
+use serde::Deserialize;
+use std::{error::Error, fs, path::Path, result::Result};
+
+// SingleStepTests(https://github.com/SingleStepTests/65x02)の1命令分のテスト
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct TestCase {
+    pub name: String,
+    pub initial: CpuState,
+    #[serde(rename = "final")]
+    pub expected: CpuState,
+    // 1クロックごとのバスアクセス。(アドレス, 値, "read"か"write")
+    pub cycles: Vec<(u16, u8, String)>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct CpuState {
+    pub pc: u16,
+    pub s: u8,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub ram: Vec<(u16, u8)>,
+}
+
+// 1つのopcodeのテストが全部入ったJSON
+pub fn parse(json: &str) -> Result<Vec<TestCase>, Box<dyn Error>> {
+    Ok(serde_json::from_str(json)?)
+}
+
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Vec<TestCase>, Box<dyn Error>> {
+    parse(&fs::read_to_string(path)?)
+}
+
+// 64KBのRAMだけのバスで1命令実行して、レジスタとメモリとバスアクセスを比べる。
+// 食い違ったところを全部返す
+pub fn run(test: &TestCase) -> Result<(), Vec<String>> {
+    let initial = &test.initial;
+    let mut cpu = Cpu::with_bus(TestBus::default());
+    for (addr, value) in &initial.ram {
+        cpu.poke(*addr, *value);
+    }
+    cpu.set_registers(Registers {
+        accumulator: initial.a,
+        index_x: initial.x,
+        index_y: initial.y,
+        stack_pointer: initial.s,
+        status: initial.p.into(),
+        program_counter: initial.pc,
+    });
+
+    let clock = cpu
+        .run()
+        .map_err(|err| vec![format!("CPU error: {}", err)])?
+        .cycles;
+
+    let mut errors = Vec::new();
+    let expected = &test.expected;
+    let registers = cpu.registers();
+    let mut check = |name: &str, expected: u16, actual: u16| {
+        if expected != actual {
+            errors.push(format!(
+                "{}: expected {:02X}, got {:02X}",
+                name, expected, actual
+            ));
+        }
+    };
+    check("PC", expected.pc, registers.program_counter);
+    check("S", expected.s as u16, registers.stack_pointer as u16);
+    check("A", expected.a as u16, registers.accumulator as u16);
+    check("X", expected.x as u16, registers.index_x as u16);
+    check("Y", expected.y as u16, registers.index_y as u16);
+    check("P", expected.p as u16, u8::from(&registers.status) as u16);
+    for (addr, value) in &expected.ram {
+        check(
+            &format!("${:04X}", addr),
+            *value as u16,
+            cpu.peek(*addr).unwrap_or(0) as u16,
+        );
+    }
+    if clock as usize != test.cycles.len() {
+        errors.push(format!(
+            "cycles: expected {}, got {}",
+            test.cycles.len(),
+            clock
+        ));
+    }
+
+    let accesses: Vec<(u16, u8, &str)> = cpu
+        .accesses()
+        .iter()
+        .map(|access| {
+            let kind = match access.kind {
+                AccessKind::Execute | AccessKind::Read => "read",
+                AccessKind::Write => "write",
+            };
+            (access.addr, access.value, kind)
+        })
+        .collect();
+    let expected_accesses: Vec<(u16, u8, &str)> = test
+        .cycles
+        .iter()
+        .map(|(addr, value, kind)| (*addr, *value, kind.as_str()))
+        .collect();
+    if accesses != expected_accesses {
+        errors.push(format!(
+            "bus: expected {:?}, got {:?}",
+            expected_accesses, accesses
+        ));
+    }
+
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{parse, run};
+
+    const LDA_IMMEDIATE: &str = r#"[
+        {
+            "name": "a9 80 00",
+            "initial": {"pc": 4660, "s": 253, "a": 0, "x": 1, "y": 2, "p": 38, "ram": [[4660, 169], [4661, 128]]},
+            "final": {"pc": 4662, "s": 253, "a": 128, "x": 1, "y": 2, "p": 164, "ram": [[4660, 169], [4661, 128]]},
+            "cycles": [[4660, 169, "read"], [4661, 128, "read"]]
+        }
+    ]"#;
+
+    #[test]
+    fn test_run() {
+        let tests = parse(LDA_IMMEDIATE).unwrap();
+        assert_eq!(tests.len(), 1);
+        assert_eq!(tests[0].name, "a9 80 00");
+        assert_eq!(run(&tests[0]), Ok(()));
+
+        let mut test = tests[0].clone();
+        test.expected.a = 0x7f;
+        test.cycles.push((0x1236, 0x00, "read".to_string()));
+        let errors = run(&test).unwrap_err();
+        assert_eq!(errors.len(), 3);
+        assert_eq!(errors[0], "A: expected 7F, got 80");
+        assert_eq!(errors[1], "cycles: expected 3, got 2");
+    }
+
+    #[test]
+    fn test_run_unimplemented() {
+        let mut test = parse(LDA_IMMEDIATE).unwrap().remove(0);
+        // ADC #$80
+        test.initial.ram[0].1 = 0x69;
+        let errors = run(&test).unwrap_err();
+        assert!(errors[0].starts_with("CPU error"));
+    }
+}
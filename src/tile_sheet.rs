@@ -0,0 +1,91 @@
+use alloc::{vec, vec::Vec};
+
+// 1行に並べるタイルの数。4KBのパターンテーブル1枚が128x128ドットになる
+pub const TILES_PER_ROW: usize = 16;
+const TILE_SIZE: usize = 8;
+const TILE_BYTES: usize = 16;
+
+// 色番号0〜3に当てる色。パレットが無いときに使う
+pub const GRAYSCALE: [[u8; 4]; 4] = [
+    [0x00, 0x00, 0x00, 0xff],
+    [0x55, 0x55, 0x55, 0xff],
+    [0xaa, 0xaa, 0xaa, 0xff],
+    [0xff, 0xff, 0xff, 0xff],
+];
+
+// パターンテーブルのタイルを並べた画像。1ドットが色番号(0〜3)1バイト
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TileSheet {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl TileSheet {
+    // 16バイトで1タイル。最初の8バイトが下位ビット、次の8バイトが上位ビット
+    pub fn decode(chr: &[u8]) -> Self {
+        let tiles = chr.len().div_ceil(TILE_BYTES);
+        let rows = tiles.div_ceil(TILES_PER_ROW);
+        let width = TILES_PER_ROW * TILE_SIZE;
+        let height = rows * TILE_SIZE;
+        let mut pixels = vec![0; width * height];
+        for (i, tile) in chr.chunks(TILE_BYTES).enumerate() {
+            let left = (i % TILES_PER_ROW) * TILE_SIZE;
+            let top = (i / TILES_PER_ROW) * TILE_SIZE;
+            for y in 0..TILE_SIZE {
+                let low = tile.get(y).copied().unwrap_or(0);
+                let high = tile.get(y + TILE_SIZE).copied().unwrap_or(0);
+                for x in 0..TILE_SIZE {
+                    let bit = 7 - x;
+                    let color = ((low >> bit) & 1) | (((high >> bit) & 1) << 1);
+                    pixels[(top + y) * width + left + x] = color;
+                }
+            }
+        }
+        Self {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    pub fn to_rgba(&self, palette: &[[u8; 4]; 4]) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|&color| palette[color as usize & 0x03])
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{TileSheet, GRAYSCALE};
+
+    #[test]
+    fn test_decode() {
+        let mut chr = vec![0; 32];
+        // 1枚目の1行目: 左端が色1、右端が色2、その隣が色3
+        chr[0] = 0b1000_0010;
+        chr[8] = 0b0000_0011;
+        // 2枚目の左上が色3
+        chr[16] = 0x80;
+        chr[24] = 0x80;
+        let sheet = TileSheet::decode(&chr);
+        assert_eq!((sheet.width, sheet.height), (128, 8));
+        assert_eq!(&sheet.pixels[..8], &[1, 0, 0, 0, 0, 0, 3, 2]);
+        assert_eq!(sheet.pixels[8], 3);
+        assert_eq!(sheet.pixels[128], 0);
+
+        let rgba = sheet.to_rgba(&GRAYSCALE);
+        assert_eq!(rgba.len(), 128 * 8 * 4);
+        assert_eq!(&rgba[..4], &[0x55, 0x55, 0x55, 0xff]);
+        assert_eq!(&rgba[24..28], &[0xff, 0xff, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn test_decode_pattern_tables() {
+        let sheet = TileSheet::decode(&[0xff; 0x2000]);
+        assert_eq!((sheet.width, sheet.height), (128, 256));
+        assert!(sheet.pixels.iter().all(|&color| color == 3));
+    }
+}
@@ -0,0 +1,56 @@
+#![cfg(feature = "std")]
+
+use nes::{testing::blargg, Nes, Rom};
+use std::path::Path;
+
+// 10秒分実行しても終わらなければ失敗にする
+const MAX_CYCLES: u64 = 1_789_773 * 10;
+
+// blarggのテストROMはリポジトリに入れていないので、tests/rom/blargg/に置いてから
+// cargo test -- --ignored で動かす。CPUの命令が揃うまでは途中で止まる
+fn run(name: &str) {
+    let path = Path::new("./tests/rom/blargg").join(name);
+    if !path.exists() {
+        eprintln!("skipped: {} is required", path.display());
+        return;
+    }
+    let mut nes = Nes::new();
+    nes.set_rom(Rom::open(&path).unwrap()).unwrap();
+    let outcome = blargg::run(&mut nes, MAX_CYCLES);
+    assert!(outcome.is_passed(), "{}: {}", name, outcome);
+}
+
+macro_rules! blargg_tests {
+    ($($test:ident => $name:expr,)*) => {
+        $(
+            #[test]
+            #[ignore]
+            fn $test() {
+                run($name);
+            }
+        )*
+    };
+}
+
+blargg_tests! {
+    test_instr_basics => "instr_test-v5/01-basics.nes",
+    test_instr_implied => "instr_test-v5/02-implied.nes",
+    test_instr_immediate => "instr_test-v5/03-immediate.nes",
+    test_instr_zero_page => "instr_test-v5/04-zero_page.nes",
+    test_instr_absolute => "instr_test-v5/07-absolute.nes",
+    test_instr_branches => "instr_test-v5/10-branches.nes",
+    test_instr_stack => "instr_test-v5/11-stack.nes",
+    test_instr_jmp_jsr => "instr_test-v5/12-jmp_jsr.nes",
+    test_instr_rts => "instr_test-v5/13-rts.nes",
+    // PPUができたら通るようにする。VBlankフラグとNMIのタイミング
+    test_ppu_vbl_basics => "ppu_vbl_nmi/rom_singles/01-vbl_basics.nes",
+    test_ppu_vbl_set_time => "ppu_vbl_nmi/rom_singles/02-vbl_set_time.nes",
+    test_ppu_vbl_clear_time => "ppu_vbl_nmi/rom_singles/03-vbl_clear_time.nes",
+    test_ppu_nmi_control => "ppu_vbl_nmi/rom_singles/04-nmi_control.nes",
+    test_ppu_nmi_timing => "ppu_vbl_nmi/rom_singles/05-nmi_timing.nes",
+    test_ppu_suppression => "ppu_vbl_nmi/rom_singles/06-suppression.nes",
+    test_ppu_nmi_on_timing => "ppu_vbl_nmi/rom_singles/07-nmi_on_timing.nes",
+    test_ppu_nmi_off_timing => "ppu_vbl_nmi/rom_singles/08-nmi_off_timing.nes",
+    test_ppu_even_odd_frames => "ppu_vbl_nmi/rom_singles/09-even_odd_frames.nes",
+    test_ppu_even_odd_timing => "ppu_vbl_nmi/rom_singles/10-even_odd_timing.nes",
+}
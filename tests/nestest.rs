@@ -0,0 +1,24 @@
+#![cfg(feature = "std")]
+
+use nes::{testing::nestest, Nes, Rom};
+use std::{fs, path::Path};
+
+const ROM_PATH: &str = "./tests/rom/nestest.nes";
+const LOG_PATH: &str = "./tests/rom/nestest.log";
+
+// nestest.nesとnestest.logはリポジトリに入れていないので、tests/rom/に置いてから
+// cargo test -- --ignored で動かす。CPUの命令が揃うまでは途中で食い違う
+#[test]
+#[ignore]
+fn test_nestest() {
+    if !Path::new(ROM_PATH).exists() || !Path::new(LOG_PATH).exists() {
+        eprintln!("skipped: {} and {} are required", ROM_PATH, LOG_PATH);
+        return;
+    }
+    let mut nes = Nes::new();
+    nes.set_rom(Rom::open(ROM_PATH).unwrap()).unwrap();
+    let log = fs::read_to_string(LOG_PATH).unwrap();
+    if let Err(divergence) = nestest::run(&mut nes, &log) {
+        panic!("{}", divergence);
+    }
+}
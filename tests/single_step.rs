@@ -0,0 +1,35 @@
+#![cfg(feature = "std")]
+
+use nes::testing::single_step;
+use std::{fs, path::Path};
+
+const TEST_DIR: &str = "./tests/single_step";
+
+// SingleStepTestsのnes6502/v1/*.jsonはリポジトリに入れていないので、tests/single_step/に置いてから
+// cargo test -- --ignored で動かす。CPUの命令が揃うまでは未実装のopcodeで失敗する
+#[test]
+#[ignore]
+fn test_single_step() {
+    if !Path::new(TEST_DIR).exists() {
+        eprintln!("skipped: {} is required", TEST_DIR);
+        return;
+    }
+    let mut paths: Vec<_> = fs::read_dir(TEST_DIR)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|e| e == "json"))
+        .collect();
+    paths.sort();
+
+    let mut failures = Vec::new();
+    for path in paths {
+        for test in single_step::load(&path).unwrap() {
+            if let Err(errors) = single_step::run(&test) {
+                failures.push(format!("{}: {}", test.name, errors.join(", ")));
+                // 同じopcodeの失敗は1つ見れば十分
+                break;
+            }
+        }
+    }
+    assert!(failures.is_empty(), "\n{}", failures.join("\n"));
+}